@@ -0,0 +1,23 @@
+//! Exposes the version of rumqttc that Cargo.toml depends on, so that reports
+//! can record which client library produced them
+
+use std::fs;
+
+use toml::Value;
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.toml");
+
+    let version = fs::read_to_string("Cargo.toml")
+        .ok()
+        .and_then(|manifest| manifest.parse::<Value>().ok())
+        .and_then(
+            |manifest| match manifest.get("dependencies")?.get("rumqttc")? {
+                Value::String(version) => Some(version.clone()),
+                rumqttc => rumqttc.get("version")?.as_str().map(str::to_owned),
+            },
+        )
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    println!("cargo:rustc-env=RUMQTTC_VERSION={version}");
+}
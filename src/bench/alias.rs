@@ -0,0 +1,205 @@
+//! Mqtt 5 topic aliases. A dedicated v5 connection publishes to long topics
+//! twice, once with full topic names and once replacing them with aliases
+//! after the first publish to each topic, so that the bandwidth saved and the
+//! effect on the broker's throughput can be compared side by side
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use bytes::Bytes;
+use rumqttc::{
+    v5::{
+        mqttbytes::{
+            v5::{Packet, Publish, PublishProperties},
+            QoS,
+        },
+        Event,
+    },
+    Outgoing,
+};
+use serde::Serialize;
+use tokio::{task, time};
+
+use crate::{
+    bench::{payload, v5_connect, v5_options},
+    common::{latency_histogram, LatencySummary},
+    BenchConfig,
+};
+
+/// Both passes of the comparison
+#[derive(Debug, Serialize)]
+pub struct AliasReport {
+    pub topic_length: usize,
+    pub topics: usize,
+    /// aliases the broker allowed, at most `--topic-alias-max`
+    pub aliases: u16,
+    pub without_aliases: AliasPass,
+    pub with_aliases: AliasPass,
+    /// share of wire bytes saved by aliases
+    pub saved_percent: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AliasPass {
+    pub publishes: u64,
+    pub duration_secs: f64,
+    pub throughput: f64,
+    /// size of the publishes on the wire, including mqtt headers
+    pub wire_bytes: u64,
+    pub ack_latencies: LatencySummary,
+}
+
+/// Publishes `--count` publishes to `--topic-alias-max` long topics without
+/// and then with aliases
+pub(crate) async fn compare(config: Arc<BenchConfig>) -> anyhow::Result<AliasReport> {
+    let topics: Vec<String> = (0..config.topic_alias_max.unwrap_or(1).max(1))
+        .map(|i| long_topic(i, config.alias_topic_length))
+        .collect();
+
+    let (without_aliases, _) = pass(&config, &topics, false).await?;
+    let (with_aliases, aliases) = pass(&config, &topics, true).await?;
+    let saved_percent = match without_aliases.wire_bytes {
+        0 => 0.0,
+        bytes => (1.0 - with_aliases.wire_bytes as f64 / bytes as f64) * 100.0,
+    };
+
+    Ok(AliasReport {
+        topic_length: config.alias_topic_length,
+        topics: topics.len(),
+        aliases,
+        without_aliases,
+        with_aliases,
+        saved_percent,
+    })
+}
+
+/// `hello/alias-{i}/` padded to `length` bytes
+fn long_topic(i: u16, length: usize) -> String {
+    let topic = format!("hello/alias-{i:05}/");
+    let padding = length.saturating_sub(topic.len());
+    topic + &"x".repeat(padding)
+}
+
+/// Returns the pass and the number of aliases used
+async fn pass(
+    config: &Arc<BenchConfig>,
+    topics: &[String],
+    aliased: bool,
+) -> anyhow::Result<(AliasPass, u16)> {
+    let mut options = v5_options(config, "mqttwrk-alias")?;
+    options.set_topic_alias_max(config.topic_alias_max);
+    let (client, mut eventloop, connack) = v5_connect(options).await?;
+    let broker_max = connack.properties.and_then(|p| p.topic_alias_max);
+    let aliases = if aliased {
+        config
+            .topic_alias_max
+            .unwrap_or(0)
+            .min(broker_max.unwrap_or(0))
+    } else {
+        0
+    };
+    if aliased && aliases == 0 {
+        warn!("Id = mqttwrk-alias, Broker doesn't allow topic aliases");
+    }
+
+    let count = config.count;
+    let wire_bytes = Arc::new(AtomicU64::new(0));
+    let requests = task::spawn({
+        let client = client.clone();
+        let topics = topics.to_vec();
+        let payload_size = config.payload_size;
+        let wire_bytes = wire_bytes.clone();
+        async move {
+            for i in 0..count {
+                let index = i % topics.len();
+                let alias = (index < aliases as usize).then_some(index as u16 + 1);
+                // the first publish to a topic maps the alias to it
+                let topic = match alias {
+                    Some(_) if i >= topics.len() => "",
+                    _ => topics[index].as_str(),
+                };
+                let properties = alias.map(|alias| PublishProperties {
+                    topic_alias: Some(alias),
+                    ..Default::default()
+                });
+
+                let payload = Bytes::from(payload::generate(payload_size, false, None));
+                let mut publish =
+                    Publish::new(topic, QoS::AtLeastOnce, payload.clone(), properties.clone());
+                // size only counts the packet id once one is assigned
+                publish.pkid = 1;
+                wire_bytes.fetch_add(publish.size() as u64, Ordering::Relaxed);
+
+                let published = match properties {
+                    Some(properties) => {
+                        client
+                            .publish_with_properties(
+                                topic,
+                                QoS::AtLeastOnce,
+                                false,
+                                payload,
+                                properties,
+                            )
+                            .await
+                    }
+                    None => {
+                        client
+                            .publish(topic, QoS::AtLeastOnce, false, payload)
+                            .await
+                    }
+                };
+                if let Err(e) = published {
+                    error!("Id = mqttwrk-alias, Publish error = {:?}", e);
+                    return;
+                }
+            }
+        }
+    });
+
+    let inflight = config.max_inflight as usize;
+    let mut sent: Vec<Option<Instant>> = vec![None; inflight + 1];
+    let mut histogram = latency_histogram();
+    let mut acks = 0;
+    let start = Instant::now();
+    let timeout = config.conn_timeout.max(config.receive_timeout);
+    while acks < count {
+        let event = match time::timeout(timeout, eventloop.poll()).await {
+            Ok(event) => event?,
+            Err(_) => {
+                warn!("Id = mqttwrk-alias, {} of {} publishes acked", acks, count);
+                break;
+            }
+        };
+
+        match event {
+            Event::Outgoing(Outgoing::Publish(pkid)) => {
+                sent[pkid as usize] = Some(Instant::now());
+            }
+            Event::Incoming(Packet::PubAck(puback)) => {
+                acks += 1;
+                if let Some(sent) = sent[puback.pkid as usize].take() {
+                    histogram.record(sent.elapsed().as_micros() as u64).unwrap();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let elapsed = start.elapsed();
+    requests.abort();
+    let _ = client.try_disconnect();
+
+    let pass = AliasPass {
+        publishes: acks as u64,
+        duration_secs: elapsed.as_secs_f64(),
+        throughput: acks as f64 / elapsed.as_secs_f64(),
+        wire_bytes: wire_bytes.load(Ordering::Relaxed),
+        ack_latencies: LatencySummary::from(&histogram),
+    };
+    Ok((pass, aliases))
+}
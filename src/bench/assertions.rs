@@ -0,0 +1,66 @@
+//! Thresholds on the final summary. A run that violates any of them exits with
+//! a non-zero status so that benchmarks can gate CI pipelines
+
+use crate::{bench::report::Summary, BenchConfig};
+
+/// Outcome of checking one threshold
+#[derive(Debug)]
+pub(crate) struct Assertion {
+    pub name: &'static str,
+    /// why the threshold was violated, if it was
+    pub failure: Option<String>,
+}
+
+/// Checks every configured threshold against the summary of the run
+pub(crate) fn check(config: &BenchConfig, summary: &Summary) -> Vec<Assertion> {
+    let mut assertions = Vec::new();
+    let mut assert = |name, failure| assertions.push(Assertion { name, failure });
+
+    if let Some(max) = config.assert_p99_latency {
+        let max = max.as_secs_f64() * 1000.0;
+        let p99 = summary.latencies.p99;
+        assert(
+            "p99_latency",
+            (p99 > max).then(|| format!("p99 latency {p99:.3}ms exceeds {max:.3}ms")),
+        );
+    }
+
+    if let Some(max) = config.assert_p99_ack_latency {
+        let max = max.as_secs_f64() * 1000.0;
+        let p99 = summary.ack_latencies.p99;
+        assert(
+            "p99_ack_latency",
+            (p99 > max).then(|| format!("p99 ack latency {p99:.3}ms exceeds {max:.3}ms")),
+        );
+    }
+
+    if let Some(max) = config.assert_loss {
+        let loss = summary.loss_percent;
+        assert(
+            "loss",
+            (loss > max).then(|| format!("loss {loss:.2}% exceeds {max:.2}%")),
+        );
+    }
+
+    if let Some(min) = config.assert_throughput {
+        let throughput = summary.incoming_throughput;
+        assert(
+            "throughput",
+            (throughput < min).then(|| {
+                format!(
+                    "incoming throughput {throughput:.2} messages/s is below {min:.2} messages/s"
+                )
+            }),
+        );
+    }
+
+    if let Some(max) = config.assert_reconnects {
+        let reconnects = summary.reconnects;
+        assert(
+            "reconnects",
+            (reconnects > max).then(|| format!("{reconnects} reconnects exceed {max}")),
+        );
+    }
+
+    assertions
+}
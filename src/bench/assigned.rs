@@ -0,0 +1,64 @@
+//! Client ids assigned by the broker. `--assigned-client-ids` v5 clients
+//! connect at once with empty client ids, and every connack is expected to
+//! carry an assigned client identifier that no other client got
+
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use futures::future::try_join_all;
+use serde::Serialize;
+
+use crate::{
+    bench::{v5_connect, v5_disconnect, v5_options},
+    common::{latency_histogram, LatencySummary},
+    BenchConfig,
+};
+
+#[derive(Debug, Serialize)]
+pub struct AssignedIdsReport {
+    pub clients: u64,
+    /// connacks with an assigned client identifier
+    pub assigned: u64,
+    /// connacks without one
+    pub missing: u64,
+    /// clients that got the same client identifier as another
+    pub duplicates: u64,
+    pub connack_latencies: LatencySummary,
+}
+
+pub(crate) async fn verify(config: Arc<BenchConfig>) -> anyhow::Result<AssignedIdsReport> {
+    let clients = config.assigned_client_ids.unwrap_or(0);
+    let connections = try_join_all((0..clients).map(|_| connect(&config))).await?;
+
+    let mut latencies = latency_histogram();
+    let mut assigned: HashMap<String, u64> = HashMap::new();
+    let mut missing = 0;
+    for (id, latency) in connections {
+        latencies.record(latency)?;
+        match id {
+            Some(id) if !id.is_empty() => *assigned.entry(id).or_default() += 1,
+            _ => missing += 1,
+        }
+    }
+
+    Ok(AssignedIdsReport {
+        clients,
+        assigned: assigned.values().sum(),
+        missing,
+        duplicates: assigned.values().filter(|&&count| count > 1).sum(),
+        connack_latencies: LatencySummary::from(&latencies),
+    })
+}
+
+/// Connects with an empty client id and disconnects. Returns the client id
+/// the broker assigned along with the connack latency in microseconds
+async fn connect(config: &BenchConfig) -> anyhow::Result<(Option<String>, u64)> {
+    let start = Instant::now();
+    let (client, mut eventloop, connack) = v5_connect(v5_options(config, "")?).await?;
+    let latency = start.elapsed().as_micros() as u64;
+    let id = connack
+        .properties
+        .and_then(|properties| properties.assigned_client_identifier);
+
+    v5_disconnect(&client, &mut eventloop).await?;
+    Ok((id, latency))
+}
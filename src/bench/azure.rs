@@ -0,0 +1,96 @@
+//! Azure iot hub. Every connection authenticates as the device named after
+//! its client id, with a sas token signed by the shared access policy of
+//! `--azure-connection-string`. The policy needs the DeviceConnect permission
+//! and the devices have to be registered with the hub
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::hmac;
+
+const API_VERSION: &str = "2021-04-12";
+
+#[derive(Debug, Clone)]
+pub struct ConnectionString {
+    pub host: String,
+    key_name: String,
+    key: hmac::Key,
+}
+
+/// `HostName=...;SharedAccessKeyName=...;SharedAccessKey=...`
+pub(crate) fn parse(connection_string: &str) -> Result<ConnectionString, String> {
+    let mut host = None;
+    let mut key_name = None;
+    let mut key = None;
+    for field in connection_string
+        .split(';')
+        .filter(|field| !field.is_empty())
+    {
+        // base64 keys end with `=`, so only the first one separates the value
+        match field.split_once('=') {
+            Some(("HostName", value)) => host = Some(value.to_owned()),
+            Some(("SharedAccessKeyName", value)) => key_name = Some(value.to_owned()),
+            Some(("SharedAccessKey", value)) => key = Some(value.to_owned()),
+            Some(("DeviceId", _)) => {
+                return Err("device connection strings authenticate a single device, \
+                            use the connection string of a policy with DeviceConnect"
+                    .to_owned())
+            }
+            Some(_) => {}
+            None => return Err(format!("expected KEY=VALUE, got `{field}`")),
+        }
+    }
+
+    let key = key.ok_or("SharedAccessKey is missing")?;
+    let key = STANDARD
+        .decode(key)
+        .map_err(|e| format!("invalid SharedAccessKey: {e}"))?;
+    Ok(ConnectionString {
+        host: host.ok_or("HostName is missing")?,
+        key_name: key_name.ok_or("SharedAccessKeyName is missing")?,
+        key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+    })
+}
+
+/// Username of device `id`
+pub(crate) fn username(connection: &ConnectionString, id: &str) -> String {
+    format!("{}/{}/?api-version={}", connection.host, id, API_VERSION)
+}
+
+/// Sas token of device `id` that expires in `expiry`
+pub(crate) fn sas_token(connection: &ConnectionString, id: &str, expiry: Duration) -> String {
+    let expires_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        + expiry;
+    let resource = encode(&format!("{}/devices/{}", connection.host, id));
+    let message = format!("{}\n{}", resource, expires_at.as_secs());
+    let signature = hmac::sign(&connection.key, message.as_bytes());
+
+    format!(
+        "SharedAccessSignature sr={}&sig={}&se={}&skn={}",
+        resource,
+        encode(&STANDARD.encode(signature)),
+        expires_at.as_secs(),
+        encode(&connection.key_name)
+    )
+}
+
+/// Device to cloud telemetry topic of device `id`. Devices can't publish
+/// anywhere else
+pub(crate) fn topic(id: &str) -> String {
+    format!("devices/{id}/messages/events/")
+}
+
+/// Percent encodes everything but unreserved characters
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,233 @@
+//! Connection churn. With `--churn`, a fraction of the connections of a run
+//! keeps disconnecting and reconnecting at `--churn-rate` while the stable
+//! publishers and subscribers benchmark the broker. This measures how fast
+//! the broker handles connects, and the latencies of the stable connections
+//! show what churn costs everyone else. Churning connections either start
+//! clean every time or resume a persistent session holding a subscription
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use futures::future::try_join_all;
+use hdrhistogram::Histogram;
+use rumqttc::{AsyncClient, ConnAck, Event, EventLoop, Incoming, Outgoing, QoS};
+use serde::Serialize;
+use tokio::{
+    sync::Notify,
+    task::{self, JoinHandle},
+    time::{self, Duration},
+};
+
+use crate::{
+    bench::{network_options, options, pacer::Pacer},
+    common::{latency_histogram, LatencySummary},
+    BenchConfig, ChurnSession,
+};
+
+#[derive(Debug, Serialize)]
+pub struct ChurnReport {
+    pub connections: usize,
+    pub session: ChurnSession,
+    /// reconnects per second across churning connections asked for
+    pub target_rate: u64,
+    /// reconnects the broker acked
+    pub reconnects: u64,
+    /// reconnects per second the broker acked
+    pub reconnect_rate: f64,
+    /// connects that failed or timed out
+    pub failed: u64,
+    /// reconnects the broker resumed a session for
+    pub resumed: u64,
+    /// disconnect to connack latencies of reconnects
+    pub reconnect_latencies: LatencySummary,
+}
+
+/// What churning connections saw so far
+struct Cycles {
+    reconnects: u64,
+    failed: u64,
+    resumed: u64,
+    latencies: Histogram<u64>,
+}
+
+pub struct Churn {
+    session: ChurnSession,
+    rate: u64,
+    start: Instant,
+    /// hands out turns to reconnect
+    controller: JoinHandle<()>,
+    churners: Vec<JoinHandle<()>>,
+    cycles: Arc<Mutex<Cycles>>,
+}
+
+impl Churn {
+    /// Connects the churning connections and starts cycling through them
+    pub(crate) async fn start(config: Arc<BenchConfig>) -> anyhow::Result<Churn> {
+        let ids: Vec<String> = (0..connections(&config))
+            .map(|i| format!("mqttwrk-churn-{i:05}"))
+            .collect();
+        let connected = try_join_all(ids.iter().map(|id| subscribed(&config, id))).await?;
+
+        let cycles = Arc::new(Mutex::new(Cycles {
+            reconnects: 0,
+            failed: 0,
+            resumed: 0,
+            latencies: latency_histogram(),
+        }));
+        let turns: Vec<Arc<Notify>> = ids.iter().map(|_| Arc::new(Notify::new())).collect();
+        let churners = ids
+            .into_iter()
+            .zip(connected)
+            .zip(turns.iter().cloned())
+            .map(|((id, connection), turn)| {
+                let config = config.clone();
+                let cycles = cycles.clone();
+                task::spawn(churn(config, id, connection, turn, cycles))
+            })
+            .collect();
+
+        let rate = config.churn_rate;
+        let controller = task::spawn(async move {
+            let Some(mut pacer) = Pacer::new(rate) else {
+                return;
+            };
+            for turn in turns.iter().cycle() {
+                pacer.wait().await;
+                turn.notify_one();
+            }
+        });
+
+        Ok(Churn {
+            session: config.churn_session,
+            rate,
+            start: Instant::now(),
+            controller,
+            churners,
+            cycles,
+        })
+    }
+
+    /// Stops churning and summarizes the reconnects so far
+    pub(crate) fn stop(self) -> ChurnReport {
+        self.controller.abort();
+        for churner in self.churners.iter() {
+            churner.abort();
+        }
+
+        let cycles = self.cycles.lock().unwrap();
+        ChurnReport {
+            connections: self.churners.len(),
+            session: self.session,
+            target_rate: self.rate,
+            reconnects: cycles.reconnects,
+            reconnect_rate: cycles.reconnects as f64 / self.start.elapsed().as_secs_f64(),
+            failed: cycles.failed,
+            resumed: cycles.resumed,
+            reconnect_latencies: LatencySummary::from(&cycles.latencies),
+        }
+    }
+}
+
+/// Churning connections that make up `--churn` of all connections of the run
+fn connections(config: &BenchConfig) -> usize {
+    let fraction = config.churn.unwrap_or(0.0);
+    let stable = (config.publishers + config.subscribers) as f64;
+    ((stable * fraction / (1.0 - fraction)).round() as usize).max(1)
+}
+
+/// Stays connected until its turn, then disconnects and reconnects
+async fn churn(
+    config: Arc<BenchConfig>,
+    id: String,
+    mut connection: (AsyncClient, EventLoop),
+    turn: Arc<Notify>,
+    cycles: Arc<Mutex<Cycles>>,
+) {
+    loop {
+        let (client, mut eventloop) = connection;
+        loop {
+            tokio::select! {
+                _ = turn.notified() => break,
+                event = eventloop.poll() => if let Err(e) = event {
+                    error!("Id = {}, Connection error = {:?}", id, e);
+                    time::sleep(Duration::from_secs(1)).await;
+                },
+            }
+        }
+
+        let _ = client.try_disconnect();
+        let _ = time::timeout(config.conn_timeout, async {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        })
+        .await;
+
+        let start = Instant::now();
+        connection = loop {
+            match connect(&config, &id).await {
+                Ok((client, eventloop, connack)) => {
+                    let mut cycles = cycles.lock().unwrap();
+                    cycles.reconnects += 1;
+                    cycles.resumed += connack.session_present as u64;
+                    cycles
+                        .latencies
+                        .record(start.elapsed().as_micros() as u64)
+                        .unwrap();
+                    break (client, eventloop);
+                }
+                Err(e) => {
+                    error!("Id = {}, Failed to reconnect = {:#}", id, e);
+                    cycles.lock().unwrap().failed += 1;
+                    time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        };
+    }
+}
+
+/// Connects for the first time, subscribing so that persistent sessions hold
+/// something for the broker to resume
+async fn subscribed(
+    config: &Arc<BenchConfig>,
+    id: &str,
+) -> anyhow::Result<(AsyncClient, EventLoop)> {
+    let (client, mut eventloop, _) = connect(config, id).await?;
+    if config.churn_session == ChurnSession::Persistent {
+        client
+            .subscribe(format!("hello/churn/{id}"), QoS::AtLeastOnce)
+            .await?;
+        loop {
+            match time::timeout(config.conn_timeout, eventloop.poll()).await?? {
+                Event::Incoming(Incoming::SubAck(_)) => break,
+                Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+                Event::Outgoing(_) => {}
+            }
+        }
+    }
+
+    Ok((client, eventloop))
+}
+
+async fn connect(
+    config: &Arc<BenchConfig>,
+    id: &str,
+) -> anyhow::Result<(AsyncClient, EventLoop, ConnAck)> {
+    let mut options = options(config.clone(), id)?;
+    options.set_clean_session(config.churn_session == ChurnSession::Clean);
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    eventloop.network_options = network_options(config)?;
+
+    loop {
+        match time::timeout(config.conn_timeout, eventloop.poll()).await?? {
+            Event::Incoming(Incoming::ConnAck(connack)) => return Ok((client, eventloop, connack)),
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+}
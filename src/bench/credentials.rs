@@ -0,0 +1,63 @@
+//! Distinct credentials for every connection, read from a csv with a
+//! `client_id,username,password[,cert,key]` row per client id. Fields can't be
+//! quoted, lines starting with `#` and a `client_id,...` header are skipped
+
+use std::{collections::HashMap, fs, sync::Arc};
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    /// pem encoded certificate and key files to authenticate with over tls
+    pub identity: Option<(String, String)>,
+}
+
+/// Credentials by client id
+#[derive(Debug, Clone)]
+pub struct CredentialsFile(Arc<HashMap<String, Credentials>>);
+
+impl CredentialsFile {
+    pub fn get(&self, id: &str) -> Option<&Credentials> {
+        self.0.get(id)
+    }
+}
+
+pub(crate) fn load(path: &str) -> Result<CredentialsFile, String> {
+    let csv = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+
+    let mut credentials = HashMap::new();
+    for (i, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("client_id,") {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let identity = match fields[..] {
+            [_, _, _] => None,
+            [_, _, _, cert, key] => Some((cert.to_owned(), key.to_owned())),
+            _ => {
+                return Err(format!(
+                    "{path}:{}: expected client_id,username,password[,cert,key]",
+                    i + 1
+                ))
+            }
+        };
+
+        let id = fields[0].to_owned();
+        let row = Credentials {
+            username: fields[1].to_owned(),
+            password: fields[2].to_owned(),
+            identity,
+        };
+        if credentials.insert(id, row).is_some() {
+            return Err(format!(
+                "{path}:{}: duplicate client id {}",
+                i + 1,
+                fields[0]
+            ));
+        }
+    }
+
+    Ok(CredentialsFile(Arc::new(credentials)))
+}
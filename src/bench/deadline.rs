@@ -0,0 +1,57 @@
+//! Runs of a fixed duration. Publishers publish until `--duration` is over
+//! instead of `--count` times, so how many publishes subscribers have to wait
+//! for is only known once every publisher is done
+
+use std::{
+    future,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use tokio::sync::Notify;
+
+/// Publishes of all publishers, once they're done publishing
+pub(crate) struct Published {
+    /// publishers yet to be done
+    remaining: AtomicUsize,
+    total: AtomicU64,
+    done: Notify,
+}
+
+impl Published {
+    pub(crate) fn new(publishers: usize) -> Published {
+        Published {
+            remaining: AtomicUsize::new(publishers),
+            total: AtomicU64::new(0),
+            done: Notify::new(),
+        }
+    }
+
+    /// Adds the publishes of a publisher that's done
+    pub(crate) fn finished(&self, published: u64) {
+        self.total.fetch_add(published, Ordering::Relaxed);
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.done.notify_waiters();
+        }
+    }
+
+    /// Resolves to the publishes of all publishers once they're done
+    pub(crate) async fn total(&self) -> u64 {
+        loop {
+            let notified = self.done.notified();
+            if self.remaining.load(Ordering::Acquire) == 0 {
+                return self.total.load(Ordering::Relaxed);
+            }
+
+            notified.await;
+        }
+    }
+}
+
+/// Resolves to the publishes of all publishers once they're done. Never
+/// resolves for runs of a fixed count
+pub(crate) async fn total(published: Option<&Published>) -> u64 {
+    match published {
+        Some(published) => published.total().await,
+        None => future::pending().await,
+    }
+}
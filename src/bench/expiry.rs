@@ -0,0 +1,110 @@
+//! Mqtt 5 message expiry. A v5 subscriber with a persistent session goes
+//! offline, `--count` publishes with `--message-expiry` are queued for it and,
+//! once they expired, it comes back to check that the broker dropped them
+//! instead of delivering them. A last publish without expiry is expected to
+//! be delivered regardless, to tell expired publishes apart from a broker that
+//! didn't keep the session at all
+
+use std::{sync::Arc, time::Duration};
+
+use rumqttc::v5::{
+    mqttbytes::{
+        v5::{ConnectProperties, Packet, PublishProperties},
+        QoS,
+    },
+    Event,
+};
+use serde::Serialize;
+use tokio::time;
+
+use crate::{
+    bench::{v5_connect, v5_disconnect, v5_options, v5_publish},
+    BenchConfig,
+};
+
+const TOPIC: &str = "hello/expiry/world";
+const CONTROL_TOPIC: &str = "hello/expiry/control";
+const PUBLISHER: &str = "mqttwrk-expiry";
+const SUBSCRIBER: &str = "mqttwrk-expiry-sub";
+
+/// What the subscriber received once it came back
+#[derive(Debug, Serialize)]
+pub struct ExpiryReport {
+    pub expiry_secs: u32,
+    /// publishes with an expiry queued for the subscriber
+    pub published: usize,
+    /// publishes delivered although they expired
+    pub delivered: usize,
+    /// publishes the broker dropped
+    pub expired: usize,
+    /// whether the broker resumed the session of the subscriber
+    pub session_present: bool,
+    /// whether the publish without expiry was delivered
+    pub control_delivered: bool,
+}
+
+pub(crate) async fn verify(config: Arc<BenchConfig>) -> anyhow::Result<ExpiryReport> {
+    let expiry = config.message_expiry.unwrap_or(0);
+
+    // the session has to outlive the publishes queued in it
+    let mut options = v5_options(&config, SUBSCRIBER)?;
+    let mut properties = ConnectProperties::new();
+    properties.session_expiry_interval = Some(expiry.saturating_add(3600));
+    options.set_connect_properties(properties);
+    let (client, mut eventloop, _) = v5_connect(options).await?;
+    client.subscribe("hello/expiry/+", QoS::AtLeastOnce).await?;
+    loop {
+        match eventloop.poll().await? {
+            Event::Incoming(Packet::SubAck(_)) => break,
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+    v5_disconnect(&client, &mut eventloop).await?;
+
+    let properties = PublishProperties {
+        message_expiry_interval: Some(expiry),
+        ..Default::default()
+    };
+    v5_publish(&config, PUBLISHER, TOPIC, config.count, Some(properties)).await?;
+    v5_publish(&config, PUBLISHER, CONTROL_TOPIC, 1, None).await?;
+    time::sleep(Duration::from_secs(expiry as u64 + 1)).await;
+
+    // resumes the session, which ends once the subscriber disconnects
+    let mut options = v5_options(&config, SUBSCRIBER)?;
+    options.set_clean_start(false);
+    let (client, mut eventloop, connack) = v5_connect(options).await?;
+    let mut delivered = 0;
+    let mut control_delivered = false;
+    loop {
+        let event = match time::timeout(config.receive_timeout, eventloop.poll()).await {
+            Ok(event) => event?,
+            Err(_) => break,
+        };
+
+        if let Event::Incoming(Packet::Publish(publish)) = event {
+            if publish.topic == CONTROL_TOPIC {
+                control_delivered = true;
+            } else {
+                delivered += 1;
+            }
+        }
+    }
+    v5_disconnect(&client, &mut eventloop).await?;
+
+    if !connack.session_present || !control_delivered {
+        warn!(
+            "Id = {}, Broker didn't keep the session, expired publishes can't be told apart from lost ones",
+            SUBSCRIBER
+        );
+    }
+
+    Ok(ExpiryReport {
+        expiry_secs: expiry,
+        published: config.count,
+        delivered,
+        expired: config.count.saturating_sub(delivered),
+        session_present: connack.session_present,
+        control_delivered,
+    })
+}
@@ -0,0 +1,176 @@
+//! Saturation search. With `--find-max`, the run is the first probe of a
+//! search for the highest `--rate` at which the broker still meets every
+//! `--assert-*` threshold of the run. The rate doubles, or halves, until a
+//! probe fails, or passes, and is then bisected between the highest passing
+//! and the lowest failing rate until they're within 5% of each other or
+//! `--find-max-probes` runs out. Probes that publish well below their rate fail
+//! too, as the broker or mqttwrk couldn't sustain it
+
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::{
+    bench::{
+        assertions,
+        report::{Report, Summary},
+        workload,
+    },
+    BenchConfig,
+};
+
+/// Share of the publishes of a rate that a probe has to publish to sustain it
+const SUSTAINED: f64 = 0.9;
+
+#[derive(Debug, Serialize)]
+pub struct FindMaxReport {
+    pub probes: Vec<Probe>,
+    /// highest rate of every publisher that met every threshold
+    pub max_rate: Option<u64>,
+    /// incoming publishes per second at `max_rate`
+    pub max_throughput: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Probe {
+    /// publishes per second of every publisher
+    pub rate: u64,
+    /// publishes per second of all publishers together
+    pub target_throughput: f64,
+    pub publish_throughput: f64,
+    pub incoming_throughput: f64,
+    pub loss_percent: f64,
+    pub p99_ack_latency: f64,
+    pub p99_latency: f64,
+    /// why the probe failed, if it did
+    pub failure: Option<String>,
+}
+
+impl Probe {
+    fn new(config: &BenchConfig, summary: &Summary) -> Probe {
+        let target_throughput = (config.rate * config.publishers as u64) as f64;
+        let mut failures: Vec<String> = assertions::check(config, summary)
+            .into_iter()
+            .filter_map(|assertion| assertion.failure)
+            .collect();
+        if summary.publish_throughput < target_throughput * SUSTAINED {
+            failures.push(format!(
+                "publish throughput {:.2} messages/s is below {:.2} messages/s",
+                summary.publish_throughput, target_throughput
+            ));
+        }
+
+        Probe {
+            rate: config.rate,
+            target_throughput,
+            publish_throughput: summary.publish_throughput,
+            incoming_throughput: summary.incoming_throughput,
+            loss_percent: summary.loss_percent,
+            p99_ack_latency: summary.ack_latencies.p99,
+            p99_latency: summary.latencies.p99,
+            failure: (!failures.is_empty()).then(|| failures.join(", ")),
+        }
+    }
+}
+
+/// Searches for the highest rate that meets the thresholds, starting from the
+/// run with `summary`
+pub(crate) async fn search(config: &Arc<BenchConfig>, summary: &Summary) -> FindMaxReport {
+    let mut probes = vec![Probe::new(config, summary)];
+    // highest passing and lowest failing rates so far
+    let (mut pass, mut fail) = match probes[0].failure {
+        None => (Some(config.rate), None),
+        Some(_) => (None, Some(config.rate)),
+    };
+
+    while (probes.len() as u64) < config.find_max_probes {
+        let Some(rate) = next_rate(pass, fail) else {
+            break;
+        };
+
+        println!("Probing --rate {rate}");
+        let mut step = (**config).clone();
+        step.rate = rate;
+        let config = Arc::new(step);
+
+        let workload = workload(&config, None).await;
+        let summary = Report::new(
+            &config,
+            workload.started_at,
+            workload.connect_elapsed,
+            workload.elapsed,
+            &workload.pub_stats,
+            &workload.sub_stats,
+            Vec::new(),
+        )
+        .summary;
+
+        let probe = Probe::new(&config, &summary);
+        match probe.failure {
+            None => pass = pass.max(Some(rate)),
+            Some(_) => fail = Some(fail.map_or(rate, |fail| fail.min(rate))),
+        }
+        probes.push(probe);
+    }
+
+    let max = probes
+        .iter()
+        .filter(|probe| probe.failure.is_none())
+        .max_by_key(|probe| probe.rate);
+    FindMaxReport {
+        max_rate: max.map(|probe| probe.rate),
+        max_throughput: max.map_or(0.0, |probe| probe.incoming_throughput),
+        probes,
+    }
+}
+
+/// Rate to probe next given the highest passing and the lowest failing rates
+/// so far, unless the search is over
+fn next_rate(pass: Option<u64>, fail: Option<u64>) -> Option<u64> {
+    match (pass, fail) {
+        (Some(pass), None) => Some(pass * 2),
+        (None, Some(fail)) if fail > 1 => Some(fail / 2),
+        (Some(pass), Some(fail)) if fail > pass && fail - pass > (pass / 20).max(1) => {
+            Some((pass + fail) / 2)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::next_rate;
+
+    #[test]
+    fn doubles_until_a_probe_fails() {
+        assert_eq!(next_rate(Some(100), None), Some(200));
+    }
+
+    #[test]
+    fn halves_until_a_probe_passes() {
+        assert_eq!(next_rate(None, Some(100)), Some(50));
+        assert_eq!(next_rate(None, Some(1)), None);
+    }
+
+    #[test]
+    fn bisects_between_pass_and_fail() {
+        assert_eq!(next_rate(Some(100), Some(200)), Some(150));
+        assert_eq!(next_rate(Some(100), Some(110)), Some(105));
+    }
+
+    #[test]
+    fn stops_within_5_percent() {
+        assert_eq!(next_rate(Some(100), Some(105)), None);
+        assert_eq!(next_rate(Some(1000), Some(1040)), None);
+        // a rate apart at low rates
+        assert_eq!(next_rate(Some(2), Some(3)), None);
+        assert_eq!(next_rate(Some(2), Some(4)), Some(3));
+    }
+
+    #[test]
+    fn stops_when_fail_is_not_above_pass() {
+        // a lower rate failed after a higher one passed, e.g. from noise
+        assert_eq!(next_rate(Some(200), Some(100)), None);
+        assert_eq!(next_rate(None, None), None);
+    }
+}
@@ -0,0 +1,109 @@
+//! Flapping clients. With `--flappy`, that percent of publishers and
+//! subscribers drop their connection every `--flap-every` and reconnect right
+//! away, as devices on a bad network would, while the rest stay connected. The
+//! first flap is `--flap-every` into the run, so the report can compare
+//! latencies of stable clients before it and while the others flap, to tell
+//! whether flapping connections slow down the broker for everyone else.
+//! Stable subscribers only count publishes of stable publishers, as those of
+//! flappy ones pay for their own reconnects
+
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future;
+use tokio::time;
+
+use crate::{bench::publisher_topic, BenchConfig};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Flap {
+    every: Duration,
+    /// whether the client flaps or stays connected
+    flappy: bool,
+    /// of the flaps of the client within `every`, so that flappy clients
+    /// don't all drop at once
+    offset: Duration,
+    /// topics of flappy publishers, for subscribers
+    topics: Arc<HashSet<String>>,
+    /// when the first flap of any client is due, once the run started
+    first: Option<Instant>,
+    /// when the client flaps next
+    next: Option<Instant>,
+}
+
+impl Flap {
+    /// Flaps of client `index` of `clients`, with `--flappy`
+    pub(crate) fn new(
+        config: &BenchConfig,
+        index: usize,
+        clients: usize,
+        topics: &Arc<HashSet<String>>,
+    ) -> Option<Flap> {
+        config.flappy?;
+        Some(Flap {
+            every: config.flap_every,
+            flappy: flappy(config, index, clients),
+            offset: config.flap_every.mul_f64(index as f64 / clients as f64),
+            topics: topics.clone(),
+            first: None,
+            next: None,
+        })
+    }
+
+    pub(crate) fn flappy(&self) -> bool {
+        self.flappy
+    }
+
+    /// Starts the clock once the client starts publishing or receiving
+    pub(crate) fn start(&mut self) {
+        let first = Instant::now() + self.every;
+        self.first = Some(first);
+        self.next = Some(first + self.offset).filter(|_| self.flappy);
+    }
+
+    /// Whether any client may have flapped yet
+    pub(crate) fn flapping(&self) -> bool {
+        matches!(self.first, Some(first) if Instant::now() >= first)
+    }
+
+    /// Schedules the next flap once the client flapped
+    pub(crate) fn flapped(&mut self) {
+        self.next = self.next.map(|next| next + self.every);
+    }
+
+    /// Whether a publish on `topic` comes from a stable publisher
+    pub(crate) fn stable(&self, topic: &str) -> bool {
+        !self.topics.contains(topic)
+    }
+}
+
+/// Whether client `index` of `clients` flaps. Flappy clients are spread evenly
+/// among the rest, across groups and brokers
+pub(crate) fn flappy(config: &BenchConfig, index: usize, clients: usize) -> bool {
+    let Some(percent) = config.flappy else {
+        return false;
+    };
+
+    let flappy = (clients as f64 * percent / 100.0).round() as usize;
+    index * flappy / clients != (index + 1) * flappy / clients
+}
+
+/// Topics of flappy publishers
+pub(crate) fn topics(config: &BenchConfig) -> HashSet<String> {
+    (0..config.publishers)
+        .filter(|&i| flappy(config, i, config.publishers))
+        .map(|i| publisher_topic(config, i))
+        .collect()
+}
+
+/// Resolves once the client is due to flap. Never resolves for stable clients
+/// or without `--flappy`
+pub(crate) async fn due(flap: Option<&Flap>) {
+    match flap.and_then(|flap| flap.next) {
+        Some(next) => time::sleep_until(next.into()).await,
+        None => future::pending().await,
+    }
+}
@@ -0,0 +1,163 @@
+//! Mqtt 5 flow control. v5 subscribers connect with a small
+//! `--receive-maximum` and hold on to every QoS 1 publish for `--ack-delay`
+//! before acking it, so that the broker has to stop sending once the quota of
+//! unacked publishes is used up. Any publish beyond the quota is a violation
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::try_join_all;
+use rumqttc::{
+    v5::{
+        mqttbytes::{v5::Packet, QoS},
+        Event,
+    },
+    Outgoing,
+};
+use serde::Serialize;
+use tokio::{task, time};
+
+use crate::{
+    bench::{v5_connect, v5_options, v5_publish},
+    BenchConfig,
+};
+
+const TOPIC: &str = "hello/flow/world";
+
+#[derive(Debug, Serialize)]
+pub struct FlowReport {
+    pub receive_maximum: u16,
+    pub ack_delay_ms: f64,
+    pub subscribers: usize,
+    pub published: usize,
+    /// publishes received by all subscribers
+    pub received: u64,
+    /// most unacked publishes a subscriber had at once
+    pub max_unacked: u16,
+    /// publishes received while a subscriber's quota was used up
+    pub violations: u64,
+    /// publishes received per second by all subscribers
+    pub throughput: f64,
+}
+
+/// What a subscriber received
+struct Received {
+    received: u64,
+    max_unacked: u16,
+    violations: u64,
+    elapsed: Duration,
+}
+
+pub(crate) async fn verify(config: Arc<BenchConfig>) -> anyhow::Result<FlowReport> {
+    let receive_maximum = config.receive_maximum.unwrap_or(u16::MAX);
+    let subscribers = config.subscribers.max(1);
+
+    let mut handles = Vec::with_capacity(subscribers);
+    for i in 0..subscribers {
+        let id = format!("mqttwrk-flow-sub-{i:05}");
+        handles.push(subscribe(config.clone(), id).await?);
+    }
+
+    v5_publish(&config, "mqttwrk-flow", TOPIC, config.count, None).await?;
+    let received = try_join_all(handles)
+        .await?
+        .into_iter()
+        .collect::<anyhow::Result<Vec<Received>>>()?;
+
+    let total = received.iter().map(|received| received.received).sum();
+    let elapsed = received
+        .iter()
+        .map(|received| received.elapsed)
+        .max()
+        .unwrap_or_default();
+    Ok(FlowReport {
+        receive_maximum,
+        ack_delay_ms: config.ack_delay.as_secs_f64() * 1000.0,
+        subscribers,
+        published: config.count,
+        received: total,
+        max_unacked: received
+            .iter()
+            .map(|received| received.max_unacked)
+            .max()
+            .unwrap_or(0),
+        violations: received.iter().map(|received| received.violations).sum(),
+        throughput: total as f64 / elapsed.as_secs_f64(),
+    })
+}
+
+/// Subscribes with `--receive-maximum` and manual acks. The returned task
+/// receives until all publishes arrived or none did for `--receive-timeout`
+async fn subscribe(
+    config: Arc<BenchConfig>,
+    id: String,
+) -> anyhow::Result<task::JoinHandle<anyhow::Result<Received>>> {
+    let receive_maximum = config.receive_maximum.unwrap_or(u16::MAX);
+    let mut options = v5_options(&config, &id)?;
+    options.set_receive_maximum(Some(receive_maximum));
+    options.set_manual_acks(true);
+
+    let (client, mut eventloop, _) = v5_connect(options).await?;
+    client.subscribe(TOPIC, QoS::AtLeastOnce).await?;
+    loop {
+        match eventloop.poll().await? {
+            Event::Incoming(Packet::SubAck(_)) => break,
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+
+    Ok(task::spawn(async move {
+        let mut received = 0;
+        let mut unacked: u16 = 0;
+        let mut max_unacked = 0;
+        let mut violations = 0;
+        let mut start = None;
+        let mut elapsed = Duration::ZERO;
+        while received < config.count as u64 {
+            let event = match time::timeout(config.receive_timeout, eventloop.poll()).await {
+                Ok(event) => event?,
+                Err(_) => {
+                    warn!(
+                        "Id = {}, {} of {} publishes received",
+                        id, received, config.count
+                    );
+                    break;
+                }
+            };
+
+            match event {
+                Event::Incoming(Packet::Publish(publish)) => {
+                    let start = *start.get_or_insert_with(Instant::now);
+                    elapsed = start.elapsed();
+                    received += 1;
+                    if unacked >= receive_maximum {
+                        violations += 1;
+                    }
+                    unacked = unacked.saturating_add(1);
+                    max_unacked = max_unacked.max(unacked);
+
+                    // acks are held back to use up the quota
+                    let client = client.clone();
+                    let delay = config.ack_delay;
+                    task::spawn(async move {
+                        time::sleep(delay).await;
+                        let _ = client.ack(&publish).await;
+                    });
+                }
+                Event::Outgoing(Outgoing::PubAck(_)) => unacked = unacked.saturating_sub(1),
+                _ => {}
+            }
+        }
+
+        let _ = client.try_disconnect();
+        Ok(Received {
+            received,
+            max_unacked,
+            violations,
+            elapsed,
+        })
+    }))
+}
@@ -0,0 +1,253 @@
+//! Heterogeneous publishers, read from a `--groups` file with a
+//! `NAME PUBLISHERS [KEY=VALUE..]` row per group, e.g.
+//! `sensors 9000 qos=0 payload=64 rate=1`. Every group publishes with its own
+//! `qos`, `payload` size, `rate` per publisher, `count` and `topic`, a template
+//! of the topic with `{id}` for the publisher id, and falls back to the command
+//! line for the rest. Groups run side by side and the report breaks publishes
+//! and latencies down by group, going by the topic of incoming publishes.
+//! Lines starting with `#` are skipped
+
+use std::{collections::HashMap, fs};
+
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+
+use crate::{bench::publisher_topic, parse_size, BenchConfig};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Group {
+    pub name: String,
+    pub publishers: usize,
+    pub qos: Option<i16>,
+    pub payload_size: Option<usize>,
+    pub rate: Option<u64>,
+    pub count: Option<usize>,
+    pub topic: Option<String>,
+}
+
+impl Group {
+    /// `config` with the settings of the group
+    pub(crate) fn apply(&self, config: &BenchConfig) -> BenchConfig {
+        let mut config = config.clone();
+        config.publish_qos = self.qos.unwrap_or(config.publish_qos);
+        config.payload_size = self.payload_size.unwrap_or(config.payload_size);
+        config.rate = self.rate.unwrap_or(config.rate);
+        config.count = self.count.unwrap_or(config.count);
+        config
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Groups {
+    pub groups: Vec<Group>,
+    /// group of the publisher of every topic, once a subscriber asks
+    #[serde(skip)]
+    topics: OnceCell<HashMap<String, usize>>,
+}
+
+impl Groups {
+    /// Publishers of all groups together
+    pub fn publishers(&self) -> usize {
+        self.groups.iter().map(|group| group.publishers).sum()
+    }
+
+    /// Index of the group of publisher `i`, publishers of the first group
+    /// coming first
+    pub fn of(&self, i: usize) -> usize {
+        let mut end = 0;
+        for (g, group) in self.groups.iter().enumerate() {
+            end += group.publishers;
+            if i < end {
+                return g;
+            }
+        }
+
+        self.groups.len() - 1
+    }
+
+    /// Index of the group publishing to `topic`. The first group wins when
+    /// groups share topics
+    pub(crate) fn of_topic(&self, config: &BenchConfig, topic: &str) -> Option<usize> {
+        let topics = self.topics.get_or_init(|| {
+            let mut topics = HashMap::new();
+            for i in (0..config.publishers).rev() {
+                topics.insert(publisher_topic(config, i), self.of(i));
+            }
+            topics
+        });
+        topics.get(topic).copied()
+    }
+}
+
+/// Topic of the publisher with `id` of a group with a topic template
+pub(crate) fn topic(config: &BenchConfig, i: usize, id: &str) -> Option<String> {
+    let groups = config.groups.as_ref()?;
+    let template = groups.groups[groups.of(i)].topic.as_ref()?;
+    Some(template.replace("{id}", id))
+}
+
+/// Publishes of publisher `i`, the count of its group with `--groups`
+pub(crate) fn count(config: &BenchConfig, i: usize) -> usize {
+    match &config.groups {
+        Some(groups) => groups.groups[groups.of(i)].count.unwrap_or(config.count),
+        None => config.count,
+    }
+}
+
+pub(crate) fn load(path: &str) -> Result<Groups, String> {
+    let file = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    parse(path, file.lines())
+}
+
+/// Groups of `rows`, from a file or a scenario. Errors point at the row of
+/// `source`
+pub(crate) fn parse<'a>(
+    source: &str,
+    rows: impl Iterator<Item = &'a str>,
+) -> Result<Groups, String> {
+    let mut groups: Vec<Group> = Vec::new();
+    for (i, line) in rows.enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let err = |e: String| format!("{source}:{}: {e}", i + 1);
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(publishers)) = (fields.next(), fields.next()) else {
+            return Err(err("expected NAME PUBLISHERS [KEY=VALUE..]".to_owned()));
+        };
+        if groups.iter().any(|group| group.name == name) {
+            return Err(err(format!("duplicate group {name}")));
+        }
+
+        let mut group = Group {
+            name: name.to_owned(),
+            publishers: publishers
+                .parse()
+                .map_err(|e| err(format!("invalid publishers `{publishers}`: {e}")))?,
+            qos: None,
+            payload_size: None,
+            rate: None,
+            count: None,
+            topic: None,
+        };
+        for field in fields {
+            let Some((key, value)) = field.split_once('=') else {
+                return Err(err(format!("expected KEY=VALUE, got `{field}`")));
+            };
+            let invalid = |e: &dyn std::fmt::Display| err(format!("invalid {key} `{value}`: {e}"));
+            match key {
+                "qos" => match value.parse() {
+                    Ok(qos @ 0..=2) => group.qos = Some(qos),
+                    Ok(_) => return Err(invalid(&"not 0, 1 or 2")),
+                    Err(e) => return Err(invalid(&e)),
+                },
+                "payload" => group.payload_size = Some(parse_size(value).map_err(|e| invalid(&e))?),
+                "rate" => group.rate = Some(value.parse().map_err(|e| invalid(&e))?),
+                "count" => group.count = Some(value.parse().map_err(|e| invalid(&e))?),
+                "topic" => group.topic = Some(value.to_owned()),
+                _ => return Err(err(format!("unknown key `{key}`"))),
+            }
+        }
+        groups.push(group);
+    }
+
+    if groups.iter().all(|group| group.publishers == 0) {
+        return Err(format!("{source}: no publishers"));
+    }
+
+    Ok(Groups {
+        groups,
+        topics: OnceCell::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Groups};
+
+    fn groups(rows: &[&str]) -> Result<Groups, String> {
+        parse("groups", rows.iter().copied())
+    }
+
+    #[test]
+    fn parses_rows() {
+        let groups = groups(&[
+            "# name publishers settings",
+            "sensors 9000 qos=0 payload=64 rate=1",
+            "",
+            "  gateways   5 qos=2 payload=4K count=1000 topic=gw/{id}/data",
+            "idle 0",
+        ])
+        .unwrap();
+
+        let sensors = &groups.groups[0];
+        assert_eq!(sensors.name, "sensors");
+        assert_eq!(sensors.publishers, 9000);
+        assert_eq!(sensors.qos, Some(0));
+        assert_eq!(sensors.payload_size, Some(64));
+        assert_eq!(sensors.rate, Some(1));
+        assert_eq!(sensors.count, None);
+        assert_eq!(sensors.topic, None);
+
+        let gateways = &groups.groups[1];
+        assert_eq!(gateways.qos, Some(2));
+        assert_eq!(gateways.payload_size, Some(4096));
+        assert_eq!(gateways.count, Some(1000));
+        assert_eq!(gateways.topic.as_deref(), Some("gw/{id}/data"));
+
+        assert_eq!(groups.groups[2].publishers, 0);
+        assert_eq!(groups.publishers(), 9005);
+    }
+
+    #[test]
+    fn publishers_of_the_first_group_come_first() {
+        let groups = groups(&["a 2", "none 0", "b 3"]).unwrap();
+
+        let of: Vec<_> = (0..5).map(|i| groups.of(i)).collect();
+        assert_eq!(of, [0, 0, 2, 2, 2]);
+        // past the last publisher
+        assert_eq!(groups.of(5), 2);
+    }
+
+    #[test]
+    fn errors_point_at_the_row() {
+        let error = |rows: &[&str]| groups(rows).unwrap_err();
+
+        assert_eq!(
+            error(&["a 1", "# b", "b"]),
+            "groups:3: expected NAME PUBLISHERS [KEY=VALUE..]"
+        );
+        assert_eq!(error(&["a 1", "a 2"]), "groups:2: duplicate group a");
+        assert!(error(&["a lots"]).starts_with("groups:1: invalid publishers `lots`"));
+        assert_eq!(
+            error(&["a 1 qos"]),
+            "groups:1: expected KEY=VALUE, got `qos`"
+        );
+        assert_eq!(error(&["a 1 retain=1"]), "groups:1: unknown key `retain`");
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let error = |row: &str| groups(&[row]).unwrap_err();
+
+        assert_eq!(
+            error("a 1 qos=3"),
+            "groups:1: invalid qos `3`: not 0, 1 or 2"
+        );
+        assert!(error("a 1 qos=-1").starts_with("groups:1: invalid qos `-1`"));
+        assert!(error("a 1 payload=4G").starts_with("groups:1: invalid payload `4G`"));
+        assert!(error("a 1 rate=fast").starts_with("groups:1: invalid rate `fast`"));
+        assert!(error("a 1 count=-5").starts_with("groups:1: invalid count `-5`"));
+    }
+
+    #[test]
+    fn rejects_groups_without_publishers() {
+        assert_eq!(groups(&[]).unwrap_err(), "groups: no publishers");
+        assert_eq!(
+            groups(&["# a 1", "a 0"]).unwrap_err(),
+            "groups: no publishers"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Max inflight sweep. Once the run is done, the same publishers and
+//! subscribers run again with every inflight window of `--inflight-sweep`, one
+//! after the other, to find the window past which throughput stops growing and
+//! only latencies do
+
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::{
+    bench::{report::Report, workload},
+    common::LatencySummary,
+    BenchConfig,
+};
+
+#[derive(Debug, Serialize)]
+pub struct InflightSweepReport {
+    pub steps: Vec<InflightStep>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InflightStep {
+    pub max_inflight: u16,
+    pub outgoing_publish: u64,
+    /// publishes per second
+    pub publish_throughput: f64,
+    pub incoming_publish: u64,
+    /// receives per second
+    pub incoming_throughput: f64,
+    pub loss_percent: f64,
+    pub reconnects: u64,
+    pub ack_latencies: LatencySummary,
+    pub latencies: LatencySummary,
+}
+
+pub(crate) async fn sweep(config: &Arc<BenchConfig>) -> InflightSweepReport {
+    let mut steps = Vec::with_capacity(config.inflight_sweep.len());
+    for &max_inflight in config.inflight_sweep.iter() {
+        println!("Rerunning with --max-inflight {max_inflight}");
+        let mut step = (**config).clone();
+        step.max_inflight = max_inflight;
+        let config = Arc::new(step);
+
+        let workload = workload(&config, None).await;
+        let summary = Report::new(
+            &config,
+            workload.started_at,
+            workload.connect_elapsed,
+            workload.elapsed,
+            &workload.pub_stats,
+            &workload.sub_stats,
+            Vec::new(),
+        )
+        .summary;
+
+        steps.push(InflightStep {
+            max_inflight,
+            outgoing_publish: summary.outgoing_publish,
+            publish_throughput: summary.publish_throughput,
+            incoming_publish: summary.incoming_publish,
+            incoming_throughput: summary.incoming_throughput,
+            loss_percent: summary.loss_percent,
+            reconnects: summary.reconnects,
+            ack_latencies: summary.ack_latencies,
+            latencies: summary.latencies,
+        });
+    }
+
+    InflightSweepReport { steps }
+}
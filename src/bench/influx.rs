@@ -0,0 +1,98 @@
+//! Pushes per second samples to InfluxDB using the v2 write api and line protocol
+
+use std::{fmt::Write as _, time::UNIX_EPOCH};
+
+use tokio::task;
+
+use crate::bench::{metrics::Sample, reporter::Reporter};
+
+#[derive(Clone)]
+pub struct Influx {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    host: String,
+}
+
+impl Influx {
+    pub fn new(url: &str, org: &str, bucket: &str, token: Option<String>) -> Influx {
+        let url = format!(
+            "{}/api/v2/write?org={}&bucket={}&precision=ns",
+            url.trim_end_matches('/'),
+            org,
+            bucket
+        );
+
+        Influx {
+            client: reqwest::Client::new(),
+            url,
+            token,
+            host: whoami::fallible::hostname().unwrap_or_else(|_| "unknown".to_owned()),
+        }
+    }
+
+    pub async fn push(&self, sample: &Sample) -> Result<(), reqwest::Error> {
+        let mut request = self.client.post(&self.url).body(self.line(sample));
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Token {token}"));
+        }
+
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    fn line(&self, sample: &Sample) -> String {
+        let timestamp = sample
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        let mut line = format!("mqttwrk,host={} ", self.host.replace(' ', "\\ "));
+        let _ = write!(
+            line,
+            "connections={}i,inflight={}i,reconnects={}i,outgoing_rate={},incoming_rate={},ack_rate={}",
+            sample.connections,
+            sample.inflight,
+            sample.reconnects,
+            sample.outgoing_rate,
+            sample.incoming_rate,
+            sample.ack_rate,
+        );
+
+        for (name, latencies) in [
+            ("ack_latency", &sample.ack_latencies),
+            ("latency", &sample.latencies),
+        ] {
+            if latencies.samples == 0 {
+                continue;
+            }
+
+            let _ = write!(
+                line,
+                ",{name}_p50={},{name}_p90={},{name}_p99={},{name}_max={}",
+                latencies.p50, latencies.p90, latencies.p99, latencies.max
+            );
+        }
+
+        let _ = write!(line, " {timestamp}");
+        line
+    }
+}
+
+impl Reporter for Influx {
+    fn name(&self) -> &'static str {
+        "influx"
+    }
+
+    fn sample(&mut self, sample: &Sample) {
+        // don't let a slow influx delay the next sample
+        let influx = self.clone();
+        let sample = sample.clone();
+        task::spawn(async move {
+            if let Err(e) = influx.push(&sample).await {
+                warn!("Failed to push sample to influx = {:?}", e);
+            }
+        });
+    }
+}
@@ -0,0 +1,72 @@
+//! Rolling summaries printed while a long benchmark is running, so that soak
+//! tests show progress without waiting for the final report
+
+use std::time::Duration;
+
+use crate::bench::{
+    metrics::{Sample, METRICS},
+    reporter::Reporter,
+};
+
+pub(crate) struct Interim {
+    every: Duration,
+    /// elapsed time of the last summary
+    last_secs: f64,
+    /// cumulative reconnects at the last summary
+    last_reconnects: u64,
+    outgoing_publishes: u64,
+    incoming_publishes: u64,
+}
+
+impl Interim {
+    pub fn new(every: Duration) -> Interim {
+        Interim {
+            every,
+            last_secs: 0.0,
+            last_reconnects: 0,
+            outgoing_publishes: 0,
+            incoming_publishes: 0,
+        }
+    }
+
+    /// Adds a sample to the current window and prints a summary of the window
+    /// once it's `every` long
+    fn push(&mut self, sample: &Sample) {
+        self.outgoing_publishes += sample.outgoing_publishes;
+        self.incoming_publishes += sample.incoming_publishes;
+
+        // samples are a second apart give or take some jitter
+        let window = sample.elapsed_secs - self.last_secs;
+        if window + 0.5 < self.every.as_secs_f64() {
+            return;
+        }
+
+        let (ack_latencies, latencies) = METRICS.interval_latencies();
+        println!(
+            "[{:>6.0}s] Publish rate = {:.2}/s, Receive rate = {:.2}/s, Reconnects = {}, Connections = {}, Inflight = {}, Ack p99 = {:.3}ms, Latency p99 = {:.3}ms",
+            sample.elapsed_secs,
+            self.outgoing_publishes as f64 / window,
+            self.incoming_publishes as f64 / window,
+            sample.reconnects - self.last_reconnects,
+            sample.connections,
+            sample.inflight,
+            ack_latencies.p99,
+            latencies.p99,
+        );
+
+        self.last_secs = sample.elapsed_secs;
+        self.last_reconnects = sample.reconnects;
+        self.outgoing_publishes = 0;
+        self.incoming_publishes = 0;
+    }
+}
+
+impl Reporter for Interim {
+    fn name(&self) -> &'static str {
+        "interim"
+    }
+
+    fn sample(&mut self, sample: &Sample) {
+        self.push(sample);
+    }
+}
@@ -0,0 +1,155 @@
+//! Keep alive sweep. For every keep alive of `--keep-alive-sweep`, a batch of
+//! `--sweep-connections` idle connections waits for `--sweep-pings` pings each,
+//! timing how fast the broker answers them and counting connections the broker
+//! dropped although they kept pinging
+
+use std::{sync::Arc, time::Duration};
+
+use futures::future::try_join_all;
+use hdrhistogram::Histogram;
+use rumqttc::{AsyncClient, Event, Incoming, Outgoing};
+use serde::Serialize;
+use tokio::{
+    task,
+    time::{self, Instant},
+};
+
+use crate::{
+    bench::{network_options, options},
+    common::{latency_histogram, LatencySummary},
+    BenchConfig,
+};
+
+#[derive(Debug, Serialize)]
+pub struct KeepAliveSweepReport {
+    pub connections: usize,
+    /// pings every connection waited for
+    pub pings: u32,
+    pub batches: Vec<KeepAliveBatch>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeepAliveBatch {
+    pub keep_alive_secs: u64,
+    pub connected: usize,
+    pub pings: u64,
+    pub responses: u64,
+    /// connections dropped while idle
+    pub disconnects: usize,
+    /// ping responses per second across the batch
+    pub ping_rate: f64,
+    pub ping_latencies: LatencySummary,
+}
+
+/// What an idle connection saw
+struct Idle {
+    connected: bool,
+    pings: u64,
+    responses: u64,
+    disconnected: bool,
+    latencies: Histogram<u64>,
+}
+
+pub(crate) async fn sweep(config: Arc<BenchConfig>) -> anyhow::Result<KeepAliveSweepReport> {
+    let mut batches = Vec::with_capacity(config.keep_alive_sweep.len());
+    for &keep_alive in config.keep_alive_sweep.iter() {
+        batches.push(batch(&config, keep_alive).await?);
+    }
+
+    Ok(KeepAliveSweepReport {
+        connections: config.sweep_connections,
+        pings: config.sweep_pings,
+        batches,
+    })
+}
+
+async fn batch(config: &Arc<BenchConfig>, keep_alive: u64) -> anyhow::Result<KeepAliveBatch> {
+    // half a keep alive more for the last ping to be answered
+    let hold = Duration::from_secs(keep_alive) * config.sweep_pings
+        + Duration::from_millis(keep_alive * 500);
+
+    let handles = (0..config.sweep_connections).map(|i| {
+        let config = config.clone();
+        let id = format!("mqttwrk-keepalive-{keep_alive}-{i:05}");
+        task::spawn(async move { idle(config, id, keep_alive, hold).await })
+    });
+    let idles = try_join_all(handles)
+        .await?
+        .into_iter()
+        .collect::<anyhow::Result<Vec<Idle>>>()?;
+
+    let mut latencies = latency_histogram();
+    for idle in idles.iter() {
+        latencies.add(&idle.latencies)?;
+    }
+    let responses = idles.iter().map(|idle| idle.responses).sum::<u64>();
+    Ok(KeepAliveBatch {
+        keep_alive_secs: keep_alive,
+        connected: idles.iter().filter(|idle| idle.connected).count(),
+        pings: idles.iter().map(|idle| idle.pings).sum(),
+        responses,
+        disconnects: idles.iter().filter(|idle| idle.disconnected).count(),
+        ping_rate: responses as f64 / hold.as_secs_f64(),
+        ping_latencies: LatencySummary::from(&latencies),
+    })
+}
+
+/// Connects with `keep_alive` and stays idle for `hold`
+async fn idle(
+    config: Arc<BenchConfig>,
+    id: String,
+    keep_alive: u64,
+    hold: Duration,
+) -> anyhow::Result<Idle> {
+    let mut options = options(config.clone(), &id)?;
+    options.set_keep_alive(Duration::from_secs(keep_alive));
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    eventloop.network_options = network_options(&config)?;
+
+    let mut idle = Idle {
+        connected: false,
+        pings: 0,
+        responses: 0,
+        disconnected: false,
+        latencies: latency_histogram(),
+    };
+
+    let mut deadline = Instant::now() + config.conn_timeout;
+    let mut ping = None;
+    loop {
+        let event = match time::timeout_at(deadline, eventloop.poll()).await {
+            Ok(Ok(event)) => event,
+            Ok(Err(e)) => {
+                if idle.connected {
+                    error!("Id = {}, Dropped while idle = {:?}", id, e);
+                    idle.disconnected = true;
+                } else {
+                    error!("Id = {}, Connection error = {:?}", id, e);
+                }
+                return Ok(idle);
+            }
+            Err(_) => break,
+        };
+
+        match event {
+            Event::Incoming(Incoming::ConnAck(_)) => {
+                idle.connected = true;
+                deadline = Instant::now() + hold;
+            }
+            Event::Outgoing(Outgoing::PingReq) => {
+                idle.pings += 1;
+                ping = Some(Instant::now());
+            }
+            Event::Incoming(Incoming::PingResp) => {
+                idle.responses += 1;
+                if let Some(ping) = ping.take() {
+                    idle.latencies.record(ping.elapsed().as_micros() as u64)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let _ = client.try_disconnect();
+    Ok(idle)
+}
@@ -0,0 +1,275 @@
+//! Mqtt 3.1 (protocol level 3), which rumqttc doesn't speak. Its connect
+//! packet is the only one that differs from 3.1.1, so legacy clients send
+//! their own connect and reuse the 3.1.1 codec for everything else. A client
+//! id over the 23 bytes 3.1 allows is expected to be rejected, and a legacy
+//! subscriber receives `--count` QoS 1 publishes of a legacy publisher
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::{BufMut, BytesMut};
+use rumqttc::mqttbytes::{
+    self,
+    v4::{self, ConnectReturnCode, Packet, PubAck, Publish, Subscribe},
+    QoS,
+};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::oneshot,
+    task, time,
+};
+
+use crate::{
+    bench::{credentials, payload},
+    common::{latency_histogram, LatencySummary},
+    BenchConfig, Transport,
+};
+
+const TOPIC: &str = "hello/mqttwrk-v31/world";
+const PUBLISHER: &str = "mqttwrk-v31-pub";
+const SUBSCRIBER: &str = "mqttwrk-v31-sub";
+/// Longest client id of mqtt 3.1
+const MAX_CLIENT_ID: usize = 23;
+
+#[derive(Debug, Serialize)]
+pub struct Mqtt31Report {
+    /// whether the broker accepted the legacy publisher and subscriber
+    pub accepted: bool,
+    /// whether the broker rejected a client id over 23 bytes
+    pub long_id_rejected: bool,
+    pub published: usize,
+    pub acked: usize,
+    pub received: usize,
+    /// publishes received per second
+    pub throughput: f64,
+    pub ack_latencies: LatencySummary,
+}
+
+pub(crate) async fn verify(config: Arc<BenchConfig>) -> anyhow::Result<Mqtt31Report> {
+    if config.transport != Transport::Tcp || config.ca_file.is_some() {
+        anyhow::bail!("mqtt 3.1 is only spoken over plain tcp");
+    }
+
+    let long_id = "x".repeat(MAX_CLIENT_ID + 1);
+    let long_id_rejected = match Legacy::connect(&config, &long_id).await {
+        Ok((_, code)) => code != ConnectReturnCode::Success,
+        // brokers may close the connection instead of answering
+        Err(_) => true,
+    };
+
+    let (subscribed_tx, subscribed_rx) = oneshot::channel();
+    let subscriber = task::spawn(subscribe(config.clone(), subscribed_tx));
+    let subscribed = subscribed_rx.await.unwrap_or(false);
+
+    let (publisher, code) = Legacy::connect(&config, PUBLISHER).await?;
+    let accepted = subscribed && code == ConnectReturnCode::Success;
+    let (acked, ack_latencies) = match code {
+        ConnectReturnCode::Success => publish(&config, publisher).await?,
+        _ => (0, LatencySummary::from(&latency_histogram())),
+    };
+    let (received, elapsed) = subscriber.await??;
+
+    Ok(Mqtt31Report {
+        accepted,
+        long_id_rejected,
+        published: config.count,
+        acked,
+        received,
+        throughput: received as f64 / elapsed.as_secs_f64(),
+        ack_latencies,
+    })
+}
+
+/// Publishes `--count` QoS 1 publishes with at most `--max-inflight` unacked
+async fn publish(
+    config: &BenchConfig,
+    mut publisher: Legacy,
+) -> anyhow::Result<(usize, LatencySummary)> {
+    let payload = payload::generate(config.payload_size, false, None);
+    let inflight = config.max_inflight.max(1) as usize;
+    let mut sent: Vec<Option<Instant>> = vec![None; u16::MAX as usize + 1];
+    let mut histogram = latency_histogram();
+    let (mut published, mut acked) = (0, 0);
+
+    while acked < config.count {
+        while published < config.count && published - acked < inflight {
+            let mut publish = Publish::new(TOPIC, QoS::AtLeastOnce, payload.clone());
+            publish.pkid = (published % u16::MAX as usize) as u16 + 1;
+            sent[publish.pkid as usize] = Some(Instant::now());
+            publisher.send(|buffer| publish.write(buffer)).await?;
+            published += 1;
+        }
+
+        match time::timeout(config.receive_timeout, publisher.next()).await {
+            Ok(packet) => {
+                if let Packet::PubAck(ack) = packet? {
+                    acked += 1;
+                    if let Some(sent) = sent[ack.pkid as usize].take() {
+                        histogram.record(sent.elapsed().as_micros() as u64)?;
+                    }
+                }
+            }
+            Err(_) => {
+                warn!(
+                    "Id = {}, {} of {} publishes acked",
+                    PUBLISHER, acked, config.count
+                );
+                break;
+            }
+        }
+    }
+
+    Ok((acked, LatencySummary::from(&histogram)))
+}
+
+/// Subscribes and receives until all publishes arrived or none did for
+/// `--receive-timeout`. Returns the publishes received and how long that took
+async fn subscribe(
+    config: Arc<BenchConfig>,
+    subscribed: oneshot::Sender<bool>,
+) -> anyhow::Result<(usize, Duration)> {
+    let (mut subscriber, code) = Legacy::connect(&config, SUBSCRIBER).await?;
+    if code != ConnectReturnCode::Success {
+        let _ = subscribed.send(false);
+        return Ok((0, Duration::ZERO));
+    }
+
+    let mut subscribe = Subscribe::new(TOPIC, QoS::AtLeastOnce);
+    subscribe.pkid = 1;
+    subscriber.send(|buffer| subscribe.write(buffer)).await?;
+    match subscriber.next().await? {
+        Packet::SubAck(_) => {
+            let _ = subscribed.send(true);
+        }
+        packet => anyhow::bail!("Wrong packet = {:?}", packet),
+    }
+
+    let mut received = 0;
+    let mut start = None;
+    let mut elapsed = Duration::ZERO;
+    while received < config.count {
+        let packet = match time::timeout(config.receive_timeout, subscriber.next()).await {
+            Ok(packet) => packet?,
+            Err(_) => break,
+        };
+
+        if let Packet::Publish(publish) = packet {
+            let start = *start.get_or_insert_with(Instant::now);
+            elapsed = start.elapsed();
+            received += 1;
+            if publish.qos != QoS::AtMostOnce {
+                let ack = PubAck::new(publish.pkid);
+                subscriber.send(|buffer| ack.write(buffer)).await?;
+            }
+        }
+    }
+
+    Ok((received, elapsed))
+}
+
+/// Mqtt 3.1 connection over plain tcp
+struct Legacy {
+    stream: TcpStream,
+    read: BytesMut,
+    write: BytesMut,
+}
+
+impl Legacy {
+    /// Connects with a 3.1 connect packet. Returns the code of the connack
+    async fn connect(
+        config: &BenchConfig,
+        id: &str,
+    ) -> anyhow::Result<(Legacy, ConnectReturnCode)> {
+        let endpoint = &config.servers[0];
+        let port = endpoint.port.unwrap_or(config.port);
+        let stream = TcpStream::connect((endpoint.host.as_str(), port)).await?;
+        let mut legacy = Legacy {
+            stream,
+            read: BytesMut::with_capacity(10 * 1024),
+            write: BytesMut::with_capacity(10 * 1024),
+        };
+
+        let credentials = credentials(config, id)?;
+        legacy
+            .send(|buffer| Ok(connect(buffer, config, id, credentials)))
+            .await?;
+        match time::timeout(config.conn_timeout, legacy.next()).await?? {
+            Packet::ConnAck(connack) => Ok((legacy, connack.code)),
+            packet => anyhow::bail!("Wrong packet = {:?}", packet),
+        }
+    }
+
+    async fn send(
+        &mut self,
+        write: impl FnOnce(&mut BytesMut) -> Result<usize, mqttbytes::Error>,
+    ) -> anyhow::Result<()> {
+        write(&mut self.write)?;
+        self.stream.write_all(&self.write).await?;
+        self.write.clear();
+        Ok(())
+    }
+
+    async fn next(&mut self) -> anyhow::Result<Packet> {
+        loop {
+            match v4::read(&mut self.read, usize::MAX) {
+                Ok(packet) => return Ok(packet),
+                Err(mqttbytes::Error::InsufficientBytes(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            if self.stream.read_buf(&mut self.read).await? == 0 {
+                anyhow::bail!("Connection closed by broker");
+            }
+        }
+    }
+}
+
+/// Writes a connect packet with the `MQIsdp` protocol name and level 3
+fn connect(
+    buffer: &mut BytesMut,
+    config: &BenchConfig,
+    id: &str,
+    credentials: Option<(String, String)>,
+) -> usize {
+    let mut flags = 0x02;
+    let mut payload = BytesMut::new();
+    put_string(&mut payload, id);
+    if let Some((username, password)) = &credentials {
+        flags |= 0x80 | 0x40;
+        put_string(&mut payload, username);
+        put_string(&mut payload, password);
+    }
+
+    let mut packet = BytesMut::new();
+    put_string(&mut packet, "MQIsdp");
+    packet.put_u8(3);
+    packet.put_u8(flags);
+    packet.put_u16(config.keep_alive.min(u16::MAX as u64) as u16);
+    packet.extend_from_slice(&payload);
+
+    let start = buffer.len();
+    buffer.put_u8(0x10);
+    let mut remaining = packet.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        buffer.put_u8(byte);
+        if remaining == 0 {
+            break;
+        }
+    }
+    buffer.extend_from_slice(&packet);
+    buffer.len() - start
+}
+
+fn put_string(buffer: &mut BytesMut, value: &str) {
+    buffer.put_u16(value.len() as u16);
+    buffer.extend_from_slice(value.as_bytes());
+}
@@ -0,0 +1,46 @@
+//! Topic matrix. With `--topics`, publishers take turns publishing to that
+//! many topics instead of a topic each, and every subscriber subscribes to
+//! `--topics-per-subscriber` consecutive ones of them. Publishers and
+//! subscribers hence meet in any N:M pattern, and every subscriber expects the
+//! publishes of all publishers of the topics it subscribed to
+
+use crate::BenchConfig;
+
+/// Topic `t` of the matrix
+pub(crate) fn topic(t: usize) -> String {
+    format!("hello/topic-{t:05}/world")
+}
+
+/// Topics subscriber `i` subscribes to
+pub(crate) fn topics(config: &BenchConfig, i: usize) -> Vec<usize> {
+    let Some(topics) = config.topics.map(|topics| topics as usize) else {
+        return Vec::new();
+    };
+
+    let per_subscriber = config.topics_per_subscriber.min(topics);
+    (0..per_subscriber)
+        .map(|k| (i * per_subscriber + k) % topics)
+        .collect()
+}
+
+/// Publishers that publish to topic `t`
+fn publishers(config: &BenchConfig, t: usize) -> usize {
+    match config.topics.map(|topics| topics as usize) {
+        Some(topics) => config.publishers / topics + usize::from(t < config.publishers % topics),
+        None => 0,
+    }
+}
+
+/// Publishes subscriber `i` receives
+pub(crate) fn expected(config: &BenchConfig, i: usize) -> usize {
+    let publishers: usize = topics(config, i)
+        .into_iter()
+        .map(|t| publishers(config, t))
+        .sum();
+    publishers * config.count
+}
+
+/// Publishes all subscribers receive together
+pub(crate) fn expected_total(config: &BenchConfig) -> usize {
+    (0..config.subscribers).map(|i| expected(config, i)).sum()
+}
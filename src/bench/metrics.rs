@@ -0,0 +1,257 @@
+//! Live counters updated by publishers and subscribers while a benchmark is
+//! running. Final statistics are still collected from `PubStats` and `SubStats`,
+//! these are only to observe a run while it is in progress
+
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use hdrhistogram::Histogram;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
+
+use crate::common::{latency_histogram, LatencySummary};
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+#[derive(Debug)]
+pub struct Metrics {
+    /// currently connected publishers and subscribers
+    pub connections: AtomicI64,
+    /// publishes written to the network by publishers
+    pub outgoing_publishes: AtomicU64,
+    /// publishes received by subscribers
+    pub incoming_publishes: AtomicU64,
+    /// acks received by publishers
+    pub acks: AtomicU64,
+    /// qos 1 and 2 publishes waiting for an ack
+    pub inflight: AtomicI64,
+    /// connection errors across all connections
+    pub reconnects: AtomicU64,
+    /// ack latencies since the last sample and interim summary
+    ack_latencies: Mutex<Latencies>,
+    /// subscriber latencies since the last sample and interim summary
+    latencies: Mutex<Latencies>,
+}
+
+#[derive(Debug)]
+struct Latencies {
+    /// since the last sample
+    sample: Histogram<u64>,
+    /// since the last interim summary of `--report-interval`
+    interval: Histogram<u64>,
+}
+
+impl Latencies {
+    fn new() -> Latencies {
+        Latencies {
+            sample: latency_histogram(),
+            interval: latency_histogram(),
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            connections: AtomicI64::new(0),
+            outgoing_publishes: AtomicU64::new(0),
+            incoming_publishes: AtomicU64::new(0),
+            acks: AtomicU64::new(0),
+            inflight: AtomicI64::new(0),
+            reconnects: AtomicU64::new(0),
+            ack_latencies: Mutex::new(Latencies::new()),
+            latencies: Mutex::new(Latencies::new()),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn connected(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn disconnected(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn outgoing_publish(&self, pkid: u16) {
+        self.outgoing_publishes.fetch_add(1, Ordering::Relaxed);
+        // qos 0 publishes don't have a packet id and are never acked
+        if pkid != 0 {
+            self.inflight.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn incoming_publish(&self, latency: Option<Duration>) {
+        self.incoming_publishes.fetch_add(1, Ordering::Relaxed);
+        if let Some(latency) = latency {
+            record(&self.latencies, latency);
+        }
+    }
+
+    pub fn ack(&self, latency: Duration) {
+        self.acks.fetch_add(1, Ordering::Relaxed);
+        self.inflight.fetch_sub(1, Ordering::Relaxed);
+        record(&self.ack_latencies, latency);
+    }
+
+    pub fn reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Zeroes counters left over by an earlier run of the same process, e.g.
+    /// the previous phase of a scenario
+    pub fn reset(&self) {
+        self.connections.store(0, Ordering::Relaxed);
+        self.outgoing_publishes.store(0, Ordering::Relaxed);
+        self.incoming_publishes.store(0, Ordering::Relaxed);
+        self.acks.store(0, Ordering::Relaxed);
+        self.inflight.store(0, Ordering::Relaxed);
+        self.reconnects.store(0, Ordering::Relaxed);
+        *self.ack_latencies.lock().unwrap() = Latencies::new();
+        *self.latencies.lock().unwrap() = Latencies::new();
+    }
+
+    /// Summarizes and resets ack and subscriber latencies since the last
+    /// interim summary, so that days long runs summarize every interval on
+    /// its own
+    pub fn interval_latencies(&self) -> (LatencySummary, LatencySummary) {
+        let drain = |latencies: &Mutex<Latencies>| {
+            let mut latencies = latencies.lock().unwrap();
+            let summary = LatencySummary::from(&latencies.interval);
+            latencies.interval.reset();
+            summary
+        };
+
+        (drain(&self.ack_latencies), drain(&self.latencies))
+    }
+}
+
+fn record(latencies: &Mutex<Latencies>, latency: Duration) {
+    let mut latencies = latencies.lock().unwrap();
+    let latency = latency.as_micros() as u64;
+    latencies.sample.record(latency).unwrap();
+    latencies.interval.record(latency).unwrap();
+}
+
+/// Summarizes and resets latencies since the last sample
+fn drain(latencies: &Mutex<Latencies>) -> LatencySummary {
+    let mut latencies = latencies.lock().unwrap();
+    let summary = LatencySummary::from(&latencies.sample);
+    latencies.sample.reset();
+    summary
+}
+
+/// State of the benchmark over one sampling interval
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    #[serde(skip)]
+    pub timestamp: SystemTime,
+    /// time since sampling started at the end of this interval
+    pub elapsed_secs: f64,
+    pub connections: i64,
+    pub inflight: i64,
+    pub reconnects: u64,
+    /// publishes sent during the interval
+    pub outgoing_publishes: u64,
+    /// publishes received during the interval
+    pub incoming_publishes: u64,
+    /// acks received during the interval
+    pub acks: u64,
+    /// publishes sent per second
+    pub outgoing_rate: f64,
+    /// publishes received per second
+    pub incoming_rate: f64,
+    /// acks received per second
+    pub ack_rate: f64,
+    pub ack_latencies: LatencySummary,
+    pub latencies: LatencySummary,
+    /// cpu used by mqttwrk itself, 100% per fully used core
+    pub cpu_percent: f32,
+    /// resident memory of mqttwrk itself
+    pub rss_bytes: u64,
+}
+
+/// Turns the cumulative counters in `METRICS` into per interval samples. There
+/// should only be one sampler as sampling resets interval latencies
+pub struct Sampler {
+    start: Instant,
+    last: Instant,
+    outgoing_publishes: u64,
+    incoming_publishes: u64,
+    acks: u64,
+    system: System,
+    pid: Pid,
+}
+
+impl Sampler {
+    pub fn new() -> Sampler {
+        let pid = Pid::from_u32(std::process::id());
+        // the first refresh only adds the process, the second one sets the
+        // baseline for cpu usage of the first sample
+        let mut system = System::new();
+        system.refresh_process(pid);
+        system.refresh_process(pid);
+
+        Sampler {
+            start: Instant::now(),
+            last: Instant::now(),
+            outgoing_publishes: METRICS.outgoing_publishes.load(Ordering::Relaxed),
+            incoming_publishes: METRICS.incoming_publishes.load(Ordering::Relaxed),
+            acks: METRICS.acks.load(Ordering::Relaxed),
+            system,
+            pid,
+        }
+    }
+
+    pub fn sample(&mut self) -> Sample {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        let delta = |current: u64, last: &mut u64| {
+            let delta = current.saturating_sub(*last);
+            *last = current;
+            delta
+        };
+
+        let outgoing_publishes = delta(
+            METRICS.outgoing_publishes.load(Ordering::Relaxed),
+            &mut self.outgoing_publishes,
+        );
+        let incoming_publishes = delta(
+            METRICS.incoming_publishes.load(Ordering::Relaxed),
+            &mut self.incoming_publishes,
+        );
+        let acks = delta(METRICS.acks.load(Ordering::Relaxed), &mut self.acks);
+        self.last = Instant::now();
+
+        // cpu usage is relative to the previous refresh
+        self.system.refresh_process(self.pid);
+        let (cpu_percent, rss_bytes) = match self.system.process(self.pid) {
+            Some(process) => (process.cpu_usage(), process.memory()),
+            None => (0.0, 0),
+        };
+
+        Sample {
+            timestamp: SystemTime::now(),
+            elapsed_secs: self.start.elapsed().as_secs_f64(),
+            connections: METRICS.connections.load(Ordering::Relaxed),
+            inflight: METRICS.inflight.load(Ordering::Relaxed),
+            reconnects: METRICS.reconnects.load(Ordering::Relaxed),
+            outgoing_publishes,
+            incoming_publishes,
+            acks,
+            outgoing_rate: outgoing_publishes as f64 / elapsed,
+            incoming_rate: incoming_publishes as f64 / elapsed,
+            ack_rate: acks as f64 / elapsed,
+            ack_latencies: drain(&METRICS.ack_latencies),
+            latencies: drain(&METRICS.latencies),
+            cpu_percent,
+            rss_bytes,
+        }
+    }
+}
@@ -1,17 +1,105 @@
-use std::{fs, io, sync::Arc, time::Duration};
+use std::{
+    fs,
+    future::Future,
+    io,
+    net::Ipv6Addr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
+use bytes::Bytes;
+#[cfg(feature = "websocket")]
+use futures::future;
 use futures::StreamExt;
 use indicatif::ProgressBar;
-use rumqttc::{MqttOptions, QoS, Transport};
-use tokio::{sync::Barrier, task};
+use once_cell::sync::Lazy;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rumqttc::{
+    v5::{
+        self,
+        mqttbytes::v5::{ConnAck, Packet, PublishProperties},
+    },
+    MqttOptions, NetworkOptions, Outgoing, Proxy, QoS, TlsConfiguration, Transport,
+};
+use tokio::{
+    sync::{oneshot, Barrier},
+    task, time,
+};
 
 use crate::{
     common::{PubStats, Stats, SubStats, PROGRESS_STYLE},
-    BenchConfig,
+    BenchConfig, Endpoint, Profile, ProxyServer, TlsBackend,
 };
+use churn::Churn;
+use otlp::{Otlp, Phase};
+use progress::Progress;
+use rampdown::{RampDown, RampDownReport};
+use report::Report;
+use sys::SysMonitor;
+use takeover::Takeover;
+use will::WillMonitor;
 
+mod alias;
+mod assertions;
+mod assigned;
+pub(crate) mod azure;
+mod churn;
+pub(crate) mod credentials;
+mod deadline;
+mod expiry;
+mod findmax;
+mod flap;
+mod flow;
+pub(crate) mod groups;
+mod inflightsweep;
+mod influx;
+mod interim;
+mod jwt;
+mod keepalive;
+mod legacy;
+mod matrix;
+mod metrics;
+mod otlp;
+mod outage;
+pub(crate) mod pacer;
+mod packetsize;
+mod payload;
+mod payloadsweep;
+mod profile;
+mod progress;
+mod prometheus;
+mod properties;
 mod publisher;
+pub(crate) mod qosmix;
+mod rampdown;
+mod ratesweep;
+pub(crate) mod report;
+mod reporter;
+mod retain;
+mod retainflood;
+pub(crate) mod schedule;
+mod sequence;
+mod sessions;
+mod shared;
+mod slow;
+mod soak;
+mod statsd;
+mod store;
+mod storm;
+mod subopts;
 mod subscriber;
+mod sys;
+mod takeover;
+mod timeseries;
+mod tls;
+pub(crate) mod tunnel;
+pub(crate) mod wildcard;
+mod will;
+mod willdelay;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ConnectionError {
@@ -26,11 +114,390 @@ pub enum ConnectionError {
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
-pub(crate) async fn start(config: BenchConfig) {
+pub(crate) async fn start(mut config: BenchConfig) {
+    // a schedule publishes until its last phase is over
+    if let Some(schedule) = &config.schedule {
+        config.duration = Some(schedule.total());
+    }
+
+    // publishers of all groups together
+    if let Some(groups) = &config.groups {
+        config.publishers = groups.publishers();
+    }
+
+    // publishes come from elsewhere
+    if config.subscribe_only {
+        config.publishers = 0;
+    }
+
+    // idle publishers don't publish at all
+    if config.idle.is_some() {
+        config.count = 0;
+    }
+
+    // a soak run is a run of a fixed duration that ends on a signal
+    if config.forever {
+        config.duration = Some(soak::FOREVER);
+        config.report_interval.get_or_insert(soak::REPORT_INTERVAL);
+    }
+
+    if let (Some(min), Some(max)) = (config.payload_size_min, config.payload_size_max) {
+        if min > max {
+            e_red_ln!(
+                "--payload-size-min of {} bytes is over --payload-size-max of {} bytes",
+                min,
+                max
+            );
+            std::process::exit(2);
+        }
+    }
+
+    if let Err(e) = check_empty_client_id(&config) {
+        e_red_ln!("{}", e);
+        std::process::exit(2);
+    }
+
+    if config.find_max && config.rate == 0 {
+        e_red_ln!("--find-max searches from --rate, which has to be over 0");
+        std::process::exit(2);
+    }
+
+    let violations = profile::apply(&mut config);
+    if !violations.is_empty() {
+        e_red_ln!("Benchmark exceeds the limits of the broker profile");
+        for violation in violations {
+            e_red_ln!("        {}", violation);
+        }
+        std::process::exit(2);
+    }
+
+    // rumqttc can't tunnel through socks5 proxies or connect from a range of
+    // addresses, connections go through a local tunnel that does
+    match tunnel::start(&config).await {
+        Ok(tunnel) => config.tunnel = tunnel,
+        Err(e) => {
+            e_red_ln!("Failed to start the local tunnel = {}", e);
+            std::process::exit(2);
+        }
+    }
+
     let config = Arc::new(config);
+    if config.forever {
+        soak::listen();
+    }
+
+    let otlp = config.otlp_endpoint.as_deref().map(Otlp::new);
+    let reporters = reporter::reporters(&config, otlp.as_ref()).await;
+    let (stop_sampling, stop) = oneshot::channel();
+    let keep = config.forever.then_some(soak::SAMPLES);
+    let sampling = task::spawn(timeseries::collect(reporters, keep, stop));
+
+    let sys_monitor = optional(
+        "start $SYS monitor",
+        config.sys_monitor,
+        SysMonitor::start(config.clone()),
+    )
+    .await;
+
+    let will_monitor = optional(
+        "start will monitor",
+        config.will_topic.is_some(),
+        WillMonitor::start(config.clone()),
+    )
+    .await;
+
+    // churns alongside the stable connections for the whole run
+    let churn = optional(
+        "start churn",
+        config.churn.is_some(),
+        Churn::start(config.clone()),
+    )
+    .await;
+
+    // taken over while the run goes on
+    let takeover = optional(
+        "start takeover",
+        config.takeover.is_some(),
+        Takeover::start(config.clone()),
+    )
+    .await;
+
+    let Workload {
+        started_at,
+        connect_elapsed,
+        elapsed,
+        pub_stats: all_pubstats,
+        sub_stats: all_substats,
+        ramp_down,
+    } = workload(&config, will_monitor.as_ref()).await;
+
+    let _ = stop_sampling.send(());
+    let (samples, mut reporters) = sampling.await.unwrap();
+    let broker = match sys_monitor {
+        Some(monitor) => monitor.stop().await,
+        None => Default::default(),
+    };
+    let wills = match will_monitor {
+        Some(monitor) => monitor.stop().await,
+        None => None,
+    };
+    let churn = churn.map(Churn::stop);
+    let takeover = match takeover {
+        Some(takeover) => takeover.stop().await,
+        None => None,
+    };
+
+    // late subscriber for the retained publishes, once all publishers are done
+    let retained = optional(
+        "verify retained publishes",
+        retain::ratio(&config) > 0.0,
+        retain::verify(config.clone()),
+    )
+    .await;
+
+    // compared on dedicated v5 connections once the run is done
+    let aliases = optional(
+        "compare topic aliases",
+        config.topic_alias_max.is_some(),
+        alias::compare(config.clone()),
+    )
+    .await;
+
+    let properties = optional(
+        "compare user properties",
+        !config.user_properties.is_empty(),
+        properties::compare(config.clone()),
+    )
+    .await;
+
+    let expired = optional(
+        "verify message expiry",
+        config.message_expiry.is_some(),
+        expiry::verify(config.clone()),
+    )
+    .await;
+
+    let will_delay = optional(
+        "verify will delay",
+        config.will_delay.is_some(),
+        willdelay::verify(config.clone()),
+    )
+    .await;
+
+    let sessions = optional(
+        "verify session expiry",
+        config.session_expiry.is_some(),
+        sessions::verify(config.clone()),
+    )
+    .await;
+
+    let flow = optional(
+        "verify flow control",
+        config.receive_maximum.is_some(),
+        flow::verify(config.clone()),
+    )
+    .await;
+
+    let subscription_options = optional(
+        "verify subscription options",
+        config.no_local || config.retain_as_published || config.retain_handling.is_some(),
+        subopts::verify(config.clone()),
+    )
+    .await;
+
+    let max_packet_size = optional(
+        "verify maximum packet size",
+        config.max_packet_size.is_some(),
+        packetsize::verify(config.clone()),
+    )
+    .await;
+
+    let packet_size_probe = optional(
+        "probe packet size",
+        config.probe_packet_size.is_some(),
+        packetsize::probe(config.clone()),
+    )
+    .await;
+
+    let mqtt31 = optional(
+        "verify mqtt 3.1",
+        config.mqtt31,
+        legacy::verify(config.clone()),
+    )
+    .await;
+
+    let assigned_client_ids = optional(
+        "verify assigned client ids",
+        config.assigned_client_ids.is_some(),
+        assigned::verify(config.clone()),
+    )
+    .await;
+
+    let keep_alive_sweep = optional(
+        "sweep keep alives",
+        !config.keep_alive_sweep.is_empty(),
+        keepalive::sweep(config.clone()),
+    )
+    .await;
+
+    let payload_sweep = optional(
+        "sweep payload sizes",
+        !config.payload_sweep.is_empty(),
+        payloadsweep::sweep(config.clone()),
+    )
+    .await;
+
+    let retain_flood = optional(
+        "flood retained publishes",
+        config.retain_flood.is_some(),
+        retainflood::flood(config.clone()),
+    )
+    .await;
+
+    // reruns the whole workload, once everything else is done with the broker
+    let inflight_sweep = if config.inflight_sweep.is_empty() {
+        None
+    } else {
+        Some(inflightsweep::sweep(&config).await)
+    };
+    let rate_sweep = if config.rate_sweep.is_empty() {
+        None
+    } else {
+        Some(ratesweep::sweep(&config).await)
+    };
+
+    if let Some(otlp) = otlp.filter(|_| config.otlp_traces) {
+        let end = SystemTime::now();
+        let run_start = end - elapsed;
+        let connect_start = run_start - connect_elapsed;
+        let phases = [
+            Phase {
+                name: "connect",
+                start: connect_start,
+                end: run_start,
+            },
+            Phase {
+                name: "publish",
+                start: run_start,
+                end,
+            },
+        ];
+
+        if let Err(e) = otlp.trace(&config, connect_start, end, &phases).await {
+            error!("Failed to export trace to otlp = {:?}", e);
+        }
+    }
+
+    let mut report = Report::new(
+        &config,
+        started_at,
+        connect_elapsed,
+        elapsed,
+        &all_pubstats,
+        &all_substats,
+        samples,
+    );
+    report.broker = broker;
+    report.retained = retained;
+    report.wills = wills;
+    report.ramp_down = ramp_down;
+    report.aliases = aliases;
+    report.properties = properties;
+    report.expired = expired;
+    report.session_expiry = sessions;
+    report.will_delay = will_delay;
+    report.flow = flow;
+    report.subscription_options = subscription_options;
+    report.max_packet_size = max_packet_size;
+    report.packet_size_probe = packet_size_probe;
+    report.assigned_client_ids = assigned_client_ids;
+    report.keep_alive_sweep = keep_alive_sweep;
+    report.mqtt31 = mqtt31;
+    report.churn = churn;
+    report.takeover = takeover;
+    report.retain_flood = retain_flood;
+    report.payload_sweep = payload_sweep;
+    report.inflight_sweep = inflight_sweep;
+    report.rate_sweep = rate_sweep;
+
+    // reruns the whole workload too, from what the run itself achieved
+    if config.find_max {
+        report.find_max = Some(findmax::search(&config, &report.summary).await);
+    }
+
+    let assertions = assertions::check(&config, &report.summary);
+    for reporter in reporters.iter_mut() {
+        if let Err(e) = reporter.finish(&report, &assertions) {
+            error!("Failed to write {} report = {:#}", reporter.name(), e);
+        }
+    }
+
+    // assertions are what the search probed for
+    if let Some(find_max) = &report.find_max {
+        if find_max.max_rate.is_none() {
+            e_red_ln!("No rate met the assertions");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let failures: Vec<&String> = assertions
+        .iter()
+        .filter_map(|assertion| assertion.failure.as_ref())
+        .collect();
+    if !failures.is_empty() {
+        e_red_ln!("Assertions failed");
+        for failure in failures {
+            e_red_ln!("        {}", failure);
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Statistics of one pass of the publishers and subscribers of the run
+struct Workload {
+    started_at: SystemTime,
+    connect_elapsed: Duration,
+    elapsed: Duration,
+    pub_stats: Vec<PubStats>,
+    sub_stats: Vec<SubStats>,
+    ramp_down: Option<RampDownReport>,
+}
+
+/// Connects the subscribers and publishers of the run and waits for all of
+/// them to be done
+async fn workload(config: &Arc<BenchConfig>, will_monitor: Option<&WillMonitor>) -> Workload {
+    // counters start over for every pass, and every phase of a scenario
+    metrics::METRICS.reset();
+
     let mut handles = futures::stream::FuturesUnordered::new();
     let barrier_sub = Arc::new(Barrier::new(config.subscribers));
-    let barrier_pub = Arc::new(Barrier::new(config.publishers));
+    // publishers of a publish only run don't wait for each other
+    let barrier_pub = Arc::new(Barrier::new(if config.publish_only {
+        1
+    } else {
+        config.publishers
+    }));
+
+    let started_at = SystemTime::now();
+    let connect_start = Instant::now();
+    let mut connect_pacer = pacer::connect_pacer(config);
+
+    // subscribers in a shared subscription receive every publish between them
+    let group = config.share_group.as_ref().map(|_| {
+        let expected = subscribed_publishes(config);
+        Arc::new(shared::Group::new(expected as u64))
+    });
+    let published = config
+        .duration
+        .map(|_| Arc::new(deadline::Published::new(config.publishers)));
+    let storm = config.reconnect_storm.map(|_| storm::Storm::default());
+    // stable subscribers tell publishes of flappy publishers apart by topic
+    let flappy_topics = Arc::new(flap::topics(config));
+    // connections that are done stay open until the ramp down closes them
+    let ramp_down = config
+        .ramp_down_rate
+        .map(|_| Arc::new(RampDown::new(will_monitor.map(WillMonitor::kills))));
 
     // spawning subscribers
     let sub_bar = ProgressBar::new(config.subscribers as u64)
@@ -38,13 +505,32 @@ pub(crate) async fn start(config: BenchConfig) {
         .with_style((*PROGRESS_STYLE).clone());
 
     for i in 0..config.subscribers {
-        let config = Arc::clone(&config);
-        let id = format!("sub-{i:05}");
+        let config = Arc::clone(config);
+        let id = subscriber_id(&config, i);
         let barrier_handle = barrier_sub.clone();
         sub_bar.set_message(format!("spawning {id}"));
-        let mut subscriber = subscriber::Subscriber::new(id, config).await.unwrap();
+        if let Some(pacer) = &mut connect_pacer {
+            pacer.wait().await;
+        }
+        let mut subscriber = subscriber::Subscriber::new(
+            id,
+            i,
+            config.clone(),
+            endpoint(&config, i),
+            group.clone(),
+            published.clone(),
+            storm.clone(),
+            flap::Flap::new(&config, i, config.subscribers, &flappy_topics),
+        )
+        .await
+        .unwrap();
+        let ramp_down = ramp_down.clone();
         handles.push(task::spawn(async move {
-            Stats::SubStats(subscriber.start(barrier_handle).await)
+            let stats = subscriber.start(barrier_handle).await;
+            if let Some(ramp_down) = ramp_down {
+                ramp_down.hold(subscriber.into_connection());
+            }
+            Stats::SubStats(Box::new(stats))
         }));
         sub_bar.inc(1);
     }
@@ -55,61 +541,750 @@ pub(crate) async fn start(config: BenchConfig) {
         .with_prefix("Publishers Spawned:")
         .with_style((*PROGRESS_STYLE).clone());
 
+    // publishers of a group publish with the settings of their group
+    let group_configs: Vec<Arc<BenchConfig>> = match &config.groups {
+        Some(groups) => groups
+            .groups
+            .iter()
+            .map(|group| Arc::new(group.apply(config)))
+            .collect(),
+        None => Vec::new(),
+    };
+
     for i in 0..config.publishers {
-        let config = Arc::clone(&config);
-        let id = format!("pub-{i:05}");
+        let config = match &config.groups {
+            Some(groups) => group_configs[groups.of(i)].clone(),
+            None => Arc::clone(config),
+        };
+        let id = publisher_id(&config, i);
         let barrier_handle = barrier_pub.clone();
         pub_bar.set_message(format!("spawning {id}"));
-        let mut publisher = publisher::Publisher::new(id, config).await.unwrap();
+        if let Some(pacer) = &mut connect_pacer {
+            pacer.wait().await;
+        }
+        let kills = will_monitor.map(WillMonitor::kills);
+        let kill = i < config.kill_publishers;
+        let mut publisher = publisher::Publisher::new(
+            id,
+            i,
+            config.clone(),
+            endpoint(&config, i),
+            published.clone(),
+            storm.clone(),
+            flap::Flap::new(&config, i, config.publishers, &flappy_topics),
+        )
+        .await
+        .unwrap();
+        let ramp_down = ramp_down.clone();
         handles.push(task::spawn(async move {
-            Stats::PubStats(publisher.start(barrier_handle).await)
+            let stats = publisher.start(barrier_handle).await;
+            // without a will there's nothing to tell killed and gracefully
+            // disconnected publishers apart
+            match (ramp_down, kills) {
+                (Some(ramp_down), _) => ramp_down.hold(publisher.into_connection()),
+                (None, Some(kills)) if kill => publisher.kill(&kills),
+                (None, Some(_)) => publisher.disconnect().await,
+                (None, None) => {}
+            }
+            Stats::PubStats(Box::new(stats))
         }));
         pub_bar.inc(1);
     }
     pub_bar.finish_with_message("Done!");
+    let connect_elapsed = connect_start.elapsed();
 
-    let mut aggregate_substats = SubStats::default();
-    let mut aggregate_pubstats = PubStats::default();
+    // interim summaries would interleave with progress bars
+    let progress = match config.report_interval {
+        Some(_) => None,
+        None => Progress::start(config),
+    };
+    // publishers of a publish only run started publishing while connecting
+    let start = if config.publish_only {
+        connect_start
+    } else {
+        Instant::now()
+    };
+    if let (Some(storm), Some(after)) = (&storm, config.reconnect_storm) {
+        storm.start(after.saturating_sub(start.elapsed()));
+    }
+
+    let mut sub_stats = Vec::with_capacity(config.subscribers);
+    let mut pub_stats = Vec::with_capacity(config.publishers);
     // await and consume all futures
     while let Some(some_stat) = handles.next().await {
         match some_stat.unwrap() {
-            Stats::SubStats(substats) => {
-                aggregate_substats.publish_count += substats.publish_count;
-                aggregate_substats.puback_count += substats.puback_count;
-                aggregate_substats.reconnects += substats.reconnects;
-                aggregate_substats.throughput += substats.throughput;
-            }
-            Stats::PubStats(pubstats) => {
-                aggregate_pubstats.outgoing_publish += pubstats.outgoing_publish;
-                aggregate_pubstats.throughput += pubstats.throughput;
-                aggregate_pubstats.reconnects += pubstats.reconnects;
-            }
+            Stats::SubStats(substats) => sub_stats.push(*substats),
+            Stats::PubStats(pubstats) => pub_stats.push(*pubstats),
+        }
+    }
+
+    // publishes from elsewhere are timed from the first one
+    let first_publish = sub_stats
+        .iter()
+        .filter_map(|stats| stats.first_publish)
+        .min();
+    let elapsed = match (config.subscribe_only, first_publish) {
+        (true, Some(first_publish)) => first_publish.elapsed(),
+        _ => start.elapsed(),
+    };
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    // closing connections isn't part of the run
+    let ramp_down = match ramp_down {
+        Some(ramp_down) => Some(ramp_down.close(config).await),
+        None => None,
+    };
+
+    Workload {
+        started_at,
+        connect_elapsed,
+        elapsed,
+        pub_stats,
+        sub_stats,
+        ramp_down,
+    }
+}
+
+/// Result of an optional part of the run if it's `enabled`. Failures are
+/// logged as failing to `what` and leave the part out of the report
+async fn optional<T, E>(
+    what: &str,
+    enabled: bool,
+    part: impl Future<Output = Result<T, E>>,
+) -> Option<T>
+where
+    E: Into<anyhow::Error>,
+{
+    if !enabled {
+        return None;
+    }
+
+    match part.await {
+        Ok(part) => Some(part),
+        Err(e) => {
+            error!("Failed to {} = {:#}", what, e.into());
+            None
         }
     }
-    println!(
-        "Aggregate PubStats: {:#?}\nAggregate SubStats: {:#?}",
-        &aggregate_pubstats, &aggregate_substats
-    );
 }
 
 pub(crate) fn options(config: Arc<BenchConfig>, id: &str) -> io::Result<MqttOptions> {
-    let mut options = MqttOptions::new(id, &config.server, config.port);
+    let endpoint = config.servers[0].clone();
+    endpoint_options(config, id, &endpoint)
+}
+
+/// Brokers only assign client ids to clean sessions
+fn check_empty_client_id(config: &BenchConfig) -> Result<(), &'static str> {
+    if config.empty_client_id && !config.clean_session {
+        return Err(
+            "--empty-client-id needs clean sessions, brokers only assign client ids to those",
+        );
+    }
+
+    Ok(())
+}
+
+/// Options of a connection to `endpoint` of `--server`
+pub(crate) fn endpoint_options(
+    config: Arc<BenchConfig>,
+    id: &str,
+    endpoint: &Endpoint,
+) -> io::Result<MqttOptions> {
+    check_empty_client_id(&config).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let port = endpoint.port.unwrap_or(config.port);
+    // ids still name connections in reports, topics and credentials
+    let client_id = if config.empty_client_id { "" } else { id };
+    let mut options = MqttOptions::new(client_id, broker_addr(&config, endpoint), port);
     options.set_keep_alive(Duration::from_secs(config.keep_alive));
     options.set_inflight(config.max_inflight);
+    options.set_max_packet_size(max_packet_size(&config), max_packet_size(&config));
+    options.set_transport(transport(&config, id, endpoint)?);
+    if let Some((username, password)) = credentials(&config, id)? {
+        options.set_credentials(username, password);
+    }
+    if let Some(proxy) = proxy(&config) {
+        options.set_proxy(proxy);
+    }
 
-    if let Some(ca_file) = &config.ca_file {
-        let ca = fs::read(ca_file)?;
-        options.set_transport(Transport::tls(ca, None, None));
+    #[cfg(feature = "websocket")]
+    if matches!(
+        config.transport,
+        crate::Transport::Ws | crate::Transport::Wss
+    ) {
+        options.set_request_modifier(ws_request(&config, endpoint));
     }
 
     Ok(options)
 }
 
+/// Proxy that rumqttc tunnels connections through, the ports of the local
+/// tunnel in turn if the run has one
+fn proxy(config: &BenchConfig) -> Option<Proxy> {
+    if !config.tunnel.is_empty() {
+        let next = NEXT_TUNNEL.fetch_add(1, Ordering::Relaxed);
+        return Some(config.tunnel[next % config.tunnel.len()].clone());
+    }
+
+    match &config.proxy {
+        Some(ProxyServer::Http(proxy)) => Some(proxy.clone()),
+        _ => None,
+    }
+}
+
+/// Largest payload any connection of the run publishes
+fn largest_payload(config: &BenchConfig) -> usize {
+    config
+        .payload_sweep
+        .iter()
+        .copied()
+        .fold(payload::Sizes::new(config).max(), usize::max)
+}
+
+/// Largest packet connections send and accept, the largest payload behind the
+/// longest topic, up to what mqtt allows
+fn max_packet_size(config: &BenchConfig) -> usize {
+    (largest_payload(config) + 64 * 1024).min(MAX_PACKET_SIZE)
+}
+
+/// Options of the mqtt 5 connections of scenarios that need v5 features
+pub(crate) fn v5_options(config: &BenchConfig, id: &str) -> io::Result<v5::MqttOptions> {
+    let endpoint = &config.servers[0];
+    let port = endpoint.port.unwrap_or(config.port);
+    let mut options = v5::MqttOptions::new(id, broker_addr(config, endpoint), port);
+    options.set_keep_alive(Duration::from_secs(config.keep_alive));
+    options.set_outgoing_inflight_upper_limit(config.max_inflight);
+    options.set_transport(transport(config, id, endpoint)?);
+    options.set_network_options(network_options(config)?);
+    // advertised to the broker, so only once publishes outgrow the default
+    if largest_payload(config) > 8 * 1024 {
+        options.set_max_packet_size(Some(max_packet_size(config) as u32));
+    }
+    if let Some((username, password)) = credentials(config, id)? {
+        options.set_credentials(username, password);
+    }
+    if let Some(proxy) = proxy(config) {
+        options.set_proxy(proxy);
+    }
+
+    #[cfg(feature = "websocket")]
+    if matches!(
+        config.transport,
+        crate::Transport::Ws | crate::Transport::Wss
+    ) {
+        options.set_request_modifier(ws_request(config, endpoint));
+    }
+
+    Ok(options)
+}
+
+/// Username and password of connection `id`: its row in `--credentials-file`,
+/// or else `--username` along with a jwt of `--jwt-key`, `--password` or
+/// `--password-file`. Azure iot hub devices use their sas tokens instead
+fn credentials(config: &BenchConfig, id: &str) -> io::Result<Option<(String, String)>> {
+    let row = config
+        .credentials_file
+        .as_ref()
+        .and_then(|file| file.get(id));
+    if let Some(row) = row {
+        return Ok(Some((row.username.clone(), row.password.clone())));
+    }
+
+    if config.jwt_key.is_some() {
+        let username = config.username.as_deref().unwrap_or(id);
+        return Ok(Some((username.to_owned(), jwt::token(config, id)?)));
+    }
+
+    if let Some(connection) = &config.azure_connection_string {
+        let token = azure::sas_token(connection, id, config.sas_expiry);
+        return Ok(Some((azure::username(connection, id), token)));
+    }
+
+    let password = config.password.as_ref().or(config.password_file.as_ref());
+    Ok(config.username.as_ref().map(|username| {
+        let password = password.cloned().unwrap_or_default();
+        (username.clone(), password)
+    }))
+}
+
+/// Signs a fresh jwt or sas token for the next reconnect of connection `id`,
+/// as the one it connected with might have expired
+pub(crate) fn refresh_token(config: &BenchConfig, id: &str, options: &mut MqttOptions) {
+    // jwts win over sas tokens, as in `credentials`
+    let kind = match (&config.jwt_key, &config.azure_connection_string) {
+        (Some(_), _) => "jwt",
+        (None, Some(_)) => "sas",
+        (None, None) => return,
+    };
+
+    match credentials(config, id) {
+        Ok(Some((username, password))) => {
+            options.set_credentials(username, password);
+        }
+        Ok(None) => {}
+        Err(e) => error!("Id = {}, Failed to refresh {} token = {:?}", id, kind, e),
+    }
+}
+
+/// Whole seconds of a connection timeout, as rumqttc takes it, rounded up so
+/// that short timeouts don't turn into none
+pub(crate) fn timeout_secs(timeout: Duration) -> u64 {
+    timeout.as_secs() + (timeout.subsec_nanos() != 0) as u64
+}
+
+/// Next of `--bind-interface` to bind a connection to
+static NEXT_INTERFACE: AtomicUsize = AtomicUsize::new(0);
+static NEXT_TUNNEL: AtomicUsize = AtomicUsize::new(0);
+
+/// Network options of a connection, bound to the next of `--bind-interface`
+/// unless the local tunnel binds its connections instead
+pub(crate) fn network_options(config: &BenchConfig) -> io::Result<NetworkOptions> {
+    let mut network_options = NetworkOptions::new();
+    network_options.set_connection_timeout(timeout_secs(config.conn_timeout));
+    if !config.tunnel.is_empty() {
+        return Ok(network_options);
+    }
+
+    if let Some(interface) = next_interface(config) {
+        bind_device(&mut network_options, interface)?;
+    }
+    Ok(network_options)
+}
+
+/// Next of `--bind-interface` in turn, if any
+pub(crate) fn next_interface(config: &BenchConfig) -> Option<&str> {
+    if config.bind_interfaces.is_empty() {
+        return None;
+    }
+
+    let next = NEXT_INTERFACE.fetch_add(1, Ordering::Relaxed);
+    Some(&config.bind_interfaces[next % config.bind_interfaces.len()])
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(network_options: &mut NetworkOptions, interface: &str) -> io::Result<()> {
+    network_options.set_bind_device(interface);
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_: &mut NetworkOptions, _: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to interfaces is only supported on linux",
+    ))
+}
+
+/// Endpoint of `--server` that connection `i` of publishers or subscribers
+/// connects to. Endpoints take turns for as many connections as their weight
+pub(crate) fn endpoint(config: &BenchConfig, i: usize) -> &Endpoint {
+    let total: usize = config.servers.iter().map(|e| e.weight as usize).sum();
+    let mut slot = i % total;
+    for endpoint in config.servers.iter() {
+        match slot.checked_sub(endpoint.weight as usize) {
+            Some(rest) => slot = rest,
+            None => return endpoint,
+        }
+    }
+
+    &config.servers[0]
+}
+
+/// `HOST:PORT` of an endpoint, as reports label it
+pub(crate) fn endpoint_label(config: &BenchConfig, endpoint: &Endpoint) -> String {
+    match config.transport {
+        crate::Transport::Unix => endpoint.host.clone(),
+        _ => format!(
+            "{}:{}",
+            bracketed(&endpoint.host),
+            endpoint.port.unwrap_or(config.port)
+        ),
+    }
+}
+
+/// Ipv6 addresses are put in brackets to be told apart from the port
+fn bracketed(host: &str) -> String {
+    match host.parse::<Ipv6Addr>() {
+        Ok(_) => format!("[{host}]"),
+        Err(_) => host.to_owned(),
+    }
+}
+
+/// Websocket connections take the url of the endpoint instead of a host
+fn broker_addr(config: &BenchConfig, endpoint: &Endpoint) -> String {
+    let scheme = match config.transport {
+        crate::Transport::Tcp => return bracketed(&endpoint.host),
+        crate::Transport::Unix => return endpoint.host.clone(),
+        crate::Transport::Ws => "ws",
+        crate::Transport::Wss => "wss",
+    };
+
+    format!(
+        "{}://{}:{}{}",
+        scheme,
+        bracketed(&endpoint.host),
+        endpoint.port.unwrap_or(config.port),
+        config.ws_path
+    )
+}
+
+fn transport(config: &BenchConfig, id: &str, endpoint: &Endpoint) -> io::Result<Transport> {
+    let ca = match &config.ca_file {
+        Some(ca_file) => Some(fs::read(ca_file)?),
+        None => None,
+    };
+
+    match (config.transport, ca) {
+        // rumqttc takes the broker's address as server name of tls over tcp
+        (crate::Transport::Tcp, Some(_)) if endpoint.host.parse::<Ipv6Addr>().is_ok() => {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tls over tcp needs a hostname instead of an ipv6 address",
+            ))
+        }
+        (crate::Transport::Tcp, Some(ca)) => {
+            Ok(Transport::tls_with_config(tls_config(config, id, ca)?))
+        }
+        // managed brokers are verified with the platform's certificates
+        (crate::Transport::Tcp, None) if config.profile == Some(Profile::AzureIotHub) => {
+            Ok(Transport::tls_with_default_config())
+        }
+        (crate::Transport::Tcp, None) => Ok(Transport::tcp()),
+        #[cfg(unix)]
+        (crate::Transport::Unix, _) => Ok(Transport::unix()),
+        #[cfg(not(unix))]
+        (crate::Transport::Unix, _) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix sockets are only supported on unix",
+        )),
+        #[cfg(feature = "websocket")]
+        (crate::Transport::Wss, _) if config.tls_backend == TlsBackend::NativeTls => {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "secure websockets only support the rustls tls backend",
+            ))
+        }
+        #[cfg(feature = "websocket")]
+        (crate::Transport::Ws, _) => Ok(Transport::ws()),
+        #[cfg(feature = "websocket")]
+        (crate::Transport::Wss, Some(ca)) => {
+            Ok(Transport::wss_with_config(tls_config(config, id, ca)?))
+        }
+        #[cfg(feature = "websocket")]
+        (crate::Transport::Wss, None) => Ok(Transport::wss_with_default_config()),
+        #[cfg(not(feature = "websocket"))]
+        (crate::Transport::Ws | crate::Transport::Wss, _) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "websockets need mqttwrk built with `--features websocket`",
+        )),
+    }
+}
+
+/// Tls configuration of `--tls-backend` for connection `id`, verifying the
+/// broker with `ca`
+fn tls_config(config: &BenchConfig, id: &str, ca: Vec<u8>) -> io::Result<TlsConfiguration> {
+    let client_auth = client_auth(config, id)?;
+    let alpn = (!config.alpn.is_empty()).then(|| {
+        config
+            .alpn
+            .iter()
+            .map(|alpn| alpn.clone().into_bytes())
+            .collect()
+    });
+    match (config.tls_backend, &config.tls_server_name) {
+        (TlsBackend::Rustls, Some(server_name)) => {
+            let mut tls = tls::config(&ca, client_auth, server_name)?;
+            tls.alpn_protocols = alpn.unwrap_or_default();
+            Ok(TlsConfiguration::Rustls(Arc::new(tls)))
+        }
+        (TlsBackend::Rustls, None) => Ok(TlsConfiguration::Simple {
+            ca,
+            alpn,
+            client_auth,
+        }),
+        (TlsBackend::NativeTls, _) if alpn.is_some() => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--alpn only supports the rustls tls backend",
+        )),
+        (TlsBackend::NativeTls, Some(_)) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--tls-server-name only supports the rustls tls backend",
+        )),
+        (TlsBackend::NativeTls, None) if client_auth.is_some() => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "client certificates only support the rustls tls backend",
+        )),
+        (TlsBackend::NativeTls, None) => Ok(TlsConfiguration::SimpleNative {
+            ca,
+            client_auth: None,
+        }),
+    }
+}
+
+/// Pem encoded certificate and key that connection `id` authenticates with,
+/// preferring the ones of its row in `--credentials-file`
+fn client_auth(config: &BenchConfig, id: &str) -> io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let row = config
+        .credentials_file
+        .as_ref()
+        .and_then(|file| file.get(id))
+        .and_then(|row| row.identity.as_ref());
+    let (cert, key) = match (row, &config.client_cert, &config.client_key) {
+        (Some((cert, key)), _, _) => (PathBuf::from(cert), PathBuf::from(key)),
+        (None, Some(cert), Some(key)) => (identity(cert, id, "pem"), identity(key, id, "key")),
+        _ => return Ok(None),
+    };
+
+    let read = |path: PathBuf| {
+        fs::read(&path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    };
+    Ok(Some((read(cert)?, read(key)?)))
+}
+
+/// File of connection `id`. `{id}` in `path` is replaced by the id, and a
+/// directory holds an `{id}.{extension}` file for every connection
+fn identity(path: &str, id: &str, extension: &str) -> PathBuf {
+    if path.contains("{id}") {
+        return PathBuf::from(path.replace("{id}", id));
+    }
+
+    let path = PathBuf::from(path);
+    if path.is_dir() {
+        path.join(format!("{id}.{extension}"))
+    } else {
+        path
+    }
+}
+
+/// Requests `--ws-protocol` instead of the default `mqtt` sub-protocol and
+/// adds `--ws-header`s to the upgrade request. Secure websockets connect to
+/// `--tls-server-name` in the url, which is what their sni is taken from
+#[cfg(feature = "websocket")]
+fn ws_request(
+    config: &BenchConfig,
+    endpoint: &Endpoint,
+) -> impl Fn(http::Request<()>) -> future::Ready<http::Request<()>> + Send + Sync + 'static {
+    let protocol = config.ws_protocol.clone();
+    let headers = config.ws_headers.clone();
+    let uri = match (config.transport, &config.tls_server_name) {
+        (crate::Transport::Wss, Some(server_name)) => Some(format!(
+            "wss://{}:{}{}",
+            server_name,
+            endpoint.port.unwrap_or(config.port),
+            config.ws_path
+        )),
+        _ => None,
+    };
+    move |mut request| {
+        if let Some(uri) = &uri {
+            match uri.parse() {
+                Ok(uri) => *request.uri_mut() = uri,
+                Err(e) => error!("Invalid websocket url {} = {:?}", uri, e),
+            }
+        }
+
+        let protocol = ("Sec-WebSocket-Protocol", protocol.as_str());
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()));
+        for (name, value) in std::iter::once(protocol).chain(headers) {
+            let name = match http::header::HeaderName::from_bytes(name.as_bytes()) {
+                Ok(name) => name,
+                Err(e) => {
+                    error!("Invalid websocket header name {} = {:?}", name, e);
+                    continue;
+                }
+            };
+            match value.parse() {
+                Ok(value) => {
+                    request.headers_mut().insert(name, value);
+                }
+                Err(e) => error!("Invalid websocket header value {} = {:?}", value, e),
+            }
+        }
+
+        future::ready(request)
+    }
+}
+
+/// Connects a v5 client and waits for the broker to accept the connection
+pub(crate) async fn v5_connect(
+    options: v5::MqttOptions,
+) -> anyhow::Result<(v5::AsyncClient, v5::EventLoop, ConnAck)> {
+    let (client, mut eventloop) = v5::AsyncClient::new(options, 10);
+    loop {
+        match eventloop.poll().await? {
+            v5::Event::Incoming(Packet::ConnAck(connack)) => {
+                return Ok((client, eventloop, connack))
+            }
+            v5::Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            v5::Event::Outgoing(_) => {}
+        }
+    }
+}
+
+/// Disconnects a v5 client gracefully, so that the broker keeps its session
+pub(crate) async fn v5_disconnect(
+    client: &v5::AsyncClient,
+    eventloop: &mut v5::EventLoop,
+) -> anyhow::Result<()> {
+    client.try_disconnect()?;
+    loop {
+        if let v5::Event::Outgoing(Outgoing::Disconnect) = eventloop.poll().await? {
+            return Ok(());
+        }
+    }
+}
+
+/// Publishes `count` QoS 1 publishes to `topic` on a fresh v5 connection and
+/// waits until the broker acked all of them
+pub(crate) async fn v5_publish(
+    config: &BenchConfig,
+    id: &str,
+    topic: &'static str,
+    count: usize,
+    properties: Option<PublishProperties>,
+) -> anyhow::Result<()> {
+    let (client, mut eventloop, _) = v5_connect(v5_options(config, id)?).await?;
+    let requests = task::spawn({
+        let client = client.clone();
+        let payload = Bytes::from(payload::generate(config.payload_size, false, None));
+        async move {
+            for _ in 0..count {
+                match &properties {
+                    Some(properties) => {
+                        client
+                            .publish_with_properties(
+                                topic,
+                                v5::mqttbytes::QoS::AtLeastOnce,
+                                false,
+                                payload.clone(),
+                                properties.clone(),
+                            )
+                            .await?
+                    }
+                    None => {
+                        client
+                            .publish(
+                                topic,
+                                v5::mqttbytes::QoS::AtLeastOnce,
+                                false,
+                                payload.clone(),
+                            )
+                            .await?
+                    }
+                }
+            }
+
+            Ok::<_, v5::ClientError>(())
+        }
+    });
+
+    let timeout = config.conn_timeout.max(config.receive_timeout);
+    let mut acks = 0;
+    while acks < count {
+        match time::timeout(timeout, eventloop.poll()).await {
+            Ok(Ok(v5::Event::Incoming(Packet::PubAck(_)))) => acks += 1,
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => anyhow::bail!("Id = {}, {} of {} publishes acked", id, acks, count),
+        }
+    }
+
+    requests.await??;
+    v5_disconnect(&client, &mut eventloop).await
+}
+
+/// Seed of the `{random}` part of client ids. Random parts derive from it and
+/// the index of the client, so that subscribers know the topics of publishers
+static CLIENT_ID_SEED: Lazy<u64> = Lazy::new(rand::random);
+
+static HOSTNAME: Lazy<String> =
+    Lazy::new(|| whoami::fallible::hostname().unwrap_or_else(|_| "unknown".to_owned()));
+
+pub(crate) fn publisher_id(config: &BenchConfig, i: usize) -> String {
+    match &config.client_id_template {
+        Some(template) => client_id(template, i),
+        None => format!("pub-{i:05}"),
+    }
+}
+
+pub(crate) fn subscriber_id(config: &BenchConfig, i: usize) -> String {
+    match &config.client_id_template {
+        Some(template) => client_id(template, config.publishers + i),
+        None => format!("sub-{i:05}"),
+    }
+}
+
+/// Client id of the client at `index` of `--client-id-template`
+fn client_id(template: &str, index: usize) -> String {
+    let random = StdRng::seed_from_u64(*CLIENT_ID_SEED ^ index as u64).gen::<u32>();
+    template
+        .replace("{index}", &format!("{index:05}"))
+        .replace("{random}", &format!("{random:08x}"))
+        .replace("{hostname}", &HOSTNAME)
+}
+
+/// Publishers every subscriber receives the publishes of, only the first one
+/// with `--fan-out`
+pub(crate) fn subscribed_publishers(config: &BenchConfig) -> usize {
+    if config.fan_out {
+        config.publishers.min(1)
+    } else {
+        config.publishers
+    }
+}
+
+/// Publishes of all the publishers every subscriber receives the publishes of
+pub(crate) fn subscribed_publishes(config: &BenchConfig) -> usize {
+    if config.groups.is_none() {
+        return config.count * subscribed_publishers(config);
+    }
+
+    (0..config.publishers)
+        .filter(|&i| subscribed(config, i))
+        .map(|i| groups::count(config, i))
+        .sum()
+}
+
+/// Whether subscribers subscribe to the topic of publisher `i`. Groups with
+/// topics of their own might not match the filter of subscribers
+pub(crate) fn subscribed(config: &BenchConfig, i: usize) -> bool {
+    config.groups.is_none()
+        || wildcard::matches(&shared::filter(config), &publisher_topic(config, i))
+}
+
+/// Largest packet mqtt allows, the fixed header and a remaining length of
+/// 256 MiB
+const MAX_PACKET_SIZE: usize = 5 + 268_435_455;
+
+/// Topic that every publisher publishes to with `--fan-in`
+pub(crate) const FAN_IN_TOPIC: &str = "hello/fan-in/world";
+
+/// Topic that the publisher with `id` publishes to
+pub(crate) fn topic(id: &str) -> String {
+    format!("hello/{id}/world")
+}
+
+/// Topic that publisher `i` publishes to, given the layout of the run
+pub(crate) fn publisher_topic(config: &BenchConfig, i: usize) -> String {
+    let id = publisher_id(config, i);
+    match (config.profile, config.topics) {
+        (Some(Profile::AzureIotHub), _) => azure::topic(&id),
+        _ if config.fan_in => FAN_IN_TOPIC.to_owned(),
+        (_, Some(topics)) => matrix::topic(i % topics as usize),
+        _ if config.topic_depth > 0 => wildcard::topic(&id, i, config.topic_depth),
+        _ => groups::topic(config, i, &id).unwrap_or_else(|| topic(&id)),
+    }
+}
+
 /// get QoS level. Default is AtLeastOnce.
 fn get_qos(qos: i16) -> QoS {
     match qos {
         0 => QoS::AtMostOnce,
         1 => QoS::AtLeastOnce,
+        2 => QoS::ExactlyOnce,
         _ => QoS::AtLeastOnce,
     }
 }
@@ -0,0 +1,202 @@
+//! Exports per second samples as OpenTelemetry gauges and, optionally, spans
+//! for the connect and publish phases of a run. Uses OTLP over http with json
+//! encoding, so any collector listening on the http port (4318) accepts them
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use tokio::task;
+
+use crate::{
+    bench::{metrics::Sample, reporter::Reporter},
+    BenchConfig,
+};
+
+#[derive(Clone)]
+pub struct Otlp {
+    client: reqwest::Client,
+    endpoint: String,
+    resource: Value,
+}
+
+/// A phase of the run, exported as a child span of the whole run
+pub struct Phase {
+    pub name: &'static str,
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+impl Otlp {
+    pub fn new(endpoint: &str) -> Otlp {
+        let host = whoami::fallible::hostname().unwrap_or_else(|_| "unknown".to_owned());
+        Otlp {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            resource: json!({
+                "attributes": [
+                    attribute("service.name", json!({ "stringValue": "mqttwrk" })),
+                    attribute("host.name", json!({ "stringValue": host })),
+                ]
+            }),
+        }
+    }
+
+    pub async fn push(&self, sample: &Sample) -> Result<(), reqwest::Error> {
+        let time = nanos(sample.timestamp);
+        let int = |name: &str, unit: &str, value: i64| {
+            gauge(
+                name,
+                unit,
+                json!({ "timeUnixNano": time, "asInt": value.to_string() }),
+            )
+        };
+        let double = |name: &str, unit: &str, value: f64| {
+            gauge(
+                name,
+                unit,
+                json!({ "timeUnixNano": time, "asDouble": value }),
+            )
+        };
+
+        let mut metrics = vec![
+            int("mqttwrk.connections", "{connection}", sample.connections),
+            int("mqttwrk.inflight", "{publish}", sample.inflight),
+            int(
+                "mqttwrk.reconnects",
+                "{reconnect}",
+                sample.reconnects as i64,
+            ),
+            double("mqttwrk.outgoing_rate", "{publish}/s", sample.outgoing_rate),
+            double("mqttwrk.incoming_rate", "{publish}/s", sample.incoming_rate),
+            double("mqttwrk.ack_rate", "{ack}/s", sample.ack_rate),
+        ];
+
+        for (name, latencies) in [
+            ("mqttwrk.ack_latency", &sample.ack_latencies),
+            ("mqttwrk.latency", &sample.latencies),
+        ] {
+            if latencies.samples == 0 {
+                continue;
+            }
+
+            for (percentile, value) in [
+                ("p50", latencies.p50),
+                ("p90", latencies.p90),
+                ("p99", latencies.p99),
+                ("max", latencies.max),
+            ] {
+                metrics.push(double(&format!("{name}.{percentile}"), "ms", value));
+            }
+        }
+
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": self.resource,
+                "scopeMetrics": [{ "scope": { "name": "mqttwrk" }, "metrics": metrics }]
+            }]
+        });
+
+        self.send("/v1/metrics", &body).await
+    }
+
+    /// Exports one trace with a span for the whole run and a child span for
+    /// every phase
+    pub async fn trace(
+        &self,
+        config: &BenchConfig,
+        start: SystemTime,
+        end: SystemTime,
+        phases: &[Phase],
+    ) -> Result<(), reqwest::Error> {
+        let trace_id = hex(&rand::random::<[u8; 16]>());
+        let root_id = hex(&rand::random::<[u8; 8]>());
+
+        let mut spans = vec![json!({
+            "traceId": trace_id,
+            "spanId": root_id,
+            "name": "bench",
+            "kind": 1,
+            "startTimeUnixNano": nanos(start),
+            "endTimeUnixNano": nanos(end),
+            "attributes": [
+                int_attribute("mqttwrk.publishers", config.publishers),
+                int_attribute("mqttwrk.subscribers", config.subscribers),
+                int_attribute("mqttwrk.count", config.count),
+                int_attribute("mqttwrk.publish_qos", config.publish_qos),
+                int_attribute("mqttwrk.payload_size", config.payload_size),
+            ]
+        })];
+
+        for phase in phases {
+            spans.push(json!({
+                "traceId": trace_id,
+                "spanId": hex(&rand::random::<[u8; 8]>()),
+                "parentSpanId": root_id,
+                "name": phase.name,
+                "kind": 1,
+                "startTimeUnixNano": nanos(phase.start),
+                "endTimeUnixNano": nanos(phase.end),
+            }));
+        }
+
+        let body = json!({
+            "resourceSpans": [{
+                "resource": self.resource,
+                "scopeSpans": [{ "scope": { "name": "mqttwrk" }, "spans": spans }]
+            }]
+        });
+
+        self.send("/v1/traces", &body).await
+    }
+
+    async fn send(&self, path: &str, body: &Value) -> Result<(), reqwest::Error> {
+        self.client
+            .post(format!("{}{}", self.endpoint, path))
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+impl Reporter for Otlp {
+    fn name(&self) -> &'static str {
+        "otlp"
+    }
+
+    fn sample(&mut self, sample: &Sample) {
+        let otlp = self.clone();
+        let sample = sample.clone();
+        task::spawn(async move {
+            if let Err(e) = otlp.push(&sample).await {
+                warn!("Failed to export sample to otlp = {:?}", e);
+            }
+        });
+    }
+}
+
+fn gauge(name: &str, unit: &str, point: Value) -> Value {
+    json!({ "name": name, "unit": unit, "gauge": { "dataPoints": [point] } })
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn int_attribute(key: &str, value: impl ToString) -> Value {
+    attribute(key, json!({ "intValue": value.to_string() }))
+}
+
+/// Nanoseconds since unix epoch. 64 bit integers are strings in OTLP json
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,62 @@
+//! Tracks periods during which a connection was down, from the first error of
+//! the event loop until the broker accepts the connection again
+
+use std::time::SystemTime;
+
+use crate::common::Outage;
+
+#[derive(Debug, Default)]
+pub struct Outages {
+    /// when the ongoing outage started
+    current: Option<SystemTime>,
+    outages: Vec<Outage>,
+}
+
+impl Outages {
+    /// Marks the connection as down. Returns true if it was up until now
+    pub fn disconnected(&mut self) -> bool {
+        if self.current.is_some() {
+            return false;
+        }
+
+        self.current = Some(SystemTime::now());
+        true
+    }
+
+    /// Marks the connection as up again. Returns true if it was down until now
+    pub fn reconnected(&mut self) -> bool {
+        let disconnected_at = match self.current.take() {
+            Some(disconnected_at) => disconnected_at,
+            None => return false,
+        };
+
+        let reconnected_at = SystemTime::now();
+        self.outages.push(Outage {
+            disconnected_at,
+            reconnected_at: Some(reconnected_at),
+            downtime: reconnected_at
+                .duration_since(disconnected_at)
+                .unwrap_or_default(),
+        });
+        true
+    }
+
+    /// Whether the connection is down right now
+    pub fn ongoing(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// All outages of the connection. An outage that is still ongoing lasts
+    /// until now and has no reconnection time
+    pub fn finish(mut self) -> Vec<Outage> {
+        if let Some(disconnected_at) = self.current.take() {
+            self.outages.push(Outage {
+                disconnected_at,
+                reconnected_at: None,
+                downtime: disconnected_at.elapsed().unwrap_or_default(),
+            });
+        }
+
+        self.outages
+    }
+}
@@ -0,0 +1,311 @@
+//! Pacing of publishes and connections at a target rate
+
+use std::{
+    f64::consts::PI,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use tokio::time;
+
+use crate::{Arrival, BenchConfig, Pattern};
+
+/// Token bucket that fills up at a rate and that every publish or connection
+/// drains by one. The bucket has no capacity, so that whatever fell behind
+/// catches up with the schedule instead of shifting it
+pub(crate) struct Pacer {
+    shape: Shape,
+    arrival: Arrival,
+    /// when the bucket started filling up
+    start: Instant,
+    /// time since `start` at which the next token is due
+    next: Duration,
+    /// tokens drained since `start`
+    drained: u64,
+}
+
+/// How the rate changes over time
+enum Shape {
+    /// a token every interval
+    Constant(Duration),
+    /// tokens per second changing linearly from one rate to another, and
+    /// staying at that one afterwards
+    Ramp { from: f64, to: f64, over: Duration },
+    /// as many tokens as the size at once every interval
+    Burst { size: u64, interval: Duration },
+    /// tokens per second following a sine wave from the low to the peak and
+    /// back every period
+    Sine {
+        low: f64,
+        peak: f64,
+        period: Duration,
+    },
+    /// tokens per second of every step until its end, none during steps of 0
+    Steps(Vec<(Duration, f64)>),
+}
+
+impl Pacer {
+    /// Pacer of `rate` publishes per second, unless `rate` is 0
+    pub(crate) fn new(rate: u64) -> Option<Pacer> {
+        (rate != 0).then(|| Pacer::every(Duration::from_secs_f64(1.0 / rate as f64)))
+    }
+
+    /// Pacer of publishes `interval` apart
+    pub(crate) fn every(interval: Duration) -> Pacer {
+        Pacer::with_shape(Shape::Constant(interval))
+    }
+
+    /// Pacer of a rate that changes linearly from `from` to `to` per second
+    /// over `over`
+    pub(crate) fn ramp(from: f64, to: f64, over: Duration) -> Pacer {
+        Pacer::with_shape(Shape::Ramp { from, to, over })
+    }
+
+    /// Pacer of `size` publishes at once every `interval`
+    pub(crate) fn bursts(size: u64, interval: Duration) -> Pacer {
+        Pacer::with_shape(Shape::Burst { size, interval })
+    }
+
+    /// Pacer of a rate between `low` and `peak` per second, starting at the
+    /// low and peaking half way through every `period`
+    pub(crate) fn sine(low: f64, peak: f64, period: Duration) -> Pacer {
+        Pacer::with_shape(Shape::Sine { low, peak, period })
+    }
+
+    /// Pacer of rates per second that change in steps, each until its end
+    /// since the start
+    pub(crate) fn steps(steps: Vec<(Duration, f64)>) -> Pacer {
+        Pacer {
+            next: active(&steps, Duration::ZERO),
+            ..Pacer::with_shape(Shape::Steps(steps))
+        }
+    }
+
+    fn with_shape(shape: Shape) -> Pacer {
+        Pacer {
+            shape,
+            arrival: Arrival::Fixed,
+            start: Instant::now(),
+            next: Duration::ZERO,
+            drained: 0,
+        }
+    }
+
+    /// Spreads tokens over time as `arrival` says, at the same rate on average
+    pub(crate) fn arrival(mut self, arrival: Arrival) -> Pacer {
+        self.arrival = arrival;
+        self
+    }
+
+    /// Waits for a token. Returns when it was due
+    pub(crate) async fn wait(&mut self) -> Instant {
+        let due = self.start + self.next;
+        let interval = self.interval(self.next);
+        self.drained += 1;
+        self.next += match self.arrival {
+            Arrival::Fixed => interval,
+            // inverse transform sampling of an exponential distribution
+            Arrival::Poisson => interval.mul_f64(-(1.0 - rand::thread_rng().gen::<f64>()).ln()),
+        };
+        time::sleep_until(due.into()).await;
+        due
+    }
+
+    /// Time until the token after the one due at `at`
+    fn interval(&self, at: Duration) -> Duration {
+        match &self.shape {
+            Shape::Constant(interval) => *interval,
+            Shape::Ramp { from, to, over } => {
+                let progress = (at.as_secs_f64() / over.as_secs_f64()).min(1.0);
+                Duration::from_secs_f64(1.0 / (from + (to - from) * progress))
+            }
+            // the token due at `at` is yet to be drained
+            Shape::Burst { size, interval } => match (self.drained + 1) % size {
+                0 => *interval,
+                _ => Duration::ZERO,
+            },
+            Shape::Sine { low, peak, period } => {
+                let phase = at.as_secs_f64() / period.as_secs_f64() * 2.0 * PI;
+                Duration::from_secs_f64(1.0 / (low + (peak - low) * (1.0 - phase.cos()) / 2.0))
+            }
+            Shape::Steps(steps) => {
+                let Some(&(end, rate)) = steps.iter().find(|(end, _)| at < *end) else {
+                    return Duration::ZERO;
+                };
+
+                // the next step starts at its own rate rather than the one
+                // of the token before it
+                let next = match rate {
+                    rate if rate > 0.0 => (at + Duration::from_secs_f64(1.0 / rate)).min(end),
+                    _ => end,
+                };
+                active(steps, next) - at
+            }
+        }
+    }
+}
+
+/// The first time from `at` on that isn't during a step of 0
+fn active(steps: &[(Duration, f64)], mut at: Duration) -> Duration {
+    while let Some(&(end, _)) = steps
+        .iter()
+        .find(|(end, _)| at < *end)
+        .filter(|(_, rate)| *rate == 0.0)
+    {
+        at = end;
+    }
+
+    at
+}
+
+/// Pacer of establishing the connections of a run, at `--connect-rate` or
+/// over `--ramp-up`
+pub(crate) fn connect_pacer(config: &BenchConfig) -> Option<Pacer> {
+    let connections = (config.publishers + config.subscribers).max(1) as u32;
+    match (config.connect_rate, config.ramp_up) {
+        (Some(rate), _) => Pacer::new(rate),
+        (None, Some(ramp_up)) => Some(Pacer::every(ramp_up / connections)),
+        (None, None) => None,
+    }
+}
+
+/// Pacer of every publisher of a run, at `--rate`, `--delay` apart, at its
+/// share of `--rate-ramp`, `--pattern` or `--schedule`, or in bursts
+pub(crate) fn pacer(config: &BenchConfig) -> Option<Pacer> {
+    let publishers = config.publishers.max(1) as f64;
+    if let Some(schedule) = &config.schedule {
+        let steps = schedule
+            .bounds()
+            .zip(&schedule.phases)
+            .map(|((_, end), phase)| (end, phase.rate as f64 / publishers))
+            .collect();
+        return Some(Pacer::steps(steps).arrival(config.arrival));
+    }
+
+    if let (Some(size), Some(interval)) = (config.burst_size, config.burst_interval) {
+        return Some(Pacer::bursts(size, interval));
+    }
+
+    let pacer = match (config.pattern, &config.rate_ramp, config.delay) {
+        (Some(Pattern::Sine), ..) => Some(Pacer::sine(
+            config.min_rate.unwrap_or(1) as f64 / publishers,
+            config.max_rate.unwrap_or(1) as f64 / publishers,
+            config.period.unwrap_or(Duration::from_secs(3600)),
+        )),
+        (None, Some(ramp), _) => Some(Pacer::ramp(
+            ramp.from as f64 / publishers,
+            ramp.to as f64 / publishers,
+            ramp.over,
+        )),
+        (None, None, Some(delay)) => Some(Pacer::every(delay)),
+        (None, None, None) => Pacer::new(config.rate),
+    };
+
+    pacer.map(|pacer| pacer.arrival(config.arrival))
+}
+
+/// Random offset within `--start-jitter` at which a publisher starts after all
+/// are connected
+pub(crate) fn start_offset(config: &BenchConfig) -> Duration {
+    match &config.start_jitter {
+        Some(jitter) => rand::thread_rng().gen_range(jitter.min..=jitter.max),
+        None => Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{active, Pacer};
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    /// 2/s for 10s, a pause until 20s, 4/s until 30s
+    fn steps() -> Vec<(Duration, f64)> {
+        vec![(secs(10.0), 2.0), (secs(20.0), 0.0), (secs(30.0), 4.0)]
+    }
+
+    #[test]
+    fn active_skips_pauses() {
+        let steps = steps();
+
+        assert_eq!(active(&steps, secs(0.0)), secs(0.0));
+        assert_eq!(active(&steps, secs(9.5)), secs(9.5));
+        assert_eq!(active(&steps, secs(10.0)), secs(20.0));
+        assert_eq!(active(&steps, secs(15.0)), secs(20.0));
+        assert_eq!(active(&steps, secs(35.0)), secs(35.0));
+    }
+
+    #[test]
+    fn active_skips_back_to_back_pauses() {
+        let steps = vec![(secs(5.0), 0.0), (secs(10.0), 0.0), (secs(20.0), 1.0)];
+
+        assert_eq!(active(&steps, secs(0.0)), secs(10.0));
+        assert_eq!(active(&steps, secs(7.0)), secs(10.0));
+    }
+
+    #[test]
+    fn steps_start_after_a_leading_pause() {
+        let pacer = Pacer::steps(vec![(secs(5.0), 0.0), (secs(10.0), 1.0)]);
+        assert_eq!(pacer.next, secs(5.0));
+
+        let pacer = Pacer::steps(steps());
+        assert_eq!(pacer.next, secs(0.0));
+    }
+
+    #[test]
+    fn steps_pace_at_the_rate_of_their_step() {
+        let pacer = Pacer::steps(steps());
+
+        assert_eq!(pacer.interval(secs(0.0)), secs(0.5));
+        // the last token of a step is followed by the start of the next
+        // active step
+        assert_eq!(pacer.interval(secs(9.75)), secs(10.25));
+        assert_eq!(pacer.interval(secs(20.0)), secs(0.25));
+        assert_eq!(pacer.interval(secs(29.75)), secs(0.25));
+        // the schedule is over
+        assert_eq!(pacer.interval(secs(30.0)), Duration::ZERO);
+    }
+
+    #[test]
+    fn bursts_wait_after_the_last_token_of_a_burst() {
+        let mut pacer = Pacer::bursts(3, secs(1.0));
+
+        let intervals: Vec<_> = (0..6)
+            .map(|_| {
+                let interval = pacer.interval(Duration::ZERO);
+                pacer.drained += 1;
+                interval
+            })
+            .collect();
+        assert_eq!(
+            intervals,
+            [
+                Duration::ZERO,
+                Duration::ZERO,
+                secs(1.0),
+                Duration::ZERO,
+                Duration::ZERO,
+                secs(1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn ramps_stay_at_the_last_rate() {
+        let pacer = Pacer::ramp(1.0, 3.0, secs(10.0));
+
+        assert_eq!(pacer.interval(secs(0.0)), secs(1.0));
+        assert_eq!(pacer.interval(secs(5.0)), secs(0.5));
+        assert_eq!(pacer.interval(secs(20.0)), secs(1.0 / 3.0));
+    }
+
+    #[test]
+    fn zero_rates_are_unpaced() {
+        assert!(Pacer::new(0).is_none());
+        assert_eq!(Pacer::new(4).unwrap().interval(Duration::ZERO), secs(0.25));
+    }
+}
@@ -0,0 +1,261 @@
+//! Mqtt 5 maximum packet size. With `--max-packet-size`, a v5 subscriber
+//! connects with that maximum packet size and a publisher alternates between
+//! the largest publishes that fit and ones a byte too large, which the broker
+//! has to drop instead of sending them. With `--probe-packet-size`, a
+//! publisher publishes increasingly large payloads, each on a fresh
+//! connection, to find the largest publish the broker accepts regardless of
+//! the maximum it advertises
+
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use rumqttc::v5::{
+    mqttbytes::{
+        v5::{Packet, PubAckReason},
+        QoS,
+    },
+    AsyncClient, ConnectionError, Event, EventLoop, StateError,
+};
+use serde::Serialize;
+use tokio::{task, time};
+
+use crate::{
+    bench::{payload, v5_connect, v5_disconnect, v5_options},
+    BenchConfig,
+};
+
+const TOPIC: &str = "hello/packetsize/world";
+const CONTROL_TOPIC: &str = "hello/packetsize/control";
+const PUBLISHER: &str = "mqttwrk-packetsize";
+const SUBSCRIBER: &str = "mqttwrk-packetsize-sub";
+const PROBE: &str = "mqttwrk-packetsize-probe";
+/// Smallest payload of the probe, doubled until the broker rejects it
+const PROBE_START: usize = 1024;
+
+#[derive(Debug, Serialize)]
+pub struct MaxPacketSizeReport {
+    pub max_packet_size: u32,
+    /// publishes that fit the maximum, and as many that are a byte too large
+    pub published: usize,
+    /// publishes that fit and were delivered
+    pub received: usize,
+    /// packets over the maximum the broker sent
+    pub oversized: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PacketSizeProbeReport {
+    /// largest payload probed
+    pub limit: usize,
+    /// maximum packet size of the broker's connack
+    pub advertised: Option<u32>,
+    /// largest publish packet the broker accepted
+    pub largest_accepted: Option<usize>,
+    /// smallest publish packet the broker rejected
+    pub smallest_rejected: Option<usize>,
+    pub attempts: usize,
+}
+
+pub(crate) async fn verify(config: Arc<BenchConfig>) -> anyhow::Result<MaxPacketSizeReport> {
+    let max_packet_size = config.max_packet_size.unwrap_or(u32::MAX);
+    let fitting = (0..max_packet_size as usize)
+        .rev()
+        .find(|&payload| packet_size(TOPIC, payload) <= max_packet_size as usize)
+        .unwrap_or(0);
+
+    let (publisher, mut eventloop, connack) = v5_connect(v5_options(&config, PUBLISHER)?).await?;
+    let advertised = connack.properties.and_then(|p| p.max_packet_size);
+    if let Some(advertised) = advertised.filter(|&a| a <= max_packet_size) {
+        anyhow::bail!(
+            "Broker accepts packets up to {} bytes, use a smaller --max-packet-size",
+            advertised
+        );
+    }
+
+    let mut options = v5_options(&config, SUBSCRIBER)?;
+    options.set_max_packet_size(Some(max_packet_size));
+    let (subscriber, mut subscriber_loop, _) = v5_connect(options).await?;
+    subscriber
+        .subscribe("hello/packetsize/+", QoS::AtLeastOnce)
+        .await?;
+    loop {
+        match subscriber_loop.poll().await? {
+            Event::Incoming(Packet::SubAck(_)) => break,
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+    let received = task::spawn(receive(subscriber_loop, fitting, config.receive_timeout));
+
+    let payloads = [
+        Bytes::from(payload::generate(fitting, false, None)),
+        Bytes::from(payload::generate(fitting + 1, false, None)),
+    ];
+    for i in 0..config.count * 2 {
+        publish(
+            &publisher,
+            &mut eventloop,
+            &config,
+            TOPIC,
+            payloads[i % 2].clone(),
+        )
+        .await?;
+    }
+    publish(
+        &publisher,
+        &mut eventloop,
+        &config,
+        CONTROL_TOPIC,
+        Bytes::new(),
+    )
+    .await?;
+    v5_disconnect(&publisher, &mut eventloop).await?;
+
+    let (received, oversized, subscriber_loop) = received.await??;
+    if let Some(mut subscriber_loop) = subscriber_loop {
+        v5_disconnect(&subscriber, &mut subscriber_loop).await?;
+    }
+
+    Ok(MaxPacketSizeReport {
+        max_packet_size,
+        published: config.count,
+        received,
+        oversized,
+    })
+}
+
+pub(crate) async fn probe(config: Arc<BenchConfig>) -> anyhow::Result<PacketSizeProbeReport> {
+    let limit = config.probe_packet_size.unwrap_or(PROBE_START);
+    let (client, mut eventloop, connack) = v5_connect(v5_options(&config, PROBE)?).await?;
+    let advertised = connack.properties.and_then(|p| p.max_packet_size);
+    v5_disconnect(&client, &mut eventloop).await?;
+
+    // doubles the payload until the broker rejects it, then bisects
+    let mut accepted = None;
+    let mut rejected = None;
+    let mut attempts = 0;
+    let mut size = PROBE_START.min(limit);
+    loop {
+        attempts += 1;
+        if attempt(&config, size).await? {
+            accepted = Some(size);
+        } else {
+            rejected = Some(size);
+        }
+
+        let lower = accepted.unwrap_or(0);
+        size = match rejected {
+            Some(upper) if upper - lower > 1 => lower + (upper - lower) / 2,
+            Some(_) => break,
+            None if lower < limit => (lower * 2).min(limit),
+            None => break,
+        };
+    }
+
+    Ok(PacketSizeProbeReport {
+        limit,
+        advertised,
+        largest_accepted: accepted.map(|payload| packet_size(TOPIC, payload)),
+        smallest_rejected: rejected.map(|payload| packet_size(TOPIC, payload)),
+        attempts,
+    })
+}
+
+/// Publishes `payload` bytes on a fresh connection. Returns whether the broker
+/// acked it rather than rejecting it or dropping the connection
+async fn attempt(config: &BenchConfig, payload: usize) -> anyhow::Result<bool> {
+    let (client, mut eventloop, _) = v5_connect(v5_options(config, PROBE)?).await?;
+    // rumqttc refuses to send publishes over the advertised maximum
+    eventloop.state.max_outgoing_packet_size = None;
+
+    let payload = Bytes::from(payload::generate(payload, false, None));
+    client
+        .publish(TOPIC, QoS::AtLeastOnce, false, payload)
+        .await?;
+    let accepted = loop {
+        match time::timeout(config.receive_timeout, eventloop.poll()).await {
+            Ok(Ok(Event::Incoming(Packet::PubAck(ack)))) => {
+                break matches!(
+                    ack.reason,
+                    PubAckReason::Success | PubAckReason::NoMatchingSubscribers
+                )
+            }
+            // brokers drop connections with publishes over their maximum
+            Ok(Ok(Event::Incoming(Packet::Disconnect(_)))) | Ok(Err(_)) | Err(_) => {
+                return Ok(false)
+            }
+            Ok(Ok(_)) => {}
+        }
+    };
+
+    v5_disconnect(&client, &mut eventloop).await?;
+    Ok(accepted)
+}
+
+/// Publishes with QoS 1 and waits for the broker to ack
+async fn publish(
+    client: &AsyncClient,
+    eventloop: &mut EventLoop,
+    config: &BenchConfig,
+    topic: &str,
+    payload: Bytes,
+) -> anyhow::Result<()> {
+    client
+        .publish(topic, QoS::AtLeastOnce, false, payload)
+        .await?;
+    loop {
+        match time::timeout(config.receive_timeout, eventloop.poll()).await {
+            Ok(Ok(Event::Incoming(Packet::PubAck(_)))) => return Ok(()),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => anyhow::bail!("Id = {}, Publish to {} wasn't acked", PUBLISHER, topic),
+        }
+    }
+}
+
+/// Receives until the control publish. Returns the publishes that fit and
+/// the packets over the maximum received, along with the eventloop unless a
+/// packet over the maximum ended the connection. rumqttc only drops packets
+/// whose remaining length is over the maximum, so publishes with a payload
+/// that doesn't fit are told apart by their size
+async fn receive(
+    mut eventloop: EventLoop,
+    fitting: usize,
+    timeout: Duration,
+) -> anyhow::Result<(usize, usize, Option<EventLoop>)> {
+    let (mut received, mut oversized) = (0, 0);
+    loop {
+        let event = match time::timeout(timeout, eventloop.poll()).await {
+            Ok(Ok(event)) => event,
+            Ok(Err(ConnectionError::MqttState(StateError::IncomingPacketTooLarge { .. }))) => {
+                return Ok((received, oversized + 1, None))
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => anyhow::bail!("Id = {}, Timed out waiting for broker", SUBSCRIBER),
+        };
+
+        if let Event::Incoming(Packet::Publish(publish)) = event {
+            if publish.topic == CONTROL_TOPIC {
+                return Ok((received, oversized, Some(eventloop)));
+            }
+            if publish.payload.len() > fitting {
+                oversized += 1;
+            } else {
+                received += 1;
+            }
+        }
+    }
+}
+
+/// Size of a QoS 1 publish packet without properties
+fn packet_size(topic: &str, payload: usize) -> usize {
+    // topic, packet identifier, properties length and payload
+    let remaining = 2 + topic.len() + 2 + 1 + payload;
+    let length_bytes = match remaining {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    };
+    1 + length_bytes + remaining
+}
@@ -0,0 +1,205 @@
+//! Payloads published by benchmark publishers. Payloads start with a header that
+//! carries, when tracking is enabled, the time at which the payload was created
+//! (so that subscribers can compute end to end delivery latency) and the sequence
+//! number of the payload among the publishes of its publisher
+//!
+//! | bytes | field                          |
+//! |-------|--------------------------------|
+//! | 0..8  | timestamp, nanoseconds (be)    |
+//! | 8..16 | sequence number (be)           |
+
+use std::{
+    convert::TryInto,
+    f64::consts::TAU,
+    time::{Duration, Instant},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use rumqttc::QoS;
+
+use crate::{BenchConfig, PayloadDistribution};
+
+/// Reference point of embedded timestamps. Publishers and subscribers live in the
+/// same process, so a monotonic clock is enough to compare them
+static EPOCH: Lazy<Instant> = Lazy::new(Instant::now);
+
+const TIMESTAMP: std::ops::Range<usize> = 0..8;
+const SEQUENCE: std::ops::Range<usize> = 8..16;
+
+/// Size on the wire of a mqtt 3.1.1 publish with a `payload` bytes payload to a
+/// topic of `topic` bytes, including fixed and variable headers
+pub(crate) fn publish_size(topic: usize, qos: QoS, payload: usize) -> usize {
+    let pkid = match qos {
+        QoS::AtMostOnce => 0,
+        QoS::AtLeastOnce | QoS::ExactlyOnce => 2,
+    };
+
+    let remaining = 2 + topic + pkid + payload;
+    let remaining_length = match remaining {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    };
+
+    1 + remaining_length + remaining
+}
+
+/// Generates a payload of `size` bytes. With `latency_tracking` or a `sequence`,
+/// payloads are at least big enough to hold the header
+pub(crate) fn generate(size: usize, latency_tracking: bool, sequence: Option<u64>) -> Vec<u8> {
+    let header = match (latency_tracking, sequence) {
+        (_, Some(_)) => SEQUENCE.end,
+        (true, None) => TIMESTAMP.end,
+        (false, None) => 0,
+    };
+
+    let mut payload = vec![0; size.max(header)];
+    if latency_tracking {
+        payload[TIMESTAMP].copy_from_slice(&now().to_be_bytes());
+    }
+
+    if let Some(sequence) = sequence {
+        payload[SEQUENCE].copy_from_slice(&sequence.to_be_bytes());
+    }
+
+    payload
+}
+
+/// Payload sizes of a run. Fixed at `--payload-size`, or with
+/// `--payload-size-min` and `--payload-size-max` drawn for every publish from
+/// `--payload-distribution`, so that the broker allocates buffers of all sorts
+/// of sizes like it would in production
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Sizes {
+    Fixed(usize),
+    Random {
+        min: usize,
+        max: usize,
+        distribution: PayloadDistribution,
+    },
+}
+
+impl Sizes {
+    pub(crate) fn new(config: &BenchConfig) -> Sizes {
+        match (config.payload_size_min, config.payload_size_max) {
+            (Some(min), Some(max)) => Sizes::Random {
+                min,
+                max,
+                distribution: config.payload_distribution,
+            },
+            _ => Sizes::Fixed(config.payload_size),
+        }
+    }
+
+    /// Largest size of the run
+    pub(crate) fn max(&self) -> usize {
+        match *self {
+            Sizes::Fixed(size) => size,
+            Sizes::Random { max, .. } => max,
+        }
+    }
+
+    /// Size of the next payload. Normal and lognormal sizes are centered
+    /// between min and max, with max and min 3 standard deviations away, and
+    /// the few beyond them are clamped
+    fn sample(&self) -> usize {
+        let (min, max, distribution) = match *self {
+            Sizes::Fixed(size) => return size,
+            Sizes::Random { min, max, .. } if min >= max => return min,
+            Sizes::Random {
+                min,
+                max,
+                distribution,
+            } => (min, max, distribution),
+        };
+
+        let mut rng = rand::thread_rng();
+        let size = match distribution {
+            PayloadDistribution::Uniform => return rng.gen_range(min..=max),
+            PayloadDistribution::Normal => {
+                let (min, max) = (min as f64, max as f64);
+                (min + max) / 2.0 + standard_normal(&mut rng) * (max - min) / 6.0
+            }
+            PayloadDistribution::Lognormal => {
+                // at least a byte, logarithms of 0 aren't finite
+                let (min, max) = ((min.max(1) as f64).ln(), (max as f64).ln());
+                ((min + max) / 2.0 + standard_normal(&mut rng) * (max - min) / 6.0).exp()
+            }
+        };
+
+        (size.round() as usize).clamp(min, max)
+    }
+}
+
+/// Normally distributed number with a mean of 0 and a standard deviation of 1,
+/// by the Box-Muller transform
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    // (0, 1], the logarithm of 0 isn't finite
+    let u = 1.0 - rng.gen::<f64>();
+    let v = rng.gen::<f64>();
+    (-2.0 * u.ln()).sqrt() * (TAU * v).cos()
+}
+
+/// Payloads of a run. Payloads without a header are slices of one buffer, and
+/// payloads with one copy it behind their header, so that large payloads
+/// aren't allocated and zeroed from scratch for every publish
+pub(crate) struct Payloads {
+    template: Bytes,
+    sizes: Sizes,
+    latency_tracking: bool,
+}
+
+impl Payloads {
+    pub(crate) fn new(sizes: Sizes, latency_tracking: bool, sequence_tracking: bool) -> Payloads {
+        let sequence = sequence_tracking.then_some(0);
+        Payloads {
+            template: Bytes::from(generate(sizes.max(), latency_tracking, sequence)),
+            sizes,
+            latency_tracking,
+        }
+    }
+
+    /// Payload of the publish with `sequence`, when tracking sequences
+    pub(crate) fn next(&self, sequence: Option<u64>) -> Bytes {
+        let header = match (self.latency_tracking, sequence) {
+            (_, Some(_)) => SEQUENCE.end,
+            (true, None) => TIMESTAMP.end,
+            (false, None) => 0,
+        };
+        // payloads are at least big enough to hold the header, like generated ones
+        let size = self.sizes.sample().max(header);
+        if header == 0 {
+            return self.template.slice(..size);
+        }
+
+        let mut payload = BytesMut::with_capacity(size);
+        payload.put_u64(if self.latency_tracking { now() } else { 0 });
+        if let Some(sequence) = sequence {
+            payload.put_u64(sequence);
+        }
+        payload.put_slice(&self.template[header..size]);
+        payload.freeze()
+    }
+}
+
+/// Time elapsed since the payload was generated. `None` if the payload is too
+/// short to carry a timestamp
+pub(crate) fn latency(payload: &[u8]) -> Option<Duration> {
+    let timestamp = payload.get(TIMESTAMP)?;
+    let timestamp = u64::from_be_bytes(timestamp.try_into().ok()?);
+    Some(Duration::from_nanos(now().saturating_sub(timestamp)))
+}
+
+/// Sequence number of the payload. `None` if the payload is too short to carry
+/// one
+pub(crate) fn sequence(payload: &[u8]) -> Option<u64> {
+    let sequence = payload.get(SEQUENCE)?;
+    Some(u64::from_be_bytes(sequence.try_into().ok()?))
+}
+
+fn now() -> u64 {
+    EPOCH.elapsed().as_nanos() as u64
+}
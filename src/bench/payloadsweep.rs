@@ -0,0 +1,191 @@
+//! Payload size sweep. Once the run is done, a dedicated publisher and
+//! subscriber exchange `--payload-sweep-count` publishes of every size of
+//! `--payload-sweep`, one size after the other, to show how latencies and
+//! throughput degrade as payloads grow and at which size the broker starts
+//! dropping connections. With `--sys-monitor`, the memory the broker reports
+//! under `$SYS` is kept for every size as well
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Instant,
+};
+
+use hdrhistogram::Histogram;
+use rumqttc::{AsyncClient, Event, EventLoop, Incoming, Outgoing, QoS};
+use serde::Serialize;
+use tokio::{task, time};
+
+use crate::{
+    bench::{
+        network_options, options,
+        payload::{self, Payloads, Sizes},
+        sys::SysMonitor,
+    },
+    common::{latency_histogram, LatencySummary},
+    BenchConfig,
+};
+
+const TOPIC: &str = "hello/payload-sweep/world";
+
+#[derive(Debug, Serialize)]
+pub struct PayloadSweepReport {
+    /// publishes of every size
+    pub publishes: usize,
+    pub steps: Vec<PayloadStep>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PayloadStep {
+    pub payload_size: usize,
+    /// publishes the broker acked
+    pub acked: usize,
+    pub received: usize,
+    pub duration_secs: f64,
+    /// payload megabytes (10^6 bytes) received per second
+    pub payload_mb_per_sec: f64,
+    pub ack_latencies: LatencySummary,
+    /// end to end latencies of received publishes
+    pub latencies: LatencySummary,
+    /// whether the broker dropped a connection of the pair
+    pub dropped: bool,
+    /// `$SYS` topics about memory at the end of the step, with `--sys-monitor`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub broker_memory: BTreeMap<String, String>,
+}
+
+pub(crate) async fn sweep(config: Arc<BenchConfig>) -> anyhow::Result<PayloadSweepReport> {
+    let mut steps = Vec::with_capacity(config.payload_sweep.len());
+    for &size in config.payload_sweep.iter() {
+        let monitor = if config.sys_monitor {
+            Some(SysMonitor::start(config.clone()).await?)
+        } else {
+            None
+        };
+
+        let mut step = step(&config, size).await?;
+        if let Some(monitor) = monitor {
+            step.broker_memory = monitor
+                .stop()
+                .await
+                .into_iter()
+                .filter(|(topic, _)| topic.contains("heap") || topic.contains("memory"))
+                .map(|(topic, value)| (topic, value.last))
+                .collect();
+        }
+        steps.push(step);
+    }
+
+    Ok(PayloadSweepReport {
+        publishes: config.payload_sweep_count,
+        steps,
+    })
+}
+
+/// Publishes `--payload-sweep-count` publishes of `size` bytes to a
+/// subscriber, until either connection drops or neither hears from the
+/// broker for `--receive-timeout`
+async fn step(config: &Arc<BenchConfig>, size: usize) -> anyhow::Result<PayloadStep> {
+    let count = config.payload_sweep_count;
+    let (subscriber, mut incoming) = connect(config, "mqttwrk-payload-sub").await?;
+    subscriber.subscribe(TOPIC, QoS::AtLeastOnce).await?;
+    loop {
+        match time::timeout(config.conn_timeout, incoming.poll()).await?? {
+            Event::Incoming(Incoming::SubAck(_)) => break,
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+
+    let (publisher, mut outgoing) = connect(config, "mqttwrk-payload-pub").await?;
+    let requests = task::spawn({
+        let publisher = publisher.clone();
+        let payloads = Payloads::new(Sizes::Fixed(size), true, false);
+        async move {
+            for _ in 0..count {
+                publisher
+                    .publish_bytes(TOPIC, QoS::AtLeastOnce, false, payloads.next(None))
+                    .await?;
+            }
+            anyhow::Ok(())
+        }
+    });
+
+    let start = Instant::now();
+    let mut deadline = start + config.receive_timeout;
+    let mut sent: HashMap<u16, Instant> = HashMap::new();
+    let mut ack_latencies = latency_histogram();
+    let mut latencies: Histogram<u64> = latency_histogram();
+    let (mut acked, mut received, mut dropped) = (0, 0, false);
+    while acked < count || received < count {
+        let event = tokio::select! {
+            event = outgoing.poll() => event,
+            event = incoming.poll() => event,
+            _ = time::sleep_until(deadline.into()) => {
+                warn!(
+                    "Id = mqttwrk-payload-sub, {} of {} publishes of {} bytes received",
+                    received, count, size
+                );
+                break;
+            }
+        };
+
+        match event {
+            Ok(Event::Outgoing(Outgoing::Publish(pkid))) => {
+                sent.insert(pkid, Instant::now());
+            }
+            Ok(Event::Incoming(Incoming::PubAck(ack))) => {
+                acked += 1;
+                deadline = Instant::now() + config.receive_timeout;
+                if let Some(sent) = sent.remove(&ack.pkid) {
+                    ack_latencies.record(sent.elapsed().as_micros() as u64)?;
+                }
+            }
+            Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                received += 1;
+                deadline = Instant::now() + config.receive_timeout;
+                if let Some(latency) = payload::latency(&publish.payload) {
+                    latencies.record(latency.as_micros() as u64)?;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!(
+                    "Id = mqttwrk-payload, Dropped with {} byte payloads = {:?}",
+                    size, e
+                );
+                dropped = true;
+                break;
+            }
+        }
+    }
+
+    requests.abort();
+    let _ = publisher.try_disconnect();
+    let _ = subscriber.try_disconnect();
+    let duration_secs = start.elapsed().as_secs_f64();
+    Ok(PayloadStep {
+        payload_size: size,
+        acked,
+        received,
+        duration_secs,
+        payload_mb_per_sec: (received * size) as f64 / duration_secs / 1_000_000.0,
+        ack_latencies: LatencySummary::from(&ack_latencies),
+        latencies: LatencySummary::from(&latencies),
+        dropped,
+        broker_memory: BTreeMap::new(),
+    })
+}
+
+async fn connect(config: &Arc<BenchConfig>, id: &str) -> anyhow::Result<(AsyncClient, EventLoop)> {
+    let (client, mut eventloop) = AsyncClient::new(options(config.clone(), id)?, 10);
+    eventloop.network_options = network_options(config)?;
+
+    loop {
+        match time::timeout(config.conn_timeout, eventloop.poll()).await?? {
+            Event::Incoming(Incoming::ConnAck(_)) => return Ok((client, eventloop)),
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+}
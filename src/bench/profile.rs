@@ -0,0 +1,248 @@
+//! Profiles of managed brokers. A profile fills in the connection quirks of
+//! the broker and checks the benchmark against its limits before connecting,
+//! as managed brokers throttle or disconnect clients that exceed them instead
+//! of failing fast
+
+use std::time::Duration;
+
+use crate::{
+    bench::{payload, qosmix},
+    BenchConfig, Endpoint, Profile, TlsBackend, Transport,
+};
+
+/// Limits of aws iot core, see
+/// https://docs.aws.amazon.com/general/latest/gr/iot-core.html#message-broker-limits
+mod aws {
+    pub const ALPN: &str = "x-amzn-mqtt-ca";
+    pub const KEEP_ALIVE: std::ops::RangeInclusive<u64> = 30..=1200;
+    pub const PAYLOAD_SIZE: usize = 128 * 1024;
+    pub const TOPIC_LENGTH: usize = 256;
+    pub const TOPIC_SLASHES: usize = 7;
+    pub const TOPIC_ALIASES: u16 = 8;
+    pub const INFLIGHT: u16 = 100;
+    pub const PUBLISH_RATE: u64 = 100;
+}
+
+/// Limits of azure iot hub, see
+/// https://learn.microsoft.com/azure/iot-hub/iot-hub-mqtt-support
+mod azure {
+    pub const PAYLOAD_SIZE: usize = 256 * 1024;
+    pub const KEEP_ALIVE: u64 = 1767;
+}
+
+/// Applies `--profile`. Returns the limits that the benchmark exceeds
+pub(crate) fn apply(config: &mut BenchConfig) -> Vec<String> {
+    match config.profile {
+        Some(Profile::AwsIot) => aws_iot(config),
+        Some(Profile::AzureIotHub) => azure_iot_hub(config),
+        None => Vec::new(),
+    }
+}
+
+fn aws_iot(config: &mut BenchConfig) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    // mqtt over tls is served on 8883, and on 443 to clients offering the alpn
+    match config.port {
+        1883 => config.port = 8883,
+        443 if config.alpn.is_empty() => config.alpn.push(aws::ALPN.to_owned()),
+        _ => {}
+    }
+
+    if config.ca_file.is_none() {
+        violations.push("--ca-file with the amazon root ca is required".to_owned());
+    }
+    if config.client_cert.is_none() && config.credentials_file.is_none() {
+        violations
+            .push("--client-cert and --client-key of a registered thing are required".to_owned());
+    }
+    if config.tls_backend != TlsBackend::Rustls {
+        violations.push("client certificates need --tls-backend rustls".to_owned());
+    }
+    if config.transport != Transport::Tcp {
+        violations.push("websockets need sigv4 auth, use --transport tcp".to_owned());
+    }
+    if qosmix::max_qos(config) == 2 || config.subscribe_qos == 2 {
+        violations.push("qos 2 isn't supported".to_owned());
+    }
+    let payload_size = payload::Sizes::new(config).max();
+    if payload_size > aws::PAYLOAD_SIZE {
+        violations.push(format!(
+            "payload of {} bytes is over {} bytes",
+            payload_size,
+            aws::PAYLOAD_SIZE
+        ));
+    }
+    if let Some(will_topic) = &config.will_topic {
+        violations.extend(topic_violation(will_topic));
+    }
+    if config.topic_alias_max.is_some() {
+        violations.extend(topic_violation(&"x".repeat(config.alias_topic_length)));
+    }
+    if config.topic_alias_max.unwrap_or(0) > aws::TOPIC_ALIASES {
+        violations.push(format!("more than {} topic aliases", aws::TOPIC_ALIASES));
+    }
+    if config.rate > aws::PUBLISH_RATE {
+        violations.push(format!(
+            "rate of {}/s per publisher is over {}/s",
+            config.rate,
+            aws::PUBLISH_RATE
+        ));
+    }
+    if let Some(&rate) = config.rate_sweep.iter().max() {
+        if rate > aws::PUBLISH_RATE {
+            violations.push(format!(
+                "rate sweep up to {}/s per publisher is over {}/s",
+                rate,
+                aws::PUBLISH_RATE
+            ));
+        }
+    }
+    if let Some(delay) = config
+        .delay
+        .filter(|&d| d < Duration::from_secs(1) / aws::PUBLISH_RATE as u32)
+    {
+        violations.push(format!(
+            "delay of {} per publisher is under {}/s",
+            humantime::format_duration(delay),
+            aws::PUBLISH_RATE
+        ));
+    }
+    if let Some(ramp) = &config.rate_ramp {
+        let peak = ramp.from.max(ramp.to) / config.publishers.max(1) as u64;
+        if peak > aws::PUBLISH_RATE {
+            violations.push(format!(
+                "rate ramp up to {}/s per publisher is over {}/s",
+                peak,
+                aws::PUBLISH_RATE
+            ));
+        }
+    }
+
+    if let Some(max_rate) = config.pattern.and(config.max_rate) {
+        let peak = max_rate / config.publishers.max(1) as u64;
+        if peak > aws::PUBLISH_RATE {
+            violations.push(format!(
+                "pattern peaking at {}/s per publisher is over {}/s",
+                peak,
+                aws::PUBLISH_RATE
+            ));
+        }
+    }
+
+    if let Some(schedule) = &config.schedule {
+        let peak = schedule
+            .phases
+            .iter()
+            .map(|phase| phase.rate)
+            .max()
+            .unwrap_or(0)
+            / config.publishers.max(1) as u64;
+        if peak > aws::PUBLISH_RATE {
+            violations.push(format!(
+                "schedule peaking at {}/s per publisher is over {}/s",
+                peak,
+                aws::PUBLISH_RATE
+            ));
+        }
+    }
+
+    // the broker clamps keep alives to its range anyway
+    let keep_alive = config
+        .keep_alive
+        .clamp(*aws::KEEP_ALIVE.start(), *aws::KEEP_ALIVE.end());
+    if keep_alive != config.keep_alive {
+        warn!("Clamping --keep-alive to {}s for aws iot", keep_alive);
+        config.keep_alive = keep_alive;
+    }
+
+    // throttled connections would only measure the throttling
+    if config.rate == 0
+        && config.delay.is_none()
+        && config.rate_ramp.is_none()
+        && config.pattern.is_none()
+        && config.schedule.is_none()
+    {
+        warn!(
+            "Capping --rate to {}/s per publisher for aws iot",
+            aws::PUBLISH_RATE
+        );
+        config.rate = aws::PUBLISH_RATE;
+    }
+    if config.max_inflight > aws::INFLIGHT {
+        warn!("Capping --max-inflight to {} for aws iot", aws::INFLIGHT);
+        config.max_inflight = aws::INFLIGHT;
+    }
+    if config.inflight_sweep.iter().any(|&max| max > aws::INFLIGHT) {
+        warn!("Capping --inflight-sweep to {} for aws iot", aws::INFLIGHT);
+        for max in config.inflight_sweep.iter_mut() {
+            *max = (*max).min(aws::INFLIGHT);
+        }
+    }
+
+    violations
+}
+
+fn azure_iot_hub(config: &mut BenchConfig) -> Vec<String> {
+    let mut violations = Vec::new();
+    match &config.azure_connection_string {
+        Some(connection) => {
+            config.servers = vec![Endpoint {
+                host: connection.host.clone(),
+                port: None,
+                weight: 1,
+            }]
+        }
+        None => violations.push("--azure-connection-string is required".to_owned()),
+    }
+    if config.port == 1883 {
+        config.port = 8883;
+    }
+
+    if config.transport != Transport::Tcp {
+        violations.push("use --transport tcp".to_owned());
+    }
+    if config.username.is_some() || config.jwt_key.is_some() || config.credentials_file.is_some() {
+        violations.push("devices authenticate with sas tokens only".to_owned());
+    }
+    if config.subscribers > 0 {
+        violations.push("devices can't subscribe to the telemetry of other devices".to_owned());
+    }
+    if qosmix::max_qos(config) == 2 || config.subscribe_qos == 2 {
+        violations.push("qos 2 isn't supported".to_owned());
+    }
+    if config.retain || config.retain_ratio.is_some() {
+        violations.push("retained publishes aren't supported".to_owned());
+    }
+    let payload_size = payload::Sizes::new(config).max();
+    if payload_size > azure::PAYLOAD_SIZE {
+        violations.push(format!(
+            "payload of {} bytes is over {} bytes",
+            payload_size,
+            azure::PAYLOAD_SIZE
+        ));
+    }
+    if config.keep_alive > azure::KEEP_ALIVE {
+        warn!(
+            "Capping --keep-alive to {}s for azure iot hub",
+            azure::KEEP_ALIVE
+        );
+        config.keep_alive = azure::KEEP_ALIVE;
+    }
+
+    violations
+}
+
+fn topic_violation(topic: &str) -> Option<String> {
+    if topic.len() > aws::TOPIC_LENGTH {
+        return Some(format!(
+            "topic of {} bytes is over {} bytes",
+            topic.len(),
+            aws::TOPIC_LENGTH
+        ));
+    }
+
+    let slashes = topic.matches('/').count();
+    (slashes > aws::TOPIC_SLASHES)
+        .then(|| format!("topic {topic} has more than {} slashes", aws::TOPIC_SLASHES))
+}
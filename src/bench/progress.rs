@@ -0,0 +1,81 @@
+//! Aggregate progress of a fixed count benchmark, driven by live metrics
+
+use std::{sync::atomic::Ordering, time::Duration};
+
+use indicatif::{MultiProgress, ProgressBar};
+use tokio::{task, task::JoinHandle, time};
+
+use crate::{
+    bench::{groups, matrix, metrics::METRICS, subscribed_publishes, wildcard},
+    common::ETA_PROGRESS_STYLE,
+    BenchConfig,
+};
+
+pub struct Progress {
+    publishes: ProgressBar,
+    receives: ProgressBar,
+    updater: JoinHandle<()>,
+}
+
+impl Progress {
+    /// Progress bars for publishes and receives. Idle runs (count = 0) don't
+    /// have an end and hence no progress, and runs of a fixed duration don't
+    /// know how many publishes make up their end
+    pub fn start(config: &BenchConfig) -> Option<Progress> {
+        if config.count == 0 || config.duration.is_some() {
+            return None;
+        }
+
+        let expected_publishes: usize = (0..config.publishers)
+            .map(|i| groups::count(config, i))
+            .sum();
+        let expected_receives = match (config.topics, &config.sink_filter) {
+            (Some(_), _) => matrix::expected_total(config),
+            (None, _) if config.subscribe_only => config.count * config.subscribers,
+            (None, Some(_)) => wildcard::expected(config) * config.subscribers,
+            (None, None) => subscribed_publishes(config) * config.subscribers,
+        } as u64;
+
+        let bars = MultiProgress::new();
+        let publishes = bars.add(
+            ProgressBar::new(expected_publishes as u64)
+                .with_prefix("Published:")
+                .with_style((*ETA_PROGRESS_STYLE).clone()),
+        );
+        let receives = bars.add(
+            ProgressBar::new(expected_receives)
+                .with_prefix("Received:")
+                .with_style((*ETA_PROGRESS_STYLE).clone()),
+        );
+
+        let updater = {
+            let publishes = publishes.clone();
+            let receives = receives.clone();
+            task::spawn(async move {
+                let mut interval = time::interval(Duration::from_millis(100));
+                loop {
+                    interval.tick().await;
+                    update(&publishes, &receives);
+                }
+            })
+        };
+
+        Some(Progress {
+            publishes,
+            receives,
+            updater,
+        })
+    }
+
+    pub fn finish(self) {
+        self.updater.abort();
+        update(&self.publishes, &self.receives);
+        self.publishes.finish();
+        self.receives.finish();
+    }
+}
+
+fn update(publishes: &ProgressBar, receives: &ProgressBar) {
+    publishes.set_position(METRICS.outgoing_publishes.load(Ordering::Relaxed));
+    receives.set_position(METRICS.incoming_publishes.load(Ordering::Relaxed));
+}
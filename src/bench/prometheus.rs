@@ -0,0 +1,119 @@
+//! Minimal http server exposing live benchmark metrics in prometheus text format
+
+use std::{fmt::Write as _, io, sync::atomic::Ordering};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task,
+};
+
+use crate::bench::{metrics::METRICS, reporter::Reporter};
+
+/// Serves live metrics for as long as the benchmark runs. Scrapers pull the
+/// metrics, so samples aren't needed
+pub(crate) struct Prometheus;
+
+impl Prometheus {
+    pub fn start(port: u16) -> Prometheus {
+        task::spawn(async move {
+            if let Err(e) = serve(port).await {
+                error!("Prometheus endpoint failed = {:?}", e);
+            }
+        });
+
+        Prometheus
+    }
+}
+
+impl Reporter for Prometheus {
+    fn name(&self) -> &'static str {
+        "prometheus"
+    }
+}
+
+async fn serve(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("Serving prometheus metrics on port {}", port);
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        task::spawn(async move {
+            if let Err(e) = respond(stream).await {
+                debug!("Failed to serve metrics to {} = {:?}", addr, e);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream) -> io::Result<()> {
+    // Request body is never needed. Request line is enough to route
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let response = if path == "/metrics" {
+        let body = render();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn render() -> String {
+    let metrics = [
+        (
+            "mqttwrk_connections",
+            "gauge",
+            "Connected publishers and subscribers",
+            METRICS.connections.load(Ordering::Relaxed),
+        ),
+        (
+            "mqttwrk_outgoing_publishes_total",
+            "counter",
+            "Publishes sent by publishers",
+            METRICS.outgoing_publishes.load(Ordering::Relaxed) as i64,
+        ),
+        (
+            "mqttwrk_incoming_publishes_total",
+            "counter",
+            "Publishes received by subscribers",
+            METRICS.incoming_publishes.load(Ordering::Relaxed) as i64,
+        ),
+        (
+            "mqttwrk_acks_total",
+            "counter",
+            "Acks received by publishers",
+            METRICS.acks.load(Ordering::Relaxed) as i64,
+        ),
+        (
+            "mqttwrk_inflight",
+            "gauge",
+            "Publishes waiting for an ack",
+            METRICS.inflight.load(Ordering::Relaxed),
+        ),
+        (
+            "mqttwrk_reconnects_total",
+            "counter",
+            "Connection errors across all connections",
+            METRICS.reconnects.load(Ordering::Relaxed) as i64,
+        ),
+    ];
+
+    let mut body = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(body, "# HELP {name} {help}");
+        let _ = writeln!(body, "# TYPE {name} {kind}");
+        let _ = writeln!(body, "{name} {value}");
+    }
+
+    body
+}
@@ -0,0 +1,245 @@
+//! Mqtt 5 user properties. A dedicated v5 connection publishes twice, once
+//! without and once with `--user-property` on every publish, while a v5
+//! subscriber checks that the properties arrive intact. This measures what
+//! carrying properties costs on the wire and in throughput, for platforms that
+//! route on them
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use bytes::Bytes;
+use rumqttc::{
+    v5::{
+        mqttbytes::{
+            v5::{Packet, Publish, PublishProperties},
+            QoS,
+        },
+        Event,
+    },
+    Outgoing,
+};
+use serde::Serialize;
+use tokio::{
+    task::{self, JoinHandle},
+    time,
+};
+
+use crate::{
+    bench::{payload, v5_connect, v5_options},
+    common::{latency_histogram, LatencySummary},
+    BenchConfig,
+};
+
+const TOPIC: &str = "hello/properties/world";
+
+/// Both passes of the comparison
+#[derive(Debug, Serialize)]
+pub struct PropertiesReport {
+    /// user properties on every publish of the second pass
+    pub properties: usize,
+    pub without_properties: PropertiesPass,
+    pub with_properties: PropertiesPass,
+    /// extra wire bytes spent on properties
+    pub overhead_percent: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PropertiesPass {
+    pub publishes: u64,
+    pub duration_secs: f64,
+    pub throughput: f64,
+    /// size of the publishes on the wire, including mqtt headers
+    pub wire_bytes: u64,
+    pub ack_latencies: LatencySummary,
+    /// publishes received by the subscriber
+    pub received: u64,
+    /// received publishes that carried exactly the properties published
+    pub intact: u64,
+}
+
+/// Publishes `--count` publishes without and then with user properties
+pub(crate) async fn compare(config: Arc<BenchConfig>) -> anyhow::Result<PropertiesReport> {
+    let without_properties = pass(&config, false).await?;
+    let with_properties = pass(&config, true).await?;
+    let overhead_percent = match without_properties.wire_bytes {
+        0 => 0.0,
+        bytes => (with_properties.wire_bytes as f64 / bytes as f64 - 1.0) * 100.0,
+    };
+
+    Ok(PropertiesReport {
+        properties: config.user_properties.len(),
+        without_properties,
+        with_properties,
+        overhead_percent,
+    })
+}
+
+/// User properties of the publish numbered `seq`, with `{seq}` in values
+/// replaced by it
+fn user_properties(config: &BenchConfig, seq: usize) -> Vec<(String, String)> {
+    config
+        .user_properties
+        .iter()
+        .map(|(key, value)| (key.clone(), value.replace("{seq}", &seq.to_string())))
+        .collect()
+}
+
+async fn pass(config: &Arc<BenchConfig>, with_properties: bool) -> anyhow::Result<PropertiesPass> {
+    let received = subscribe(config, with_properties).await?;
+
+    let options = v5_options(config, "mqttwrk-properties")?;
+    let (client, mut eventloop, _) = v5_connect(options).await?;
+
+    let count = config.count;
+    let wire_bytes = Arc::new(AtomicU64::new(0));
+    let requests = task::spawn({
+        let client = client.clone();
+        let config = config.clone();
+        let wire_bytes = wire_bytes.clone();
+        async move {
+            for seq in 0..count {
+                let properties = with_properties.then(|| PublishProperties {
+                    user_properties: user_properties(&config, seq),
+                    ..Default::default()
+                });
+
+                let payload = Bytes::from(payload::generate(config.payload_size, false, None));
+                let mut publish =
+                    Publish::new(TOPIC, QoS::AtLeastOnce, payload.clone(), properties.clone());
+                // size only counts the packet id once one is assigned
+                publish.pkid = 1;
+                wire_bytes.fetch_add(publish.size() as u64, Ordering::Relaxed);
+
+                let published = match properties {
+                    Some(properties) => {
+                        client
+                            .publish_with_properties(
+                                TOPIC,
+                                QoS::AtLeastOnce,
+                                false,
+                                payload,
+                                properties,
+                            )
+                            .await
+                    }
+                    None => {
+                        client
+                            .publish(TOPIC, QoS::AtLeastOnce, false, payload)
+                            .await
+                    }
+                };
+                if let Err(e) = published {
+                    error!("Id = mqttwrk-properties, Publish error = {:?}", e);
+                    return;
+                }
+            }
+        }
+    });
+
+    let inflight = config.max_inflight as usize;
+    let mut sent: Vec<Option<Instant>> = vec![None; inflight + 1];
+    let mut histogram = latency_histogram();
+    let mut acks = 0;
+    let start = Instant::now();
+    let timeout = config.conn_timeout.max(config.receive_timeout);
+    while acks < count {
+        let event = match time::timeout(timeout, eventloop.poll()).await {
+            Ok(event) => event?,
+            Err(_) => {
+                warn!(
+                    "Id = mqttwrk-properties, {} of {} publishes acked",
+                    acks, count
+                );
+                break;
+            }
+        };
+
+        match event {
+            Event::Outgoing(Outgoing::Publish(pkid)) => {
+                sent[pkid as usize] = Some(Instant::now());
+            }
+            Event::Incoming(Packet::PubAck(puback)) => {
+                acks += 1;
+                if let Some(sent) = sent[puback.pkid as usize].take() {
+                    histogram.record(sent.elapsed().as_micros() as u64).unwrap();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let elapsed = start.elapsed();
+    requests.abort();
+    let _ = client.try_disconnect();
+    let (received, intact) = received.await?;
+
+    Ok(PropertiesPass {
+        publishes: acks as u64,
+        duration_secs: elapsed.as_secs_f64(),
+        throughput: acks as f64 / elapsed.as_secs_f64(),
+        wire_bytes: wire_bytes.load(Ordering::Relaxed),
+        ack_latencies: LatencySummary::from(&histogram),
+        received,
+        intact,
+    })
+}
+
+/// Subscribes to the publishes of a pass. Resolves to the publishes received
+/// and how many of them were intact, once all of them arrived or none did for
+/// `--receive-timeout`
+async fn subscribe(
+    config: &Arc<BenchConfig>,
+    with_properties: bool,
+) -> anyhow::Result<JoinHandle<(u64, u64)>> {
+    let options = v5_options(config, "mqttwrk-properties-sub")?;
+    let (client, mut eventloop, _) = v5_connect(options).await?;
+    client.subscribe(TOPIC, QoS::AtLeastOnce).await?;
+    loop {
+        match eventloop.poll().await? {
+            Event::Incoming(Packet::SubAck(_)) => break,
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+
+    let config = config.clone();
+    let received = task::spawn(async move {
+        let (mut received, mut intact) = (0, 0);
+        while received < config.count as u64 {
+            let event = match time::timeout(config.receive_timeout, eventloop.poll()).await {
+                Ok(Ok(event)) => event,
+                Ok(Err(e)) => {
+                    error!("Id = mqttwrk-properties-sub, Connection error = {:?}", e);
+                    break;
+                }
+                Err(_) => break,
+            };
+
+            if let Event::Incoming(Packet::Publish(publish)) = event {
+                let expected = if with_properties {
+                    user_properties(&config, received as usize)
+                } else {
+                    vec![]
+                };
+                let properties = publish
+                    .properties
+                    .map(|properties| properties.user_properties)
+                    .unwrap_or_default();
+                received += 1;
+                if properties == expected {
+                    intact += 1;
+                }
+            }
+        }
+
+        let _ = client.try_disconnect();
+        (received, intact)
+    });
+
+    Ok(received)
+}
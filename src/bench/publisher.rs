@@ -1,21 +1,51 @@
-use std::{fs, io, sync::Arc, time::Instant};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
 use hdrhistogram::Histogram;
-use rumqttc::{AsyncClient, Event, EventLoop, Incoming, MqttOptions, Outgoing, QoS, Transport};
+use rumqttc::{AsyncClient, Event, EventLoop, Incoming, Outgoing, PubAck, PubComp, PubRec, QoS};
 use tokio::{
-    sync::Barrier,
+    sync::{mpsc, oneshot, Barrier},
     task,
     time::{self, Duration},
 };
 
 use crate::{
-    bench::{ConnectionError, PubStats},
-    BenchConfig,
+    bench::{
+        deadline::Published,
+        endpoint_label, endpoint_options,
+        flap::{self, Flap},
+        metrics::METRICS,
+        network_options,
+        outage::Outages,
+        pacer, payload, publisher_topic,
+        rampdown::Connection,
+        refresh_token, retain, soak,
+        storm::{self, Storm},
+        subscribed,
+        will::{self, Kills},
+        ConnectionError, PubStats,
+    },
+    common::{latency_histogram, Flaps, IntervalStats, LatencySummary, Recovery, RequestStats},
+    BenchConfig, Endpoint,
 };
 
 pub struct Publisher {
     id: String,
+    /// of the publisher among all publishers
+    index: usize,
+    /// `HOST:PORT` of the broker connected to
+    endpoint: String,
     config: Arc<BenchConfig>,
+    /// time from connect to connack
+    connack_latency: Duration,
+    /// publishes of all publishers, with `--duration`
+    published: Option<Arc<Published>>,
+    /// drops every connection at once, with `--reconnect-storm`
+    storm: Option<Storm>,
+    /// drops the connection every now and then, with `--flappy`
+    flap: Option<Flap>,
     client: AsyncClient,
     eventloop: EventLoop,
 }
@@ -23,20 +53,32 @@ pub struct Publisher {
 impl Publisher {
     pub(crate) async fn new(
         id: String,
+        index: usize,
         config: Arc<BenchConfig>,
+        endpoint: &Endpoint,
+        published: Option<Arc<Published>>,
+        storm: Option<Storm>,
+        flap: Option<Flap>,
     ) -> Result<Publisher, ConnectionError> {
-        let (client, mut eventloop) = AsyncClient::new(options(config.clone(), &id)?, 10);
-        eventloop
-            .network_options
-            .set_connection_timeout(config.conn_timeout);
+        let mut options = endpoint_options(config.clone(), &id, endpoint)?;
+        options.set_clean_session(config.clean_session);
+        if let Some(will) = will::last_will(&config, &id) {
+            options.set_last_will(will);
+        }
 
-        loop {
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+        eventloop.network_options = network_options(&config)?;
+
+        let mut connect = Instant::now();
+        let connack_latency = loop {
             let event = match eventloop.poll().await {
                 Ok(v) => v,
                 Err(rumqttc::ConnectionError::NetworkTimeout)
                 | Err(rumqttc::ConnectionError::FlushTimeout) => {
                     println!("{id} reconnecting");
+                    refresh_token(&config, &id, &mut eventloop.mqtt_options);
                     time::sleep(Duration::from_secs(1)).await;
+                    connect = Instant::now();
                     continue;
                 }
                 Err(e) => return Err(e.into()),
@@ -46,83 +88,114 @@ impl Publisher {
                 match v {
                     Incoming::ConnAck(_) => {
                         // println!("{id} connected");
-                        break;
+                        METRICS.connected();
+                        break connect.elapsed();
                     }
                     incoming => return Err(ConnectionError::WrongPacket(incoming)),
                 }
             }
-        }
+        };
 
         Ok(Publisher {
             id,
+            index,
+            endpoint: endpoint_label(&config, endpoint),
             config,
+            connack_latency,
+            published,
+            storm,
+            flap,
             client,
             eventloop,
         })
     }
 
     pub async fn start(&mut self, barrier_handle: Arc<Barrier>) -> PubStats {
-        let qos = get_qos(self.config.publish_qos);
-        let inflight = self.config.max_inflight;
-        let payload_size = self.config.payload_size;
         let count = self.config.count;
-        let rate = self.config.rate;
-        let id = self.id.clone();
-
         let start = Instant::now();
-        let mut acks_expected = count;
         let mut outgoing_elapsed = Duration::from_secs(0);
         let mut acks_count = 0;
 
-        let topic = format!("hello/{}/world", self.id);
+        let topic = publisher_topic(&self.config, self.index);
+        let mut written = Written::new(&self.config, topic.len());
         let client = self.client.clone();
-
-        let wait = barrier_handle.wait();
-        tokio::pin!(wait);
-
-        // Keep sending pings until all publishers are spawned
-        loop {
-            tokio::select! {
-                _ = wait.as_mut() => {
-                    break;
-                }
-                _ = self.eventloop.poll() => {
-                }
-            };
+        // publishes in the order they were handed to the client, which is also
+        // the order in which the eventloop sends them
+        let (enqueued_tx, mut enqueued_rx) = mpsc::unbounded_channel();
+        // how many publishes to expect acks for, when publishing for a duration
+        let (published_tx, mut published_rx) = oneshot::channel();
+
+        self.wait_turn(barrier_handle).await;
+        let warmup_end = Instant::now() + self.config.warmup;
+        if let Some(flap) = &mut self.flap {
+            flap.start();
         }
 
         // If publish count is 0, don't publish. This is an idle connection
         // which can be used to test pings
-        if count != 0 {
-            // delay between messages in milliseconds
-            let delay = if rate == 0 { 0 } else { 1000 / rate };
-            task::spawn(async move {
-                requests(topic, payload_size, count, client, qos, delay).await;
-            });
+        let requests = Arc::new(Mutex::new(Requests::default()));
+        let requests_task = if count != 0 || self.config.duration.is_some() {
+            let config = self.config.clone();
+            let requests = requests.clone();
+            Some(task::spawn(async move {
+                let published = make_requests(topic, client, config, enqueued_tx, requests).await;
+                let _ = published_tx.send(published);
+            }))
         } else {
-            // Just keep this connection alive
-            acks_expected = 1;
-        }
-
-        if self.config.publish_qos == 0 {
-            // only last extra publish is qos 1 for synchronization
-            acks_expected = 1;
-        }
+            None
+        };
+        let mut acks_expected = acks_expected(&self.config, requests_task.is_some());
 
         let mut reconnects: u64 = 0;
-        let mut latencies: Vec<Option<Instant>> = vec![None; inflight as usize + 1];
-        let mut histogram = Histogram::<u64>::new(4).unwrap();
+        let mut outages = Outages::default();
+        let mut recovery = Recovery::default();
+        let mut flaps = Flaps::default();
+        match self.flap.as_ref().map(Flap::flappy) {
+            Some(true) => flaps.flappy = 1,
+            Some(false) => flaps.stable = 1,
+            None => {}
+        }
+        let mut inflight = Inflight::new(self.config.max_inflight);
+        let mut acks = Acks::new(&self.config);
+        let mut pings = Pings::new();
+        // idle connections only keep alive until `--idle` is over
+        let hold = time::sleep(self.config.idle.unwrap_or_default());
+        tokio::pin!(hold);
 
         loop {
-            let event = match self.eventloop.poll().await {
+            let polled = tokio::select! {
+                polled = self.eventloop.poll() => polled,
+                _ = &mut hold, if self.config.idle.is_some() => {
+                    outgoing_elapsed = start.elapsed();
+                    break;
+                }
+                Ok(published) = &mut published_rx, if acks_expected == usize::MAX => {
+                    acks_expected = published;
+                    if acks_count >= acks_expected {
+                        outgoing_elapsed = start.elapsed();
+                        break;
+                    }
+                    continue;
+                }
+                _ = storm::hit(self.storm.as_ref()), if recovery.waiting() => {
+                    self.drop_connection(&mut recovery, &mut outages);
+                    continue;
+                }
+                _ = flap::due(self.flap.as_ref()) => {
+                    self.flap(&mut flaps, &mut outages);
+                    continue;
+                }
+            };
+            let event = match polled {
                 Ok(v) => v,
                 Err(e) => {
                     error!("Id = {}, Connection error = {:?}", self.id, e);
-                    reconnects += 1;
-                    if reconnects >= 1 {
+                    if self.disconnected(&mut reconnects, &mut outages) {
                         break;
                     }
 
+                    // the next poll reconnects
+                    time::sleep(Duration::from_secs(1)).await;
                     continue;
                 }
             };
@@ -130,31 +203,65 @@ impl Publisher {
             debug!("Id = {}, {:?}, count {}", self.id, event, acks_count);
             match event {
                 Event::Incoming(v) => match v {
-                    Incoming::PubAck(ack) => {
+                    Incoming::ConnAck(_) => {
+                        recovery.connected();
+                        flaps.connected();
+                        if outages.reconnected() {
+                            METRICS.connected();
+                        }
+                    }
+                    ack @ (Incoming::PubAck(PubAck { pkid })
+                    | Incoming::PubComp(PubComp { pkid })) => {
                         acks_count += 1;
-                        let elapsed = match latencies[ack.pkid as usize] {
-                            Some(instant) => instant.elapsed(),
+                        recovery.flowing();
+                        let completed = matches!(ack, Incoming::PubComp(_));
+                        let acked = match inflight.acked(pkid, completed) {
+                            Some(acked) => acked,
                             None => {
-                                warn!("Id = {}, Unsolicited PubAck", ack.pkid);
+                                warn!("Id = {}, Unsolicited ack", pkid);
                                 continue;
                             }
                         };
-                        histogram.record(elapsed.as_millis() as u64).unwrap();
+                        let elapsed = acked.sent.elapsed();
+                        METRICS.ack(elapsed);
+                        if Instant::now() < warmup_end {
+                            acks.warmup.record(elapsed.as_micros() as u64).unwrap();
+                        } else {
+                            acks.record(elapsed, &acked, self.config.burst_size);
+                            if let Some(flap) = self.stable_flap() {
+                                flaps.record(elapsed, flap.flapping());
+                            }
+                        }
                     }
-                    Incoming::PingResp => {
-                        debug!("ping response")
+                    Incoming::PubRec(PubRec { pkid }) => {
+                        if let Some(sent) = inflight.received(pkid) {
+                            if Instant::now() >= warmup_end {
+                                let elapsed = sent.elapsed().as_micros() as u64;
+                                acks.pubrecs.record(elapsed).unwrap();
+                            }
+                        }
                     }
+                    Incoming::PingResp => pings.responded(),
                     incoming => {
-                        error!("Id = {}, Unexpected incoming packet = {:?}", id, incoming);
+                        error!(
+                            "Id = {}, Unexpected incoming packet = {:?}",
+                            self.id, incoming
+                        );
                         break;
                     }
                 },
+                Event::Outgoing(Outgoing::AwaitAck(pkid)) => inflight.held_back(pkid),
                 Event::Outgoing(Outgoing::Publish(pkid)) => {
-                    latencies[pkid as usize] = Some(Instant::now());
-                }
-                Event::Outgoing(Outgoing::PingReq) => {
-                    debug!("ping request")
+                    METRICS.outgoing_publish(pkid);
+                    // qos 0 publishes flow as soon as they're written
+                    if pkid == 0 {
+                        recovery.flowing();
+                    }
+                    let publish = inflight.written(pkid, &mut enqueued_rx);
+                    written.record(publish.as_ref());
                 }
+                Event::Outgoing(Outgoing::PubRel(pkid)) => inflight.released(pkid),
+                Event::Outgoing(Outgoing::PingReq) => pings.sent(),
                 _ => (),
             }
 
@@ -164,7 +271,27 @@ impl Publisher {
             }
         }
 
-        let outgoing_throughput = (count * 1000) as f32 / outgoing_elapsed.as_millis() as f32;
+        // connections that are down were already counted as disconnected
+        if !outages.ongoing() {
+            METRICS.disconnected();
+        }
+        let outages = outages.finish();
+        // the task is stuck on a full request queue if the publisher gave up
+        if let Some(task) = requests_task {
+            task.abort();
+        }
+        let requests = requests.lock().unwrap().stats();
+        // publishers of a fixed duration publish as many as they can
+        let published = match self.config.duration {
+            Some(_) => requests.enqueued as usize,
+            None => count,
+        };
+        let subscribed = subscribed(&self.config, self.index);
+        if let Some(all) = &self.published {
+            // subscribers don't wait for publishes they don't subscribe to
+            all.finished(if subscribed { requests.enqueued } else { 0 });
+        }
+        let outgoing_throughput = (published * 1000) as f32 / outgoing_elapsed.as_millis() as f32;
 
         if self.config.show_pub_stat {
             println!(
@@ -172,85 +299,636 @@ impl Publisher {
             Throughputs
             ----------------------------
             Outgoing publishes : {:<7} Throughput = {} messages/s
+            Enqueued publishes : {:<7} Failed = {}, Rate = {:.2} messages/s
             Reconnects         : {}
-
-            Latencies of {} samples
-            ----------------------------
-            100                 : {}
-            99.9999 percentile  : {}
-            99.999 percentile   : {}
-            90 percentile       : {}
-            50 percentile       : {}
+            Ack latencies      : {}
             ",
                 self.id,
                 acks_count,
                 outgoing_throughput,
+                requests.enqueued,
+                requests.failed,
+                requests.rate,
                 reconnects,
-                histogram.len(),
-                histogram.value_at_percentile(100.0),
-                histogram.value_at_percentile(99.9999),
-                histogram.value_at_percentile(99.999),
-                histogram.value_at_percentile(90.0),
-                histogram.value_at_percentile(50.0),
+                LatencySummary::from(&acks.latencies),
             );
         }
 
         // if publish_qos is 0 assume we send all publishes
-        if self.config.publish_qos == 0 {
-            acks_count = self.config.count;
+        if self.config.publish_qos == 0 || self.config.qos_mix.is_some() {
+            acks_count = published;
         }
 
+        let mut connack_latencies = latency_histogram();
+        connack_latencies
+            .record(self.connack_latency.as_micros() as u64)
+            .unwrap();
+
         PubStats {
+            id: self.id.clone(),
+            endpoint: self.endpoint.clone(),
             outgoing_publish: acks_count as u64,
             throughput: outgoing_throughput,
             reconnects,
+            ack_latencies: acks.latencies,
+            warmup_ack_latencies: acks.warmup,
+            corrected_ack_latencies: acks.corrected,
+            burst_drains: acks.burst_drains,
+            phase_publishes: written.phases,
+            phase_ack_latencies: acks.phases,
+            rtts: acks.rtts,
+            qos_publishes: written.qos,
+            pubrec_latencies: acks.pubrecs,
+            pubcomp_latencies: acks.pubcomps,
+            connack_latencies,
+            publish_intervals: written.intervals,
+            payload_bytes: written.payload_bytes,
+            wire_bytes: written.wire_bytes,
+            requests,
+            outages,
+            pings: pings.sent,
+            ping_responses: pings.responses,
+            ping_latencies: pings.latencies,
+            group: self
+                .config
+                .groups
+                .as_ref()
+                .map(|groups| groups.of(self.index)),
+            subscribed,
+            recovery,
+            flaps,
         }
     }
+
+    /// Keeps the connection alive with pings until all publishers are spawned
+    /// and it's our turn to start
+    async fn wait_turn(&mut self, barrier_handle: Arc<Barrier>) {
+        let offset = pacer::start_offset(&self.config);
+        let wait = async {
+            barrier_handle.wait().await;
+            time::sleep(offset).await;
+        };
+        tokio::pin!(wait);
+
+        loop {
+            tokio::select! {
+                _ = wait.as_mut() => {
+                    break;
+                }
+                _ = self.eventloop.poll() => {
+                }
+            };
+        }
+    }
+
+    /// Counts a connection error. Returns true if the publisher gives up on
+    /// reconnecting
+    fn disconnected(&mut self, reconnects: &mut u64, outages: &mut Outages) -> bool {
+        refresh_token(&self.config, &self.id, &mut self.eventloop.mqtt_options);
+        METRICS.reconnect();
+        if outages.disconnected() {
+            METRICS.disconnected();
+        }
+
+        *reconnects += 1;
+        *reconnects > self.config.max_reconnects
+    }
+
+    /// Drops the connection without a disconnect, as the storm hit. The next
+    /// poll reconnects
+    fn drop_connection(&mut self, recovery: &mut Recovery, outages: &mut Outages) {
+        self.eventloop.clean();
+        recovery.dropped();
+        if outages.disconnected() {
+            METRICS.disconnected();
+        }
+    }
+
+    /// Drops the connection without a disconnect, as it's its turn to flap.
+    /// The next poll reconnects
+    fn flap(&mut self, flaps: &mut Flaps, outages: &mut Outages) {
+        self.eventloop.clean();
+        flaps.flapped();
+        if let Some(flap) = &mut self.flap {
+            flap.flapped();
+        }
+        if outages.disconnected() {
+            METRICS.disconnected();
+        }
+    }
+
+    /// Flaps of a stable publisher
+    fn stable_flap(&self) -> Option<&Flap> {
+        self.flap.as_ref().filter(|flap| !flap.flappy())
+    }
+
+    /// The connection, to close along with the others during the ramp down
+    pub(crate) fn into_connection(self) -> Connection {
+        Connection {
+            will: self.config.will_topic.is_some(),
+            id: self.id,
+            client: self.client,
+            eventloop: self.eventloop,
+        }
+    }
+
+    /// Disconnects gracefully, so that the broker discards the last will
+    pub(crate) async fn disconnect(mut self) {
+        // the request queue might be full if the publisher gave up on the broker
+        if let Err(e) = self.client.try_disconnect() {
+            error!("Id = {}, Failed to disconnect = {:?}", self.id, e);
+            return;
+        }
+
+        let _ = time::timeout(self.config.conn_timeout, async {
+            loop {
+                match self.eventloop.poll().await {
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        })
+        .await;
+    }
+
+    /// Drops the connection without disconnecting, so that the broker
+    /// publishes the last will
+    pub(crate) fn kill(self, kills: &Kills) {
+        let id = self.id.clone();
+        let killed_at = Instant::now();
+        drop(self);
+        kills.lock().unwrap().insert(id, killed_at);
+    }
+}
+
+/// A publish handed to the client
+#[derive(Debug, Clone, Copy)]
+struct Enqueued {
+    at: Instant,
+    /// when the publish was due at the configured rate. Later than `at` if
+    /// the publisher fell behind, e.g. because the broker was slow to ack
+    due: Option<Instant>,
+    qos: QoS,
+    /// size of the payload in bytes
+    payload: usize,
+    /// phase of `--schedule` the publish was due in
+    phase: Option<usize>,
+}
+
+/// Publishes handed to the client by the requests task so far. Shared rather
+/// than returned by the task, as the task never finishes when the publisher
+/// gives up on the broker
+#[derive(Debug, Default)]
+struct Requests {
+    enqueued: u64,
+    failed: u64,
+    first: Option<Instant>,
+    last: Option<Instant>,
 }
 
-/// make count number of requests at specified QoS.
-async fn requests(
+impl Requests {
+    fn enqueued(&mut self) {
+        let now = Instant::now();
+        self.enqueued += 1;
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+
+    fn stats(&self) -> RequestStats {
+        let elapsed = match (self.first, self.last) {
+            (Some(first), Some(last)) => (last - first).as_secs_f64(),
+            _ => 0.0,
+        };
+
+        RequestStats {
+            enqueued: self.enqueued,
+            failed: self.failed,
+            rate: match elapsed {
+                elapsed if elapsed > 0.0 => self.enqueued as f64 / elapsed,
+                _ => 0.0,
+            },
+        }
+    }
+}
+
+/// Acks to wait for before the publisher is done, unknown until the end of
+/// `--duration` for publishers of a fixed duration
+fn acks_expected(config: &BenchConfig, publishing: bool) -> usize {
+    if let Some(mix) = config.qos_mix {
+        mix.acked(config.count)
+    } else if config.publish_qos == 0 || !publishing {
+        // only last extra publish is qos 1 for synchronization, and idle
+        // connections just keep alive
+        1
+    } else if config.duration.is_some() {
+        usize::MAX
+    } else {
+        config.count
+    }
+}
+
+/// Publishes written to the network, by pkid until they're acked
+struct Inflight {
+    /// when the publish holding a pkid was written
+    sent: Vec<Option<Instant>>,
+    /// the publish holding a pkid
+    enqueued: Vec<Option<Enqueued>>,
+    /// qos 2 publishes between pubrec and pubcomp, which already left their
+    /// pkid to later publishes
+    released: Vec<Option<(Instant, Option<Enqueued>)>>,
+    /// when the pubrel of a qos 2 publish was written
+    pubrels: Vec<Option<Instant>>,
+    /// pkid of a publish the client holds back until the publish still
+    /// holding it is acked, when acks come out of order
+    collision: Option<u16>,
+    /// the held back publish once sent, which goes out just before that ack
+    /// and takes the pkid over with it
+    collided: Option<(u16, Instant, Enqueued)>,
+}
+
+/// A publish that was acked
+struct Acked {
+    /// when it was written
+    sent: Instant,
+    enqueued: Option<Enqueued>,
+    /// when its pubrel was written, for qos 2 publishes
+    pubrel: Option<Instant>,
+}
+
+impl Inflight {
+    fn new(inflight: u16) -> Inflight {
+        let pkids = inflight as usize + 1;
+        Inflight {
+            sent: vec![None; pkids],
+            enqueued: vec![None; pkids],
+            released: vec![None; pkids],
+            pubrels: vec![None; pkids],
+            collision: None,
+            collided: None,
+        }
+    }
+
+    /// The client holds back the next publish with `pkid`
+    fn held_back(&mut self, pkid: u16) {
+        self.collision = Some(pkid);
+    }
+
+    /// Tracks a publish written with `pkid`. Returns the next publish of
+    /// `enqueued` it is, unless it's a retransmission
+    fn written(
+        &mut self,
+        pkid: u16,
+        enqueued: &mut mpsc::UnboundedReceiver<Enqueued>,
+    ) -> Option<Enqueued> {
+        let slot = pkid as usize;
+        let collides = self.collision == Some(pkid);
+        if collides {
+            self.collision = None;
+        }
+        let held_back = collides && self.enqueued[slot].is_some();
+        if !held_back {
+            self.sent[slot] = Some(Instant::now());
+        }
+
+        // retransmissions after a reconnect keep their pkid and were enqueued
+        // only once
+        if !held_back && pkid != 0 && self.enqueued[slot].is_some() {
+            return None;
+        }
+        let publish = enqueued.try_recv().ok()?;
+        // qos 0 publishes are never acked
+        if held_back {
+            self.collided = Some((pkid, Instant::now(), publish));
+        } else if pkid != 0 {
+            self.enqueued[slot] = Some(publish);
+        }
+        Some(publish)
+    }
+
+    /// Moves qos 2 publish `pkid` on to the second half of the handshake.
+    /// Returns when it was written
+    fn received(&mut self, pkid: u16) -> Option<Instant> {
+        let slot = pkid as usize;
+        let sent = self.sent[slot]?;
+        self.released[slot] = Some((sent, self.enqueued[slot].take()));
+        Some(sent)
+    }
+
+    fn released(&mut self, pkid: u16) {
+        self.pubrels[pkid as usize] = Some(Instant::now());
+    }
+
+    /// Takes publish `pkid` off the inflight ones as it's acked, with a
+    /// pubcomp if `completed` or else a puback. `None` for unsolicited acks
+    fn acked(&mut self, pkid: u16, completed: bool) -> Option<Acked> {
+        let slot = pkid as usize;
+        let (sent, enqueued) = if completed {
+            self.released[slot].take()?
+        } else {
+            let acked = (self.sent[slot], self.enqueued[slot].take());
+            match self.collided.take() {
+                Some((held_back, at, publish)) if held_back == pkid => {
+                    self.sent[slot] = Some(at);
+                    self.enqueued[slot] = Some(publish);
+                }
+                other => self.collided = other,
+            }
+            (acked.0?, acked.1)
+        };
+
+        Some(Acked {
+            sent,
+            enqueued,
+            // only qos 2 publishes get a pubrel
+            pubrel: self.pubrels[slot].take(),
+        })
+    }
+}
+
+/// Latencies of the acks of a publisher, in microseconds
+struct Acks {
+    /// publish to ack
+    latencies: Histogram<u64>,
+    /// publish to ack during warmup, excluded from the rest
+    warmup: Histogram<u64>,
+    /// from when publishes were due at the configured rate to their acks
+    corrected: Histogram<u64>,
+    /// enqueue to ack, by qos
+    rtts: [Histogram<u64>; 3],
+    /// publish to pubrec of qos 2 publishes
+    pubrecs: Histogram<u64>,
+    /// pubrel to pubcomp of qos 2 publishes
+    pubcomps: Histogram<u64>,
+    /// publish to ack in every phase of `--schedule`
+    phases: Vec<Histogram<u64>>,
+    /// when the burst being acked was due and how many of it were acked
+    burst: Option<(Instant, u64)>,
+    /// start of a burst until its last publish was acked
+    burst_drains: Histogram<u64>,
+}
+
+impl Acks {
+    fn new(config: &BenchConfig) -> Acks {
+        let phases = config.schedule.as_ref().map_or(0, |s| s.phases.len());
+        Acks {
+            latencies: latency_histogram(),
+            warmup: latency_histogram(),
+            corrected: latency_histogram(),
+            rtts: [
+                latency_histogram(),
+                latency_histogram(),
+                latency_histogram(),
+            ],
+            pubrecs: latency_histogram(),
+            pubcomps: latency_histogram(),
+            phases: vec![latency_histogram(); phases],
+            burst: None,
+            burst_drains: latency_histogram(),
+        }
+    }
+
+    /// Records a publish acked `elapsed` after it was written, past warmup
+    fn record(&mut self, elapsed: Duration, acked: &Acked, burst_size: Option<u64>) {
+        let micros = elapsed.as_micros() as u64;
+        self.latencies.record(micros).unwrap();
+        if let Some(pubrel) = acked.pubrel {
+            let elapsed = pubrel.elapsed().as_micros() as u64;
+            self.pubcomps.record(elapsed).unwrap();
+        }
+
+        let enqueued = match acked.enqueued {
+            Some(enqueued) => enqueued,
+            None => return,
+        };
+        let rtt = enqueued.at.elapsed().as_micros() as u64;
+        self.rtts[enqueued.qos as usize].record(rtt).unwrap();
+        if let Some(due) = enqueued.due {
+            let corrected = due.elapsed().as_micros() as u64;
+            self.corrected.record(corrected).unwrap();
+        }
+        if let Some(phase) = enqueued.phase {
+            self.phases[phase].record(micros).unwrap();
+        }
+        // publishes of a burst are all due at its start
+        if let (Some(due), Some(size)) = (enqueued.due, burst_size) {
+            let acked = match self.burst {
+                Some((start, acked)) if start == due => acked + 1,
+                _ => 1,
+            };
+            self.burst = Some((due, acked));
+            if acked == size {
+                let drain = due.elapsed().as_micros() as u64;
+                self.burst_drains.record(drain).unwrap();
+            }
+        }
+    }
+}
+
+/// Publishes written to the network
+struct Written {
+    /// length of the topic publishes go to
+    topic_len: usize,
+    /// by qos
+    qos: [u64; 3],
+    /// in every phase of `--schedule`
+    phases: Vec<u64>,
+    payload_bytes: u64,
+    /// estimated size on the wire, including mqtt headers
+    wire_bytes: u64,
+    /// intervals between consecutive publishes
+    intervals: IntervalStats,
+    last: Option<Instant>,
+}
+
+impl Written {
+    fn new(config: &BenchConfig, topic_len: usize) -> Written {
+        let phases = config.schedule.as_ref().map_or(0, |s| s.phases.len());
+        Written {
+            topic_len,
+            qos: [0; 3],
+            phases: vec![0; phases],
+            payload_bytes: 0,
+            wire_bytes: 0,
+            intervals: IntervalStats::default(),
+            last: None,
+        }
+    }
+
+    /// Records a publish written to the network, `publish` unless it's a
+    /// retransmission
+    fn record(&mut self, publish: Option<&Enqueued>) {
+        if let Some(last) = self.last {
+            self.intervals.record(last.elapsed());
+        }
+        self.last = Some(Instant::now());
+
+        let publish = match publish {
+            Some(publish) => publish,
+            None => return,
+        };
+        if let Some(phase) = publish.phase {
+            self.phases[phase] += 1;
+        }
+        self.qos[publish.qos as usize] += 1;
+        self.payload_bytes += publish.payload as u64;
+        self.wire_bytes +=
+            payload::publish_size(self.topic_len, publish.qos, publish.payload) as u64;
+    }
+}
+
+/// Keep alive pings of a connection
+struct Pings {
+    sent: u64,
+    responses: u64,
+    /// when the ping awaiting a response was sent
+    pending: Option<Instant>,
+    /// ping to ping response, in microseconds
+    latencies: Histogram<u64>,
+}
+
+impl Pings {
+    fn new() -> Pings {
+        Pings {
+            sent: 0,
+            responses: 0,
+            pending: None,
+            latencies: latency_histogram(),
+        }
+    }
+
+    fn sent(&mut self) {
+        self.sent += 1;
+        self.pending = Some(Instant::now());
+    }
+
+    fn responded(&mut self) {
+        self.responses += 1;
+        if let Some(ping) = self.pending.take() {
+            self.latencies
+                .record(ping.elapsed().as_micros() as u64)
+                .unwrap();
+        }
+    }
+}
+
+/// make count number of requests at specified QoS, or as many as fit in
+/// `--duration`. Every publish is announced on `enqueued` just before it's
+/// handed to the client, to measure round trip times from that point on.
+/// Returns how many publishes were handed to the client
+async fn make_requests(
     topic: String,
-    payload_size: usize,
-    mut count: usize,
     client: AsyncClient,
-    qos: QoS,
-    delay: u64,
-) {
-    let mut interval = match delay {
-        0 => None,
-        delay => Some(time::interval(time::Duration::from_millis(delay))),
-    };
+    config: Arc<BenchConfig>,
+    enqueued: mpsc::UnboundedSender<Enqueued>,
+    requests: Arc<Mutex<Requests>>,
+) -> usize {
+    let qos = get_qos(config.publish_qos);
+    let mut count = config.count;
+    let mut levels = config.qos_mix.map(|mix| mix.levels(count));
+    let latency_tracking = config.latency_tracking;
+    let sequence_tracking = config.sequence_tracking;
+    let retain_ratio = retain::ratio(&config);
+    let payloads = payload::Payloads::new(
+        payload::Sizes::new(&config),
+        latency_tracking,
+        sequence_tracking,
+    );
+
+    // the deadline is no later than the pacer's own start, so that tokens of
+    // a schedule that's over are past it
+    let start = Instant::now();
+    let deadline = config.duration.map(|duration| start + duration);
+    let mut pacer = pacer::pacer(&config);
 
     // For QoS requests we send an extra publish at last with QoS1 which is used for syncronization
-    if qos == QoS::AtMostOnce {
+    if deadline.is_some() {
+        count = usize::MAX;
+    } else if qos == QoS::AtMostOnce && levels.is_none() {
         count -= 1;
     }
 
+    let mut published = 0;
     for i in 0..count {
-        let payload = vec![0; payload_size];
-        if let Some(interval) = &mut interval {
-            interval.tick().await;
-        }
+        let due = match (&mut pacer, deadline) {
+            (Some(pacer), Some(deadline)) => {
+                // tokens already due resolve even once the deadline passed
+                let due = tokio::select! {
+                    due = time::timeout_at(deadline.into(), pacer.wait()) => due.ok(),
+                    _ = soak::stopped() => None,
+                };
+                match due {
+                    Some(due) if due < deadline => Some(due),
+                    _ => break,
+                }
+            }
+            (Some(pacer), None) => Some(pacer.wait().await),
+            (None, Some(deadline)) if Instant::now() >= deadline || soak::is_stopped() => break,
+            (None, _) => None,
+        };
+
+        let payload = payloads.next(sequence_tracking.then_some(i as u64));
+        let qos = match &mut levels {
+            Some(levels) => levels.next().unwrap_or(qos),
+            None => qos,
+        };
+        let phase = config
+            .schedule
+            .as_ref()
+            .zip(due)
+            .map(|(schedule, due)| schedule.phase_at(due.saturating_duration_since(start)));
 
         // These errors are usually due to eventloop task being dead. We can ignore the
         // error here as the failed eventloop task would have already printed an error
-        if let Err(_e) = client.publish(topic.as_str(), qos, false, payload).await {
-            break;
+        let _ = enqueued.send(Enqueued {
+            at: Instant::now(),
+            due,
+            qos,
+            payload: payload.len(),
+            phase,
+        });
+        let retain = retain::retained(i, retain_ratio);
+        if let Err(_e) = client
+            .publish_bytes(topic.as_str(), qos, retain, payload)
+            .await
+        {
+            // the client is closed, so none of the remaining publishes can be sent
+            requests.lock().unwrap().failed += match deadline {
+                Some(_) => 1,
+                None => count as u64 - i as u64,
+            };
+            return published;
         }
 
+        requests.lock().unwrap().enqueued();
+        published += 1;
+
         info!("published {}", i);
     }
 
-    if qos == QoS::AtMostOnce {
-        let payload = vec![0; payload_size];
-        if let Err(_e) = client
-            .publish(topic.as_str(), QoS::AtLeastOnce, false, payload)
+    if qos == QoS::AtMostOnce && levels.is_none() {
+        let count = published;
+        let payload = payloads.next(sequence_tracking.then_some(count as u64));
+        let _ = enqueued.send(Enqueued {
+            at: Instant::now(),
+            due: None,
+            qos: QoS::AtLeastOnce,
+            payload: payload.len(),
+            phase: None,
+        });
+        let retain = retain::retained(count, retain_ratio);
+        match client
+            .publish_bytes(topic.as_str(), QoS::AtLeastOnce, retain, payload)
             .await
         {
-            // TODO
+            Ok(_) => {
+                requests.lock().unwrap().enqueued();
+                published += 1;
+            }
+            Err(_e) => requests.lock().unwrap().failed += 1,
         }
     }
+
+    published
 }
 
 /// get QoS level. Default is AtLeastOnce.
@@ -262,16 +940,3 @@ fn get_qos(qos: i16) -> QoS {
         _ => QoS::AtLeastOnce,
     }
 }
-
-fn options(config: Arc<BenchConfig>, id: &str) -> io::Result<MqttOptions> {
-    let mut options = MqttOptions::new(id, &config.server, config.port);
-    options.set_keep_alive(Duration::from_secs(config.keep_alive));
-    options.set_inflight(config.max_inflight);
-
-    if let Some(ca_file) = &config.ca_file {
-        let ca = fs::read(ca_file)?;
-        options.set_transport(Transport::tls(ca, None, None));
-    }
-
-    Ok(options)
-}
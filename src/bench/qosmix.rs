@@ -0,0 +1,107 @@
+//! Blend of publish QoS levels. With `--qos-mix`, every publisher publishes
+//! at all QoS levels of the mix, each with its weight, instead of at
+//! `--publish-qos` only. Levels are interleaved evenly rather than randomly,
+//! so that every run publishes the same sequence, and the report breaks
+//! publishes, receives and latencies down by QoS
+
+use rumqttc::QoS;
+use serde::Serialize;
+
+use crate::BenchConfig;
+
+/// Weights of QoS 0, 1 and 2
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct QosMix {
+    pub weights: [u64; 3],
+}
+
+impl QosMix {
+    /// Highest QoS of the mix
+    pub fn max(&self) -> i16 {
+        (0..3).rev().find(|&qos| self.weights[qos] > 0).unwrap_or(0) as i16
+    }
+
+    /// QoS levels of `count` publishes, in order
+    pub(crate) fn levels(&self, count: usize) -> Levels {
+        Levels {
+            weights: self.weights,
+            current: [0; 3],
+            remaining: count,
+        }
+    }
+
+    /// Publishes of `count` that get acked
+    pub(crate) fn acked(&self, count: usize) -> usize {
+        self.levels(count)
+            .filter(|&qos| qos != QoS::AtMostOnce)
+            .count()
+    }
+}
+
+/// Smooth weighted round robin over the QoS levels of a mix. The last publish
+/// is at least QoS 1, so that its ack tells that every publish before it went
+/// out
+pub(crate) struct Levels {
+    weights: [u64; 3],
+    current: [i64; 3],
+    remaining: usize,
+}
+
+impl Iterator for Levels {
+    type Item = QoS;
+
+    fn next(&mut self) -> Option<QoS> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        let total: u64 = self.weights.iter().sum();
+        for (current, weight) in self.current.iter_mut().zip(self.weights) {
+            *current += weight as i64;
+        }
+        // ties go to the lowest qos
+        let qos = (0..3)
+            .rev()
+            .max_by_key(|&qos| self.current[qos])
+            .unwrap_or(0);
+        self.current[qos] -= total as i64;
+
+        Some(match qos {
+            0 if self.remaining == 0 => QoS::AtLeastOnce,
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            _ => QoS::ExactlyOnce,
+        })
+    }
+}
+
+/// Highest QoS publishers of the run publish at
+pub(crate) fn max_qos(config: &BenchConfig) -> i16 {
+    match config.qos_mix {
+        Some(mix) => mix.max(),
+        None => config.publish_qos,
+    }
+}
+
+/// `QOS:WEIGHT` pairs, e.g. `0:50,1:40,2:10`
+pub(crate) fn parse(mix: &str) -> Result<QosMix, String> {
+    let mut weights = [0; 3];
+    for pair in mix.split(',') {
+        let Some((qos, weight)) = pair.split_once(':') else {
+            return Err(format!("expected QOS:WEIGHT, got `{pair}`"));
+        };
+        let qos = match qos.trim() {
+            "0" => 0,
+            "1" => 1,
+            "2" => 2,
+            qos => return Err(format!("invalid qos `{qos}`")),
+        };
+        weights[qos] = weight
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid weight `{weight}`: {e}"))?;
+    }
+
+    if weights.iter().all(|&weight| weight == 0) {
+        return Err("weights add up to 0".to_owned());
+    }
+
+    Ok(QosMix { weights })
+}
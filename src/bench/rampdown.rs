@@ -0,0 +1,143 @@
+//! Ramp down. With `--ramp-down-rate`, publishers and subscribers keep their
+//! connections once they're done, and the run closes them at that rate once
+//! every one of them is done instead of all at once. Graceful ramp downs send
+//! a DISCONNECT and time how long the broker takes to close the connection,
+//! abrupt ones drop the connection so that the broker has to notice and, with
+//! `--will-topic`, deliver the wills of the publishers
+
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use rumqttc::{AsyncClient, Event, EventLoop, Outgoing};
+use serde::Serialize;
+use tokio::{task, time};
+
+use crate::{
+    bench::will::Kills,
+    common::{latency_histogram, LatencySummary},
+    BenchConfig, RampDownMode,
+};
+
+#[derive(Debug, Serialize)]
+pub struct RampDownReport {
+    pub mode: RampDownMode,
+    /// connections closed per second
+    pub rate: u64,
+    pub closed: usize,
+    /// from closing the first connection until the last one was closed
+    pub duration_secs: f64,
+    /// from the DISCONNECT until the broker closed the connection, when
+    /// graceful
+    pub close_latencies: LatencySummary,
+    /// connections the broker didn't close within `--conn-timeout` of their
+    /// DISCONNECT
+    pub lingering: usize,
+}
+
+/// A connection kept open until its turn to close
+pub(crate) struct Connection {
+    pub id: String,
+    /// whether the connection has a last will
+    pub will: bool,
+    pub client: AsyncClient,
+    pub eventloop: EventLoop,
+}
+
+/// Connections that are done, in the order they finished
+pub(crate) struct RampDown {
+    connections: Mutex<Vec<Connection>>,
+    /// when publishers were dropped, for the will monitor
+    kills: Option<Kills>,
+}
+
+impl RampDown {
+    pub(crate) fn new(kills: Option<Kills>) -> RampDown {
+        RampDown {
+            connections: Mutex::new(Vec::new()),
+            kills,
+        }
+    }
+
+    pub(crate) fn hold(&self, connection: Connection) {
+        self.connections.lock().unwrap().push(connection);
+    }
+
+    /// Closes every connection held, `--ramp-down-rate` per second
+    pub(crate) async fn close(&self, config: &Arc<BenchConfig>) -> RampDownReport {
+        let connections = mem::take(&mut *self.connections.lock().unwrap());
+        let closed = connections.len();
+        let mode = config.ramp_down_mode;
+        let rate = config.ramp_down_rate.unwrap_or(1);
+        let mut interval = time::interval(Duration::from_secs_f64(1.0 / rate as f64));
+
+        let start = Instant::now();
+        let mut closing = futures::stream::FuturesUnordered::new();
+        for connection in connections {
+            interval.tick().await;
+            match mode {
+                RampDownMode::Graceful => {
+                    let timeout = config.conn_timeout;
+                    closing.push(task::spawn(disconnect(connection, timeout)));
+                }
+                RampDownMode::Abrupt => {
+                    let Connection { id, will, .. } = connection;
+                    if let Some(kills) = self.kills.as_ref().filter(|_| will) {
+                        kills.lock().unwrap().insert(id, Instant::now());
+                    }
+                }
+            }
+        }
+
+        let mut histogram = latency_histogram();
+        let mut lingering = 0;
+        while let Some(closed) = closing.next().await {
+            match closed {
+                Ok(Some(latency)) => histogram.record(latency.as_micros() as u64).unwrap(),
+                Ok(None) => lingering += 1,
+                Err(e) => error!("Failed to close connection = {:?}", e),
+            }
+        }
+
+        RampDownReport {
+            mode,
+            rate,
+            closed,
+            duration_secs: start.elapsed().as_secs_f64(),
+            close_latencies: LatencySummary::from(&histogram),
+            lingering,
+        }
+    }
+}
+
+/// Sends a DISCONNECT and waits for the broker to close the connection.
+/// Returns how long that took, unless the broker kept it open past `timeout`
+async fn disconnect(mut connection: Connection, timeout: Duration) -> Option<Duration> {
+    if let Err(e) = connection.client.try_disconnect() {
+        error!("Id = {}, Failed to disconnect = {:?}", connection.id, e);
+        return None;
+    }
+
+    let mut sent = None;
+    let closed = time::timeout(timeout, async {
+        loop {
+            match connection.eventloop.poll().await {
+                Ok(Event::Outgoing(Outgoing::Disconnect)) => sent = Some(Instant::now()),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+    })
+    .await;
+
+    match closed {
+        Ok(()) => sent.map(|sent| sent.elapsed()),
+        Err(_) => {
+            warn!("Id = {}, Broker kept the connection open", connection.id);
+            None
+        }
+    }
+}
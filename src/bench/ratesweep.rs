@@ -0,0 +1,128 @@
+//! Rate sweep. Once the run is done, the same publishers and subscribers run
+//! again at every rate of `--rate-sweep`, one after the other, for the curve
+//! of latencies against throughput. Latencies stay flat while the broker keeps
+//! up and shoot up past the rate it can sustain, which `--rate-sweep-file`
+//! writes out as csv to plot
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use crate::{
+    bench::{assertions::Assertion, report::Report, reporter::Reporter, workload},
+    common::LatencySummary,
+    BenchConfig,
+};
+
+#[derive(Debug, Serialize)]
+pub struct RateSweepReport {
+    pub steps: Vec<RateStep>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RateStep {
+    /// publishes per second of every publisher
+    pub rate: u64,
+    /// publishes per second of all publishers together
+    pub target_throughput: f64,
+    pub publish_throughput: f64,
+    pub incoming_throughput: f64,
+    pub loss_percent: f64,
+    pub ack_latencies: LatencySummary,
+    pub latencies: LatencySummary,
+}
+
+pub(crate) async fn sweep(config: &Arc<BenchConfig>) -> RateSweepReport {
+    let mut steps = Vec::with_capacity(config.rate_sweep.len());
+    for &rate in config.rate_sweep.iter() {
+        println!("Rerunning with --rate {rate}");
+        let mut step = (**config).clone();
+        step.rate = rate;
+        let config = Arc::new(step);
+
+        let workload = workload(&config, None).await;
+        let summary = Report::new(
+            &config,
+            workload.started_at,
+            workload.connect_elapsed,
+            workload.elapsed,
+            &workload.pub_stats,
+            &workload.sub_stats,
+            Vec::new(),
+        )
+        .summary;
+
+        steps.push(RateStep {
+            rate,
+            target_throughput: (rate * config.publishers as u64) as f64,
+            publish_throughput: summary.publish_throughput,
+            incoming_throughput: summary.incoming_throughput,
+            loss_percent: summary.loss_percent,
+            ack_latencies: summary.ack_latencies,
+            latencies: summary.latencies,
+        });
+    }
+
+    RateSweepReport { steps }
+}
+
+/// Writes the steps of the sweep to a csv file once it's done
+pub(crate) struct RateSweepCsv {
+    path: PathBuf,
+}
+
+impl RateSweepCsv {
+    pub fn new(path: PathBuf) -> RateSweepCsv {
+        RateSweepCsv { path }
+    }
+}
+
+impl Reporter for RateSweepCsv {
+    fn name(&self) -> &'static str {
+        "rate sweep"
+    }
+
+    fn finish(&mut self, report: &Report, _assertions: &[Assertion]) -> anyhow::Result<()> {
+        if let Some(sweep) = &report.rate_sweep {
+            write_csv(&sweep.steps, &self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes one row per step to a csv file at `path`
+fn write_csv(steps: &[RateStep], path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "rate,target_throughput,publish_throughput,incoming_throughput,loss_percent,ack_latency_p50_ms,ack_latency_p99_ms,ack_latency_max_ms,latency_p50_ms,latency_p99_ms,latency_max_ms"
+    )?;
+
+    for step in steps {
+        writeln!(
+            writer,
+            "{},{:.2},{:.2},{:.2},{:.2},{},{}",
+            step.rate,
+            step.target_throughput,
+            step.publish_throughput,
+            step.incoming_throughput,
+            step.loss_percent,
+            csv_latencies(&step.ack_latencies),
+            csv_latencies(&step.latencies),
+        )?;
+    }
+
+    writer.flush()
+}
+
+fn csv_latencies(latencies: &LatencySummary) -> String {
+    format!(
+        "{:.3},{:.3},{:.3}",
+        latencies.p50, latencies.p99, latencies.max
+    )
+}
@@ -0,0 +1,2183 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bench::{
+        alias::AliasReport,
+        assertions::Assertion,
+        assigned::AssignedIdsReport,
+        churn::ChurnReport,
+        endpoint_label,
+        expiry::ExpiryReport,
+        findmax::FindMaxReport,
+        flow::FlowReport,
+        inflightsweep::InflightSweepReport,
+        keepalive::KeepAliveSweepReport,
+        legacy::Mqtt31Report,
+        matrix,
+        metrics::Sample,
+        packetsize::{MaxPacketSizeReport, PacketSizeProbeReport},
+        payloadsweep::PayloadSweepReport,
+        properties::PropertiesReport,
+        qosmix,
+        rampdown::RampDownReport,
+        ratesweep::RateSweepReport,
+        retain::RetainedReport,
+        retainflood::RetainFloodReport,
+        sessions::SessionExpiryReport,
+        shared::Distribution,
+        subopts::SubscriptionOptionsReport,
+        subscribed_publishes,
+        sys::SysValue,
+        takeover::TakeoverReport,
+        wildcard,
+        will::WillReport,
+        willdelay::WillDelayReport,
+    },
+    common::{
+        Flaps, IntervalStats, LatencySummary, Outage, PubStats, RequestStats, Resumption, SubStats,
+    },
+    BenchConfig, RampDownMode,
+};
+
+/// Final results of a benchmark run
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub metadata: Metadata,
+    pub summary: Summary,
+    pub aggregate: Aggregate,
+    pub publishers: Vec<PublisherReport>,
+    pub subscribers: Vec<SubscriberReport>,
+    /// publishers and subscribers of every broker, when given several
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<EndpointReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub topics: Vec<TopicReport>,
+    /// publishes that never arrived, when tracking sequences
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub gaps: Vec<GapReport>,
+    /// per second samples over the whole run, or its last hour with `--forever`
+    pub timeseries: Vec<Sample>,
+    /// `$SYS` topics reported by the broker during the run
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub broker: BTreeMap<String, SysValue>,
+    /// retained publishes delivered to a late subscriber, when retaining
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retained: Option<RetainedReport>,
+    /// last wills delivered for killed publishers, when configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wills: Option<WillReport>,
+    /// connections closed at a controlled rate once the run was done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ramp_down: Option<RampDownReport>,
+    /// publishing to long topics with and without mqtt 5 topic aliases
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<AliasReport>,
+    /// publishing with and without mqtt 5 user properties
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<PropertiesReport>,
+    /// expired publishes queued for an offline mqtt 5 subscriber
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expired: Option<ExpiryReport>,
+    /// mqtt 5 sessions that came back before and after they expired
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_expiry: Option<SessionExpiryReport>,
+    /// delayed wills of mqtt 5 clients that dropped their connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub will_delay: Option<WillDelayReport>,
+    /// publishes delivered to mqtt 5 subscribers with a small receive maximum
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow: Option<FlowReport>,
+    /// mqtt 5 subscription options honoured by the broker
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription_options: Option<SubscriptionOptionsReport>,
+    /// packets over the maximum packet size of an mqtt 5 subscriber
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_packet_size: Option<MaxPacketSizeReport>,
+    /// largest publish the broker accepts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packet_size_probe: Option<PacketSizeProbeReport>,
+    /// client ids the broker assigned to mqtt 5 clients without one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assigned_client_ids: Option<AssignedIdsReport>,
+    /// pings of batches of idle connections with different keep alives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive_sweep: Option<KeepAliveSweepReport>,
+    /// mqtt 3.1 publisher and subscriber
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mqtt31: Option<Mqtt31Report>,
+    /// connections that kept reconnecting during the run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub churn: Option<ChurnReport>,
+    /// connections taken over by another with the same client id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub takeover: Option<TakeoverReport>,
+    /// backlog of retained publishes delivered to a wildcard subscriber
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retain_flood: Option<RetainFloodReport>,
+    /// publishes of growing payload sizes between a dedicated pair
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_sweep: Option<PayloadSweepReport>,
+    /// reruns of the workload with different inflight windows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inflight_sweep: Option<InflightSweepReport>,
+    /// latencies against throughput across rates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_sweep: Option<RateSweepReport>,
+    /// highest rate that met the assertions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub find_max: Option<FindMaxReport>,
+}
+
+/// What produced the report, so that it can be interpreted long after the run
+#[derive(Debug, Serialize)]
+pub struct Metadata {
+    pub mqttwrk_version: &'static str,
+    pub rumqttc_version: &'static str,
+    pub hostname: String,
+    /// rfc3339, when connections started to be established
+    pub started_at: String,
+    /// rfc3339, when the last connection finished
+    pub finished_at: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// every option of the run, including defaults
+    pub config: serde_json::Value,
+}
+
+impl Metadata {
+    fn new(config: &BenchConfig, started_at: SystemTime, finished_at: SystemTime) -> Metadata {
+        Metadata {
+            mqttwrk_version: env!("CARGO_PKG_VERSION"),
+            rumqttc_version: env!("RUMQTTC_VERSION"),
+            hostname: whoami::fallible::hostname().unwrap_or_else(|_| "unknown".to_owned()),
+            started_at: humantime::format_rfc3339_millis(started_at).to_string(),
+            finished_at: humantime::format_rfc3339_millis(finished_at).to_string(),
+            tags: config.tags.clone(),
+            config: serde_json::to_value(config).unwrap_or_default(),
+        }
+    }
+}
+
+/// Consolidated view of the whole run across all connections
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Summary {
+    pub duration_secs: f64,
+    pub publishers: usize,
+    pub subscribers: usize,
+    /// connections established per second while spawning
+    pub connection_rate: f64,
+    pub connack_latencies: LatencySummary,
+    pub outgoing_publish: u64,
+    /// publishes per second over the whole run
+    pub publish_throughput: f64,
+    pub outgoing_bytes: Bytes,
+    pub expected_incoming: u64,
+    pub incoming_publish: u64,
+    /// receives per second over the whole run
+    pub incoming_throughput: f64,
+    pub incoming_bytes: Bytes,
+    pub lost: u64,
+    pub loss_percent: f64,
+    /// publishes delivered more than once, when tracking sequences
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<Duplicates>,
+    /// publishes delivered out of order, when tracking sequences
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reordering: Option<Reordering>,
+    pub reconnects: u64,
+    pub downtime: Downtime,
+    pub ack_latencies: LatencySummary,
+    /// ack latencies from when publishes were due at the configured rate, i.e.
+    /// corrected for coordinated omission. Only with a rate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corrected_ack_latencies: Option<LatencySummary>,
+    /// enqueue to puback (qos 1) or pubcomp (qos 2) round trip times
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rtts: Vec<QosLatencies>,
+    /// halves of the qos 2 handshake, when publishing at qos 2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake: Option<Handshake>,
+    /// whether every qos 2 publish arrived exactly once, when publishing and
+    /// subscribing at qos 2 and tracking sequences
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exactly_once: Option<ExactlyOnce>,
+    /// end to end latencies, with `--latency-tracking`
+    pub latencies: LatencySummary,
+    pub suback_latencies: LatencySummary,
+    /// intervals between publishes written by each publisher
+    pub publish_jitter: Jitter,
+    /// intervals between publishes arriving at each subscriber
+    pub arrival_jitter: Jitter,
+    /// percentiles of the intervals of `arrival_jitter`
+    pub arrival_gaps: LatencySummary,
+    /// latencies measured during warmup, excluded from the ones above
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<Warmup>,
+    /// how fast the broker took in bursts, when publishing in bursts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bursts: Option<Bursts>,
+    /// how publishers held up keeping their connections alive, with `--idle`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle: Option<Idle>,
+    /// publishes and ack latencies in every phase, with a schedule
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub phases: Vec<PhaseSummary>,
+    /// publishes, receives and latencies by qos, with `--qos-mix`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub qos_mix: Vec<QosShare>,
+    /// publishes, receives and latencies of every group, with `--groups`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupSummary>,
+    /// deliveries of the first publisher to every subscriber, with `--fan-out`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fan_out: Option<FanOut>,
+    /// spread of publishes across subscribers, when sharing a subscription
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared: Option<Distribution>,
+    /// publishes queued for subscribers that went offline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sessions: Option<Sessions>,
+    /// how the fleet came back from dropping every connection at once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reconnect_storm: Option<ReconnectStorm>,
+    /// latencies of stable connections before and while flappy ones flap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flapping: Option<Flapping>,
+    /// of mqttwrk over the samples of `timeseries`
+    pub resources: ResourceUsage,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Warmup {
+    pub duration_secs: f64,
+    pub ack_latencies: LatencySummary,
+    pub latencies: LatencySummary,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Idle {
+    pub hold_secs: f64,
+    pub connections: usize,
+    /// connections that were up when the hold was over
+    pub held: u64,
+    pub pings: u64,
+    /// pings the broker answered
+    pub ping_responses: u64,
+    pub ping_latencies: LatencySummary,
+    /// times the broker or the network dropped a connection
+    pub disconnects: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectStorm {
+    /// publishing time before every connection was dropped
+    pub after_secs: f64,
+    pub connections: usize,
+    /// connections still up when the storm hit
+    pub dropped: u64,
+    /// connections the broker accepted again
+    pub reconnected: u64,
+    /// connections with publishes flowing again
+    pub resumed: u64,
+    /// from the drop until the broker accepted each connection again
+    pub reconnect_latencies: LatencySummary,
+    /// from the drop until the first ack or publish on each connection
+    pub resume_latencies: LatencySummary,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Flapping {
+    pub percent: f64,
+    pub every_secs: f64,
+    pub flappy_publishers: u64,
+    pub stable_publishers: u64,
+    pub flappy_subscribers: u64,
+    pub stable_subscribers: u64,
+    pub flaps: u64,
+    /// from a flap until the broker accepted the connection again
+    pub reconnect_latencies: LatencySummary,
+    /// ack latencies of stable publishers before the first flap
+    pub calm_ack_latencies: LatencySummary,
+    /// ack latencies of stable publishers once flappy connections flapped
+    pub flapping_ack_latencies: LatencySummary,
+    /// latencies of publishes from stable publishers to stable subscribers
+    /// before the first flap
+    pub calm_latencies: LatencySummary,
+    /// latencies of publishes from stable publishers to stable subscribers
+    /// once flappy connections flapped
+    pub flapping_latencies: LatencySummary,
+    /// change of p99 ack latency of stable publishers once flapping, unless
+    /// either had no samples
+    pub ack_p99_change_percent: Option<f64>,
+    /// change of p99 latency of stable subscribers once flapping
+    pub p99_change_percent: Option<f64>,
+}
+
+impl Flapping {
+    fn new(
+        config: &BenchConfig,
+        percent: f64,
+        publishers: &Flaps,
+        subscribers: &Flaps,
+    ) -> Flapping {
+        let mut flaps = publishers.clone();
+        flaps.merge(subscribers);
+        let calm_ack_latencies = LatencySummary::from(&publishers.calm);
+        let flapping_ack_latencies = LatencySummary::from(&publishers.flapping);
+        let calm_latencies = LatencySummary::from(&subscribers.calm);
+        let flapping_latencies = LatencySummary::from(&subscribers.flapping);
+        let change = |calm: &LatencySummary, flapping: &LatencySummary| {
+            (calm.samples > 0 && flapping.samples > 0 && calm.p99 > 0.0)
+                .then(|| (flapping.p99 / calm.p99 - 1.0) * 100.0)
+        };
+
+        Flapping {
+            percent,
+            every_secs: config.flap_every.as_secs_f64(),
+            flappy_publishers: publishers.flappy,
+            stable_publishers: publishers.stable,
+            flappy_subscribers: subscribers.flappy,
+            stable_subscribers: subscribers.stable,
+            flaps: flaps.flaps,
+            reconnect_latencies: LatencySummary::from(&flaps.reconnects),
+            ack_p99_change_percent: change(&calm_ack_latencies, &flapping_ack_latencies),
+            p99_change_percent: change(&calm_latencies, &flapping_latencies),
+            calm_ack_latencies,
+            flapping_ack_latencies,
+            calm_latencies,
+            flapping_latencies,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bursts {
+    pub size: u64,
+    pub interval_secs: f64,
+    /// bursts whose every publish was acked
+    pub acked: u64,
+    /// time from the start of a burst until its last publish was acked
+    pub drain_latencies: LatencySummary,
+    /// ack latencies from the start of the burst, which publishes later in a
+    /// burst wait longer for
+    pub ack_latencies: LatencySummary,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GroupSummary {
+    pub name: String,
+    pub publishers: usize,
+    pub outgoing_publish: u64,
+    /// publishes per second of the group over the whole run
+    pub publish_throughput: f64,
+    /// publishes of the group delivered to subscribers
+    pub incoming_publish: u64,
+    pub reconnects: u64,
+    pub ack_latencies: LatencySummary,
+    /// end to end latencies of publishes of the group
+    pub latencies: LatencySummary,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QosShare {
+    pub qos: u8,
+    /// weight of the qos in the mix
+    pub weight: u64,
+    /// publishes written to the network at the qos
+    pub outgoing_publish: u64,
+    /// publishes delivered at the qos, which is capped by `--subscribe-qos`
+    pub incoming_publish: u64,
+    /// enqueue to ack round trip times, unless qos 0
+    pub ack_rtts: LatencySummary,
+    /// end to end latencies of publishes delivered at the qos
+    pub latencies: LatencySummary,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PhaseSummary {
+    pub start_secs: f64,
+    pub end_secs: f64,
+    /// publishes per second of the schedule
+    pub target_rate: u64,
+    pub outgoing_publish: u64,
+    /// publishes per second achieved
+    pub publish_throughput: f64,
+    pub ack_latencies: LatencySummary,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FanOut {
+    pub subscribers: usize,
+    /// deliveries per publish of the fanned out publisher
+    pub amplification: f64,
+    /// deliveries per second across all subscribers
+    pub delivery_throughput: f64,
+    /// median latencies of subscribers
+    pub p50: Spread,
+    /// p99 latencies of subscribers
+    pub p99: Spread,
+    /// subscriber with the highest p99 latency
+    pub slowest: String,
+}
+
+/// How a latency varies across connections, in milliseconds
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Spread {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+impl Spread {
+    fn new(latencies: &[f64]) -> Spread {
+        let count = latencies.len().max(1) as f64;
+        let mean = latencies.iter().sum::<f64>() / count;
+        let variance = latencies.iter().map(|l| (l - mean).powi(2)).sum::<f64>() / count;
+
+        Spread {
+            min: latencies.iter().copied().reduce(f64::min).unwrap_or(0.0),
+            max: latencies.iter().copied().reduce(f64::max).unwrap_or(0.0),
+            mean,
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+impl fmt::Display for Spread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min = {:.3}ms, max = {:.3}ms, mean = {:.3}ms, stddev = {:.3}ms",
+            self.min, self.max, self.mean, self.stddev
+        )
+    }
+}
+
+/// Latencies of the two round trips of qos 2 publishes. Publish to pubcomp is
+/// reported as the ack latency
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Handshake {
+    /// publish to pubrec
+    pub pubrec_latencies: LatencySummary,
+    /// pubrel to pubcomp
+    pub pubcomp_latencies: LatencySummary,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExactlyOnce {
+    pub lost: u64,
+    pub duplicates: u64,
+    /// neither lost nor duplicated publishes
+    pub verified: bool,
+}
+
+/// Subscribers going offline for `--offline-for` and resuming their sessions
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sessions {
+    pub offline_secs: f64,
+    /// subscribers that reconnected
+    pub resumed: usize,
+    /// subscribers whose session the broker kept
+    pub session_present: usize,
+    /// publishes sent while subscribers were offline and delivered afterwards
+    pub queued: u64,
+    /// longest time from reconnecting until the last queued publish arrived
+    pub drain_secs: f64,
+    /// queued publishes delivered per second while draining
+    pub drain_rate: f64,
+}
+
+impl Sessions {
+    fn new(offline_for: Duration, resumptions: &[Resumption]) -> Sessions {
+        let queued = resumptions.iter().map(|r| r.queued).sum();
+        let drain = resumptions
+            .iter()
+            .map(|r| r.drain)
+            .max()
+            .unwrap_or_default();
+
+        Sessions {
+            offline_secs: offline_for.as_secs_f64(),
+            resumed: resumptions.len(),
+            session_present: resumptions.iter().filter(|r| r.session_present).count(),
+            queued,
+            drain_secs: drain.as_secs_f64(),
+            drain_rate: match drain.as_secs_f64() {
+                secs if secs > 0.0 => queued as f64 / secs,
+                _ => 0.0,
+            },
+        }
+    }
+}
+
+/// Bytes transferred in publishes. Wire bytes are estimated from the size of
+/// mqtt headers, without tcp/tls overhead
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bytes {
+    pub payload: u64,
+    pub wire: u64,
+    /// payload megabytes (10^6 bytes) per second over the whole run
+    pub payload_mb_per_sec: f64,
+    pub wire_mb_per_sec: f64,
+}
+
+impl Bytes {
+    fn new(payload: u64, wire: u64, duration_secs: f64) -> Bytes {
+        Bytes {
+            payload,
+            wire,
+            payload_mb_per_sec: payload as f64 / duration_secs / 1_000_000.0,
+            wire_mb_per_sec: wire as f64 / duration_secs / 1_000_000.0,
+        }
+    }
+}
+
+/// Spread of the intervals between consecutive events
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Jitter {
+    pub intervals: u64,
+    pub mean_ms: f64,
+    pub stddev_ms: f64,
+    /// coefficient of variation, stddev relative to the mean interval
+    pub cov: f64,
+}
+
+impl From<&IntervalStats> for Jitter {
+    fn from(stats: &IntervalStats) -> Self {
+        Jitter {
+            intervals: stats.count,
+            mean_ms: stats.mean / 1000.0,
+            stddev_ms: stats.stddev() / 1000.0,
+            cov: stats.cov(),
+        }
+    }
+}
+
+impl fmt::Display for Jitter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "intervals = {}, mean = {:.3}ms, stddev = {:.3}ms, cov = {:.3}",
+            self.intervals, self.mean_ms, self.stddev_ms, self.cov
+        )
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct QosLatencies {
+    pub qos: u8,
+    pub latencies: LatencySummary,
+}
+
+/// Time connections spent disconnected from the broker
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Downtime {
+    pub outages: u64,
+    /// outages after which the connection never came back
+    pub unrecovered: u64,
+    /// sum of downtimes across all connections
+    pub total_secs: f64,
+    /// longest single outage
+    pub max_secs: f64,
+}
+
+/// Publishes and latencies of every group of `--groups`
+fn group_summaries(
+    config: &BenchConfig,
+    pub_stats: &[PubStats],
+    aggregate_substats: &SubStats,
+    duration_secs: f64,
+) -> Vec<GroupSummary> {
+    let Some(groups) = &config.groups else {
+        return Vec::new();
+    };
+
+    groups
+        .groups
+        .iter()
+        .enumerate()
+        .map(|(g, group)| {
+            let mut outgoing = PubStats::default();
+            for stats in pub_stats.iter().filter(|stats| stats.group == Some(g)) {
+                outgoing.merge(stats);
+            }
+            let incoming = aggregate_substats.groups.get(g);
+            GroupSummary {
+                name: group.name.clone(),
+                publishers: group.publishers,
+                outgoing_publish: outgoing.outgoing_publish,
+                publish_throughput: outgoing.outgoing_publish as f64 / duration_secs,
+                incoming_publish: incoming.map_or(0, |stats| stats.publish_count),
+                reconnects: outgoing.reconnects,
+                ack_latencies: LatencySummary::from(&outgoing.ack_latencies),
+                latencies: incoming
+                    .map(|stats| LatencySummary::from(&stats.latencies))
+                    .unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+impl Downtime {
+    fn from_outages(outages: &[Outage]) -> Downtime {
+        Downtime {
+            outages: outages.len() as u64,
+            unrecovered: outages
+                .iter()
+                .filter(|outage| outage.reconnected_at.is_none())
+                .count() as u64,
+            total_secs: downtime_secs(outages),
+            max_secs: outages
+                .iter()
+                .map(|o| o.downtime.as_secs_f64())
+                .fold(0.0, f64::max),
+        }
+    }
+}
+
+/// Duplicate deliveries by the qos they were delivered with
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Duplicates {
+    pub total: u64,
+    pub qos0: u64,
+    pub qos1: u64,
+    pub qos2: u64,
+}
+
+impl From<[u64; 3]> for Duplicates {
+    fn from([qos0, qos1, qos2]: [u64; 3]) -> Self {
+        Duplicates {
+            total: qos0 + qos1 + qos2,
+            qos0,
+            qos1,
+            qos2,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Reordering {
+    /// publishes that arrived after a later publish on the same topic
+    pub reordered: u64,
+    /// how many sequence numbers behind the latest publish on its topic a
+    /// reordered publish arrived
+    pub max_displacement: u64,
+}
+
+/// Cpu and memory used by mqttwrk itself, to tell whether the load generator
+/// rather than the broker was the bottleneck
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceUsage {
+    pub cores: usize,
+    /// 100% per fully used core
+    pub cpu_percent_mean: f32,
+    pub cpu_percent_max: f32,
+    pub rss_max_bytes: u64,
+}
+
+impl ResourceUsage {
+    fn from_samples(samples: &[Sample]) -> ResourceUsage {
+        let cores = std::thread::available_parallelism()
+            .map(|cores| cores.get())
+            .unwrap_or(1);
+        let count = samples.len().max(1) as f32;
+
+        ResourceUsage {
+            cores,
+            cpu_percent_mean: samples.iter().map(|s| s.cpu_percent).sum::<f32>() / count,
+            cpu_percent_max: samples.iter().map(|s| s.cpu_percent).fold(0.0, f32::max),
+            rss_max_bytes: samples.iter().map(|s| s.rss_bytes).max().unwrap_or(0),
+        }
+    }
+
+    /// Whether mqttwrk kept (almost) all the cores busy on average
+    pub fn saturated(&self) -> bool {
+        self.cpu_percent_mean >= self.cores as f32 * 90.0
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Aggregate {
+    pub publishers: PublisherReport,
+    pub subscribers: SubscriberReport,
+}
+
+/// Aggregate of the connections to one broker of `--server`
+#[derive(Debug, Serialize)]
+pub struct EndpointReport {
+    pub endpoint: String,
+    pub connections: usize,
+    pub publishers: PublisherReport,
+    pub subscribers: SubscriberReport,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublisherReport {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub outgoing_publish: u64,
+    pub throughput: f32,
+    pub reconnects: u64,
+    pub downtime_secs: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outages: Vec<OutageReport>,
+    /// publishes handed to the client by the publishing task
+    pub requests: RequestStats,
+    pub ack_latencies: LatencySummary,
+    pub connack_latencies: LatencySummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscriberReport {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub publish_count: u64,
+    pub puback_count: u64,
+    pub reconnects: u64,
+    pub downtime_secs: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outages: Vec<OutageReport>,
+    pub throughput: f32,
+    pub latencies: LatencySummary,
+    pub connack_latencies: LatencySummary,
+    pub suback_latencies: LatencySummary,
+}
+
+/// A period during which a connection was down, with times in milliseconds
+/// since unix epoch
+#[derive(Debug, Serialize)]
+pub struct OutageReport {
+    pub disconnected_at_ms: u64,
+    /// `None` if the connection never came back
+    pub reconnected_at_ms: Option<u64>,
+    pub downtime_secs: f64,
+}
+
+impl From<&Outage> for OutageReport {
+    fn from(outage: &Outage) -> Self {
+        OutageReport {
+            disconnected_at_ms: unix_millis(outage.disconnected_at),
+            reconnected_at_ms: outage.reconnected_at.map(unix_millis),
+            downtime_secs: outage.downtime.as_secs_f64(),
+        }
+    }
+}
+
+/// Incoming publishes on a topic across all subscribers
+#[derive(Debug, Serialize)]
+pub struct TopicReport {
+    pub topic: String,
+    pub publish_count: u64,
+    pub bytes: u64,
+    pub latencies: LatencySummary,
+}
+
+/// Sequence numbers a subscriber never received on a topic
+#[derive(Debug, Serialize)]
+pub struct GapReport {
+    pub subscriber: String,
+    pub topic: String,
+    pub missing: u64,
+    /// inclusive ranges of missing sequence numbers
+    pub ranges: Vec<(u64, u64)>,
+}
+
+impl From<&PubStats> for PublisherReport {
+    fn from(stats: &PubStats) -> Self {
+        PublisherReport {
+            id: stats.id.clone(),
+            outgoing_publish: stats.outgoing_publish,
+            throughput: stats.throughput,
+            reconnects: stats.reconnects,
+            downtime_secs: downtime_secs(&stats.outages),
+            outages: stats.outages.iter().map(Into::into).collect(),
+            requests: stats.requests,
+            ack_latencies: LatencySummary::from(&stats.ack_latencies),
+            connack_latencies: LatencySummary::from(&stats.connack_latencies),
+        }
+    }
+}
+
+impl From<&SubStats> for SubscriberReport {
+    fn from(stats: &SubStats) -> Self {
+        SubscriberReport {
+            id: stats.id.clone(),
+            publish_count: stats.publish_count,
+            puback_count: stats.puback_count,
+            reconnects: stats.reconnects,
+            downtime_secs: downtime_secs(&stats.outages),
+            outages: stats.outages.iter().map(Into::into).collect(),
+            throughput: stats.throughput,
+            latencies: LatencySummary::from(&stats.latencies),
+            connack_latencies: LatencySummary::from(&stats.connack_latencies),
+            suback_latencies: LatencySummary::from(&stats.suback_latencies),
+        }
+    }
+}
+
+impl Report {
+    /// Builds the report of a run from per connection stats and the samples
+    /// taken during the run. Connections started at `started_at`, took
+    /// `connect_elapsed` to establish and the run took `elapsed` after that
+    pub fn new(
+        config: &BenchConfig,
+        started_at: SystemTime,
+        connect_elapsed: Duration,
+        elapsed: Duration,
+        pub_stats: &[PubStats],
+        sub_stats: &[SubStats],
+        timeseries: Vec<Sample>,
+    ) -> Report {
+        let mut aggregate_pubstats = PubStats::default();
+        for stats in pub_stats {
+            aggregate_pubstats.merge(stats);
+        }
+
+        let mut aggregate_substats = SubStats::default();
+        for stats in sub_stats {
+            aggregate_substats.merge(stats);
+        }
+
+        // every subscriber receives publishes of all the publishers, unless
+        // they share a subscription and split them or fan out the first one.
+        // Publishers of a fixed duration publish as many as they can
+        let published = match config.duration {
+            Some(_) => pub_stats
+                .iter()
+                .filter(|stats| stats.subscribed)
+                .map(|stats| stats.requests.enqueued)
+                .sum(),
+            None => subscribed_publishes(config) as u64,
+        };
+        let expected_incoming = match (&config.share_group, config.topics) {
+            (Some(_), _) => published,
+            (None, Some(_)) => matrix::expected_total(config) as u64,
+            (None, None) if config.subscribe_only => (config.count * config.subscribers) as u64,
+            (None, None) if config.sink_filter.is_some() => {
+                (wildcard::expected(config) * config.subscribers) as u64
+            }
+            (None, None) => published * config.subscribers as u64,
+        };
+        // duplicates don't make up for lost publishes
+        let unique_incoming =
+            aggregate_substats.publish_count - aggregate_substats.duplicates.iter().sum::<u64>();
+        let lost = expected_incoming.saturating_sub(unique_incoming);
+        let loss_percent = match expected_incoming {
+            0 => 0.0,
+            expected => lost as f64 * 100.0 / expected as f64,
+        };
+
+        let mut connack_latencies = aggregate_pubstats.connack_latencies.clone();
+        connack_latencies
+            .add(&aggregate_substats.connack_latencies)
+            .expect("auto resizing histograms should merge");
+
+        let duration_secs = elapsed.as_secs_f64();
+        let summary = Summary {
+            duration_secs,
+            publishers: config.publishers,
+            subscribers: config.subscribers,
+            connection_rate: (config.publishers + config.subscribers) as f64
+                / connect_elapsed.as_secs_f64(),
+            connack_latencies: LatencySummary::from(&connack_latencies),
+            outgoing_publish: aggregate_pubstats.outgoing_publish,
+            publish_throughput: aggregate_pubstats.outgoing_publish as f64 / duration_secs,
+            outgoing_bytes: Bytes::new(
+                aggregate_pubstats.payload_bytes,
+                aggregate_pubstats.wire_bytes,
+                duration_secs,
+            ),
+            expected_incoming,
+            incoming_publish: aggregate_substats.publish_count,
+            incoming_throughput: aggregate_substats.publish_count as f64 / duration_secs,
+            incoming_bytes: Bytes::new(
+                aggregate_substats.payload_bytes,
+                aggregate_substats.wire_bytes,
+                duration_secs,
+            ),
+            lost,
+            loss_percent,
+            duplicates: config
+                .sequence_tracking
+                .then(|| aggregate_substats.duplicates.into()),
+            reordering: config.sequence_tracking.then_some(Reordering {
+                reordered: aggregate_substats.reordered,
+                max_displacement: aggregate_substats.max_displacement,
+            }),
+            reconnects: aggregate_pubstats.reconnects + aggregate_substats.reconnects,
+            downtime: Downtime::from_outages(
+                &[
+                    &aggregate_pubstats.outages[..],
+                    &aggregate_substats.outages[..],
+                ]
+                .concat(),
+            ),
+            ack_latencies: LatencySummary::from(&aggregate_pubstats.ack_latencies),
+            corrected_ack_latencies: (config.rate != 0
+                || config.delay.is_some()
+                || config.rate_ramp.is_some()
+                || config.pattern.is_some()
+                || config.schedule.is_some()
+                || config.burst_size.is_some())
+            .then(|| LatencySummary::from(&aggregate_pubstats.corrected_ack_latencies)),
+            rtts: (0..3)
+                .filter(|&qos| !aggregate_pubstats.rtts[qos].is_empty())
+                .map(|qos| QosLatencies {
+                    qos: qos as u8,
+                    latencies: LatencySummary::from(&aggregate_pubstats.rtts[qos]),
+                })
+                .collect(),
+            handshake: (qosmix::max_qos(config) == 2).then(|| Handshake {
+                pubrec_latencies: LatencySummary::from(&aggregate_pubstats.pubrec_latencies),
+                pubcomp_latencies: LatencySummary::from(&aggregate_pubstats.pubcomp_latencies),
+            }),
+            exactly_once: (config.publish_qos == 2
+                && config.subscribe_qos == 2
+                && config.sequence_tracking
+                && config.subscribers != 0)
+                .then(|| ExactlyOnce {
+                    lost,
+                    duplicates: aggregate_substats.duplicates[2],
+                    verified: lost == 0 && aggregate_substats.duplicates[2] == 0,
+                }),
+            latencies: LatencySummary::from(&aggregate_substats.latencies),
+            suback_latencies: LatencySummary::from(&aggregate_substats.suback_latencies),
+            publish_jitter: (&aggregate_pubstats.publish_intervals).into(),
+            arrival_jitter: (&aggregate_substats.arrival_intervals).into(),
+            arrival_gaps: LatencySummary::from(&aggregate_substats.arrival_gaps),
+            warmup: (!config.warmup.is_zero()).then(|| Warmup {
+                duration_secs: config.warmup.as_secs_f64(),
+                ack_latencies: LatencySummary::from(&aggregate_pubstats.warmup_ack_latencies),
+                latencies: LatencySummary::from(&aggregate_substats.warmup_latencies),
+            }),
+            idle: config.idle.map(|hold| {
+                let downtime = Downtime::from_outages(&aggregate_pubstats.outages);
+                Idle {
+                    hold_secs: hold.as_secs_f64(),
+                    connections: config.publishers,
+                    held: (config.publishers as u64).saturating_sub(downtime.unrecovered),
+                    pings: aggregate_pubstats.pings,
+                    ping_responses: aggregate_pubstats.ping_responses,
+                    ping_latencies: LatencySummary::from(&aggregate_pubstats.ping_latencies),
+                    disconnects: downtime.outages,
+                }
+            }),
+            bursts: config
+                .burst_size
+                .zip(config.burst_interval)
+                .map(|(size, interval)| Bursts {
+                    size,
+                    interval_secs: interval.as_secs_f64(),
+                    acked: aggregate_pubstats.burst_drains.len(),
+                    drain_latencies: LatencySummary::from(&aggregate_pubstats.burst_drains),
+                    ack_latencies: LatencySummary::from(
+                        &aggregate_pubstats.corrected_ack_latencies,
+                    ),
+                }),
+            qos_mix: match config.qos_mix {
+                Some(mix) => (0..3)
+                    .map(|qos| QosShare {
+                        qos: qos as u8,
+                        weight: mix.weights[qos],
+                        outgoing_publish: aggregate_pubstats.qos_publishes[qos],
+                        incoming_publish: aggregate_substats.qos_receives[qos],
+                        ack_rtts: LatencySummary::from(&aggregate_pubstats.rtts[qos]),
+                        latencies: LatencySummary::from(&aggregate_substats.qos_latencies[qos]),
+                    })
+                    .collect(),
+                None => Vec::new(),
+            },
+            groups: group_summaries(config, pub_stats, &aggregate_substats, duration_secs),
+            phases: match &config.schedule {
+                Some(schedule) => schedule
+                    .bounds()
+                    .zip(&schedule.phases)
+                    .enumerate()
+                    .map(|(i, ((start, end), phase))| {
+                        let outgoing_publish = aggregate_pubstats.phase_publishes[i];
+                        PhaseSummary {
+                            start_secs: start.as_secs_f64(),
+                            end_secs: end.as_secs_f64(),
+                            target_rate: phase.rate,
+                            outgoing_publish,
+                            publish_throughput: match phase.duration.as_secs_f64() {
+                                secs if secs > 0.0 => outgoing_publish as f64 / secs,
+                                _ => 0.0,
+                            },
+                            ack_latencies: LatencySummary::from(
+                                &aggregate_pubstats.phase_ack_latencies[i],
+                            ),
+                        }
+                    })
+                    .collect(),
+                None => Vec::new(),
+            },
+            fan_out: config.fan_out.then(|| {
+                let latencies: Vec<LatencySummary> = sub_stats
+                    .iter()
+                    .map(|stats| LatencySummary::from(&stats.latencies))
+                    .collect();
+                let p50: Vec<f64> = latencies.iter().map(|l| l.p50).collect();
+                let p99: Vec<f64> = latencies.iter().map(|l| l.p99).collect();
+                FanOut {
+                    subscribers: config.subscribers,
+                    amplification: match published {
+                        0 => 0.0,
+                        published => aggregate_substats.publish_count as f64 / published as f64,
+                    },
+                    delivery_throughput: aggregate_substats.publish_count as f64 / duration_secs,
+                    p50: Spread::new(&p50),
+                    p99: Spread::new(&p99),
+                    slowest: sub_stats
+                        .iter()
+                        .zip(&p99)
+                        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                        .map(|(stats, _)| stats.id.clone())
+                        .unwrap_or_default(),
+                }
+            }),
+            shared: config.share_group.as_ref().map(|group| {
+                let counts: Vec<u64> = sub_stats.iter().map(|stats| stats.publish_count).collect();
+                Distribution::new(group, &counts, duration_secs)
+            }),
+            sessions: config
+                .offline_for
+                .map(|offline_for| Sessions::new(offline_for, &aggregate_substats.resumptions)),
+            reconnect_storm: config.reconnect_storm.map(|after| {
+                let mut recovery = aggregate_pubstats.recovery.clone();
+                recovery.merge(&aggregate_substats.recovery);
+                ReconnectStorm {
+                    after_secs: after.as_secs_f64(),
+                    connections: config.publishers + config.subscribers,
+                    dropped: recovery.dropped,
+                    reconnected: recovery.reconnects.len(),
+                    resumed: recovery.resumes.len(),
+                    reconnect_latencies: LatencySummary::from(&recovery.reconnects),
+                    resume_latencies: LatencySummary::from(&recovery.resumes),
+                }
+            }),
+            flapping: config.flappy.map(|percent| {
+                Flapping::new(
+                    config,
+                    percent,
+                    &aggregate_pubstats.flaps,
+                    &aggregate_substats.flaps,
+                )
+            }),
+            resources: ResourceUsage::from_samples(&timeseries),
+        };
+
+        let endpoints = match config.servers.len() {
+            1 => Vec::new(),
+            _ => config
+                .servers
+                .iter()
+                .map(|endpoint| endpoint_label(config, endpoint))
+                .map(|endpoint| {
+                    let mut publishers = PubStats::default();
+                    let mut subscribers = SubStats::default();
+                    let mut connections = 0;
+                    for stats in pub_stats.iter().filter(|s| s.endpoint == endpoint) {
+                        publishers.merge(stats);
+                        connections += 1;
+                    }
+                    for stats in sub_stats.iter().filter(|s| s.endpoint == endpoint) {
+                        subscribers.merge(stats);
+                        connections += 1;
+                    }
+
+                    EndpointReport {
+                        endpoint,
+                        connections,
+                        publishers: (&publishers).into(),
+                        subscribers: (&subscribers).into(),
+                    }
+                })
+                .collect(),
+        };
+
+        let mut topics: Vec<TopicReport> = aggregate_substats
+            .topics
+            .iter()
+            .map(|(topic, stats)| TopicReport {
+                topic: topic.clone(),
+                publish_count: stats.publish_count,
+                bytes: stats.bytes,
+                latencies: LatencySummary::from(&stats.latencies),
+            })
+            .collect();
+        topics.sort_by(|a, b| a.topic.cmp(&b.topic));
+
+        let gaps = sub_stats
+            .iter()
+            .flat_map(|stats| {
+                stats.gaps.iter().map(move |gaps| GapReport {
+                    subscriber: stats.id.clone(),
+                    topic: gaps.topic.clone(),
+                    missing: gaps.missing(),
+                    ranges: gaps.ranges.clone(),
+                })
+            })
+            .collect();
+
+        Report {
+            metadata: Metadata::new(config, started_at, started_at + connect_elapsed + elapsed),
+            summary,
+            aggregate: Aggregate {
+                publishers: (&aggregate_pubstats).into(),
+                subscribers: (&aggregate_substats).into(),
+            },
+            publishers: pub_stats.iter().map(Into::into).collect(),
+            subscribers: sub_stats.iter().map(Into::into).collect(),
+            endpoints,
+            topics,
+            gaps,
+            timeseries,
+            broker: BTreeMap::new(),
+            retained: None,
+            wills: None,
+            ramp_down: None,
+            aliases: None,
+            properties: None,
+            expired: None,
+            session_expiry: None,
+            will_delay: None,
+            flow: None,
+            subscription_options: None,
+            max_packet_size: None,
+            packet_size_probe: None,
+            assigned_client_ids: None,
+            keep_alive_sweep: None,
+            mqtt31: None,
+            churn: None,
+            takeover: None,
+            retain_flood: None,
+            payload_sweep: None,
+            inflight_sweep: None,
+            rate_sweep: None,
+            find_max: None,
+        }
+    }
+
+    pub fn print(&self) {
+        let summary = &self.summary;
+        let duplicates = match &summary.duplicates {
+            Some(duplicates) => format!(
+                "Duplicates         : {:<7} QoS 0 = {}, QoS 1 = {}, QoS 2 = {}\n        ",
+                duplicates.total, duplicates.qos0, duplicates.qos1, duplicates.qos2
+            ),
+            None => String::new(),
+        };
+        let rtts: String = summary
+            .rtts
+            .iter()
+            .map(|rtts| {
+                format!(
+                    "Ack RTT (QoS {})    : {}\n        ",
+                    rtts.qos, rtts.latencies
+                )
+            })
+            .collect();
+        let corrected = match &summary.corrected_ack_latencies {
+            Some(latencies) => format!("Corrected acks     : {latencies}\n        "),
+            None => String::new(),
+        };
+        let reordering = match &summary.reordering {
+            Some(reordering) => format!(
+                "Reordered          : {:<7} Max displacement = {}\n        ",
+                reordering.reordered, reordering.max_displacement
+            ),
+            None => String::new(),
+        };
+
+        println!(
+            "Summary
+        ----------------------------
+        Duration           : {:.3}s
+        Connections        : Publishers = {}, Subscribers = {}, Rate = {:.2} connections/s
+        Connack latencies  : {}
+        Outgoing publishes : {:<7} Throughput = {:.2} messages/s, {:.3} MB/s payload, {:.3} MB/s wire
+        Incoming publishes : {:<7} Throughput = {:.2} messages/s, {:.3} MB/s payload, {:.3} MB/s wire
+        Lost               : {} of {} ({:.2}%)
+        {}{}Reconnects         : {:<7} Outages = {}, Unrecovered = {}, Downtime = {:.3}s total, {:.3}s max
+        Ack latencies      : {}
+        {}{}Latencies          : {}
+        Suback latencies   : {}
+        Publish jitter     : {}
+        Arrival jitter     : {}
+        Arrival gaps       : {}
+        Mqttwrk resources  : cpu mean = {:.1}%, cpu max = {:.1}%, rss max = {:.1}MiB, cores = {}
+        ",
+            summary.duration_secs,
+            summary.publishers,
+            summary.subscribers,
+            summary.connection_rate,
+            summary.connack_latencies,
+            summary.outgoing_publish,
+            summary.publish_throughput,
+            summary.outgoing_bytes.payload_mb_per_sec,
+            summary.outgoing_bytes.wire_mb_per_sec,
+            summary.incoming_publish,
+            summary.incoming_throughput,
+            summary.incoming_bytes.payload_mb_per_sec,
+            summary.incoming_bytes.wire_mb_per_sec,
+            summary.lost,
+            summary.expected_incoming,
+            summary.loss_percent,
+            duplicates,
+            reordering,
+            summary.reconnects,
+            summary.downtime.outages,
+            summary.downtime.unrecovered,
+            summary.downtime.total_secs,
+            summary.downtime.max_secs,
+            summary.ack_latencies,
+            corrected,
+            rtts,
+            summary.latencies,
+            summary.suback_latencies,
+            summary.publish_jitter,
+            summary.arrival_jitter,
+            summary.arrival_gaps,
+            summary.resources.cpu_percent_mean,
+            summary.resources.cpu_percent_max,
+            summary.resources.rss_max_bytes as f64 / (1024.0 * 1024.0),
+            summary.resources.cores,
+        );
+
+        if let Some(handshake) = &summary.handshake {
+            println!(
+                "QoS 2 handshake
+        ----------------------------
+        Pubrec latencies   : {}
+        Pubcomp latencies  : {}",
+                handshake.pubrec_latencies, handshake.pubcomp_latencies,
+            );
+            match &summary.exactly_once {
+                Some(ExactlyOnce { verified: true, .. }) => {
+                    println!("        Exactly once       : verified\n")
+                }
+                Some(exactly_once) => println!(
+                    "        Exactly once       : violated, Lost = {}, Duplicates = {}\n",
+                    exactly_once.lost, exactly_once.duplicates
+                ),
+                None => println!(),
+            }
+        }
+
+        if let Some(warmup) = &summary.warmup {
+            println!(
+                "Warmup ({:.3}s, excluded above)
+        ----------------------------
+        Ack latencies      : {}
+        Latencies          : {}
+        ",
+                warmup.duration_secs, warmup.ack_latencies, warmup.latencies,
+            );
+        }
+
+        if let Some(idle) = &summary.idle {
+            println!(
+                "Idle connections (held for {:.3}s)
+        ----------------------------
+        Held               : {} of {}
+        Pings              : {:<7} Answered = {}
+        Ping latencies     : {}
+        Disconnects        : {}
+        ",
+                idle.hold_secs,
+                idle.held,
+                idle.connections,
+                idle.pings,
+                idle.ping_responses,
+                idle.ping_latencies,
+                idle.disconnects,
+            );
+        }
+
+        if let Some(bursts) = &summary.bursts {
+            println!(
+                "Bursts ({} publishes every {:.3}s)
+        ----------------------------
+        Acked bursts       : {}
+        Drain latencies    : {}
+        Ack latencies      : {}
+        ",
+                bursts.size,
+                bursts.interval_secs,
+                bursts.acked,
+                bursts.drain_latencies,
+                bursts.ack_latencies,
+            );
+        }
+
+        if !summary.phases.is_empty() {
+            println!("Phases\n        ----------------------------");
+            for phase in summary.phases.iter() {
+                println!(
+                    "        {:<18} : Target = {:<7} Outgoing = {:<7} Throughput = {:.2} messages/s, Ack latencies = {}",
+                    format!("{:.1}s - {:.1}s", phase.start_secs, phase.end_secs),
+                    phase.target_rate,
+                    phase.outgoing_publish,
+                    phase.publish_throughput,
+                    phase.ack_latencies,
+                );
+            }
+            println!();
+        }
+
+        if !summary.qos_mix.is_empty() {
+            println!("QoS mix\n        ----------------------------");
+            for share in summary.qos_mix.iter() {
+                println!(
+                    "        {:<18} : Weight = {:<5} Outgoing = {:<7} Incoming = {:<7} Latencies = {}",
+                    format!("QoS {}", share.qos),
+                    share.weight,
+                    share.outgoing_publish,
+                    share.incoming_publish,
+                    share.latencies,
+                );
+            }
+            println!();
+        }
+
+        if !summary.groups.is_empty() {
+            println!("Groups\n        ----------------------------");
+            for group in summary.groups.iter() {
+                println!(
+                    "        {:<18} : Publishers = {:<7} Outgoing = {:<7} Throughput = {:.2} messages/s, Incoming = {:<7} Reconnects = {}",
+                    group.name,
+                    group.publishers,
+                    group.outgoing_publish,
+                    group.publish_throughput,
+                    group.incoming_publish,
+                    group.reconnects,
+                );
+                println!(
+                    "        {:<18} : Ack latencies = {}",
+                    "", group.ack_latencies
+                );
+                println!("        {:<18} : Latencies = {}", "", group.latencies);
+            }
+            println!();
+        }
+
+        if let Some(fan_out) = &summary.fan_out {
+            println!(
+                "Fan-out (1 publisher to {} subscribers)
+        ----------------------------
+        Deliveries         : {:.2} per publish, Throughput = {:.2} messages/s
+        Subscriber p50s    : {}
+        Subscriber p99s    : {}
+        Slowest subscriber : {}
+        ",
+                fan_out.subscribers,
+                fan_out.amplification,
+                fan_out.delivery_throughput,
+                fan_out.p50,
+                fan_out.p99,
+                fan_out.slowest,
+            );
+        }
+
+        if let Some(shared) = &summary.shared {
+            println!(
+                "Shared subscription ($share/{})
+        ----------------------------
+        Members            : {:<7} Throughput = {:.2} messages/s
+        Distribution       : min = {}, max = {}, mean = {:.2}, stddev = {:.2}, cov = {:.3}
+        ",
+                shared.group,
+                shared.members,
+                shared.throughput,
+                shared.min,
+                shared.max,
+                shared.mean,
+                shared.stddev,
+                shared.cov,
+            );
+        }
+
+        if let Some(sessions) = &summary.sessions {
+            println!(
+                "Sessions (offline for {:.3}s)
+        ----------------------------
+        Resumed            : {:<7} Session present = {}
+        Queued publishes   : {:<7} Drain = {:.3}s, Rate = {:.2} messages/s
+        ",
+                sessions.offline_secs,
+                sessions.resumed,
+                sessions.session_present,
+                sessions.queued,
+                sessions.drain_secs,
+                sessions.drain_rate,
+            );
+        }
+
+        if let Some(storm) = &summary.reconnect_storm {
+            println!(
+                "Reconnect storm (after {:.3}s)
+        ----------------------------
+        Dropped            : {} of {}
+        Reconnected        : {:<7} All by = {:.3}ms
+        Resumed            : {:<7} All by = {:.3}ms
+        Reconnects         : {}
+        Resumes            : {}
+        ",
+                storm.after_secs,
+                storm.dropped,
+                storm.connections,
+                storm.reconnected,
+                storm.reconnect_latencies.max,
+                storm.resumed,
+                storm.resume_latencies.max,
+                storm.reconnect_latencies,
+                storm.resume_latencies,
+            );
+        }
+
+        if let Some(flapping) = &summary.flapping {
+            let change = |change: Option<f64>| match change {
+                Some(change) => format!("{change:+.1}%"),
+                None => "n/a".to_owned(),
+            };
+            println!(
+                "Flapping ({}% every {:.3}s)
+        ----------------------------
+        Flappy             : Publishers = {} of {}, Subscribers = {} of {}
+        Flaps              : {:<7} Reconnects = {}
+        Stable ack p99     : Calm = {:.3}ms, Flapping = {:.3}ms, Change = {}
+        Stable p99         : Calm = {:.3}ms, Flapping = {:.3}ms, Change = {}
+        Calm acks          : {}
+        Flapping acks      : {}
+        Calm latencies     : {}
+        Flapping latencies : {}
+        ",
+                flapping.percent,
+                flapping.every_secs,
+                flapping.flappy_publishers,
+                flapping.flappy_publishers + flapping.stable_publishers,
+                flapping.flappy_subscribers,
+                flapping.flappy_subscribers + flapping.stable_subscribers,
+                flapping.flaps,
+                flapping.reconnect_latencies,
+                flapping.calm_ack_latencies.p99,
+                flapping.flapping_ack_latencies.p99,
+                change(flapping.ack_p99_change_percent),
+                flapping.calm_latencies.p99,
+                flapping.flapping_latencies.p99,
+                change(flapping.p99_change_percent),
+                flapping.calm_ack_latencies,
+                flapping.flapping_ack_latencies,
+                flapping.calm_latencies,
+                flapping.flapping_latencies,
+            );
+        }
+
+        if summary.resources.saturated() {
+            yellow_ln!("mqttwrk used almost all cpu, results might be limited by the load generator rather than the broker\n");
+        }
+
+        if !self.endpoints.is_empty() {
+            println!("Endpoints\n        ----------------------------");
+            for endpoint in self.endpoints.iter() {
+                println!(
+                    "        {:<30} Connections = {:<5} Outgoing = {:<7} Incoming = {:<7} Reconnects = {:<5} Ack latencies = {}",
+                    endpoint.endpoint,
+                    endpoint.connections,
+                    endpoint.publishers.outgoing_publish,
+                    endpoint.subscribers.publish_count,
+                    endpoint.publishers.reconnects + endpoint.subscribers.reconnects,
+                    endpoint.publishers.ack_latencies
+                );
+            }
+            println!();
+        }
+
+        if !self.topics.is_empty() {
+            println!("Topics\n        ----------------------------");
+            for topic in self.topics.iter() {
+                println!(
+                    "        {:<30} Incoming = {:<7} Bytes = {:<10} Latencies = {}",
+                    topic.topic, topic.publish_count, topic.bytes, topic.latencies
+                );
+            }
+        }
+
+        if !self.gaps.is_empty() {
+            println!("Gaps\n        ----------------------------");
+            for gap in self.gaps.iter() {
+                let ranges: Vec<String> = gap
+                    .ranges
+                    .iter()
+                    .map(|(first, last)| {
+                        if first == last {
+                            first.to_string()
+                        } else {
+                            format!("{first}-{last}")
+                        }
+                    })
+                    .collect();
+                println!(
+                    "        {} {:<30} Missing = {:<7} Sequences = {}",
+                    gap.subscriber,
+                    gap.topic,
+                    gap.missing,
+                    ranges.join(", ")
+                );
+            }
+        }
+
+        if let Some(retained) = &self.retained {
+            println!(
+                "Retained
+        ----------------------------
+        Delivered          : {} of {} topics, Stale = {}, Latency = {:.3}ms
+        ",
+                retained.delivered, retained.topics, retained.stale, retained.latency_ms
+            );
+        }
+
+        if let Some(ramp_down) = &self.ramp_down {
+            println!(
+                "Ramp down ({}, {} connections/s)
+        ----------------------------
+        Closed             : {:<7} Duration = {:.3}s",
+                ramp_down.mode, ramp_down.rate, ramp_down.closed, ramp_down.duration_secs
+            );
+            if ramp_down.mode == RampDownMode::Graceful {
+                println!(
+                    "        Close latencies    : {}
+        Lingering          : {}",
+                    ramp_down.close_latencies, ramp_down.lingering
+                );
+            }
+            println!();
+        }
+
+        if let Some(wills) = &self.wills {
+            println!(
+                "Last wills
+        ----------------------------
+        Delivered          : {} of {} killed, Unexpected = {}
+        Latencies          : {}
+        ",
+                wills.delivered, wills.killed, wills.unexpected, wills.latencies
+            );
+        }
+
+        if let Some(aliases) = &self.aliases {
+            println!(
+                "Topic aliases ({} topics of {} bytes, {} aliases)
+        ----------------------------",
+                aliases.topics, aliases.topic_length, aliases.aliases
+            );
+            for (name, pass) in [
+                ("Without aliases", &aliases.without_aliases),
+                ("With aliases", &aliases.with_aliases),
+            ] {
+                println!(
+                    "        {:<18} : {:<7} Throughput = {:.2} messages/s, {} wire bytes
+        {:<18}   Ack latencies = {}",
+                    name, pass.publishes, pass.throughput, pass.wire_bytes, "", pass.ack_latencies
+                );
+            }
+            println!(
+                "        Saved              : {:.2}% of wire bytes\n",
+                aliases.saved_percent
+            );
+        }
+
+        if let Some(properties) = &self.properties {
+            println!(
+                "User properties ({} per publish)
+        ----------------------------",
+                properties.properties
+            );
+            for (name, pass) in [
+                ("Without properties", &properties.without_properties),
+                ("With properties", &properties.with_properties),
+            ] {
+                println!(
+                    "        {:<18} : {:<7} Throughput = {:.2} messages/s, {} wire bytes, {} of {} received intact
+        {:<18}   Ack latencies = {}",
+                    name,
+                    pass.publishes,
+                    pass.throughput,
+                    pass.wire_bytes,
+                    pass.intact,
+                    pass.received,
+                    "",
+                    pass.ack_latencies
+                );
+            }
+            println!(
+                "        Overhead           : {:.2}% of wire bytes\n",
+                properties.overhead_percent
+            );
+        }
+
+        if let Some(expired) = &self.expired {
+            println!(
+                "Message expiry ({}s)
+        ----------------------------
+        Published          : {:<7} Delivered = {}, Expired = {}
+        Session present    : {:<7} Control delivered = {}
+        ",
+                expired.expiry_secs,
+                expired.published,
+                expired.delivered,
+                expired.expired,
+                expired.session_present,
+                expired.control_delivered
+            );
+        }
+
+        if let Some(sessions) = &self.session_expiry {
+            println!(
+                "Session expiry ({}s, {} publishes queued per session)
+        ----------------------------",
+                sessions.expiry_secs, sessions.published
+            );
+            for (name, churn) in [
+                ("Before expiry", &sessions.before_expiry),
+                ("After expiry", &sessions.after_expiry),
+            ] {
+                println!(
+                    "        {:<18} : {} of {} sessions present, Queued = {} of {} expected",
+                    name, churn.present, churn.sessions, churn.queued, churn.expected
+                );
+            }
+            println!();
+        }
+
+        if let Some(will_delay) = &self.will_delay {
+            println!(
+                "Will delay ({}s, {} clients)
+        ----------------------------
+        Delivered          : {} of {} that stayed away, Early = {}
+        Unexpected         : {:<7} Returned = {}
+        Latencies          : {}
+        ",
+                will_delay.delay_secs,
+                will_delay.clients,
+                will_delay.delivered,
+                will_delay.clients - will_delay.returned,
+                will_delay.early,
+                will_delay.unexpected,
+                will_delay.returned,
+                will_delay.latencies
+            );
+        }
+
+        if let Some(flow) = &self.flow {
+            println!(
+                "Flow control (receive maximum = {}, ack delay = {:.3}ms)
+        ----------------------------
+        Received           : {} of {} by {} subscribers, Throughput = {:.2} messages/s
+        Max unacked        : {:<7} Violations = {}
+        ",
+                flow.receive_maximum,
+                flow.ack_delay_ms,
+                flow.received,
+                flow.published * flow.subscribers,
+                flow.subscribers,
+                flow.throughput,
+                flow.max_unacked,
+                flow.violations
+            );
+        }
+
+        if let Some(options) = &self.subscription_options {
+            let retain_flag = match options.retain_flag_kept {
+                Some(kept) => kept.to_string(),
+                None => "not delivered".to_owned(),
+            };
+            let violations = if options.violations.is_empty() {
+                "none".to_owned()
+            } else {
+                options.violations.join(", ")
+            };
+            println!(
+                "Subscription options (no local = {}, retain as published = {}, retain handling = {})
+        ----------------------------
+        Loopback           : {} of {} publishes
+        Retain flag kept   : {}
+        Retained delivered : On subscribe = {}, On resubscribe = {}
+        Violations         : {}
+        ",
+                options.no_local,
+                options.retain_as_published,
+                options.retain_handling,
+                options.loopback,
+                options.published,
+                retain_flag,
+                options.retained_on_subscribe,
+                options.retained_on_resubscribe,
+                violations
+            );
+        }
+
+        if let Some(max) = &self.max_packet_size {
+            println!(
+                "Maximum packet size ({} bytes)
+        ----------------------------
+        Received           : {} of {} that fit
+        Oversized          : {} of {} a byte too large
+        ",
+                max.max_packet_size, max.received, max.published, max.oversized, max.published
+            );
+        }
+
+        if let Some(probe) = &self.packet_size_probe {
+            let bytes = |size: Option<usize>| match size {
+                Some(size) => format!("{size} bytes"),
+                None => "none".to_owned(),
+            };
+            println!(
+                "Packet size probe (payloads up to {} bytes)
+        ----------------------------
+        Advertised         : {}
+        Largest accepted   : {:<15} Smallest rejected = {}, Attempts = {}
+        ",
+                probe.limit,
+                bytes(probe.advertised.map(|size| size as usize)),
+                bytes(probe.largest_accepted),
+                bytes(probe.smallest_rejected),
+                probe.attempts
+            );
+        }
+
+        if let Some(assigned) = &self.assigned_client_ids {
+            println!(
+                "Assigned client ids
+        ----------------------------
+        Assigned           : {} of {} clients, Missing = {}, Duplicates = {}
+        Connack latencies  : {}
+        ",
+                assigned.assigned,
+                assigned.clients,
+                assigned.missing,
+                assigned.duplicates,
+                assigned.connack_latencies
+            );
+        }
+
+        if let Some(sweep) = &self.keep_alive_sweep {
+            println!(
+                "Keep alive sweep (connections = {}, pings = {})\n        ----------------------------",
+                sweep.connections, sweep.pings
+            );
+            for batch in sweep.batches.iter() {
+                let latencies = &batch.ping_latencies;
+                println!(
+                    "        {:<18} : Connected = {}, Pings = {}, Responses = {} ({:.2}/s), Disconnects = {}",
+                    format!("{}s", batch.keep_alive_secs),
+                    batch.connected,
+                    batch.pings,
+                    batch.responses,
+                    batch.ping_rate,
+                    batch.disconnects
+                );
+                println!(
+                    "        {:<18}   Ping latencies: p50 = {:.3}ms, p99 = {:.3}ms, max = {:.3}ms",
+                    "", latencies.p50, latencies.p99, latencies.max
+                );
+            }
+            println!();
+        }
+
+        if let Some(mqtt31) = &self.mqtt31 {
+            println!(
+                "Mqtt 3.1
+        ----------------------------
+        Accepted           : {:<7} Long client id rejected = {}
+        Received           : {} of {}, Acked = {}, Throughput = {:.2} messages/s
+        Ack latencies      : {}
+        ",
+                mqtt31.accepted,
+                mqtt31.long_id_rejected,
+                mqtt31.received,
+                mqtt31.published,
+                mqtt31.acked,
+                mqtt31.throughput,
+                mqtt31.ack_latencies
+            );
+        }
+
+        if let Some(churn) = &self.churn {
+            println!(
+                "Churn ({} connections, {} sessions)
+        ----------------------------
+        Reconnects         : {:<7} Rate = {:.2}/s of {}/s, Failed = {}, Resumed = {}
+        Connack latencies  : {}
+        ",
+                churn.connections,
+                churn.session,
+                churn.reconnects,
+                churn.reconnect_rate,
+                churn.target_rate,
+                churn.failed,
+                churn.resumed,
+                churn.reconnect_latencies
+            );
+        }
+
+        if let Some(takeover) = &self.takeover {
+            println!(
+                "Takeover ({} connections after {:.3}s)
+        ----------------------------
+        Taken over         : {:<7} Failed = {}, Resumed = {}
+        Old closed         : {:<7} Lingering = {}
+        New dropped        : {}
+        Connack latencies  : {}
+        Close latencies    : {}
+        ",
+                takeover.connections,
+                takeover.after_secs,
+                takeover.accepted,
+                takeover.failed,
+                takeover.resumed,
+                takeover.closed,
+                takeover.lingering,
+                takeover.dropped,
+                takeover.connack_latencies,
+                takeover.close_latencies
+            );
+        }
+
+        if let Some(sweep) = &self.payload_sweep {
+            println!(
+                "Payload sweep (publishes = {})\n        ----------------------------",
+                sweep.publishes
+            );
+            for step in sweep.steps.iter() {
+                println!(
+                    "        {:<18} : Acked = {}, Received = {}, Throughput = {:.3} MB/s payload, Dropped = {}",
+                    format!("{} bytes", step.payload_size),
+                    step.acked,
+                    step.received,
+                    step.payload_mb_per_sec,
+                    step.dropped
+                );
+                println!(
+                    "        {:<18}   Latencies: p50 = {:.3}ms, p99 = {:.3}ms, max = {:.3}ms",
+                    "", step.latencies.p50, step.latencies.p99, step.latencies.max
+                );
+                for (topic, value) in step.broker_memory.iter() {
+                    println!("        {:<18}   {} = {}", "", topic, value);
+                }
+            }
+            println!();
+        }
+
+        if let Some(sweep) = &self.inflight_sweep {
+            println!("Inflight sweep\n        ----------------------------");
+            for step in sweep.steps.iter() {
+                println!(
+                    "        {:<18} : Outgoing = {}, Throughput = {:.2} messages/s, Incoming = {}, Throughput = {:.2} messages/s, Lost = {:.2}%, Reconnects = {}",
+                    format!("max inflight {}", step.max_inflight),
+                    step.outgoing_publish,
+                    step.publish_throughput,
+                    step.incoming_publish,
+                    step.incoming_throughput,
+                    step.loss_percent,
+                    step.reconnects
+                );
+                println!(
+                    "        {:<18}   Ack latencies: p50 = {:.3}ms, p99 = {:.3}ms, Latencies: p50 = {:.3}ms, p99 = {:.3}ms",
+                    "",
+                    step.ack_latencies.p50,
+                    step.ack_latencies.p99,
+                    step.latencies.p50,
+                    step.latencies.p99
+                );
+            }
+            println!();
+        }
+
+        if let Some(sweep) = &self.rate_sweep {
+            println!(
+                "Rate sweep\n        ----------------------------\n        {:>8} {:>12} {:>12} {:>12} {:>8} {:>12} {:>12} {:>12} {:>12}",
+                "Rate", "Target/s", "Publish/s", "Incoming/s", "Lost %", "Ack p50 ms", "Ack p99 ms", "p50 ms", "p99 ms"
+            );
+            for step in sweep.steps.iter() {
+                println!(
+                    "        {:>8} {:>12.2} {:>12.2} {:>12.2} {:>8.2} {:>12.3} {:>12.3} {:>12.3} {:>12.3}",
+                    step.rate,
+                    step.target_throughput,
+                    step.publish_throughput,
+                    step.incoming_throughput,
+                    step.loss_percent,
+                    step.ack_latencies.p50,
+                    step.ack_latencies.p99,
+                    step.latencies.p50,
+                    step.latencies.p99
+                );
+            }
+            println!();
+        }
+
+        if let Some(find_max) = &self.find_max {
+            println!("Find max\n        ----------------------------");
+            for probe in find_max.probes.iter() {
+                println!(
+                    "        {:<18} : {} Throughput = {:.2} of {:.2} messages/s, Incoming = {:.2} messages/s, Lost = {:.2}%, Ack p99 = {:.3}ms, p99 = {:.3}ms",
+                    format!("rate {}", probe.rate),
+                    match probe.failure {
+                        Some(_) => "FAIL",
+                        None => "PASS",
+                    },
+                    probe.publish_throughput,
+                    probe.target_throughput,
+                    probe.incoming_throughput,
+                    probe.loss_percent,
+                    probe.p99_ack_latency,
+                    probe.p99_latency
+                );
+                if let Some(failure) = &probe.failure {
+                    println!("        {:<18}   {}", "", failure);
+                }
+            }
+            match find_max.max_rate {
+                Some(rate) => println!(
+                    "        {:<18} : Rate = {}, Throughput = {:.2} messages/s",
+                    "Max sustainable", rate, find_max.max_throughput
+                ),
+                None => println!(
+                    "        {:<18} : none of the rates met the assertions",
+                    "Max sustainable"
+                ),
+            }
+            println!();
+        }
+
+        if let Some(flood) = &self.retain_flood {
+            println!(
+                "Retained backlog ({} topics)
+        ----------------------------
+        Stored             : {:<7} Rate = {:.2}/s
+        Delivered          : {:<7} Rate = {:.2}/s, First = {:.3}ms, Last = {:.3}ms
+        ",
+                flood.topics,
+                flood.stored,
+                flood.store_rate,
+                flood.delivered,
+                flood.delivery_rate,
+                flood.first_ms,
+                flood.last_ms
+            );
+        }
+
+        if !self.broker.is_empty() {
+            println!("Broker ($SYS)\n        ----------------------------");
+            for (topic, value) in self.broker.iter() {
+                if value.first == value.last {
+                    println!("        {:<50} {}", topic, value.last);
+                } else {
+                    println!("        {:<50} {} -> {}", topic, value.first, value.last);
+                }
+            }
+        }
+    }
+
+    /// Writes one row per connection to a csv file at `path`
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "id,acks,incoming,throughput,reconnects,downtime_secs,latency_samples,latency_p50_ms,latency_p90_ms,latency_p99_ms,latency_p999_ms,latency_max_ms"
+        )?;
+
+        for publisher in self.publishers.iter() {
+            writeln!(
+                writer,
+                "{},{},,{},{},{:.3},{}",
+                publisher.id,
+                publisher.outgoing_publish,
+                publisher.throughput,
+                publisher.reconnects,
+                publisher.downtime_secs,
+                csv_latencies(&publisher.ack_latencies)
+            )?;
+        }
+
+        for subscriber in self.subscribers.iter() {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{:.3},{}",
+                subscriber.id,
+                subscriber.puback_count,
+                subscriber.publish_count,
+                subscriber.throughput,
+                subscriber.reconnects,
+                subscriber.downtime_secs,
+                csv_latencies(&subscriber.latencies)
+            )?;
+        }
+
+        writer.flush()
+    }
+
+    /// Writes the report as json to `path`, or to stdout if there is no path
+    pub fn write_json(&self, path: Option<&Path>) -> io::Result<()> {
+        match path {
+            Some(path) => serde_json::to_writer_pretty(File::create(path)?, self)?,
+            None => {
+                serde_json::to_writer_pretty(io::stdout().lock(), self)?;
+                println!();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the report as a self contained html page with charts of the
+    /// timeseries to `path`, or to stdout if there is no path
+    pub fn write_html(&self, config: &BenchConfig, path: Option<&Path>) -> io::Result<()> {
+        // json is embedded in script tags, which mustn't be closed early
+        let json = |value: serde_json::Value| value.to_string().replace("</", "<\\/");
+        let html = include_str!("report.html")
+            .replace("{{report}}", &json(serde_json::to_value(self)?))
+            .replace("{{config}}", &json(serde_json::to_value(config)?));
+
+        match path {
+            Some(path) => std::fs::write(path, html),
+            None => io::stdout().lock().write_all(html.as_bytes()),
+        }
+    }
+
+    /// Writes the report as a junit test suite to `path`, or to stdout if there
+    /// is no path. The run is one test case and every assertion is another
+    pub fn write_junit(&self, assertions: &[Assertion], path: Option<&Path>) -> io::Result<()> {
+        let mut writer: Box<dyn Write> = match path {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stdout().lock()),
+        };
+
+        let summary = &self.summary;
+        let failures = assertions.iter().filter(|a| a.failure.is_some()).count();
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<testsuite name="mqttwrk.bench" tests="{}" failures="{}" time="{:.3}">"#,
+            assertions.len() + 1,
+            failures,
+            summary.duration_secs
+        )?;
+
+        let metadata = &self.metadata;
+        let mut properties = vec![
+            ("mqttwrk_version", metadata.mqttwrk_version.to_owned()),
+            ("rumqttc_version", metadata.rumqttc_version.to_owned()),
+            ("hostname", metadata.hostname.clone()),
+            ("started_at", metadata.started_at.clone()),
+            ("finished_at", metadata.finished_at.clone()),
+            ("config", metadata.config.to_string()),
+        ];
+        properties.extend(metadata.tags.iter().map(|tag| ("tag", tag.clone())));
+        writeln!(writer, "  <properties>")?;
+        for (name, value) in properties {
+            writeln!(
+                writer,
+                r#"    <property name="{}" value="{}"/>"#,
+                name,
+                escape(&value)
+            )?;
+        }
+        writeln!(writer, "  </properties>")?;
+
+        writeln!(
+            writer,
+            r#"  <testcase classname="mqttwrk.bench" name="run" time="{:.3}">"#,
+            summary.duration_secs
+        )?;
+        writeln!(
+            writer,
+            "    <system-out>{}</system-out>",
+            escape(&serde_json::to_string_pretty(summary)?)
+        )?;
+        writeln!(writer, "  </testcase>")?;
+
+        for assertion in assertions {
+            write!(
+                writer,
+                r#"  <testcase classname="mqttwrk.bench.assert" name="{}""#,
+                assertion.name
+            )?;
+            match &assertion.failure {
+                Some(failure) => {
+                    writeln!(writer, ">")?;
+                    writeln!(writer, r#"    <failure message="{}"/>"#, escape(failure))?;
+                    writeln!(writer, "  </testcase>")?;
+                }
+                None => writeln!(writer, "/>")?,
+            }
+        }
+
+        writeln!(writer, "</testsuite>")?;
+        writer.flush()
+    }
+}
+
+fn downtime_secs(outages: &[Outage]) -> f64 {
+    outages
+        .iter()
+        .map(|o| o.downtime)
+        .sum::<Duration>()
+        .as_secs_f64()
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Escapes text for use in xml attributes and elements
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn csv_latencies(latencies: &LatencySummary) -> String {
+    format!(
+        "{},{:.3},{:.3},{:.3},{:.3},{:.3}",
+        latencies.samples,
+        latencies.p50,
+        latencies.p90,
+        latencies.p99,
+        latencies.p999,
+        latencies.max
+    )
+}
@@ -0,0 +1,173 @@
+//! Outputs of a benchmark run. Reporters see every per second sample while the
+//! run is in progress and the final report once it's done. Adding an output
+//! means implementing `Reporter` and registering it in `reporters`
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    bench::{
+        assertions::Assertion, influx::Influx, interim::Interim, metrics::Sample, otlp::Otlp,
+        prometheus::Prometheus, ratesweep::RateSweepCsv, report::Report, statsd::Statsd,
+        store::Store, timeseries::TimeseriesCsv,
+    },
+    BenchConfig, OutputFormat,
+};
+
+pub(crate) trait Reporter: Send {
+    /// Identifies the reporter in error messages
+    fn name(&self) -> &'static str;
+
+    /// Called with every sample while the run is in progress. Mustn't block
+    /// as it delays the next sample
+    fn sample(&mut self, _sample: &Sample) {}
+
+    /// Called once with the final report of the run
+    fn finish(&mut self, _report: &Report, _assertions: &[Assertion]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Every reporter enabled on the command line, in the order in which they
+/// finish. Reporters that fail to set up are logged and skipped
+pub(crate) async fn reporters(
+    config: &Arc<BenchConfig>,
+    otlp: Option<&Otlp>,
+) -> Vec<Box<dyn Reporter>> {
+    let mut reporters: Vec<Box<dyn Reporter>> = Vec::new();
+
+    if let Some(url) = &config.influx_url {
+        reporters.push(Box::new(Influx::new(
+            url,
+            &config.influx_org,
+            &config.influx_bucket,
+            config.influx_token.clone(),
+        )));
+    }
+
+    if let Some(addr) = config.statsd_addr {
+        match Statsd::connect(addr, &config.statsd_prefix).await {
+            Ok(statsd) => reporters.push(Box::new(statsd)),
+            Err(e) => error!("Failed to setup statsd = {:?}", e),
+        }
+    }
+
+    if let Some(otlp) = otlp {
+        reporters.push(Box::new(otlp.clone()));
+    }
+
+    if let Some(port) = config.prometheus_port {
+        reporters.push(Box::new(Prometheus::start(port)));
+    }
+
+    if let Some(every) = config.report_interval {
+        reporters.push(Box::new(Interim::new(every)));
+    }
+
+    if let Some(path) = &config.timeseries_file {
+        match TimeseriesCsv::create(path, config.report_interval) {
+            Ok(timeseries) => reporters.push(Box::new(timeseries)),
+            Err(e) => error!("Failed to create {} = {:?}", path.display(), e),
+        }
+    }
+
+    if let Some(path) = &config.rate_sweep_file {
+        reporters.push(Box::new(RateSweepCsv::new(path.clone())));
+    }
+
+    if let Some(path) = &config.store {
+        reporters.push(Box::new(Store::new(path.clone(), config.clone())));
+    }
+
+    let path = config.output_file.clone();
+    match config.output {
+        OutputFormat::Console => reporters.push(Box::new(Console)),
+        OutputFormat::Json => reporters.push(Box::new(Json { path })),
+        OutputFormat::Junit => reporters.push(Box::new(Junit { path })),
+        OutputFormat::Html => reporters.push(Box::new(Html {
+            path,
+            config: config.clone(),
+        })),
+    }
+
+    if let Some(path) = &config.report_file {
+        reporters.push(Box::new(Csv { path: path.clone() }));
+    }
+
+    reporters
+}
+
+/// Human readable summary on stdout
+struct Console;
+
+impl Reporter for Console {
+    fn name(&self) -> &'static str {
+        "console"
+    }
+
+    fn finish(&mut self, report: &Report, _assertions: &[Assertion]) -> anyhow::Result<()> {
+        report.print();
+        Ok(())
+    }
+}
+
+struct Json {
+    path: Option<PathBuf>,
+}
+
+impl Reporter for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn finish(&mut self, report: &Report, _assertions: &[Assertion]) -> anyhow::Result<()> {
+        report.write_json(self.path.as_deref())?;
+        Ok(())
+    }
+}
+
+struct Junit {
+    path: Option<PathBuf>,
+}
+
+impl Reporter for Junit {
+    fn name(&self) -> &'static str {
+        "junit"
+    }
+
+    fn finish(&mut self, report: &Report, assertions: &[Assertion]) -> anyhow::Result<()> {
+        report.write_junit(assertions, self.path.as_deref())?;
+        Ok(())
+    }
+}
+
+struct Html {
+    path: Option<PathBuf>,
+    config: Arc<BenchConfig>,
+}
+
+impl Reporter for Html {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn finish(&mut self, report: &Report, _assertions: &[Assertion]) -> anyhow::Result<()> {
+        report.write_html(&self.config, self.path.as_deref())?;
+        Ok(())
+    }
+}
+
+/// Per connection statistics
+struct Csv {
+    path: PathBuf,
+}
+
+impl Reporter for Csv {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn finish(&mut self, report: &Report, _assertions: &[Assertion]) -> anyhow::Result<()> {
+        report.write_csv(&self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! Retained publishes. Publishers set the retain flag on a fraction of their
+//! publishes and, once the run is done, a late subscriber checks that the
+//! broker delivers the latest retained publish of every publisher. Retained
+//! publishes are cleared afterwards so that they don't leak into later runs
+
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use rumqttc::{AsyncClient, Event, Incoming, QoS};
+use serde::Serialize;
+use tokio::time;
+
+use crate::{
+    bench::{options, payload, publisher_id, topic, ConnectionError},
+    BenchConfig,
+};
+
+/// Fraction of publishes to retain
+pub(crate) fn ratio(config: &BenchConfig) -> f64 {
+    if config.retain {
+        1.0
+    } else {
+        config.retain_ratio.unwrap_or(0.0)
+    }
+}
+
+/// Whether publish `i` of a publisher is retained. Retained publishes are
+/// spread evenly instead of randomly, so that every run retains the same ones
+pub(crate) fn retained(i: usize, ratio: f64) -> bool {
+    ((i + 1) as f64 * ratio).floor() > (i as f64 * ratio).floor()
+}
+
+/// What the late subscriber received
+#[derive(Debug, Serialize)]
+pub struct RetainedReport {
+    /// topics expected to hold a retained publish
+    pub topics: usize,
+    /// topics whose retained publish was delivered
+    pub delivered: usize,
+    /// delivered retained publishes that weren't the latest retained one of
+    /// their publisher, when tracking sequences
+    pub stale: usize,
+    /// time from subscribing until the last retained publish arrived
+    pub latency_ms: f64,
+}
+
+/// Subscribes with a fresh connection and waits for the retained publishes of
+/// all publishers, for up to `--receive-timeout`. Clears them afterwards
+pub(crate) async fn verify(config: Arc<BenchConfig>) -> Result<RetainedReport, ConnectionError> {
+    let ratio = ratio(&config);
+    // sequence number of the last retained publish of every publisher
+    let last = (0..config.count).rev().find(|&i| retained(i, ratio));
+    let expected: Vec<String> = match last {
+        Some(_) => (0..config.publishers)
+            .map(|i| topic(&publisher_id(&config, i)))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let (client, mut eventloop) = AsyncClient::new(options(config.clone(), "mqttwrk-retain")?, 10);
+    loop {
+        match eventloop.poll().await? {
+            Event::Incoming(Incoming::ConnAck(_)) => break,
+            Event::Incoming(incoming) => return Err(ConnectionError::WrongPacket(incoming)),
+            Event::Outgoing(_) => {}
+        }
+    }
+
+    client.subscribe("hello/+/world", QoS::AtLeastOnce).await?;
+    let start = Instant::now();
+    let deadline = start + config.receive_timeout;
+    let mut latency = start.elapsed();
+    // sequence numbers of the retained publishes received, by topic
+    let mut received: HashMap<String, Option<u64>> = HashMap::new();
+    while received.len() < expected.len() {
+        let event = match time::timeout_at(deadline.into(), eventloop.poll()).await {
+            Ok(event) => event?,
+            Err(_) => {
+                warn!(
+                    "Id = mqttwrk-retain, {} of {} retained publishes received",
+                    received.len(),
+                    expected.len()
+                );
+                break;
+            }
+        };
+
+        if let Event::Incoming(Incoming::Publish(publish)) = event {
+            if publish.retain {
+                latency = start.elapsed();
+                received.insert(publish.topic, payload::sequence(&publish.payload));
+            }
+        }
+    }
+
+    let stale = if config.sequence_tracking {
+        received
+            .values()
+            .filter(|&&sequence| sequence != last.map(|last| last as u64))
+            .count()
+    } else {
+        0
+    };
+
+    // an empty retained publish removes the retained publish of the topic
+    let deadline = Instant::now() + config.receive_timeout;
+    client.unsubscribe("hello/+/world").await?;
+    for topic in expected.iter() {
+        client
+            .publish(topic.as_str(), QoS::AtLeastOnce, true, Vec::new())
+            .await?;
+    }
+
+    let mut acks = 0;
+    while acks < expected.len() {
+        match time::timeout_at(deadline.into(), eventloop.poll()).await {
+            Ok(Ok(Event::Incoming(Incoming::PubAck(_)))) => acks += 1,
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => {
+                warn!("Id = mqttwrk-retain, Timed out clearing retained publishes");
+                break;
+            }
+        }
+    }
+
+    let _ = client.try_disconnect();
+    Ok(RetainedReport {
+        topics: expected.len(),
+        delivered: received.len(),
+        stale,
+        latency_ms: latency.as_secs_f64() * 1000.0,
+    })
+}
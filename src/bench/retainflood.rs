@@ -0,0 +1,153 @@
+//! Retained backlog. Once the run is done, a dedicated connection retains a
+//! publish on each of `--retain-flood` unique topics, and a fresh wildcard
+//! subscriber then times how fast the broker delivers the whole backlog on
+//! subscribe, which many brokers handle poorly. The retained publishes are
+//! cleared afterwards so that they don't leak into later runs
+
+use std::{sync::Arc, time::Instant};
+
+use rumqttc::{AsyncClient, Event, EventLoop, Incoming, QoS};
+use serde::Serialize;
+use tokio::{task, time};
+
+use crate::{
+    bench::{network_options, options, payload},
+    BenchConfig,
+};
+
+/// Filter of the wildcard subscriber
+const FILTER: &str = "hello/retain-flood/#";
+
+#[derive(Debug, Serialize)]
+pub struct RetainFloodReport {
+    pub topics: usize,
+    /// retained publishes the broker acked
+    pub stored: usize,
+    /// retained publishes acked per second
+    pub store_rate: f64,
+    /// retained publishes delivered to the wildcard subscriber
+    pub delivered: usize,
+    /// time from subscribing until the first retained publish arrived
+    pub first_ms: f64,
+    /// time from subscribing until the last retained publish arrived
+    pub last_ms: f64,
+    /// retained publishes delivered per second until the last one arrived
+    pub delivery_rate: f64,
+}
+
+fn topic(i: usize) -> String {
+    format!("hello/retain-flood/{i:06}")
+}
+
+/// Retains a publish on every topic, waits for a wildcard subscriber to
+/// receive all of them, for up to `--receive-timeout` each, and clears them
+pub(crate) async fn flood(config: Arc<BenchConfig>) -> anyhow::Result<RetainFloodReport> {
+    let topics = config.retain_flood.unwrap_or(0);
+    let (client, mut eventloop) = connect(&config, "mqttwrk-retain-flood").await?;
+
+    let start = Instant::now();
+    let payload = payload::generate(config.payload_size, false, None);
+    let stored = publish_all(&config, &client, &mut eventloop, topics, payload).await?;
+    let store_rate = stored as f64 / start.elapsed().as_secs_f64();
+
+    let (subscriber, mut backlog) = connect(&config, "mqttwrk-retain-flood-sub").await?;
+    subscriber.subscribe(FILTER, QoS::AtLeastOnce).await?;
+    let start = Instant::now();
+    let deadline = start + config.receive_timeout;
+    let mut delivered = 0;
+    let mut first = None;
+    let mut last = start.elapsed();
+    while delivered < stored {
+        let event = match time::timeout_at(deadline.into(), backlog.poll()).await {
+            Ok(event) => event?,
+            Err(_) => {
+                warn!(
+                    "Id = mqttwrk-retain-flood-sub, {} of {} retained publishes received",
+                    delivered, stored
+                );
+                break;
+            }
+        };
+
+        if let Event::Incoming(Incoming::Publish(publish)) = event {
+            if publish.retain {
+                delivered += 1;
+                last = start.elapsed();
+                first.get_or_insert(last);
+            }
+        }
+    }
+    let _ = subscriber.try_disconnect();
+
+    // an empty retained publish removes the retained publish of the topic
+    publish_all(&config, &client, &mut eventloop, topics, Vec::new()).await?;
+    let _ = client.try_disconnect();
+
+    Ok(RetainFloodReport {
+        topics,
+        stored,
+        store_rate,
+        delivered,
+        first_ms: first.unwrap_or_default().as_secs_f64() * 1000.0,
+        last_ms: last.as_secs_f64() * 1000.0,
+        delivery_rate: match last.as_secs_f64() {
+            secs if secs > 0.0 => delivered as f64 / secs,
+            _ => 0.0,
+        },
+    })
+}
+
+/// Retains `payload` on every topic. Returns the publishes the broker acked
+/// within `--receive-timeout`
+async fn publish_all(
+    config: &BenchConfig,
+    client: &AsyncClient,
+    eventloop: &mut EventLoop,
+    topics: usize,
+    payload: Vec<u8>,
+) -> anyhow::Result<usize> {
+    let requests = task::spawn({
+        let client = client.clone();
+        async move {
+            for i in 0..topics {
+                client
+                    .publish(topic(i), QoS::AtLeastOnce, true, payload.clone())
+                    .await?;
+            }
+            anyhow::Ok(())
+        }
+    });
+
+    let deadline = Instant::now() + config.receive_timeout;
+    let mut acks = 0;
+    while acks < topics {
+        match time::timeout_at(deadline.into(), eventloop.poll()).await {
+            Ok(Ok(Event::Incoming(Incoming::PubAck(_)))) => acks += 1,
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => {
+                warn!(
+                    "Id = mqttwrk-retain-flood, {} of {} retained publishes acked",
+                    acks, topics
+                );
+                break;
+            }
+        }
+    }
+
+    requests.abort();
+    Ok(acks)
+}
+
+async fn connect(config: &Arc<BenchConfig>, id: &str) -> anyhow::Result<(AsyncClient, EventLoop)> {
+    let (client, mut eventloop) = AsyncClient::new(options(config.clone(), id)?, 10);
+    eventloop.network_options = network_options(config)?;
+
+    loop {
+        match time::timeout(config.conn_timeout, eventloop.poll()).await?? {
+            Event::Incoming(Incoming::ConnAck(_)) => return Ok((client, eventloop)),
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+}
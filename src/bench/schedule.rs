@@ -0,0 +1,161 @@
+//! Scripted load in timed phases, read from a `--schedule` file with a
+//! `DURATION RATE` row per phase, e.g. `5m 1000`. Phases run back to back at
+//! their publishes per second of all publishers together, a rate of 0 pauses
+//! publishing, and the run ends with the last phase. Lines starting with `#`
+//! are skipped
+
+use std::{fs, time::Duration};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Phase {
+    pub duration: Duration,
+    /// publishes per second of all publishers together
+    pub rate: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Schedule {
+    pub phases: Vec<Phase>,
+}
+
+impl Schedule {
+    /// Time until the end of the last phase
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|phase| phase.duration).sum()
+    }
+
+    /// Index of the phase `at` into the schedule falls in, the last one once
+    /// the schedule is over
+    pub fn phase_at(&self, at: Duration) -> usize {
+        let mut end = Duration::ZERO;
+        for (i, phase) in self.phases.iter().enumerate() {
+            end += phase.duration;
+            if at < end {
+                return i;
+            }
+        }
+
+        self.phases.len() - 1
+    }
+
+    /// Start and end of every phase
+    pub fn bounds(&self) -> impl Iterator<Item = (Duration, Duration)> + '_ {
+        self.phases.iter().scan(Duration::ZERO, |start, phase| {
+            let bounds = (*start, *start + phase.duration);
+            *start += phase.duration;
+            Some(bounds)
+        })
+    }
+}
+
+pub(crate) fn load(path: &str) -> Result<Schedule, String> {
+    let schedule = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    parse(path, schedule.lines())
+}
+
+/// Phases of `rows`. Errors point at the row of `path`
+fn parse<'a>(path: &str, rows: impl Iterator<Item = &'a str>) -> Result<Schedule, String> {
+    let mut phases = Vec::new();
+    for (i, line) in rows.enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let (Some(duration), Some(rate), None) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(format!("{path}:{}: expected DURATION RATE", i + 1));
+        };
+        let duration = humantime::parse_duration(duration)
+            .map_err(|e| format!("{path}:{}: invalid duration `{duration}`: {e}", i + 1))?;
+        let rate = rate
+            .parse()
+            .map_err(|e| format!("{path}:{}: invalid rate `{rate}`: {e}", i + 1))?;
+        phases.push(Phase { duration, rate });
+    }
+
+    if phases.iter().all(|phase| phase.duration.is_zero()) {
+        return Err(format!("{path}: no phases"));
+    }
+
+    Ok(Schedule { phases })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::parse;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn parses_phases() {
+        let rows = "# warm up\n10s 100\n\n1m 0\n  5s   1000  ";
+        let schedule = parse("schedule", rows.lines()).unwrap();
+
+        let phases: Vec<_> = schedule
+            .phases
+            .iter()
+            .map(|phase| (phase.duration, phase.rate))
+            .collect();
+        assert_eq!(phases, [(secs(10), 100), (secs(60), 0), (secs(5), 1000)]);
+        assert_eq!(schedule.total(), secs(75));
+    }
+
+    #[test]
+    fn bounds_are_back_to_back() {
+        let schedule = parse("schedule", ["10s 100", "1m 0", "5s 1000"].iter().copied()).unwrap();
+
+        let bounds: Vec<_> = schedule.bounds().collect();
+        assert_eq!(
+            bounds,
+            [
+                (secs(0), secs(10)),
+                (secs(10), secs(70)),
+                (secs(70), secs(75))
+            ]
+        );
+    }
+
+    #[test]
+    fn phase_at_stays_on_the_last_phase() {
+        let schedule = parse("schedule", ["10s 100", "1m 0", "5s 1000"].iter().copied()).unwrap();
+
+        assert_eq!(schedule.phase_at(secs(0)), 0);
+        assert_eq!(schedule.phase_at(Duration::from_millis(9999)), 0);
+        assert_eq!(schedule.phase_at(secs(10)), 1);
+        assert_eq!(schedule.phase_at(secs(74)), 2);
+        assert_eq!(schedule.phase_at(secs(1000)), 2);
+    }
+
+    #[test]
+    fn errors_point_at_the_row() {
+        let error = |rows: &[&str]| parse("schedule", rows.iter().copied()).unwrap_err();
+
+        assert_eq!(
+            error(&["10s 100", "# pause", "1m"]),
+            "schedule:3: expected DURATION RATE"
+        );
+        assert_eq!(error(&["10s 100 5"]), "schedule:1: expected DURATION RATE");
+        assert!(error(&["", "10 100"]).starts_with("schedule:2: invalid duration `10`"));
+        assert!(error(&["10s -1"]).starts_with("schedule:1: invalid rate `-1`"));
+    }
+
+    #[test]
+    fn rejects_schedules_without_phases() {
+        assert_eq!(
+            parse("schedule", ["# nothing"].iter().copied()).unwrap_err(),
+            "schedule: no phases"
+        );
+        assert_eq!(
+            parse("schedule", ["0s 100"].iter().copied()).unwrap_err(),
+            "schedule: no phases"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Detection of lost, duplicate and reordered publishes using the sequence
+//! numbers that publishers embed in payloads
+
+use rumqttc::QoS;
+
+/// Sequence numbers received by a subscriber on one topic. Publishers number
+/// their publishes from 0, so `expected` publishes are `0..expected`
+#[derive(Debug)]
+pub struct Sequences {
+    /// number of times every sequence number was delivered
+    received: Vec<u32>,
+    /// deliveries of already received sequence numbers, by qos of the delivery
+    duplicates: [u64; 3],
+    /// highest sequence number received so far
+    highest: Option<u64>,
+    /// publishes that arrived after a publish with a higher sequence number
+    reordered: u64,
+    /// largest distance between a reordered publish and the highest sequence
+    /// number received before it
+    max_displacement: u64,
+}
+
+impl Sequences {
+    pub fn new(expected: usize) -> Sequences {
+        Sequences {
+            received: vec![0; expected],
+            duplicates: [0; 3],
+            highest: None,
+            reordered: 0,
+            max_displacement: 0,
+        }
+    }
+
+    /// Records a delivery of `sequence`. Returns true if it is a duplicate
+    pub fn record(&mut self, sequence: u64, qos: QoS) -> bool {
+        let received = match self.received.get_mut(sequence as usize) {
+            Some(received) => received,
+            None => {
+                warn!("Unexpected sequence number = {}", sequence);
+                return false;
+            }
+        };
+
+        *received = received.saturating_add(1);
+        if *received > 1 {
+            self.duplicates[qos as usize] += 1;
+            return true;
+        }
+
+        match self.highest {
+            Some(highest) if sequence < highest => {
+                self.reordered += 1;
+                self.max_displacement = self.max_displacement.max(highest - sequence);
+            }
+            _ => self.highest = Some(sequence),
+        }
+
+        false
+    }
+
+    pub fn duplicates(&self) -> [u64; 3] {
+        self.duplicates
+    }
+
+    pub fn reordered(&self) -> u64 {
+        self.reordered
+    }
+
+    pub fn max_displacement(&self) -> u64 {
+        self.max_displacement
+    }
+
+    /// Inclusive ranges of sequence numbers that were never received
+    pub fn gaps(&self) -> Vec<(u64, u64)> {
+        let mut gaps = Vec::new();
+        let mut start = None;
+        for (sequence, received) in self.received.iter().enumerate() {
+            match (start, *received > 0) {
+                (None, false) => start = Some(sequence as u64),
+                (Some(first), true) => {
+                    gaps.push((first, sequence as u64 - 1));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(first) = start {
+            gaps.push((first, self.received.len() as u64 - 1));
+        }
+
+        gaps
+    }
+}
@@ -0,0 +1,131 @@
+//! Mqtt 5 session expiry. `--session-churn` v5 subscribers connect with
+//! `--session-expiry`, subscribe and go offline together while `--count`
+//! publishes are queued for each of them. Half of them come back before their
+//! sessions expire and expect to resume them along with every queued publish,
+//! the other half come back after and expect a fresh session without any
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::try_join_all;
+use rumqttc::v5::{
+    mqttbytes::{
+        v5::{ConnectProperties, Packet},
+        QoS,
+    },
+    Event,
+};
+use serde::Serialize;
+use tokio::time;
+
+use crate::{
+    bench::{v5_connect, v5_disconnect, v5_options, v5_publish},
+    BenchConfig,
+};
+
+const TOPIC: &str = "hello/sessions/world";
+
+#[derive(Debug, Serialize)]
+pub struct SessionExpiryReport {
+    pub expiry_secs: u32,
+    /// publishes queued for every session
+    pub published: usize,
+    pub before_expiry: Churn,
+    pub after_expiry: Churn,
+}
+
+/// Sessions that came back at the same time
+#[derive(Debug, Serialize)]
+pub struct Churn {
+    pub sessions: usize,
+    /// sessions the broker resumed
+    pub present: usize,
+    /// queued publishes delivered once the sessions came back
+    pub queued: u64,
+    /// queued publishes expected if the broker honors session expiry
+    pub expected: u64,
+}
+
+pub(crate) async fn verify(config: Arc<BenchConfig>) -> anyhow::Result<SessionExpiryReport> {
+    let expiry = config.session_expiry.unwrap_or(0);
+    let ids: Vec<String> = (0..config.session_churn)
+        .map(|i| format!("mqttwrk-session-{i:05}"))
+        .collect();
+
+    try_join_all(ids.iter().map(|id| subscribe(&config, id, expiry))).await?;
+    let offline_at = Instant::now();
+    let expires_at = offline_at + Duration::from_secs(expiry as u64);
+    v5_publish(&config, "mqttwrk-session", TOPIC, config.count, None).await?;
+    if Instant::now() >= expires_at {
+        warn!("Id = mqttwrk-session, Sessions expired before publishes were queued, consider a longer --session-expiry");
+    }
+
+    let (before, after) = ids.split_at(ids.len() / 2);
+    let before_expiry = resume(&config, before, config.count as u64).await?;
+    time::sleep_until((expires_at + Duration::from_secs(1)).into()).await;
+    let after_expiry = resume(&config, after, 0).await?;
+
+    Ok(SessionExpiryReport {
+        expiry_secs: expiry,
+        published: config.count,
+        before_expiry,
+        after_expiry,
+    })
+}
+
+/// Subscribes with a session that expires `expiry` seconds after going
+/// offline, and goes offline
+async fn subscribe(config: &BenchConfig, id: &str, expiry: u32) -> anyhow::Result<()> {
+    let mut options = v5_options(config, id)?;
+    let mut properties = ConnectProperties::new();
+    properties.session_expiry_interval = Some(expiry);
+    options.set_connect_properties(properties);
+
+    let (client, mut eventloop, _) = v5_connect(options).await?;
+    client.subscribe(TOPIC, QoS::AtLeastOnce).await?;
+    loop {
+        match eventloop.poll().await? {
+            Event::Incoming(Packet::SubAck(_)) => break,
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+
+    v5_disconnect(&client, &mut eventloop).await
+}
+
+/// Brings sessions back together, ending them once they disconnect. `queued`
+/// is the number of publishes each of them is expected to receive
+async fn resume(config: &BenchConfig, ids: &[String], queued: u64) -> anyhow::Result<Churn> {
+    let resumed = try_join_all(ids.iter().map(|id| async move {
+        let mut options = v5_options(config, id)?;
+        options.set_clean_start(false);
+        let (client, mut eventloop, connack) = v5_connect(options).await?;
+
+        // sessions that aren't expected to receive anything wait out the timeout
+        let mut received = 0;
+        while queued == 0 || received < queued {
+            let event = match time::timeout(config.receive_timeout, eventloop.poll()).await {
+                Ok(event) => event?,
+                Err(_) => break,
+            };
+
+            if let Event::Incoming(Packet::Publish(_)) = event {
+                received += 1;
+            }
+        }
+
+        v5_disconnect(&client, &mut eventloop).await?;
+        Ok::<_, anyhow::Error>((connack.session_present, received))
+    }))
+    .await?;
+
+    Ok(Churn {
+        sessions: ids.len(),
+        present: resumed.iter().filter(|(present, _)| *present).count(),
+        queued: resumed.iter().map(|(_, received)| received).sum(),
+        expected: queued * ids.len() as u64,
+    })
+}
@@ -0,0 +1,127 @@
+//! Shared subscriptions. Subscribers in a `$share` group split the publishes
+//! between them, so that no single subscriber receives all of them. The group
+//! as a whole is done once it has received every publish
+
+use std::{
+    future,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::{
+    bench::{publisher_id, topic, wildcard},
+    BenchConfig,
+};
+
+/// Filter subscribers subscribe to, `--sink-filter` if set, and shared when
+/// `--share-group` is set
+pub(crate) fn filter(config: &BenchConfig) -> String {
+    let filter = match (&config.sink_filter, config.fan_out) {
+        (Some(filter), _) => filter.clone(),
+        (None, true) => topic(&publisher_id(config, 0)),
+        (None, false) => wildcard::filter(config.topic_depth),
+    };
+    match &config.share_group {
+        Some(group) => format!("$share/{group}/{filter}"),
+        None => filter,
+    }
+}
+
+/// Publishes received by all members of the group
+pub(crate) struct Group {
+    received: AtomicU64,
+    expected: u64,
+    done: Notify,
+}
+
+impl Group {
+    pub(crate) fn new(expected: u64) -> Group {
+        Group {
+            received: AtomicU64::new(0),
+            expected,
+            done: Notify::new(),
+        }
+    }
+
+    pub(crate) fn received(&self) {
+        if self.received.fetch_add(1, Ordering::Relaxed) + 1 == self.expected {
+            self.done.notify_waiters();
+        }
+    }
+
+    /// Resolves once the group has received every publish
+    pub(crate) async fn done(&self) {
+        loop {
+            let notified = self.done.notified();
+            if self.received.load(Ordering::Relaxed) >= self.expected {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+/// Resolves once `group` is done. Never resolves without a group
+pub(crate) async fn done(group: Option<&Group>) {
+    match group {
+        Some(group) => group.done().await,
+        None => future::pending().await,
+    }
+}
+
+/// How the broker spread publishes across the members of the group
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Distribution {
+    pub group: String,
+    pub members: usize,
+    /// fewest publishes received by a member
+    pub min: u64,
+    /// most publishes received by a member
+    pub max: u64,
+    pub mean: f64,
+    pub stddev: f64,
+    /// stddev relative to the mean. 0 is a perfectly even split
+    pub cov: f64,
+    /// publishes received per second by the group as a whole
+    pub throughput: f64,
+}
+
+impl Distribution {
+    pub(crate) fn new(group: &str, counts: &[u64], duration_secs: f64) -> Distribution {
+        let members = counts.len();
+        let total: u64 = counts.iter().sum();
+        let mean = match members {
+            0 => 0.0,
+            members => total as f64 / members as f64,
+        };
+        let variance = match members {
+            0 => 0.0,
+            members => {
+                counts
+                    .iter()
+                    .map(|&count| (count as f64 - mean).powi(2))
+                    .sum::<f64>()
+                    / members as f64
+            }
+        };
+        let stddev = variance.sqrt();
+
+        Distribution {
+            group: group.to_owned(),
+            members,
+            min: counts.iter().copied().min().unwrap_or(0),
+            max: counts.iter().copied().max().unwrap_or(0),
+            mean,
+            stddev,
+            cov: match mean {
+                mean if mean > 0.0 => stddev / mean,
+                _ => 0.0,
+            },
+            throughput: total as f64 / duration_secs,
+        }
+    }
+}
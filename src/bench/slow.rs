@@ -0,0 +1,55 @@
+//! Slow consumers. With `--sink-delay` or `--sink-rate`, subscribers take
+//! their time over every publish and don't read from their connection
+//! meanwhile, so that publishes back up into the broker. The broker then has
+//! to queue them, hold back inflight ones and buffer them in memory, as it
+//! would for devices or services that can't keep up. With `--slow-sinks`, only
+//! the first subscribers are slow and the rest keep up
+
+use std::time::{Duration, Instant};
+
+use tokio::time;
+
+use crate::BenchConfig;
+
+pub(crate) struct Slow {
+    pace: Pace,
+    /// when the subscriber reads from its connection again
+    next: Instant,
+}
+
+enum Pace {
+    /// the same time on every publish
+    Delay(Duration),
+    /// publishes at least this far apart
+    Interval(Duration),
+}
+
+impl Slow {
+    /// Pace of subscriber `index`, if it's slow
+    pub(crate) fn new(config: &BenchConfig, index: usize) -> Option<Slow> {
+        if index >= config.slow_sinks.unwrap_or(usize::MAX) {
+            return None;
+        }
+
+        let pace = match (config.sink_delay, config.sink_rate) {
+            (Some(delay), _) => Pace::Delay(delay),
+            (None, Some(rate)) => Pace::Interval(Duration::from_secs_f64(1.0 / rate as f64)),
+            (None, None) => return None,
+        };
+        Some(Slow {
+            pace,
+            next: Instant::now(),
+        })
+    }
+
+    /// Waits until the subscriber is done with the publish it just received.
+    /// Rates don't catch up on time the subscriber spent waiting for publishes
+    pub(crate) async fn consume(&mut self) {
+        let now = Instant::now();
+        self.next = match self.pace {
+            Pace::Delay(delay) => now + delay,
+            Pace::Interval(interval) => (self.next + interval).max(now),
+        };
+        time::sleep_until(self.next.into()).await;
+    }
+}
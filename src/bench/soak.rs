@@ -0,0 +1,66 @@
+//! Soak runs. With `--forever`, publishers publish until the process gets a
+//! SIGTERM or Ctrl-C instead of `--count` times, and the run then winds down
+//! like a run of a fixed duration that ended early, with the final report of
+//! the whole run. Summaries of every `--report-interval` meanwhile show how the
+//! broker holds up over days. Only the last `SAMPLES` per second samples are
+//! kept for the final report, so that memory doesn't grow with the run
+
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use tokio::task;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFuture};
+
+/// Duration of runs forever, long enough to never be over
+pub(crate) const FOREVER: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// `--report-interval` of runs forever unless given
+pub(crate) const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Per second samples runs forever keep, those of the last hour
+pub(crate) const SAMPLES: usize = 60 * 60;
+
+static STOP: Lazy<CancellationToken> = Lazy::new(CancellationToken::new);
+
+/// Stops publishers on the first SIGTERM or Ctrl-C, and exits right away on
+/// the second one
+pub(crate) fn listen() {
+    task::spawn(async {
+        signal().await;
+        println!("Stopping, waiting for the last publishes. Stop again to exit right away");
+        STOP.cancel();
+
+        signal().await;
+        std::process::exit(130);
+    });
+}
+
+/// Resolves once the run was told to stop
+pub(crate) fn stopped() -> WaitForCancellationFuture<'static> {
+    STOP.cancelled()
+}
+
+pub(crate) fn is_stopped() -> bool {
+    STOP.is_cancelled()
+}
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{self, SignalKind};
+
+    match unix::signal(SignalKind::terminate()) {
+        Ok(mut terminate) => tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        },
+        Err(e) => {
+            error!("Failed to listen for SIGTERM = {:?}", e);
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,95 @@
+//! Emits per second samples over udp in statsd format, for Datadog, Telegraf
+//! and other statsd compatible agents. Publish and ack counts are counters,
+//! connection state is gauges and interval latency percentiles are timings
+
+use std::{fmt::Write as _, io, net::SocketAddr};
+
+use tokio::net::UdpSocket;
+
+use crate::{
+    bench::{metrics::Sample, reporter::Reporter},
+    common::LatencySummary,
+};
+
+pub struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl Statsd {
+    pub async fn connect(addr: SocketAddr, prefix: &str) -> io::Result<Statsd> {
+        let bind: SocketAddr = match addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(addr).await?;
+        Ok(Statsd {
+            socket,
+            prefix: prefix.to_owned(),
+        })
+    }
+
+    /// Sends all the metrics of a sample in one datagram. Udp sends don't wait
+    /// for the agent, so this doesn't block
+    pub fn push(&self, sample: &Sample) -> io::Result<()> {
+        self.socket.try_send(self.lines(sample).as_bytes())?;
+        Ok(())
+    }
+
+    fn lines(&self, sample: &Sample) -> String {
+        let prefix = &self.prefix;
+        let mut lines = String::new();
+        let _ = writeln!(lines, "{prefix}.connections:{}|g", sample.connections);
+        let _ = writeln!(lines, "{prefix}.inflight:{}|g", sample.inflight);
+        let _ = writeln!(lines, "{prefix}.reconnects:{}|g", sample.reconnects);
+        let _ = writeln!(
+            lines,
+            "{prefix}.outgoing_publishes:{}|c",
+            sample.outgoing_publishes
+        );
+        let _ = writeln!(
+            lines,
+            "{prefix}.incoming_publishes:{}|c",
+            sample.incoming_publishes
+        );
+        let _ = writeln!(lines, "{prefix}.acks:{}|c", sample.acks);
+
+        for (name, latencies) in [
+            ("ack_latency", &sample.ack_latencies),
+            ("latency", &sample.latencies),
+        ] {
+            timings(&mut lines, &format!("{prefix}.{name}"), latencies);
+        }
+
+        lines
+    }
+}
+
+impl Reporter for Statsd {
+    fn name(&self) -> &'static str {
+        "statsd"
+    }
+
+    fn sample(&mut self, sample: &Sample) {
+        if let Err(e) = self.push(sample) {
+            warn!("Failed to send sample to statsd = {:?}", e);
+        }
+    }
+}
+
+fn timings(lines: &mut String, name: &str, latencies: &LatencySummary) {
+    if latencies.samples == 0 {
+        return;
+    }
+
+    for (percentile, value) in [
+        ("p50", latencies.p50),
+        ("p90", latencies.p90),
+        ("p99", latencies.p99),
+        ("max", latencies.max),
+    ] {
+        let _ = writeln!(lines, "{name}.{percentile}:{value}|ms");
+    }
+}
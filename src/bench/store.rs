@@ -0,0 +1,176 @@
+//! Archives runs in a sqlite database so that broker performance can be
+//! tracked across versions without any external infrastructure. Every run
+//! appends a row to `runs` and a row per connection to `connections`
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection};
+
+use crate::{
+    bench::{assertions::Assertion, endpoint_label, report::Report, reporter::Reporter},
+    common::LatencySummary,
+    BenchConfig,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id                  INTEGER PRIMARY KEY,
+    finished_at         INTEGER NOT NULL,
+    server              TEXT NOT NULL,
+    config              TEXT NOT NULL,
+    summary             TEXT NOT NULL,
+    duration_secs       REAL NOT NULL,
+    publish_throughput  REAL NOT NULL,
+    incoming_throughput REAL NOT NULL,
+    loss_percent        REAL NOT NULL,
+    reconnects          INTEGER NOT NULL,
+    ack_latency_p50_ms  REAL NOT NULL,
+    ack_latency_p99_ms  REAL NOT NULL,
+    latency_p50_ms      REAL NOT NULL,
+    latency_p99_ms      REAL NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS connections (
+    run_id         INTEGER NOT NULL REFERENCES runs(id),
+    id             TEXT NOT NULL,
+    kind           TEXT NOT NULL,
+    publishes      INTEGER NOT NULL,
+    throughput     REAL NOT NULL,
+    reconnects     INTEGER NOT NULL,
+    latency_p50_ms REAL NOT NULL,
+    latency_p99_ms REAL NOT NULL,
+    latency_max_ms REAL NOT NULL
+);
+";
+
+#[derive(thiserror::Error, Debug)]
+pub enum StoreError {
+    #[error("Sqlite error = {0:?}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Serialization error = {0:?}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Archives the final report of the run
+pub(crate) struct Store {
+    path: PathBuf,
+    config: Arc<BenchConfig>,
+}
+
+impl Store {
+    pub fn new(path: PathBuf, config: Arc<BenchConfig>) -> Store {
+        Store { path, config }
+    }
+}
+
+impl Reporter for Store {
+    fn name(&self) -> &'static str {
+        "store"
+    }
+
+    fn finish(&mut self, report: &Report, _assertions: &[Assertion]) -> anyhow::Result<()> {
+        let id = append(&self.path, &self.config, report)?;
+        info!("Stored run {} in {}", id, self.path.display());
+        Ok(())
+    }
+}
+
+/// Appends the run to the database at `path`, creating it if needed. Returns
+/// the id of the run
+fn append(path: &Path, config: &BenchConfig, report: &Report) -> Result<i64, StoreError> {
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+
+    let finished_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let summary = &report.summary;
+
+    let transaction = connection.transaction()?;
+    transaction.execute(
+        "INSERT INTO runs (
+            finished_at, server, config, summary, duration_secs, publish_throughput,
+            incoming_throughput, loss_percent, reconnects, ack_latency_p50_ms,
+            ack_latency_p99_ms, latency_p50_ms, latency_p99_ms
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            finished_at,
+            config
+                .servers
+                .iter()
+                .map(|endpoint| endpoint_label(config, endpoint))
+                .collect::<Vec<_>>()
+                .join(","),
+            serde_json::to_string(config)?,
+            serde_json::to_string(summary)?,
+            summary.duration_secs,
+            summary.publish_throughput,
+            summary.incoming_throughput,
+            summary.loss_percent,
+            summary.reconnects as i64,
+            summary.ack_latencies.p50,
+            summary.ack_latencies.p99,
+            summary.latencies.p50,
+            summary.latencies.p99,
+        ],
+    )?;
+    let run_id = transaction.last_insert_rowid();
+
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO connections (
+                run_id, id, kind, publishes, throughput, reconnects,
+                latency_p50_ms, latency_p99_ms, latency_max_ms
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+
+        let mut row = |id: &str,
+                       kind: &str,
+                       publishes: u64,
+                       throughput: f32,
+                       reconnects: u64,
+                       latencies: &LatencySummary| {
+            insert.execute(params![
+                run_id,
+                id,
+                kind,
+                publishes as i64,
+                throughput as f64,
+                reconnects as i64,
+                latencies.p50,
+                latencies.p99,
+                latencies.max,
+            ])
+        };
+
+        for publisher in report.publishers.iter() {
+            row(
+                &publisher.id,
+                "publisher",
+                publisher.outgoing_publish,
+                publisher.throughput,
+                publisher.reconnects,
+                &publisher.ack_latencies,
+            )?;
+        }
+
+        for subscriber in report.subscribers.iter() {
+            row(
+                &subscriber.id,
+                "subscriber",
+                subscriber.publish_count,
+                subscriber.throughput,
+                subscriber.reconnects,
+                &subscriber.latencies,
+            )?;
+        }
+    }
+
+    transaction.commit()?;
+    Ok(run_id)
+}
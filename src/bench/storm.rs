@@ -0,0 +1,36 @@
+//! Reconnect storms. With `--reconnect-storm`, every publisher and subscriber
+//! drops its connection at the same moment once the run has been publishing
+//! for that long, as a broker restart or a load balancer failover would, and
+//! reconnects right away. The report tells how long the broker took to accept
+//! the whole fleet again and for publishes to flow again
+
+use std::time::Duration;
+
+use futures::future;
+use tokio::{task, time};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Storm {
+    hit: CancellationToken,
+}
+
+impl Storm {
+    /// Drops every connection `after` from now
+    pub(crate) fn start(&self, after: Duration) {
+        let hit = self.hit.clone();
+        task::spawn(async move {
+            time::sleep(after).await;
+            info!("Dropping every connection");
+            hit.cancel();
+        });
+    }
+}
+
+/// Resolves once `storm` hits. Never resolves without a storm
+pub(crate) async fn hit(storm: Option<&Storm>) {
+    match storm {
+        Some(storm) => storm.hit.cancelled().await,
+        None => future::pending().await,
+    }
+}
@@ -0,0 +1,275 @@
+//! Mqtt 5 subscription options. A v5 subscriber subscribes with
+//! `--no-local`, `--retain-as-published` and `--retain-handling` and checks
+//! that the broker honours them: publishes of its own are looped back only
+//! without no local, live retained publishes keep their retain flag only with
+//! retain as published, and retained publishes are delivered on subscribing
+//! and resubscribing as the retain handling asks for. A control publish of a
+//! second connection marks the end of every step, so that nothing has to be
+//! waited for unless the broker misbehaves
+
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use rumqttc::{
+    v5::{
+        mqttbytes::{
+            v5::{Filter, Packet, Publish, RetainForwardRule},
+            QoS,
+        },
+        AsyncClient, Event, EventLoop,
+    },
+    Outgoing,
+};
+use serde::Serialize;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver},
+    task::{self, JoinHandle},
+    time,
+};
+
+use crate::{
+    bench::{payload, v5_connect, v5_disconnect, v5_options},
+    BenchConfig,
+};
+
+const FILTER: &str = "hello/subopts/+";
+const TOPIC: &str = "hello/subopts/world";
+const RETAINED_TOPIC: &str = "hello/subopts/retained";
+const CONTROL_TOPIC: &str = "hello/subopts/control";
+const PUBLISHER: &str = "mqttwrk-subopts";
+const SUBSCRIBER: &str = "mqttwrk-subopts-sub";
+
+#[derive(Debug, Serialize)]
+pub struct SubscriptionOptionsReport {
+    pub no_local: bool,
+    pub retain_as_published: bool,
+    pub retain_handling: u8,
+    /// publishes of the subscriber to a topic it's subscribed to
+    pub published: usize,
+    /// publishes of its own the subscriber received
+    pub loopback: usize,
+    /// whether a live retained publish kept its retain flag, if it was delivered
+    pub retain_flag_kept: Option<bool>,
+    /// retained publishes delivered on subscribing
+    pub retained_on_subscribe: usize,
+    /// retained publishes delivered on subscribing again to the same filter
+    pub retained_on_resubscribe: usize,
+    /// how the broker deviated from the options
+    pub violations: Vec<String>,
+}
+
+pub(crate) async fn verify(config: Arc<BenchConfig>) -> anyhow::Result<SubscriptionOptionsReport> {
+    let retain_handling = config.retain_handling.unwrap_or(0);
+    let filter = Filter {
+        path: FILTER.to_owned(),
+        qos: QoS::AtLeastOnce,
+        nolocal: config.no_local,
+        preserve_retain: config.retain_as_published,
+        retain_forward_rule: match retain_handling {
+            0 => RetainForwardRule::OnEverySubscribe,
+            1 => RetainForwardRule::OnNewSubscribe,
+            _ => RetainForwardRule::Never,
+        },
+    };
+    let payload = Bytes::from(payload::generate(config.payload_size, false, None));
+
+    let (client, eventloop, _) = v5_connect(v5_options(&config, PUBLISHER)?).await?;
+    let mut publisher = Publisher {
+        client,
+        eventloop,
+        timeout: config.receive_timeout,
+    };
+    publisher
+        .publish(RETAINED_TOPIC, true, payload.clone())
+        .await?;
+
+    let (client, eventloop, _) = v5_connect(v5_options(&config, SUBSCRIBER)?).await?;
+    let mut subscriber = Subscriber::spawn(client, eventloop, config.receive_timeout);
+
+    subscriber.client.subscribe_many([filter.clone()]).await?;
+    subscriber
+        .until(|packet| matches!(packet, Packet::SubAck(_)))
+        .await?;
+    publisher
+        .publish(CONTROL_TOPIC, false, Bytes::new())
+        .await?;
+    let received = subscriber.until(control).await?;
+    let retained_on_subscribe = count(&received, RETAINED_TOPIC);
+
+    for _ in 0..config.count {
+        subscriber
+            .client
+            .publish(TOPIC, QoS::AtLeastOnce, false, payload.clone())
+            .await?;
+    }
+    let mut acks = 0;
+    let mut received = subscriber
+        .until(|packet| {
+            acks += matches!(packet, Packet::PubAck(_)) as usize;
+            acks == config.count
+        })
+        .await?;
+    publisher.publish(RETAINED_TOPIC, true, payload).await?;
+    publisher
+        .publish(CONTROL_TOPIC, false, Bytes::new())
+        .await?;
+    received.extend(subscriber.until(control).await?);
+    let loopback = count(&received, TOPIC);
+    let retain_flag_kept = received
+        .iter()
+        .find(|publish| publish.topic == RETAINED_TOPIC)
+        .map(|publish| publish.retain);
+
+    subscriber.client.subscribe_many([filter]).await?;
+    subscriber
+        .until(|packet| matches!(packet, Packet::SubAck(_)))
+        .await?;
+    publisher
+        .publish(CONTROL_TOPIC, false, Bytes::new())
+        .await?;
+    let received = subscriber.until(control).await?;
+    let retained_on_resubscribe = count(&received, RETAINED_TOPIC);
+
+    subscriber.disconnect().await?;
+    publisher
+        .publish(RETAINED_TOPIC, true, Bytes::new())
+        .await?;
+    v5_disconnect(&publisher.client, &mut publisher.eventloop).await?;
+
+    let mut violations = Vec::new();
+    let expected_loopback = if config.no_local { 0 } else { config.count };
+    if loopback != expected_loopback {
+        violations.push(format!(
+            "{loopback} publishes looped back, expected {expected_loopback}"
+        ));
+    }
+    match retain_flag_kept {
+        Some(kept) if kept != config.retain_as_published => {
+            violations.push(format!("retain flag of live retained publish = {kept}"))
+        }
+        Some(_) => {}
+        None => violations.push("live retained publish wasn't delivered".to_owned()),
+    }
+    let expected_on_subscribe = (retain_handling != 2) as usize;
+    if retained_on_subscribe != expected_on_subscribe {
+        violations.push(format!(
+            "{retained_on_subscribe} retained publishes on subscribe, expected {expected_on_subscribe}"
+        ));
+    }
+    let expected_on_resubscribe = (retain_handling == 0) as usize;
+    if retained_on_resubscribe != expected_on_resubscribe {
+        violations.push(format!(
+            "{retained_on_resubscribe} retained publishes on resubscribe, expected {expected_on_resubscribe}"
+        ));
+    }
+
+    Ok(SubscriptionOptionsReport {
+        no_local: config.no_local,
+        retain_as_published: config.retain_as_published,
+        retain_handling,
+        published: config.count,
+        loopback,
+        retain_flag_kept,
+        retained_on_subscribe,
+        retained_on_resubscribe,
+        violations,
+    })
+}
+
+fn control(packet: &Packet) -> bool {
+    matches!(packet, Packet::Publish(publish) if publish.topic == CONTROL_TOPIC)
+}
+
+fn count(received: &[Publish], topic: &str) -> usize {
+    received
+        .iter()
+        .filter(|publish| publish.topic == topic)
+        .count()
+}
+
+/// Connection that publishes retained and control publishes
+struct Publisher {
+    client: AsyncClient,
+    eventloop: EventLoop,
+    timeout: Duration,
+}
+
+impl Publisher {
+    /// Publishes with QoS 1 and waits for the broker to ack
+    async fn publish(&mut self, topic: &str, retain: bool, payload: Bytes) -> anyhow::Result<()> {
+        self.client
+            .publish(topic, QoS::AtLeastOnce, retain, payload)
+            .await?;
+        loop {
+            match time::timeout(self.timeout, self.eventloop.poll()).await {
+                Ok(Ok(Event::Incoming(Packet::PubAck(_)))) => return Ok(()),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => anyhow::bail!("Id = {}, Publish to {} wasn't acked", PUBLISHER, topic),
+            }
+        }
+    }
+}
+
+/// Subscriber whose eventloop is polled by a task, so that it keeps receiving
+/// while the publisher publishes
+struct Subscriber {
+    client: AsyncClient,
+    incoming: UnboundedReceiver<Packet>,
+    handle: JoinHandle<anyhow::Result<()>>,
+    timeout: Duration,
+}
+
+impl Subscriber {
+    fn spawn(client: AsyncClient, mut eventloop: EventLoop, timeout: Duration) -> Subscriber {
+        let (tx, incoming) = mpsc::unbounded_channel();
+        let handle = task::spawn(async move {
+            loop {
+                match eventloop.poll().await? {
+                    Event::Incoming(packet) => {
+                        let _ = tx.send(packet);
+                    }
+                    Event::Outgoing(Outgoing::Disconnect) => return Ok(()),
+                    Event::Outgoing(_) => {}
+                }
+            }
+        });
+
+        Subscriber {
+            client,
+            incoming,
+            handle,
+            timeout,
+        }
+    }
+
+    /// Receives until `done` returns true for a packet. Returns the publishes
+    /// received before it, other than control publishes
+    async fn until(
+        &mut self,
+        mut done: impl FnMut(&Packet) -> bool,
+    ) -> anyhow::Result<Vec<Publish>> {
+        let mut received = Vec::new();
+        loop {
+            let packet = match time::timeout(self.timeout, self.incoming.recv()).await {
+                Ok(Some(packet)) => packet,
+                Ok(None) => anyhow::bail!("Id = {}, Connection closed by broker", SUBSCRIBER),
+                Err(_) => anyhow::bail!("Id = {}, Timed out waiting for broker", SUBSCRIBER),
+            };
+
+            if done(&packet) {
+                return Ok(received);
+            }
+            if let Packet::Publish(publish) = packet {
+                if publish.topic != CONTROL_TOPIC {
+                    received.push(publish);
+                }
+            }
+        }
+    }
+
+    async fn disconnect(self) -> anyhow::Result<()> {
+        self.client.disconnect().await?;
+        self.handle.await?
+    }
+}
@@ -1,114 +1,336 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use hdrhistogram::Histogram;
-use rumqttc::{AsyncClient, Event, EventLoop, Incoming, Outgoing};
-use tokio::sync::Barrier;
+use rumqttc::{AsyncClient, Event, EventLoop, Incoming, Outgoing, Publish, SubscribeFilter};
+use tokio::{sync::Barrier, time};
 
 use crate::{
-    bench::{get_qos, options, ConnectionError, SubStats},
-    BenchConfig,
+    bench::{
+        deadline::{self, Published},
+        endpoint_label, endpoint_options,
+        flap::{self, Flap},
+        get_qos, matrix,
+        metrics::METRICS,
+        network_options,
+        outage::Outages,
+        payload, publisher_id,
+        rampdown::Connection,
+        refresh_token,
+        sequence::Sequences,
+        shared::{self, Group},
+        slow::Slow,
+        storm::{self, Storm},
+        subscribed_publishers, subscribed_publishes, topic, wildcard, ConnectionError, SubStats,
+    },
+    common::{
+        latency_histogram, Flaps, IntervalStats, LatencySummary, Recovery, Resumption, TopicGaps,
+        TopicStats,
+    },
+    BenchConfig, Endpoint,
 };
 
 pub struct Subscriber {
     id: String,
+    /// `HOST:PORT` of the broker connected to
+    endpoint: String,
     config: Arc<BenchConfig>,
-    #[allow(dead_code)]
+    /// time from connect to connack
+    connack_latency: Duration,
+    /// time from subscribe to suback
+    suback_latency: Duration,
+    /// filters subscribed to
+    filters: Vec<String>,
+    /// publishes to receive, unless publishing for a duration
+    expected: usize,
+    /// shared subscription group this subscriber is a member of
+    group: Option<Arc<Group>>,
+    /// publishes of all publishers, with `--duration`
+    published: Option<Arc<Published>>,
+    /// pace of consuming publishes, when slow
+    slow: Option<Slow>,
+    /// drops every connection at once, with `--reconnect-storm`
+    storm: Option<Storm>,
+    /// drops the connection every now and then, with `--flappy`
+    flap: Option<Flap>,
     client: AsyncClient,
     eventloop: EventLoop,
 }
 
 impl Subscriber {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn new(
         id: String,
+        index: usize,
         config: Arc<BenchConfig>,
+        endpoint: &Endpoint,
+        group: Option<Arc<Group>>,
+        published: Option<Arc<Published>>,
+        storm: Option<Storm>,
+        flap: Option<Flap>,
     ) -> Result<Subscriber, ConnectionError> {
-        let (client, mut eventloop) = AsyncClient::new(options(config.clone(), &id)?, 10);
-        eventloop
-            .network_options
-            .set_connection_timeout(config.conn_timeout);
+        let mut options = endpoint_options(config.clone(), &id, endpoint)?;
+        options.set_clean_session(config.clean_session);
+
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+        eventloop.network_options = network_options(&config)?;
 
+        let connect = Instant::now();
         // waiting for connection
-        loop {
+        let connack_latency = loop {
             let event = eventloop.poll().await?;
             if let Event::Incoming(v) = event {
                 match v {
-                    Incoming::ConnAck(_) => break,
+                    Incoming::ConnAck(_) => {
+                        METRICS.connected();
+                        break connect.elapsed();
+                    }
                     incoming => return Err(ConnectionError::WrongPacket(incoming)),
                 }
             }
-        }
+        };
 
         // subscribing
+        let (filters, expected) = match config.topics {
+            Some(_) => (
+                matrix::topics(&config, index)
+                    .into_iter()
+                    .map(matrix::topic)
+                    .collect(),
+                matrix::expected(&config, index),
+            ),
+            None => (
+                vec![shared::filter(&config)],
+                match (config.subscribe_only, &config.sink_filter) {
+                    (true, _) => config.count,
+                    (false, Some(_)) => wildcard::expected(&config),
+                    (false, None) => subscribed_publishes(&config),
+                },
+            ),
+        };
+        let subscribe = Instant::now();
         client
-            .subscribe("hello/+/world", get_qos(config.subscribe_qos))
+            .subscribe_many(subscribe_filters(&filters, config.subscribe_qos))
             .await?;
 
         // waiting for subscription confirmation
-        loop {
+        let suback_latency = loop {
             let event = eventloop.poll().await?;
             if let Event::Incoming(v) = event {
                 match v {
-                    Incoming::SubAck(_) => break,
+                    Incoming::SubAck(_) => break subscribe.elapsed(),
                     incoming => return Err(ConnectionError::WrongPacket(incoming)),
                 }
             }
-        }
+        };
 
+        let slow = Slow::new(&config, index);
         Ok(Subscriber {
             id,
+            endpoint: endpoint_label(&config, endpoint),
             config,
+            connack_latency,
+            suback_latency,
+            filters,
+            expected,
+            group,
+            published,
+            slow,
+            storm,
+            flap,
             client,
             eventloop,
         })
     }
 
     pub(crate) async fn start(&mut self, barrier_handle: Arc<Barrier>) -> SubStats {
-        let required_publish_count = self.config.count * self.config.publishers;
+        // known once publishers are done, when publishing for a duration
+        let mut required_publish_count = match self.config.duration {
+            Some(_) => usize::MAX,
+            None => self.expected,
+        };
         // total number of publishes received
         let mut publish_count = 0;
-        // total number of pubacks sent
+        // publishes received more than once, when tracking sequences
+        let mut duplicate_count = 0;
+        // total number of pubacks (qos 1) and pubcomps (qos 2) sent
         let mut puback_count = 0;
         // when the very first publish arrived
         let mut start = Instant::now();
         // when the latest publish arrived
         let mut last_publish = Instant::now();
         // to record latencies
-        let mut histogram = Histogram::<u64>::new(4).unwrap();
+        let mut histogram = latency_histogram();
+        let mut qos_receives = [0; 3];
+        let mut qos_latencies = [
+            latency_histogram(),
+            latency_histogram(),
+            latency_histogram(),
+        ];
+        let mut warmup_histogram = latency_histogram();
+        let mut arrival_intervals = IntervalStats::default();
+        let mut arrival_gaps = latency_histogram();
+        let mut payload_bytes = 0;
+        let mut wire_bytes = 0;
+        let mut connack_latencies = latency_histogram();
+        connack_latencies
+            .record(self.connack_latency.as_micros() as u64)
+            .unwrap();
+        let mut suback_latencies = latency_histogram();
+        suback_latencies
+            .record(self.suback_latency.as_micros() as u64)
+            .unwrap();
         // number of reconnects attempted
         let mut reconnects = 0;
+        // periods during which the connection was down
+        let mut outages = Outages::default();
+        // coming back from `--reconnect-storm`
+        let mut recovery = Recovery::default();
+        // flapping, or latencies while others flap
+        let mut flaps = Flaps::default();
+        match self.flap.as_ref().map(Flap::flappy) {
+            Some(true) => flaps.flappy = 1,
+            Some(false) => flaps.stable = 1,
+            None => {}
+        }
+        // incoming publishes by topic, if enabled
+        let mut topics: HashMap<String, TopicStats> = HashMap::new();
+        // incoming publishes by group of the publisher, with groups
+        let mut group_stats: Vec<TopicStats> = match &self.config.groups {
+            Some(groups) => groups
+                .groups
+                .iter()
+                .map(|_| TopicStats::default())
+                .collect(),
+            None => Vec::new(),
+        };
+        // sequence numbers received from every publisher, if enabled
+        let mut sequences: HashMap<String, Sequences> = HashMap::new();
+        if self.config.sequence_tracking {
+            for i in 0..subscribed_publishers(&self.config) {
+                sequences.insert(
+                    topic(&publisher_id(&self.config, i)),
+                    Sequences::new(self.config.count),
+                );
+            }
+        }
 
         barrier_handle.wait().await;
+        let warmup_end = Instant::now() + self.config.warmup;
+        if let Some(flap) = &mut self.flap {
+            flap.start();
+        }
+        // publishers are yet to start, so everything they publish while we're
+        // offline should be queued by the broker
+        let resumed = match self.config.offline_for {
+            Some(offline_for) => self.go_offline(offline_for, &mut reconnects).await,
+            None => None,
+        };
+        // publishes sent while offline, delivered after resuming
+        let mut queued_count = 0;
+        let mut last_queued = None;
         // for the very first publish, to record the starting time of publishes
         loop {
-            let event = match self.eventloop.poll().await {
+            let event = tokio::select! {
+                event = self.eventloop.poll() => event,
+                // the rest of the group received every publish
+                _ = shared::done(self.group.as_deref()) => break,
+                _ = storm::hit(self.storm.as_ref()), if recovery.waiting() => {
+                    self.drop_connection(&mut recovery, &mut outages);
+                    continue;
+                }
+                _ = flap::due(self.flap.as_ref()) => {
+                    self.flap(&mut flaps, &mut outages);
+                    continue;
+                }
+                total = deadline::total(self.published.as_deref()), if required_publish_count == usize::MAX => {
+                    required_publish_count = total as usize;
+                    match required_publish_count {
+                        0 => break,
+                        _ => continue,
+                    }
+                }
+            };
+            let event = match event {
                 Ok(v) => v,
                 Err(e) => {
                     error!("Id = {}, Connection error = {:?}", self.id, e);
-
-                    reconnects += 1;
-                    if reconnects >= 1 {
+                    if self.disconnected(&mut reconnects, &mut outages) {
                         break;
                     }
+
+                    // the next poll reconnects
+                    time::sleep(Duration::from_secs(1)).await;
                     continue;
                 }
             };
 
             match event {
-                Event::Incoming(Incoming::Publish(_)) => {
+                Event::Incoming(Incoming::Publish(publish)) if publish.retain => {
+                    debug!("Id = {}, Retained publish from an earlier run", self.id);
+                }
+                Event::Incoming(Incoming::Publish(publish)) => {
                     publish_count += 1;
+                    recovery.flowing();
+                    if let Some(group) = &self.group {
+                        group.received();
+                    }
+                    payload_bytes += publish.payload.len() as u64;
+                    wire_bytes += wire_size(&publish);
                     start = Instant::now();
                     last_publish = start;
+                    // the very first publish can't be a duplicate
+                    record_sequence(&mut sequences, &publish);
+                    let latency = self.e2e_latency(&publish.payload);
+                    if was_queued(resumed.as_ref(), latency) {
+                        queued_count += 1;
+                        last_queued = Some(start);
+                    } else if let Some(latency) = latency {
+                        let micros = latency.as_micros() as u64;
+                        if start < warmup_end {
+                            warmup_histogram.record(micros).unwrap();
+                        } else {
+                            histogram.record(micros).unwrap();
+                            qos_latencies[publish.qos as usize].record(micros).unwrap();
+                            if let Some(flap) = self.stable_flap(&publish.topic) {
+                                flaps.record(latency, flap.flapping());
+                            }
+                        }
+                    }
+                    qos_receives[publish.qos as usize] += 1;
+                    if let Some(g) = self.group_of(&publish.topic) {
+                        group_stats[g].record(publish.payload.len(), latency);
+                    }
+                    if self.config.topic_stats {
+                        topics
+                            .entry(publish.topic)
+                            .or_default()
+                            .record(publish.payload.len(), latency);
+                    }
+                    METRICS.incoming_publish(latency);
+                    if let Some(slow) = &mut self.slow {
+                        slow.consume().await;
+                    }
                     break;
                 }
-                Event::Incoming(Incoming::PingResp) => {
+                Event::Incoming(Incoming::ConnAck(_)) => {
+                    recovery.connected();
+                    flaps.connected();
+                    self.reconnected(&mut outages);
+                }
+                Event::Incoming(Incoming::PingResp | Incoming::SubAck(_)) => {
                     debug!("ping response");
                 }
                 Event::Outgoing(Outgoing::PingReq) => {
                     debug!("ping request")
                 }
-                Event::Outgoing(Outgoing::PubAck(_)) => {
+                Event::Outgoing(Outgoing::PubAck(_) | Outgoing::PubComp(_)) => {
                     puback_count += 1;
                 }
+                // the rest of the qos 2 handshake
+                Event::Incoming(Incoming::PubRel(_)) | Event::Outgoing(Outgoing::PubRec(_)) => {}
                 packet => {
                     error!("Id = {}, Unexpected packet = {:?}", self.id, packet,);
                     continue;
@@ -117,15 +339,42 @@ impl Subscriber {
         }
 
         // for remainging publishes
-        while publish_count < required_publish_count {
-            let event = match self.eventloop.poll().await {
-                Ok(v) => v,
-                Err(e) => {
+        while publish_count - duplicate_count < required_publish_count {
+            // stop waiting for publishes which were probably lost
+            let deadline = last_publish + self.config.receive_timeout;
+            let event = tokio::select! {
+                event = time::timeout_at(deadline.into(), self.eventloop.poll()) => event,
+                _ = shared::done(self.group.as_deref()) => break,
+                _ = storm::hit(self.storm.as_ref()), if recovery.waiting() => {
+                    self.drop_connection(&mut recovery, &mut outages);
+                    continue;
+                }
+                _ = flap::due(self.flap.as_ref()) => {
+                    self.flap(&mut flaps, &mut outages);
+                    continue;
+                }
+                total = deadline::total(self.published.as_deref()), if required_publish_count == usize::MAX => {
+                    required_publish_count = total as usize;
+                    continue;
+                }
+            };
+            let event = match event {
+                Ok(Ok(v)) => v,
+                Err(_) => {
+                    warn!(
+                        "Id = {}, No publishes for {:?}, {} of {} received",
+                        self.id, self.config.receive_timeout, publish_count, required_publish_count
+                    );
+                    break;
+                }
+                Ok(Err(e)) => {
                     error!("Id = {}, Connection error = {:?}", self.id, e);
-                    reconnects += 1;
-                    if reconnects >= 2 {
+                    if self.disconnected(&mut reconnects, &mut outages) {
                         break;
                     }
+
+                    // the next poll reconnects
+                    time::sleep(Duration::from_secs(1)).await;
                     continue;
                 }
             };
@@ -133,17 +382,66 @@ impl Subscriber {
             debug!("Id = {}, {:?}, count = {}", self.id, event, publish_count);
 
             match event {
-                Event::Incoming(Incoming::Publish(_)) => {
+                Event::Incoming(Incoming::Publish(publish)) if publish.retain => {
+                    debug!("Id = {}, Retained publish from an earlier run", self.id);
+                }
+                Event::Incoming(Incoming::Publish(publish)) => {
                     publish_count += 1;
-                    histogram
-                        .record(last_publish.elapsed().as_millis() as u64)
-                        .unwrap();
+                    recovery.flowing();
+                    if let Some(group) = &self.group {
+                        group.received();
+                    }
+                    payload_bytes += publish.payload.len() as u64;
+                    wire_bytes += wire_size(&publish);
+                    if record_sequence(&mut sequences, &publish) {
+                        duplicate_count += 1;
+                    }
+                    let latency = self.e2e_latency(&publish.payload);
+                    METRICS.incoming_publish(latency);
+                    // queued publishes would only measure how long we were offline
+                    if was_queued(resumed.as_ref(), latency) {
+                        queued_count += 1;
+                        last_queued = Some(Instant::now());
+                    } else if let Some(latency) = latency {
+                        let micros = latency.as_micros() as u64;
+                        if Instant::now() < warmup_end {
+                            warmup_histogram.record(micros).unwrap();
+                        } else {
+                            histogram.record(micros).unwrap();
+                            qos_latencies[publish.qos as usize].record(micros).unwrap();
+                            if let Some(flap) = self.stable_flap(&publish.topic) {
+                                flaps.record(latency, flap.flapping());
+                            }
+                        }
+                    }
+                    qos_receives[publish.qos as usize] += 1;
+                    if let Some(g) = self.group_of(&publish.topic) {
+                        group_stats[g].record(publish.payload.len(), latency);
+                    }
+                    if self.config.topic_stats {
+                        topics
+                            .entry(publish.topic)
+                            .or_default()
+                            .record(publish.payload.len(), latency);
+                    }
+                    if let Some(slow) = &mut self.slow {
+                        slow.consume().await;
+                    }
+                    let gap = last_publish.elapsed();
+                    arrival_intervals.record(gap);
+                    arrival_gaps.record(gap.as_micros() as u64).unwrap();
                     last_publish = Instant::now();
                 }
-                Event::Outgoing(Outgoing::PubAck(_)) => {
+                Event::Outgoing(Outgoing::PubAck(_) | Outgoing::PubComp(_)) => {
                     puback_count += 1;
                 }
-                Event::Incoming(Incoming::PingResp) | Event::Outgoing(_) => {}
+                Event::Incoming(Incoming::ConnAck(_)) => {
+                    recovery.connected();
+                    flaps.connected();
+                    self.reconnected(&mut outages);
+                }
+                Event::Incoming(Incoming::PingResp | Incoming::SubAck(_) | Incoming::PubRel(_))
+                | Event::Outgoing(_) => {}
                 incoming => error!(
                     "Id = {}, Unexpected incoming packet = {:?}",
                     self.id, incoming
@@ -151,6 +449,40 @@ impl Subscriber {
             }
         }
 
+        // connections that are down were already counted as disconnected
+        if !outages.ongoing() {
+            METRICS.disconnected();
+        }
+        let outages = outages.finish();
+        let resumptions = resumed
+            .map(|resumed| Resumption {
+                session_present: resumed.session_present,
+                queued: queued_count,
+                drain: last_queued
+                    .map(|last_queued| last_queued - resumed.at)
+                    .unwrap_or_default(),
+            })
+            .into_iter()
+            .collect();
+        let mut duplicates = [0; 3];
+        let mut reordered = 0;
+        let mut max_displacement = 0;
+        for sequences in sequences.values() {
+            for (qos, count) in sequences.duplicates().iter().enumerate() {
+                duplicates[qos] += count;
+            }
+            reordered += sequences.reordered();
+            max_displacement = max_displacement.max(sequences.max_displacement());
+        }
+        let mut gaps: Vec<TopicGaps> = sequences
+            .into_iter()
+            .map(|(topic, sequences)| TopicGaps {
+                topic,
+                ranges: sequences.gaps(),
+            })
+            .filter(|gaps| !gaps.ranges.is_empty())
+            .collect();
+        gaps.sort_by(|a, b| a.topic.cmp(&b.topic));
         let outgoing_throughput =
             (publish_count * 1000) as f32 / (last_publish - start).as_millis() as f32;
 
@@ -162,34 +494,231 @@ impl Subscriber {
             Incoming publishes : {:<7} Throughput = {} messages/s
             Outgoing pubacks   : Sent = {}
             Reconnects         : {}
-
-            Latencies of {} samples
-            ----------------------------
-            100                 : {}
-            99.9999 percentile  : {}
-            99.999 percentile   : {}
-            90 percentile       : {}
-            50 percentile       : {}
+            Latencies          : {}
             ",
                 self.id,
                 publish_count,
                 outgoing_throughput,
                 puback_count,
                 reconnects,
-                histogram.len(),
-                histogram.value_at_percentile(100.0),
-                histogram.value_at_percentile(99.9999),
-                histogram.value_at_percentile(99.999),
-                histogram.value_at_percentile(90.0),
-                histogram.value_at_percentile(50.0),
+                LatencySummary::from(&histogram),
             );
         }
 
         SubStats {
+            id: self.id.clone(),
+            endpoint: self.endpoint.clone(),
             publish_count: publish_count as u64,
             puback_count,
             reconnects,
             throughput: outgoing_throughput,
+            // the first loop is left on the very first publish
+            first_publish: (publish_count > 0).then_some(start),
+            latencies: histogram,
+            warmup_latencies: warmup_histogram,
+            arrival_intervals,
+            arrival_gaps,
+            payload_bytes,
+            wire_bytes,
+            gaps,
+            resumptions,
+            recovery,
+            flaps,
+            duplicates,
+            qos_receives,
+            qos_latencies,
+            reordered,
+            max_displacement,
+            connack_latencies,
+            suback_latencies,
+            outages,
+            topics,
+            groups: group_stats,
+        }
+    }
+
+    /// Records a connection error. Returns true once the connection has
+    /// errored more than `--max-reconnects` times and should give up
+    fn disconnected(&mut self, reconnects: &mut u64, outages: &mut Outages) -> bool {
+        refresh_token(&self.config, &self.id, &mut self.eventloop.mqtt_options);
+        METRICS.reconnect();
+        if outages.disconnected() {
+            METRICS.disconnected();
+        }
+
+        *reconnects += 1;
+        *reconnects > self.config.max_reconnects
+    }
+
+    /// The connection, to close along with the others during the ramp down
+    pub(crate) fn into_connection(self) -> Connection {
+        Connection {
+            id: self.id,
+            will: false,
+            client: self.client,
+            eventloop: self.eventloop,
+        }
+    }
+
+    /// Drops the connection without a disconnect, as the storm hit. The next
+    /// poll reconnects
+    fn drop_connection(&mut self, recovery: &mut Recovery, outages: &mut Outages) {
+        self.eventloop.clean();
+        recovery.dropped();
+        if outages.disconnected() {
+            METRICS.disconnected();
+        }
+    }
+
+    /// Drops the connection without a disconnect, as it's its turn to flap.
+    /// The next poll reconnects
+    fn flap(&mut self, flaps: &mut Flaps, outages: &mut Outages) {
+        self.eventloop.clean();
+        flaps.flapped();
+        if let Some(flap) = &mut self.flap {
+            flap.flapped();
+        }
+        if outages.disconnected() {
+            METRICS.disconnected();
+        }
+    }
+
+    /// Flaps of a stable subscriber, for publishes on `topic` of a stable
+    /// publisher
+    fn stable_flap(&self, topic: &str) -> Option<&Flap> {
+        self.flap
+            .as_ref()
+            .filter(|flap| !flap.flappy() && flap.stable(topic))
+    }
+
+    /// Disconnects for `offline_for` and reconnects. Returns `None` if the
+    /// subscriber gave up on reconnecting
+    async fn go_offline(&mut self, offline_for: Duration, reconnects: &mut u64) -> Option<Resumed> {
+        if let Err(e) = self.client.try_disconnect() {
+            error!("Id = {}, Failed to disconnect = {:?}", self.id, e);
+            return None;
+        }
+
+        // the broker closes the connection once it sees the disconnect
+        let _ = time::timeout(self.config.conn_timeout, async {
+            while self.eventloop.poll().await.is_ok() {}
+        })
+        .await;
+        METRICS.disconnected();
+        time::sleep(offline_for).await;
+        refresh_token(&self.config, &self.id, &mut self.eventloop.mqtt_options);
+
+        loop {
+            match self.eventloop.poll().await {
+                Ok(Event::Incoming(Incoming::ConnAck(connack))) => {
+                    METRICS.connected();
+                    // without a session, the broker has neither our
+                    // subscription nor queued publishes
+                    if !connack.session_present {
+                        let filters = subscribe_filters(&self.filters, self.config.subscribe_qos);
+                        if let Err(e) = self.client.try_subscribe_many(filters) {
+                            error!("Id = {}, Resubscribe error = {:?}", self.id, e);
+                        }
+                    }
+
+                    return Some(Resumed {
+                        at: Instant::now(),
+                        session_present: connack.session_present,
+                    });
+                }
+                Ok(event) => {
+                    error!("Id = {}, Unexpected event = {:?}", self.id, event);
+                }
+                Err(e) => {
+                    error!("Id = {}, Connection error = {:?}", self.id, e);
+                    METRICS.reconnect();
+                    *reconnects += 1;
+                    if *reconnects > self.config.max_reconnects {
+                        return None;
+                    }
+
+                    refresh_token(&self.config, &self.id, &mut self.eventloop.mqtt_options);
+                    time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    /// Subscribes again after a reconnection, as the broker doesn't keep the
+    /// subscriptions of clean sessions
+    fn reconnected(&self, outages: &mut Outages) {
+        if !outages.reconnected() {
+            return;
+        }
+
+        METRICS.connected();
+        let filters = subscribe_filters(&self.filters, self.config.subscribe_qos);
+        if let Err(e) = self.client.try_subscribe_many(filters) {
+            error!("Id = {}, Resubscribe error = {:?}", self.id, e);
+        }
+    }
+
+    /// Group of the publisher of `topic`, with `--groups`
+    fn group_of(&self, topic: &str) -> Option<usize> {
+        self.config.groups.as_ref()?.of_topic(&self.config, topic)
+    }
+
+    /// End to end latency of a publish. Only available with latency tracking
+    fn e2e_latency(&self, payload: &[u8]) -> Option<Duration> {
+        if !self.config.latency_tracking {
+            return None;
+        }
+
+        payload::latency(payload)
+    }
+}
+
+/// Reconnection after `--offline-for`
+struct Resumed {
+    at: Instant,
+    session_present: bool,
+}
+
+/// Whether a publish was sent while the subscriber was offline, going by the
+/// timestamp in its payload
+fn was_queued(resumed: Option<&Resumed>, latency: Option<Duration>) -> bool {
+    match (resumed, latency) {
+        (Some(resumed), Some(latency)) => match Instant::now().checked_sub(latency) {
+            Some(sent) => sent < resumed.at,
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+fn wire_size(publish: &Publish) -> u64 {
+    payload::publish_size(publish.topic.len(), publish.qos, publish.payload.len()) as u64
+}
+
+fn subscribe_filters(filters: &[String], qos: i16) -> Vec<SubscribeFilter> {
+    filters
+        .iter()
+        .map(|filter| SubscribeFilter::new(filter.clone(), get_qos(qos)))
+        .collect()
+}
+
+/// Records the sequence number of a publish when tracking sequences. Returns
+/// true if the publish is a duplicate
+fn record_sequence(sequences: &mut HashMap<String, Sequences>, publish: &Publish) -> bool {
+    if sequences.is_empty() {
+        return false;
+    }
+
+    let sequence = match payload::sequence(&publish.payload) {
+        Some(sequence) => sequence,
+        None => return false,
+    };
+
+    match sequences.get_mut(&publish.topic) {
+        Some(sequences) => sequences.record(sequence, publish.qos),
+        None => {
+            warn!("Unexpected topic = {}", publish.topic);
+            false
         }
     }
 }
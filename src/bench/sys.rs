@@ -0,0 +1,82 @@
+//! Monitor connection that subscribes to `$SYS/#` on the broker under test, so
+//! that broker reported metrics like connected clients, dropped messages and
+//! memory end up in the report next to our own
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use rumqttc::{AsyncClient, Event, Incoming, QoS};
+use serde::Serialize;
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{
+    bench::{options, ConnectionError},
+    BenchConfig,
+};
+
+/// Value of a `$SYS` topic when it was first seen and at the end of the run
+#[derive(Debug, Clone, Serialize)]
+pub struct SysValue {
+    pub first: String,
+    pub last: String,
+}
+
+pub struct SysMonitor {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<BTreeMap<String, SysValue>>,
+}
+
+impl SysMonitor {
+    pub(crate) async fn start(config: Arc<BenchConfig>) -> Result<SysMonitor, ConnectionError> {
+        let (client, mut eventloop) = AsyncClient::new(options(config, "mqttwrk-sys")?, 10);
+
+        loop {
+            match eventloop.poll().await? {
+                Event::Incoming(Incoming::ConnAck(_)) => break,
+                Event::Incoming(incoming) => return Err(ConnectionError::WrongPacket(incoming)),
+                Event::Outgoing(_) => {}
+            }
+        }
+
+        client.subscribe("$SYS/#", QoS::AtMostOnce).await?;
+
+        let (stop, mut stopped) = oneshot::channel();
+        let handle = tokio::spawn(async move {
+            let mut values: BTreeMap<String, SysValue> = BTreeMap::new();
+            loop {
+                let event = tokio::select! {
+                    event = eventloop.poll() => event,
+                    _ = &mut stopped => break,
+                };
+
+                match event {
+                    Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                        let value = String::from_utf8_lossy(&publish.payload).into_owned();
+                        values
+                            .entry(publish.topic)
+                            .and_modify(|v| v.last = value.clone())
+                            .or_insert_with(|| SysValue {
+                                first: value.clone(),
+                                last: value,
+                            });
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("Id = mqttwrk-sys, Connection error = {:?}", e);
+                        break;
+                    }
+                }
+            }
+
+            let _ = client.try_disconnect();
+            values
+        });
+
+        Ok(SysMonitor { stop, handle })
+    }
+
+    /// Stops monitoring and returns the values of all `$SYS` topics seen
+    pub(crate) async fn stop(self) -> BTreeMap<String, SysValue> {
+        let _ = self.stop.send(());
+        self.handle.await.unwrap_or_default()
+    }
+}
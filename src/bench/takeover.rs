@@ -0,0 +1,289 @@
+//! Session takeovers. With `--takeover`, that many extra connections stay
+//! connected alongside the run until `--takeover-after`, when a second
+//! connection with the client id of every one of them connects at once. The
+//! broker has to close each old connection and hand its session over to the
+//! new one, all while the run goes on, and the report tells whether it closed
+//! every old connection and how long that took
+
+use std::{sync::Arc, time::Instant};
+
+use futures::future::{join_all, try_join_all};
+use hdrhistogram::Histogram;
+use rumqttc::{AsyncClient, ConnAck, Event, EventLoop, Incoming, QoS};
+use serde::Serialize;
+use tokio::{
+    sync::oneshot,
+    task::{self, JoinHandle},
+    time,
+};
+
+use crate::{
+    bench::{network_options, options},
+    common::{latency_histogram, LatencySummary},
+    BenchConfig,
+};
+
+#[derive(Debug, Serialize)]
+pub struct TakeoverReport {
+    pub connections: usize,
+    /// time into the run at which every connection was taken over
+    pub after_secs: f64,
+    /// new connections the broker accepted
+    pub accepted: u64,
+    /// new connections that failed or timed out
+    pub failed: u64,
+    /// new connections the broker resumed the session of the old one for
+    pub resumed: u64,
+    /// old connections the broker closed
+    pub closed: u64,
+    /// old connections still open `--conn-timeout` after their takeover
+    pub lingering: u64,
+    /// new connections the broker dropped before the run was over
+    pub dropped: u64,
+    /// connect to connack latencies of the new connections
+    pub connack_latencies: LatencySummary,
+    /// from the connect of the new connection until the broker closed the
+    /// old one
+    pub close_latencies: LatencySummary,
+}
+
+/// What the takeover storm saw
+struct Takeovers {
+    at: Instant,
+    accepted: u64,
+    failed: u64,
+    resumed: u64,
+    closed: u64,
+    lingering: u64,
+    connack_latencies: Histogram<u64>,
+    close_latencies: Histogram<u64>,
+    /// new connections, until the run is over
+    holders: Vec<JoinHandle<Instant>>,
+}
+
+/// Outcome of taking over a single connection
+struct Taken {
+    connack: Option<(ConnAck, Instant)>,
+    /// when the broker closed the old connection, unless it lingered
+    closed: Option<Instant>,
+    holder: Option<JoinHandle<Instant>>,
+}
+
+pub struct Takeover {
+    connections: usize,
+    start: Instant,
+    stop: oneshot::Sender<()>,
+    controller: JoinHandle<Takeovers>,
+}
+
+impl Takeover {
+    /// Connects the connections to take over and waits for `--takeover-after`
+    pub(crate) async fn start(config: Arc<BenchConfig>) -> anyhow::Result<Takeover> {
+        let ids: Vec<String> = (0..config.takeover.unwrap_or(0))
+            .map(|i| format!("mqttwrk-takeover-{i:05}"))
+            .collect();
+        let connected = try_join_all(ids.iter().map(|id| subscribed(&config, id))).await?;
+        let old: Vec<JoinHandle<Instant>> = ids
+            .iter()
+            .cloned()
+            .zip(connected)
+            .map(|(id, connection)| task::spawn(hold(id, connection)))
+            .collect();
+
+        let connections = ids.len();
+        let (stop, stopped) = oneshot::channel();
+        let controller = task::spawn(async move {
+            // runs shorter than `--takeover-after` take over once they're done
+            tokio::select! {
+                _ = time::sleep(config.takeover_after) => {}
+                _ = stopped => {}
+            }
+
+            info!("Taking over {} connections", ids.len());
+            let at = Instant::now();
+            let taken = join_all(
+                ids.into_iter()
+                    .zip(old)
+                    .map(|(id, old)| take_over(config.clone(), id, old)),
+            )
+            .await;
+
+            let mut takeovers = Takeovers {
+                at,
+                accepted: 0,
+                failed: 0,
+                resumed: 0,
+                closed: 0,
+                lingering: 0,
+                connack_latencies: latency_histogram(),
+                close_latencies: latency_histogram(),
+                holders: Vec::new(),
+            };
+            for taken in taken {
+                let Some((connack, connected)) = taken.connack else {
+                    takeovers.failed += 1;
+                    continue;
+                };
+
+                takeovers.accepted += 1;
+                takeovers.resumed += connack.session_present as u64;
+                let connack_latency = connected.saturating_duration_since(at);
+                takeovers
+                    .connack_latencies
+                    .record(connack_latency.as_micros() as u64)
+                    .unwrap();
+                match taken.closed {
+                    Some(closed) => {
+                        takeovers.closed += 1;
+                        let close_latency = closed.saturating_duration_since(at);
+                        takeovers
+                            .close_latencies
+                            .record(close_latency.as_micros() as u64)
+                            .unwrap();
+                    }
+                    None => takeovers.lingering += 1,
+                }
+                takeovers.holders.extend(taken.holder);
+            }
+
+            takeovers
+        });
+
+        Ok(Takeover {
+            connections,
+            start: Instant::now(),
+            stop,
+            controller,
+        })
+    }
+
+    /// Takes over right away if the run was shorter than `--takeover-after`,
+    /// and summarizes the takeovers
+    pub(crate) async fn stop(self) -> Option<TakeoverReport> {
+        let _ = self.stop.send(());
+        let takeovers = match self.controller.await {
+            Ok(takeovers) => takeovers,
+            Err(e) => {
+                error!("Takeover failed = {:?}", e);
+                return None;
+            }
+        };
+
+        let mut dropped = 0;
+        for holder in takeovers.holders.iter() {
+            dropped += holder.is_finished() as u64;
+            holder.abort();
+        }
+
+        Some(TakeoverReport {
+            connections: self.connections,
+            after_secs: takeovers
+                .at
+                .saturating_duration_since(self.start)
+                .as_secs_f64(),
+            accepted: takeovers.accepted,
+            failed: takeovers.failed,
+            resumed: takeovers.resumed,
+            closed: takeovers.closed,
+            lingering: takeovers.lingering,
+            dropped,
+            connack_latencies: LatencySummary::from(&takeovers.connack_latencies),
+            close_latencies: LatencySummary::from(&takeovers.close_latencies),
+        })
+    }
+}
+
+/// Keeps a connection up until the broker closes it, without reconnecting.
+/// Returns when it was closed
+async fn hold(id: String, connection: (AsyncClient, EventLoop)) -> Instant {
+    // the eventloop stops once every client is gone
+    let (_client, mut eventloop) = connection;
+    loop {
+        if let Err(e) = eventloop.poll().await {
+            debug!("Id = {}, Closed = {:?}", id, e);
+            return Instant::now();
+        }
+    }
+}
+
+/// Connects with the client id of `old` and waits for the broker to close it
+async fn take_over(config: Arc<BenchConfig>, id: String, old: JoinHandle<Instant>) -> Taken {
+    let connack = match connect(&config, &id).await {
+        Ok((client, eventloop, connack)) => {
+            let connected = Instant::now();
+            let holder = task::spawn(hold(id.clone(), (client, eventloop)));
+            Some((connack, connected, holder))
+        }
+        Err(e) => {
+            error!("Id = {}, Failed to take over = {:#}", id, e);
+            None
+        }
+    };
+
+    let Some((connack, connected, holder)) = connack else {
+        old.abort();
+        return Taken {
+            connack: None,
+            closed: None,
+            holder: None,
+        };
+    };
+
+    let abort = old.abort_handle();
+    let closed = match time::timeout(config.conn_timeout, old).await {
+        Ok(Ok(closed)) => Some(closed),
+        Ok(Err(_)) => None,
+        Err(_) => {
+            warn!("Id = {}, Broker kept the old connection open", id);
+            abort.abort();
+            None
+        }
+    };
+
+    Taken {
+        connack: Some((connack, connected)),
+        closed,
+        holder: Some(holder),
+    }
+}
+
+/// Connects for the first time, subscribing so that persistent sessions hold
+/// something to take over
+async fn subscribed(
+    config: &Arc<BenchConfig>,
+    id: &str,
+) -> anyhow::Result<(AsyncClient, EventLoop)> {
+    let (client, mut eventloop, _) = connect(config, id).await?;
+    if !config.clean_session {
+        client
+            .subscribe(format!("hello/takeover/{id}"), QoS::AtLeastOnce)
+            .await?;
+        loop {
+            match time::timeout(config.conn_timeout, eventloop.poll()).await?? {
+                Event::Incoming(Incoming::SubAck(_)) => break,
+                Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+                Event::Outgoing(_) => {}
+            }
+        }
+    }
+
+    Ok((client, eventloop))
+}
+
+async fn connect(
+    config: &Arc<BenchConfig>,
+    id: &str,
+) -> anyhow::Result<(AsyncClient, EventLoop, ConnAck)> {
+    let mut options = options(config.clone(), id)?;
+    options.set_clean_session(config.clean_session);
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    eventloop.network_options = network_options(config)?;
+
+    loop {
+        match time::timeout(config.conn_timeout, eventloop.poll()).await?? {
+            Event::Incoming(Incoming::ConnAck(connack)) => return Ok((client, eventloop, connack)),
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+}
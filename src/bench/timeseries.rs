@@ -0,0 +1,140 @@
+//! Samples the live metrics once a second for the whole run. Samples are kept
+//! for the final report and handed to reporters as they are taken
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use tokio::{sync::oneshot, time};
+
+use crate::{
+    bench::{
+        assertions::Assertion,
+        metrics::{Sample, Sampler},
+        report::Report,
+        reporter::Reporter,
+    },
+    common::LatencySummary,
+};
+
+/// Samples every second until `stop` fires, handing every sample to the
+/// reporters. Returns the samples, including a final partial interval, along
+/// with the reporters. Only the last `keep` samples are returned if given
+pub(crate) async fn collect(
+    mut reporters: Vec<Box<dyn Reporter>>,
+    keep: Option<usize>,
+    mut stop: oneshot::Receiver<()>,
+) -> (Vec<Sample>, Vec<Box<dyn Reporter>>) {
+    let mut sampler = Sampler::new();
+    let mut samples = VecDeque::new();
+    let mut interval = time::interval(Duration::from_secs(1));
+    interval.tick().await;
+
+    loop {
+        let stopped = tokio::select! {
+            _ = interval.tick() => false,
+            _ = &mut stop => true,
+        };
+
+        let sample = sampler.sample();
+        for reporter in reporters.iter_mut() {
+            reporter.sample(&sample);
+        }
+
+        if matches!(keep, Some(keep) if samples.len() >= keep) {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+        if stopped {
+            return (samples.into(), reporters);
+        }
+    }
+}
+
+/// Writes every sample of the run to a csv file as it's taken, rather than
+/// keeping them until the run is done. Rows are flushed every `flush_every`
+pub(crate) struct TimeseriesCsv {
+    writer: BufWriter<File>,
+    flush_every: Option<Duration>,
+    /// elapsed time of the last flush
+    last_flush_secs: f64,
+    /// whether writing failed, which is only logged once
+    failed: bool,
+}
+
+impl TimeseriesCsv {
+    pub fn create(path: &Path, flush_every: Option<Duration>) -> io::Result<TimeseriesCsv> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "elapsed_secs,connections,inflight,reconnects,outgoing_rate,incoming_rate,ack_rate,ack_latency_p50_ms,ack_latency_p99_ms,ack_latency_max_ms,latency_p50_ms,latency_p99_ms,latency_max_ms,cpu_percent,rss_bytes"
+        )?;
+
+        Ok(TimeseriesCsv {
+            writer,
+            flush_every,
+            last_flush_secs: 0.0,
+            failed: false,
+        })
+    }
+
+    /// Writes one row for `sample`
+    fn write(&mut self, sample: &Sample) -> io::Result<()> {
+        writeln!(
+            self.writer,
+            "{:.3},{},{},{},{:.2},{:.2},{:.2},{},{},{:.1},{}",
+            sample.elapsed_secs,
+            sample.connections,
+            sample.inflight,
+            sample.reconnects,
+            sample.outgoing_rate,
+            sample.incoming_rate,
+            sample.ack_rate,
+            csv_latencies(&sample.ack_latencies),
+            csv_latencies(&sample.latencies),
+            sample.cpu_percent,
+            sample.rss_bytes,
+        )?;
+
+        if let Some(every) = self.flush_every {
+            if sample.elapsed_secs - self.last_flush_secs >= every.as_secs_f64() {
+                self.last_flush_secs = sample.elapsed_secs;
+                self.writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Reporter for TimeseriesCsv {
+    fn name(&self) -> &'static str {
+        "timeseries"
+    }
+
+    fn sample(&mut self, sample: &Sample) {
+        if self.failed {
+            return;
+        }
+
+        if let Err(e) = self.write(sample) {
+            error!("Failed to write the timeseries = {:?}", e);
+            self.failed = true;
+        }
+    }
+
+    fn finish(&mut self, _report: &Report, _assertions: &[Assertion]) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn csv_latencies(latencies: &LatencySummary) -> String {
+    format!(
+        "{:.3},{:.3},{:.3}",
+        latencies.p50, latencies.p99, latencies.max
+    )
+}
@@ -0,0 +1,96 @@
+//! Rustls configuration for brokers whose certificate is issued for another
+//! name than the host mqttwrk connects to, e.g. brokers behind load balancers
+//! or reached by ip
+
+use std::{convert::TryFrom, io, sync::Arc};
+
+use rumqttc::tokio_rustls::rustls::{
+    self,
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+/// Verifies the broker with `ca` against `server_name`, authenticating with
+/// `client_auth` if any
+pub(crate) fn config(
+    ca: &[u8],
+    client_auth: Option<(Vec<u8>, Vec<u8>)>,
+    server_name: &str,
+) -> io::Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(ca) {
+        roots.add(cert.map_err(invalid)?).map_err(invalid)?;
+    }
+
+    let verifier = ServerNameVerifier {
+        name: ServerName::try_from(server_name.to_owned()).map_err(invalid)?,
+        inner: WebPkiServerVerifier::builder(Arc::new(roots))
+            .build()
+            .map_err(invalid)?,
+    };
+    let builder = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+
+    match client_auth {
+        Some((cert, key)) => {
+            let certs = CertificateDer::pem_slice_iter(&cert)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(invalid)?;
+            let key = PrivateKeyDer::from_pem_slice(&key).map_err(invalid)?;
+            builder.with_client_auth_cert(certs, key).map_err(invalid)
+        }
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+fn invalid(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Webpki verification against a fixed name instead of the connect host
+#[derive(Debug)]
+struct ServerNameVerifier {
+    name: ServerName<'static>,
+    inner: Arc<WebPkiServerVerifier>,
+}
+
+impl ServerCertVerifier for ServerNameVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.inner
+            .verify_server_cert(end_entity, intermediates, &self.name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
@@ -0,0 +1,355 @@
+//! Local tunnel for connections rumqttc can't make on its own, through socks5
+//! proxies or from the addresses of `--bind-address-range`. rumqttc only
+//! tunnels with http CONNECT, so it connects to this tunnel as if it were an
+//! http proxy, and the tunnel connects on to the broker. Tls and websockets
+//! still run end to end between rumqttc and the broker. With a range, the
+//! tunnel listens on as many ports as there are addresses, up to
+//! `MAX_LISTENERS`, so that the loopback side runs out of ports no sooner than
+//! the addresses it connects from
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rumqttc::{Proxy, ProxyAuth, ProxyType};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{lookup_host, TcpListener, TcpSocket, TcpStream},
+    task, time,
+};
+
+use crate::{bench::next_interface, BenchConfig, ProxyServer};
+
+/// Longest http CONNECT request rumqttc sends to the tunnel
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// Most ports the tunnel listens on
+const MAX_LISTENERS: u128 = 64;
+
+static NEXT_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+
+/// Proxy of `--proxy socks5://[USER:PASSWORD@]HOST:PORT`
+#[derive(Debug, Clone)]
+pub struct Socks5 {
+    pub addr: String,
+    pub port: u16,
+    pub auth: Option<(String, String)>,
+    /// whether the proxy resolves the broker host, with `socks5h://`
+    pub remote_dns: bool,
+}
+
+struct Tunnel {
+    config: Arc<BenchConfig>,
+    socks5: Option<Socks5>,
+}
+
+/// Starts the tunnel if the run needs one, and returns the proxies for rumqttc
+/// to connect to it through, one per port it listens on. The tunnel runs until
+/// the run is over
+pub(crate) async fn start(config: &BenchConfig) -> io::Result<Vec<Proxy>> {
+    let socks5 = match &config.proxy {
+        Some(ProxyServer::Socks5(socks5)) => Some(socks5.clone()),
+        _ => None,
+    };
+    if socks5.is_none() && config.bind_address_range.is_none() {
+        return Ok(Vec::new());
+    }
+
+    // fail before connecting if the range isn't local. Only its ends are
+    // checked, ranges can be too large to bind every address, and connections
+    // from an address in between that isn't fail as they connect
+    if let Some(range) = &config.bind_address_range {
+        for ip in [range.nth(0), range.nth(range.size() - 1)] {
+            let socket = match ip {
+                IpAddr::V4(_) => TcpSocket::new_v4()?,
+                IpAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+            socket
+                .bind(SocketAddr::new(ip, 0))
+                .map_err(|e| io::Error::new(e.kind(), format!("can't bind to {ip} = {e}")))?;
+        }
+    }
+
+    let tunnel = Arc::new(Tunnel {
+        config: Arc::new(config.clone()),
+        socks5,
+    });
+    let listeners = config
+        .bind_address_range
+        .as_ref()
+        .map_or(1, |range| range.size().min(MAX_LISTENERS));
+    let mut proxies = Vec::new();
+    for _ in 0..listeners {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        proxies.push(Proxy {
+            ty: ProxyType::Http,
+            auth: ProxyAuth::None,
+            addr: Ipv4Addr::LOCALHOST.to_string(),
+            port: listener.local_addr()?.port(),
+        });
+        task::spawn(listen(tunnel.clone(), listener));
+    }
+
+    Ok(proxies)
+}
+
+async fn listen(tunnel: Arc<Tunnel>, listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                task::spawn(tunnel.clone().relay(stream));
+            }
+            // e.g. out of file descriptors, which connections give back
+            Err(e) => {
+                warn!("Tunnel failed to accept = {:?}", e);
+                time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+impl Tunnel {
+    /// Relays a connection of rumqttc to the broker it asks for
+    async fn relay(self: Arc<Self>, mut inbound: TcpStream) {
+        let target = match connect_request(&mut inbound).await {
+            Ok(target) => target,
+            Err(e) => {
+                debug!("Tunnel got a bad request = {:?}", e);
+                return;
+            }
+        };
+
+        let connect = time::timeout(self.config.conn_timeout, self.connect(&target));
+        let mut outbound = match connect.await {
+            Ok(Ok(outbound)) => outbound,
+            Ok(Err(e)) => {
+                warn!(
+                    "Tunnel failed to connect to {}:{} = {}",
+                    target.0, target.1, e
+                );
+                let _ = inbound.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await;
+                return;
+            }
+            Err(_) => {
+                warn!("Tunnel timed out connecting to {}:{}", target.0, target.1);
+                let _ = inbound
+                    .write_all(b"HTTP/1.1 504 Gateway Timeout\r\n\r\n")
+                    .await;
+                return;
+            }
+        };
+
+        if inbound
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .is_err()
+        {
+            return;
+        }
+        let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+    }
+
+    /// Connects to `host` and `port`, through the socks5 proxy if there's one
+    async fn connect(&self, (host, port): &(String, u16)) -> io::Result<TcpStream> {
+        let Some(socks5) = &self.socks5 else {
+            return self.socket_connect(host, *port).await;
+        };
+
+        let mut stream = self.socket_connect(&socks5.addr, socks5.port).await?;
+        socks5_connect(&mut stream, socks5, host, *port).await?;
+        Ok(stream)
+    }
+
+    /// Connects to `host` and `port` from the next of `--bind-interface` and
+    /// the next address of `--bind-address-range`
+    async fn socket_connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let range = self.config.bind_address_range.as_ref();
+        // addresses of the same family as the range
+        let ipv4 = range.map(|range| range.nth(0).is_ipv4());
+        let addr = lookup_host((host, port))
+            .await?
+            .find(|addr| match ipv4 {
+                Some(ipv4) => addr.is_ipv4() == ipv4,
+                None => true,
+            })
+            .ok_or_else(|| invalid(format!("could not resolve `{host}`")))?;
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(interface) = next_interface(&self.config) {
+            bind_device(&socket, interface)?;
+        }
+        if let Some(range) = range {
+            let next = NEXT_ADDRESS.fetch_add(1, Ordering::Relaxed);
+            socket.bind(SocketAddr::new(range.nth(next as u128), 0))?;
+        }
+
+        socket.connect(addr).await
+    }
+}
+
+/// Host and port of the http CONNECT request rumqttc opens the tunnel with
+async fn connect_request(stream: &mut TcpStream) -> io::Result<(String, u16)> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST {
+            return Err(invalid("request too long".to_owned()));
+        }
+        // nothing comes after the request before the tunnel answers it
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let line = request.lines().next().unwrap_or_default();
+    let target = match line.split(' ').collect::<Vec<_>>()[..] {
+        ["CONNECT", target, _] => target,
+        _ => return Err(invalid(format!("expected CONNECT, got `{line}`"))),
+    };
+    let (host, port) = target
+        .rsplit_once(':')
+        .ok_or_else(|| invalid(format!("expected HOST:PORT, got `{target}`")))?;
+    let port = port
+        .parse()
+        .map_err(|_| invalid(format!("invalid port `{port}`")))?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+
+    Ok((host.to_owned(), port))
+}
+
+/// Asks the socks5 proxy on the other end of `stream` to connect to `host` and
+/// `port`, as in rfc 1928, authenticating as in rfc 1929
+async fn socks5_connect(
+    stream: &mut TcpStream,
+    socks5: &Socks5,
+    host: &str,
+    port: u16,
+) -> io::Result<()> {
+    let method = if socks5.auth.is_some() { 2 } else { 0 };
+    stream.write_all(&[5, 1, method]).await?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [5, method] {
+        return Err(invalid("proxy refused to authenticate".to_owned()));
+    }
+
+    if let Some((username, password)) = &socks5.auth {
+        let mut auth = vec![1, username.len() as u8];
+        auth.extend_from_slice(username.as_bytes());
+        auth.push(password.len() as u8);
+        auth.extend_from_slice(password.as_bytes());
+        stream.write_all(&auth).await?;
+        stream.read_exact(&mut reply).await?;
+        match reply {
+            [1, 0] => (),
+            [1, _] => {
+                return Err(invalid(
+                    "proxy rejected the username and password".to_owned(),
+                ))
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "malformed reply to the username and password = {reply:?}"
+                )))
+            }
+        }
+    }
+
+    let mut request = vec![5, 1, 0];
+    let ip = match host.parse::<IpAddr>() {
+        Ok(ip) => Some(ip),
+        Err(_) if socks5.remote_dns => None,
+        Err(_) => Some(
+            lookup_host((host, port))
+                .await?
+                .next()
+                .ok_or_else(|| invalid(format!("could not resolve `{host}`")))?
+                .ip(),
+        ),
+    };
+    match ip {
+        Some(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        Some(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        None => {
+            if host.len() > u8::MAX as usize {
+                return Err(invalid(format!("host `{host}` is too long for socks5")));
+            }
+            request.push(3);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut head = [0; 4];
+    stream.read_exact(&mut head).await?;
+    if head[1] != 0 {
+        return Err(invalid(format!(
+            "proxy failed to connect = {}",
+            reply_error(head[1])
+        )));
+    }
+    let bound = match head[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        atyp => return Err(invalid(format!("proxy replied with address type {atyp}"))),
+    };
+    // the address the proxy connected from, and its port
+    let mut bound = vec![0; bound + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// Reply field of rfc 1928
+fn reply_error(reply: u8) -> &'static str {
+    match reply {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "ttl expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &TcpSocket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_: &TcpSocket, _: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to interfaces is only supported on linux",
+    ))
+}
+
+fn invalid(error: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
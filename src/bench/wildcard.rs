@@ -0,0 +1,81 @@
+//! Wildcard subscriptions. With `--topic-depth`, publishers publish to deep
+//! topics with a level per digit of their index, and with `--sink-filter`
+//! subscribers subscribe to a filter of `+` and `#` wildcards of their own, so
+//! that the topic matching of the broker has some work to do. Subscribers
+//! expect the publishes of every publisher whose topic the filter matches
+
+use crate::{
+    bench::{groups, publisher_topic},
+    BenchConfig,
+};
+
+/// Topic of the publisher with `id` at `index`, `depth` levels deeper than
+/// usual. Level `k` is digit `k` of the index, the least significant first,
+/// so that e.g. `hello/+/0/#` matches every tenth publisher
+pub(crate) fn topic(id: &str, index: usize, depth: usize) -> String {
+    let levels: String = (0..depth as u32)
+        .map(|k| format!("/{}", index / 10usize.pow(k) % 10))
+        .collect();
+    format!("hello/{id}{levels}/world")
+}
+
+/// Filter matching the topics of every publisher `depth` levels deep
+pub(crate) fn filter(depth: usize) -> String {
+    format!("hello/+{}/world", "/+".repeat(depth))
+}
+
+/// Whether `filter` matches `topic`, as MQTT defines it
+pub(crate) fn matches(filter: &str, topic: &str) -> bool {
+    // wildcards at the first level don't match topics starting with `$`
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+
+    let mut topic = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic.next()) {
+            // also matches the parent level
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(name)) if level == name => {}
+            _ => return false,
+        }
+    }
+
+    topic.next().is_none()
+}
+
+/// Publishes a subscriber to `--sink-filter` receives
+pub(crate) fn expected(config: &BenchConfig) -> usize {
+    let Some(filter) = &config.sink_filter else {
+        return 0;
+    };
+
+    (0..config.publishers)
+        .filter(|&i| matches(filter, &publisher_topic(config, i)))
+        .map(|i| groups::count(config, i))
+        .sum()
+}
+
+/// Checks that `filter` is a valid topic filter
+pub(crate) fn parse(filter: &str) -> Result<String, String> {
+    if filter.is_empty() {
+        return Err("empty filter".to_owned());
+    }
+
+    let levels: Vec<&str> = filter.split('/').collect();
+    for (i, level) in levels.iter().enumerate() {
+        match *level {
+            "#" if i + 1 < levels.len() => return Err("`#` has to be the last level".to_owned()),
+            "#" | "+" => {}
+            level if level.contains(['#', '+']) => {
+                return Err(format!(
+                    "`{level}`: wildcards have to be a level of their own"
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    Ok(filter.to_owned())
+}
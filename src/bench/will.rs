@@ -0,0 +1,186 @@
+//! Last wills. Publishers connect with a last will and, once they're done, a
+//! subset of them drop their connection without disconnecting. A monitor
+//! connection subscribed to the will topics times how long the broker takes
+//! to deliver the wills of the killed publishers
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use rumqttc::{AsyncClient, Event, Incoming, LastWill};
+use serde::Serialize;
+use tokio::{sync::oneshot, task::JoinHandle, time};
+
+use crate::{
+    bench::{get_qos, options, ConnectionError},
+    common::{latency_histogram, LatencySummary},
+    BenchConfig,
+};
+
+/// When each killed publisher dropped its connection, by id
+pub(crate) type Kills = Arc<Mutex<HashMap<String, Instant>>>;
+
+/// Last will of the publisher with `id`, if configured
+pub(crate) fn last_will(config: &BenchConfig, id: &str) -> Option<LastWill> {
+    let topic = config.will_topic.as_ref()?;
+    Some(LastWill::new(
+        topic.replace("{id}", id),
+        config.will_payload.as_bytes(),
+        get_qos(config.will_qos),
+        false,
+    ))
+}
+
+/// Wills delivered to the monitor connection
+#[derive(Debug, Serialize)]
+pub struct WillReport {
+    /// publishers killed without disconnecting
+    pub killed: usize,
+    /// wills of killed publishers that were delivered
+    pub delivered: usize,
+    /// wills of publishers that weren't killed, e.g. because they lost their
+    /// connection during the run
+    pub unexpected: usize,
+    /// time from killing a publisher until its will arrived
+    pub latencies: LatencySummary,
+}
+
+pub struct WillMonitor {
+    kills: Kills,
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<WillReport>,
+}
+
+impl WillMonitor {
+    /// Subscribes to the will topics of all publishers. Has to be started
+    /// before publishers connect, so that no will goes unnoticed
+    pub(crate) async fn start(config: Arc<BenchConfig>) -> Result<WillMonitor, ConnectionError> {
+        let (client, mut eventloop) =
+            AsyncClient::new(options(config.clone(), "mqttwrk-will")?, 10);
+
+        loop {
+            match eventloop.poll().await? {
+                Event::Incoming(Incoming::ConnAck(_)) => break,
+                Event::Incoming(incoming) => return Err(ConnectionError::WrongPacket(incoming)),
+                Event::Outgoing(_) => {}
+            }
+        }
+
+        let template = config.will_topic.clone().unwrap_or_default();
+        let filter = template.replace("{id}", "+");
+        client.subscribe(filter, get_qos(config.will_qos)).await?;
+
+        let kills: Kills = Default::default();
+        let (stop, mut stopped) = oneshot::channel();
+        let handle = tokio::spawn({
+            let kills = kills.clone();
+            async move {
+                // arrival times of wills, by topic
+                let mut arrivals: HashMap<String, Vec<Instant>> = HashMap::new();
+                let mut deadline = None;
+                loop {
+                    let event = match deadline {
+                        // after the run, wait for the wills of all killed publishers
+                        Some(deadline) => {
+                            if !pending(&template, &kills.lock().unwrap(), &arrivals) {
+                                break;
+                            }
+
+                            match time::timeout_at(deadline, eventloop.poll()).await {
+                                Ok(event) => event,
+                                Err(_) => {
+                                    warn!("Id = mqttwrk-will, Timed out waiting for wills");
+                                    break;
+                                }
+                            }
+                        }
+                        None => tokio::select! {
+                            event = eventloop.poll() => event,
+                            _ = &mut stopped => {
+                                deadline = Some((Instant::now() + config.receive_timeout).into());
+                                continue;
+                            }
+                        },
+                    };
+
+                    match event {
+                        Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                            arrivals
+                                .entry(publish.topic)
+                                .or_default()
+                                .push(Instant::now());
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            error!("Id = mqttwrk-will, Connection error = {:?}", e);
+                            break;
+                        }
+                    }
+                }
+
+                let _ = client.try_disconnect();
+                let kills = kills.lock().unwrap();
+                let mut histogram = latency_histogram();
+                let mut delivered = 0;
+                for (id, killed_at) in kills.iter() {
+                    let arrived = match arrivals.get_mut(&template.replace("{id}", id)) {
+                        Some(arrived) if !arrived.is_empty() => arrived.remove(0),
+                        _ => continue,
+                    };
+
+                    delivered += 1;
+                    let latency = arrived.saturating_duration_since(*killed_at);
+                    histogram.record(latency.as_micros() as u64).unwrap();
+                }
+
+                WillReport {
+                    killed: kills.len(),
+                    delivered,
+                    unexpected: arrivals.values().map(Vec::len).sum(),
+                    latencies: LatencySummary::from(&histogram),
+                }
+            }
+        });
+
+        Ok(WillMonitor {
+            kills,
+            stop,
+            handle,
+        })
+    }
+
+    /// Handle for publishers to record when they were killed
+    pub(crate) fn kills(&self) -> Kills {
+        self.kills.clone()
+    }
+
+    /// Waits up to `--receive-timeout` for the wills of all killed publishers
+    pub(crate) async fn stop(self) -> Option<WillReport> {
+        let _ = self.stop.send(());
+        match self.handle.await {
+            Ok(report) => Some(report),
+            Err(e) => {
+                error!("Will monitor failed = {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Whether the will of any killed publisher is yet to arrive
+fn pending(
+    template: &str,
+    kills: &HashMap<String, Instant>,
+    arrivals: &HashMap<String, Vec<Instant>>,
+) -> bool {
+    let mut expected: HashMap<String, usize> = HashMap::new();
+    for id in kills.keys() {
+        *expected.entry(template.replace("{id}", id)).or_default() += 1;
+    }
+
+    expected
+        .iter()
+        .any(|(topic, count)| arrivals.get(topic).map(Vec::len).unwrap_or(0) < *count)
+}
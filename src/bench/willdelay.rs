@@ -0,0 +1,188 @@
+//! Mqtt 5 will delay interval. `--will-delay-clients` v5 clients connect with
+//! a will delayed by `--will-delay` and drop their connections together
+//! without disconnecting. Half of them come back halfway through the delay,
+//! which is expected to cancel their wills, while the wills of the others are
+//! expected once the delay elapsed, and not before. A monitor connection times
+//! the wills from the moment the connections dropped
+
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::try_join_all;
+use rumqttc::v5::{
+    mqttbytes::{
+        v5::{ConnectProperties, LastWill, LastWillProperties, Packet},
+        QoS,
+    },
+    AsyncClient, Event, EventLoop,
+};
+use serde::Serialize;
+use tokio::time;
+
+use crate::{
+    bench::{v5_connect, v5_disconnect, v5_options},
+    common::{latency_histogram, LatencySummary},
+    BenchConfig,
+};
+
+const TOPIC_PREFIX: &str = "hello/willdelay/";
+const MONITOR: &str = "mqttwrk-willdelay";
+
+#[derive(Debug, Serialize)]
+pub struct WillDelayReport {
+    pub delay_secs: u32,
+    pub clients: usize,
+    /// clients that came back before their will delay elapsed
+    pub returned: usize,
+    /// wills of clients that stayed away
+    pub delivered: usize,
+    /// wills delivered before their delay elapsed
+    pub early: usize,
+    /// wills of clients that came back, which the broker should have dropped
+    pub unexpected: usize,
+    /// time from dropping the connections until the wills arrived
+    pub latencies: LatencySummary,
+}
+
+pub(crate) async fn verify(config: Arc<BenchConfig>) -> anyhow::Result<WillDelayReport> {
+    let delay = config.will_delay.unwrap_or(0);
+    let ids: Vec<String> = (0..config.will_delay_clients)
+        .map(|i| format!("mqttwrk-willdelay-{i:05}"))
+        .collect();
+    let (returning, staying) = ids.split_at(ids.len() / 2);
+
+    let (monitor, mut monitor_loop, _) = v5_connect(v5_options(&config, MONITOR)?).await?;
+    monitor
+        .subscribe(format!("{TOPIC_PREFIX}+"), QoS::AtLeastOnce)
+        .await?;
+    loop {
+        match monitor_loop.poll().await? {
+            Event::Incoming(Packet::SubAck(_)) => break,
+            Event::Incoming(incoming) => anyhow::bail!("Wrong packet = {:?}", incoming),
+            Event::Outgoing(_) => {}
+        }
+    }
+
+    let connections = try_join_all(ids.iter().map(|id| connect(&config, id, delay))).await?;
+    let dropped_at = Instant::now();
+    // dropping eventloops closes their connections without a disconnect
+    drop(connections);
+
+    // the monitor waits for every will that may arrive, expected or not,
+    // while the returning clients come back
+    let deadline = dropped_at + Duration::from_secs(delay as u64) + config.receive_timeout;
+    let arrivals = tokio::spawn(wills(monitor_loop, ids.len(), deadline));
+    time::sleep_until((dropped_at + Duration::from_millis(delay as u64 * 500)).into()).await;
+    try_join_all(returning.iter().map(|id| comeback(&config, id))).await?;
+    let (mut monitor_loop, arrivals) = arrivals.await??;
+    v5_disconnect(&monitor, &mut monitor_loop).await?;
+
+    let returning: HashSet<&str> = returning.iter().map(String::as_str).collect();
+    let mut latencies = latency_histogram();
+    let (mut delivered, mut early, mut unexpected) = (0, 0, 0);
+    for (id, arrived_at) in arrivals.iter() {
+        if returning.contains(id.as_str()) {
+            unexpected += 1;
+            continue;
+        }
+
+        let elapsed = arrived_at.duration_since(dropped_at);
+        delivered += 1;
+        if elapsed < Duration::from_secs(delay as u64) {
+            early += 1;
+        }
+        latencies.record(elapsed.as_micros() as u64)?;
+    }
+
+    if delivered < staying.len() {
+        warn!(
+            "Id = {}, {} of {} wills received",
+            MONITOR,
+            delivered,
+            staying.len()
+        );
+    }
+
+    Ok(WillDelayReport {
+        delay_secs: delay,
+        clients: ids.len(),
+        returned: returning.len(),
+        delivered,
+        early,
+        unexpected,
+        latencies: LatencySummary::from(&latencies),
+    })
+}
+
+/// Connects with a will delayed by `delay`. The session has to outlive the
+/// delay, as brokers send wills once sessions end at the latest
+async fn connect(
+    config: &BenchConfig,
+    id: &str,
+    delay: u32,
+) -> anyhow::Result<(AsyncClient, EventLoop)> {
+    let mut options = v5_options(config, id)?;
+    let properties = LastWillProperties {
+        delay_interval: Some(delay),
+        payload_format_indicator: None,
+        message_expiry_interval: None,
+        content_type: None,
+        response_topic: None,
+        correlation_data: None,
+        user_properties: Vec::new(),
+    };
+    options.set_last_will(LastWill::new(
+        format!("{TOPIC_PREFIX}{id}"),
+        "offline",
+        QoS::AtLeastOnce,
+        false,
+        Some(properties),
+    ));
+    let mut properties = ConnectProperties::new();
+    properties.session_expiry_interval = Some(delay.saturating_add(60));
+    options.set_connect_properties(properties);
+
+    let (client, eventloop, _) = v5_connect(options).await?;
+    Ok((client, eventloop))
+}
+
+/// Resumes the session of a dropped client, which cancels its will, and ends
+/// it gracefully
+async fn comeback(config: &BenchConfig, id: &str) -> anyhow::Result<()> {
+    let mut options = v5_options(config, id)?;
+    options.set_clean_start(false);
+    let (client, mut eventloop, connack) = v5_connect(options).await?;
+    if !connack.session_present {
+        warn!("Id = {}, Broker didn't keep the session", id);
+    }
+
+    v5_disconnect(&client, &mut eventloop).await
+}
+
+/// Receives wills until all `clients` arrived or `deadline`. Returns the id of
+/// every will along with when it arrived
+async fn wills(
+    mut eventloop: EventLoop,
+    clients: usize,
+    deadline: Instant,
+) -> anyhow::Result<(EventLoop, Vec<(String, Instant)>)> {
+    let mut arrivals = Vec::new();
+    while arrivals.len() < clients {
+        let event = match time::timeout_at(deadline.into(), eventloop.poll()).await {
+            Ok(event) => event?,
+            Err(_) => break,
+        };
+
+        if let Event::Incoming(Packet::Publish(publish)) = event {
+            let topic = String::from_utf8_lossy(&publish.topic);
+            if let Some(id) = topic.strip_prefix(TOPIC_PREFIX) {
+                arrivals.push((id.to_owned(), Instant::now()));
+            }
+        }
+    }
+
+    Ok((eventloop, arrivals))
+}
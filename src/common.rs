@@ -1,6 +1,14 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant, SystemTime},
+};
+
+use hdrhistogram::Histogram;
 use indicatif::ProgressStyle;
 use once_cell::sync::Lazy;
 use rumqttc::{AsyncClient, ConnectionError, Event, EventLoop, Incoming, MqttOptions};
+use serde::{Deserialize, Serialize};
 
 pub static PROGRESS_STYLE: Lazy<indicatif::ProgressStyle> = Lazy::new(|| {
     ProgressStyle::with_template(
@@ -10,24 +18,663 @@ pub static PROGRESS_STYLE: Lazy<indicatif::ProgressStyle> = Lazy::new(|| {
     .progress_chars("##-")
 });
 
+pub static ETA_PROGRESS_STYLE: Lazy<indicatif::ProgressStyle> = Lazy::new(|| {
+    ProgressStyle::with_template(
+        "{spinner:.bold.bright.yellow} {prefix:>22} {pos:>7}/{len:7} {bar:40.cyan/blue} {per_sec:>12} eta {eta}",
+    )
+    .expect("progress style template should be correct")
+    .progress_chars("##-")
+});
+
 pub enum Stats {
-    PubStats(PubStats),
-    SubStats(SubStats),
+    PubStats(Box<PubStats>),
+    SubStats(Box<SubStats>),
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct SubStats {
+    pub id: String,
+    /// `HOST:PORT` of the broker connected to
+    pub endpoint: String,
     pub publish_count: u64,
     pub puback_count: u64,
     pub reconnects: u64,
     pub throughput: f32,
+    /// when the first publish arrived, unless none did
+    pub first_publish: Option<Instant>,
+    /// end to end delivery latencies in microseconds, with latency tracking
+    pub latencies: Histogram<u64>,
+    /// delivery latencies during warmup, excluded from `latencies`
+    pub warmup_latencies: Histogram<u64>,
+    /// intervals between consecutive incoming publishes
+    pub arrival_intervals: IntervalStats,
+    /// `arrival_intervals` in microseconds, for their percentiles
+    pub arrival_gaps: Histogram<u64>,
+    /// payload bytes received
+    pub payload_bytes: u64,
+    /// estimated size of incoming publishes on the wire, including mqtt headers
+    pub wire_bytes: u64,
+    /// connect to connack latencies in microseconds
+    pub connack_latencies: Histogram<u64>,
+    /// periods during which the connection was down
+    pub outages: Vec<Outage>,
+    /// subscribe to suback latencies in microseconds
+    pub suback_latencies: Histogram<u64>,
+    /// incoming publishes broken down by topic, when enabled
+    pub topics: HashMap<String, TopicStats>,
+    /// incoming publishes broken down by group of the publisher, with `--groups`
+    pub groups: Vec<TopicStats>,
+    /// sequence numbers that never arrived, when tracking sequences
+    pub gaps: Vec<TopicGaps>,
+    /// sessions resumed after going offline on purpose
+    pub resumptions: Vec<Resumption>,
+    /// how the connection came back from `--reconnect-storm`
+    pub recovery: Recovery,
+    /// flaps of the connection or latencies while others flap, with `--flappy`
+    pub flaps: Flaps,
+    /// publishes delivered more than once by qos of the delivery, when
+    /// tracking sequences
+    pub duplicates: [u64; 3],
+    /// publishes received by qos of the delivery
+    pub qos_receives: [u64; 3],
+    /// delivery latencies in microseconds by qos of the delivery
+    pub qos_latencies: [Histogram<u64>; 3],
+    /// publishes that arrived after a later publish on the same topic, when
+    /// tracking sequences
+    pub reordered: u64,
+    /// how far behind the latest publish on its topic a reordered publish was
+    pub max_displacement: u64,
+}
+
+/// Inclusive ranges of sequence numbers missing on a topic
+#[derive(Debug, Clone)]
+pub struct TopicGaps {
+    pub topic: String,
+    pub ranges: Vec<(u64, u64)>,
+}
+
+impl TopicGaps {
+    pub fn missing(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|(first, last)| last - first + 1)
+            .sum()
+    }
+}
+
+impl Default for SubStats {
+    fn default() -> Self {
+        SubStats {
+            id: String::new(),
+            endpoint: String::new(),
+            publish_count: 0,
+            puback_count: 0,
+            reconnects: 0,
+            throughput: 0.0,
+            first_publish: None,
+            latencies: latency_histogram(),
+            warmup_latencies: latency_histogram(),
+            arrival_intervals: IntervalStats::default(),
+            arrival_gaps: latency_histogram(),
+            payload_bytes: 0,
+            wire_bytes: 0,
+            connack_latencies: latency_histogram(),
+            outages: Vec::new(),
+            suback_latencies: latency_histogram(),
+            topics: HashMap::new(),
+            groups: Vec::new(),
+            gaps: Vec::new(),
+            resumptions: Vec::new(),
+            recovery: Recovery::default(),
+            flaps: Flaps::default(),
+            duplicates: [0; 3],
+            qos_receives: [0; 3],
+            qos_latencies: [
+                latency_histogram(),
+                latency_histogram(),
+                latency_histogram(),
+            ],
+            reordered: 0,
+            max_displacement: 0,
+        }
+    }
+}
+
+impl SubStats {
+    pub fn merge(&mut self, other: &SubStats) {
+        self.publish_count += other.publish_count;
+        self.puback_count += other.puback_count;
+        self.reconnects += other.reconnects;
+        self.throughput += other.throughput;
+        self.first_publish = match (self.first_publish, other.first_publish) {
+            (Some(first), Some(other)) => Some(first.min(other)),
+            (first, other) => first.or(other),
+        };
+        self.latencies
+            .add(&other.latencies)
+            .expect("auto resizing histograms should merge");
+        self.warmup_latencies
+            .add(&other.warmup_latencies)
+            .expect("auto resizing histograms should merge");
+        self.arrival_intervals.merge(&other.arrival_intervals);
+        self.arrival_gaps
+            .add(&other.arrival_gaps)
+            .expect("auto resizing histograms should merge");
+        self.payload_bytes += other.payload_bytes;
+        self.wire_bytes += other.wire_bytes;
+        self.connack_latencies
+            .add(&other.connack_latencies)
+            .expect("auto resizing histograms should merge");
+        self.suback_latencies
+            .add(&other.suback_latencies)
+            .expect("auto resizing histograms should merge");
+        self.outages.extend(other.outages.iter().cloned());
+        for (topic, stats) in other.topics.iter() {
+            self.topics.entry(topic.clone()).or_default().merge(stats);
+        }
+        if self.groups.len() < other.groups.len() {
+            self.groups
+                .resize_with(other.groups.len(), Default::default);
+        }
+        for (group, other) in self.groups.iter_mut().zip(&other.groups) {
+            group.merge(other);
+        }
+        self.gaps.extend(other.gaps.iter().cloned());
+        self.resumptions.extend(other.resumptions.iter().cloned());
+        self.recovery.merge(&other.recovery);
+        self.flaps.merge(&other.flaps);
+        for (qos, count) in other.duplicates.iter().enumerate() {
+            self.duplicates[qos] += count;
+        }
+        for (receives, other) in self.qos_receives.iter_mut().zip(other.qos_receives) {
+            *receives += other;
+        }
+        for (latencies, other) in self
+            .qos_latencies
+            .iter_mut()
+            .zip(other.qos_latencies.iter())
+        {
+            latencies
+                .add(other)
+                .expect("auto resizing histograms should merge");
+        }
+        self.reordered += other.reordered;
+        self.max_displacement = self.max_displacement.max(other.max_displacement);
+    }
+}
+
+#[derive(Debug)]
+pub struct TopicStats {
+    pub publish_count: u64,
+    pub bytes: u64,
+    /// delivery latencies in microseconds
+    pub latencies: Histogram<u64>,
+}
+
+impl Default for TopicStats {
+    fn default() -> Self {
+        TopicStats {
+            publish_count: 0,
+            bytes: 0,
+            latencies: latency_histogram(),
+        }
+    }
+}
+
+impl TopicStats {
+    pub fn record(&mut self, bytes: usize, latency: Option<Duration>) {
+        self.publish_count += 1;
+        self.bytes += bytes as u64;
+        if let Some(latency) = latency {
+            self.latencies.record(latency.as_micros() as u64).unwrap();
+        }
+    }
+
+    pub fn merge(&mut self, other: &TopicStats) {
+        self.publish_count += other.publish_count;
+        self.bytes += other.bytes;
+        self.latencies
+            .add(&other.latencies)
+            .expect("auto resizing histograms should merge");
+    }
+}
+
+/// How connections came back from a reconnect storm
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    /// connections the storm dropped
+    pub dropped: u64,
+    /// from the drop until the broker accepted the connection again, in
+    /// microseconds
+    pub reconnects: Histogram<u64>,
+    /// from the drop until the first ack or publish on the new connection, in
+    /// microseconds
+    pub resumes: Histogram<u64>,
+    /// when the connection was dropped, until publishes flow again
+    since: Option<Instant>,
+    reconnected: bool,
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Recovery {
+            dropped: 0,
+            reconnects: latency_histogram(),
+            resumes: latency_histogram(),
+            since: None,
+            reconnected: false,
+        }
+    }
+}
+
+impl Recovery {
+    /// Whether the storm is yet to drop the connection
+    pub fn waiting(&self) -> bool {
+        self.dropped == 0
+    }
+
+    pub fn dropped(&mut self) {
+        self.dropped += 1;
+        self.since = Some(Instant::now());
+    }
+
+    pub fn connected(&mut self) {
+        if let Some(since) = self.since.filter(|_| !self.reconnected) {
+            self.reconnected = true;
+            self.reconnects
+                .record(since.elapsed().as_micros() as u64)
+                .unwrap();
+        }
+    }
+
+    /// Records the first ack or publish once the connection is back
+    pub fn flowing(&mut self) {
+        if !self.reconnected {
+            return;
+        }
+
+        if let Some(since) = self.since.take() {
+            self.resumes
+                .record(since.elapsed().as_micros() as u64)
+                .unwrap();
+        }
+    }
+
+    pub fn merge(&mut self, other: &Recovery) {
+        self.dropped += other.dropped;
+        self.reconnects
+            .add(&other.reconnects)
+            .expect("auto resizing histograms should merge");
+        self.resumes
+            .add(&other.resumes)
+            .expect("auto resizing histograms should merge");
+    }
 }
 
-#[derive(Default, Debug)]
+/// Flaps of a flappy connection and latencies of a stable one, with `--flappy`
+#[derive(Debug, Clone)]
+pub struct Flaps {
+    /// flappy connections
+    pub flappy: u64,
+    /// stable connections
+    pub stable: u64,
+    /// times flappy connections dropped
+    pub flaps: u64,
+    /// from the flap until the broker accepted the connection again, in
+    /// microseconds
+    pub reconnects: Histogram<u64>,
+    /// latencies of stable connections before the first flap, in microseconds
+    pub calm: Histogram<u64>,
+    /// latencies of stable connections once flappy ones started flapping, in
+    /// microseconds
+    pub flapping: Histogram<u64>,
+    /// when the connection flapped, until the broker accepts it again
+    since: Option<Instant>,
+}
+
+impl Default for Flaps {
+    fn default() -> Self {
+        Flaps {
+            flappy: 0,
+            stable: 0,
+            flaps: 0,
+            reconnects: latency_histogram(),
+            calm: latency_histogram(),
+            flapping: latency_histogram(),
+            since: None,
+        }
+    }
+}
+
+impl Flaps {
+    pub fn flapped(&mut self) {
+        self.flaps += 1;
+        self.since = Some(Instant::now());
+    }
+
+    pub fn connected(&mut self) {
+        if let Some(since) = self.since.take() {
+            self.reconnects
+                .record(since.elapsed().as_micros() as u64)
+                .unwrap();
+        }
+    }
+
+    /// Records a latency of a stable connection
+    pub fn record(&mut self, latency: Duration, flapping: bool) {
+        let latencies = if flapping {
+            &mut self.flapping
+        } else {
+            &mut self.calm
+        };
+        latencies.record(latency.as_micros() as u64).unwrap();
+    }
+
+    pub fn merge(&mut self, other: &Flaps) {
+        self.flappy += other.flappy;
+        self.stable += other.stable;
+        self.flaps += other.flaps;
+        self.reconnects
+            .add(&other.reconnects)
+            .expect("auto resizing histograms should merge");
+        self.calm
+            .add(&other.calm)
+            .expect("auto resizing histograms should merge");
+        self.flapping
+            .add(&other.flapping)
+            .expect("auto resizing histograms should merge");
+    }
+}
+
+/// Running mean and variance of the intervals between consecutive events,
+/// using Welford's algorithm so that intervals needn't be kept around
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IntervalStats {
+    pub count: u64,
+    /// mean interval in microseconds
+    pub mean: f64,
+    /// sum of squared differences from the mean
+    m2: f64,
+}
+
+impl IntervalStats {
+    pub fn record(&mut self, interval: Duration) {
+        let interval = interval.as_micros() as f64;
+        self.count += 1;
+        let delta = interval - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (interval - self.mean);
+    }
+
+    pub fn merge(&mut self, other: &IntervalStats) {
+        if other.count == 0 {
+            return;
+        }
+
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * (self.count * other.count) as f64 / count as f64;
+        self.count = count;
+    }
+
+    /// Standard deviation of intervals in microseconds
+    pub fn stddev(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => (self.m2 / count as f64).sqrt(),
+        }
+    }
+
+    /// Coefficient of variation, i.e. the standard deviation relative to the
+    /// mean interval
+    pub fn cov(&self) -> f64 {
+        match self.mean {
+            mean if mean > 0.0 => self.stddev() / mean,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct PubStats {
+    pub id: String,
+    /// `HOST:PORT` of the broker connected to
+    pub endpoint: String,
     pub outgoing_publish: u64,
     pub throughput: f32,
     pub reconnects: u64,
+    /// publish to ack round trip latencies in microseconds
+    pub ack_latencies: Histogram<u64>,
+    /// publish to ack latencies during warmup, excluded from `ack_latencies`
+    /// and `rtts`
+    pub warmup_ack_latencies: Histogram<u64>,
+    /// latencies from when publishes were due at the configured rate to their
+    /// acks, correcting for coordinated omission
+    pub corrected_ack_latencies: Histogram<u64>,
+    /// time from the start of a burst until its last publish was acked, when
+    /// publishing in bursts
+    pub burst_drains: Histogram<u64>,
+    /// publishes written to the network in every phase of `--schedule`
+    pub phase_publishes: Vec<u64>,
+    /// publish to ack latencies in every phase of `--schedule`
+    pub phase_ack_latencies: Vec<Histogram<u64>>,
+    /// enqueue to puback (qos 1) or pubcomp (qos 2) round trip times in
+    /// microseconds, by qos of the publish
+    pub rtts: [Histogram<u64>; 3],
+    /// publishes written to the network by qos
+    pub qos_publishes: [u64; 3],
+    /// publish to pubrec latencies of qos 2 publishes in microseconds
+    pub pubrec_latencies: Histogram<u64>,
+    /// pubrel to pubcomp latencies of qos 2 publishes in microseconds
+    pub pubcomp_latencies: Histogram<u64>,
+    /// connect to connack latencies in microseconds
+    pub connack_latencies: Histogram<u64>,
+    /// intervals between consecutive publishes written to the network
+    pub publish_intervals: IntervalStats,
+    /// payload bytes published
+    pub payload_bytes: u64,
+    /// estimated size of publishes on the wire, including mqtt headers
+    pub wire_bytes: u64,
+    /// publishes handed to the client by the publishing task
+    pub requests: RequestStats,
+    /// periods during which the connection was down
+    pub outages: Vec<Outage>,
+    /// keep alive pings sent
+    pub pings: u64,
+    /// ping responses received
+    pub ping_responses: u64,
+    /// ping to ping response latencies in microseconds
+    pub ping_latencies: Histogram<u64>,
+    /// index of the group of the publisher, with `--groups`
+    pub group: Option<usize>,
+    /// whether subscribers subscribe to the topic of the publisher, which
+    /// groups with topics of their own might not
+    pub subscribed: bool,
+    /// how the connection came back from `--reconnect-storm`
+    pub recovery: Recovery,
+    /// flaps of the connection or latencies while others flap, with `--flappy`
+    pub flaps: Flaps,
+}
+
+impl Default for PubStats {
+    fn default() -> Self {
+        PubStats {
+            id: String::new(),
+            endpoint: String::new(),
+            outgoing_publish: 0,
+            throughput: 0.0,
+            reconnects: 0,
+            ack_latencies: latency_histogram(),
+            warmup_ack_latencies: latency_histogram(),
+            corrected_ack_latencies: latency_histogram(),
+            burst_drains: latency_histogram(),
+            phase_publishes: Vec::new(),
+            phase_ack_latencies: Vec::new(),
+            rtts: [
+                latency_histogram(),
+                latency_histogram(),
+                latency_histogram(),
+            ],
+            qos_publishes: [0; 3],
+            pubrec_latencies: latency_histogram(),
+            pubcomp_latencies: latency_histogram(),
+            connack_latencies: latency_histogram(),
+            publish_intervals: IntervalStats::default(),
+            payload_bytes: 0,
+            wire_bytes: 0,
+            requests: RequestStats::default(),
+            outages: Vec::new(),
+            pings: 0,
+            ping_responses: 0,
+            ping_latencies: latency_histogram(),
+            group: None,
+            subscribed: true,
+            recovery: Recovery::default(),
+            flaps: Flaps::default(),
+        }
+    }
+}
+
+impl PubStats {
+    pub fn merge(&mut self, other: &PubStats) {
+        self.outgoing_publish += other.outgoing_publish;
+        self.throughput += other.throughput;
+        self.reconnects += other.reconnects;
+        self.ack_latencies
+            .add(&other.ack_latencies)
+            .expect("auto resizing histograms should merge");
+        self.warmup_ack_latencies
+            .add(&other.warmup_ack_latencies)
+            .expect("auto resizing histograms should merge");
+        self.corrected_ack_latencies
+            .add(&other.corrected_ack_latencies)
+            .expect("auto resizing histograms should merge");
+        self.burst_drains
+            .add(&other.burst_drains)
+            .expect("auto resizing histograms should merge");
+        let phases = self.phase_publishes.len().max(other.phase_publishes.len());
+        self.phase_publishes.resize(phases, 0);
+        self.phase_ack_latencies.resize(phases, latency_histogram());
+        for (publishes, other) in self.phase_publishes.iter_mut().zip(&other.phase_publishes) {
+            *publishes += other;
+        }
+        for (latencies, other) in self
+            .phase_ack_latencies
+            .iter_mut()
+            .zip(&other.phase_ack_latencies)
+        {
+            latencies
+                .add(other)
+                .expect("auto resizing histograms should merge");
+        }
+        for (rtts, other) in self.rtts.iter_mut().zip(other.rtts.iter()) {
+            rtts.add(other)
+                .expect("auto resizing histograms should merge");
+        }
+        for (publishes, other) in self.qos_publishes.iter_mut().zip(other.qos_publishes) {
+            *publishes += other;
+        }
+        self.pubrec_latencies
+            .add(&other.pubrec_latencies)
+            .expect("auto resizing histograms should merge");
+        self.pubcomp_latencies
+            .add(&other.pubcomp_latencies)
+            .expect("auto resizing histograms should merge");
+        self.connack_latencies
+            .add(&other.connack_latencies)
+            .expect("auto resizing histograms should merge");
+        self.publish_intervals.merge(&other.publish_intervals);
+        self.payload_bytes += other.payload_bytes;
+        self.wire_bytes += other.wire_bytes;
+        self.requests.enqueued += other.requests.enqueued;
+        self.requests.failed += other.requests.failed;
+        self.requests.rate += other.requests.rate;
+        self.outages.extend(other.outages.iter().cloned());
+        self.pings += other.pings;
+        self.ping_responses += other.ping_responses;
+        self.ping_latencies
+            .add(&other.ping_latencies)
+            .expect("auto resizing histograms should merge");
+        self.recovery.merge(&other.recovery);
+        self.flaps.merge(&other.flaps);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct RequestStats {
+    /// publishes handed to the client
+    pub enqueued: u64,
+    /// publishes that couldn't be handed to the client as it was closed
+    pub failed: u64,
+    /// publishes handed to the client per second, from the first to the last
+    pub rate: f64,
+}
+
+/// A period during which a connection was down
+#[derive(Debug, Clone)]
+pub struct Outage {
+    pub disconnected_at: SystemTime,
+    /// `None` if the connection never came back
+    pub reconnected_at: Option<SystemTime>,
+    pub downtime: Duration,
+}
+
+/// A subscriber reconnecting after `--offline-for`
+#[derive(Debug, Clone)]
+pub struct Resumption {
+    /// whether the broker kept the session while the subscriber was offline
+    pub session_present: bool,
+    /// publishes the broker queued while the subscriber was offline
+    pub queued: u64,
+    /// from reconnecting until the last queued publish arrived
+    pub drain: Duration,
+}
+
+/// Histogram to record latencies in microseconds. 3 significant figures keep
+/// the footprint small enough to have one of these per connection
+pub fn latency_histogram() -> Histogram<u64> {
+    Histogram::new(3).expect("3 significant figures should be a valid precision")
+}
+
+/// Percentiles of a latency histogram, in milliseconds
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LatencySummary {
+    pub samples: u64,
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+}
+
+impl From<&Histogram<u64>> for LatencySummary {
+    fn from(histogram: &Histogram<u64>) -> Self {
+        let ms = |micros: u64| micros as f64 / 1000.0;
+        LatencySummary {
+            samples: histogram.len(),
+            min: ms(histogram.min()),
+            mean: histogram.mean() / 1000.0,
+            p50: ms(histogram.value_at_quantile(0.5)),
+            p90: ms(histogram.value_at_quantile(0.9)),
+            p99: ms(histogram.value_at_quantile(0.99)),
+            p999: ms(histogram.value_at_quantile(0.999)),
+            max: ms(histogram.max()),
+        }
+    }
+}
+
+impl fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "samples = {}, min = {:.3}ms, mean = {:.3}ms, p50 = {:.3}ms, p90 = {:.3}ms, p99 = {:.3}ms, p99.9 = {:.3}ms, max = {:.3}ms",
+            self.samples, self.min, self.mean, self.p50, self.p90, self.p99, self.p999, self.max
+        )
+    }
 }
 
 pub fn get_client(config: MqttOptions) -> (AsyncClient, WrappedEventLoop) {
@@ -0,0 +1,172 @@
+//! Compares the json reports of two bench runs and flags regressions in
+//! throughput and latencies beyond a tolerance
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{bench::report::Summary, CompareConfig};
+
+/// Only the summary of a saved report is compared
+#[derive(Debug, Deserialize)]
+struct SavedReport {
+    summary: Summary,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    Regression,
+    Improvement,
+    Unchanged,
+}
+
+struct Metric {
+    name: String,
+    baseline: f64,
+    current: f64,
+    higher_is_better: bool,
+}
+
+impl Metric {
+    /// Change from baseline to current in percent
+    fn change(&self) -> Option<f64> {
+        if self.baseline == 0.0 {
+            return None;
+        }
+
+        Some((self.current - self.baseline) * 100.0 / self.baseline)
+    }
+
+    fn verdict(&self, tolerance: f64) -> Verdict {
+        let change = match self.change() {
+            Some(change) => change,
+            // nothing to compare relative to, any increase is a change
+            None if self.current == 0.0 => return Verdict::Unchanged,
+            None => f64::INFINITY,
+        };
+
+        let better = if self.higher_is_better {
+            change
+        } else {
+            -change
+        };
+
+        if better < -tolerance {
+            Verdict::Regression
+        } else if better > tolerance {
+            Verdict::Improvement
+        } else {
+            Verdict::Unchanged
+        }
+    }
+}
+
+pub(crate) fn start(config: CompareConfig) -> Result<()> {
+    let baseline = load(&config.baseline)?;
+    let current = load(&config.current)?;
+    let metrics = metrics(&baseline, &current);
+
+    println!(
+        "Comparison (tolerance = {:.2}%)
+        ----------------------------
+        {:<28} {:>14} {:>14} {:>10}",
+        config.tolerance, "Metric", "Baseline", "Current", "Change"
+    );
+
+    let mut regressions = 0;
+    let mut improvements = 0;
+    for metric in metrics.iter() {
+        let change = match metric.change() {
+            Some(change) => format!("{change:+.2}%"),
+            None => "-".to_owned(),
+        };
+
+        let line = format!(
+            "        {:<28} {:>14.3} {:>14.3} {:>10}",
+            metric.name, metric.baseline, metric.current, change
+        );
+
+        match metric.verdict(config.tolerance) {
+            Verdict::Regression => {
+                regressions += 1;
+                red_ln!("{}", line);
+            }
+            Verdict::Improvement => {
+                improvements += 1;
+                green_ln!("{}", line);
+            }
+            Verdict::Unchanged => white_ln!("{}", line),
+        }
+    }
+
+    println!("\n{regressions} regressions, {improvements} improvements");
+    if regressions > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<Summary> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let report: SavedReport = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("failed to parse report {}", path.display()))?;
+    Ok(report.summary)
+}
+
+fn metrics(baseline: &Summary, current: &Summary) -> Vec<Metric> {
+    let metric = |name: &str, baseline, current, higher_is_better| Metric {
+        name: name.to_owned(),
+        baseline,
+        current,
+        higher_is_better,
+    };
+
+    let mut metrics = vec![
+        metric(
+            "publish throughput",
+            baseline.publish_throughput,
+            current.publish_throughput,
+            true,
+        ),
+        metric(
+            "incoming throughput",
+            baseline.incoming_throughput,
+            current.incoming_throughput,
+            true,
+        ),
+        metric("loss %", baseline.loss_percent, current.loss_percent, false),
+    ];
+
+    for (name, baseline, current) in [
+        (
+            "ack latency",
+            &baseline.ack_latencies,
+            &current.ack_latencies,
+        ),
+        ("latency", &baseline.latencies, &current.latencies),
+        (
+            "connack latency",
+            &baseline.connack_latencies,
+            &current.connack_latencies,
+        ),
+    ] {
+        for (percentile, baseline, current) in [
+            ("p50", baseline.p50, current.p50),
+            ("p90", baseline.p90, current.p90),
+            ("p99", baseline.p99, current.p99),
+            ("p99.9", baseline.p999, current.p999),
+            ("max", baseline.max, current.max),
+        ] {
+            metrics.push(Metric {
+                name: format!("{name} {percentile} (ms)"),
+                baseline,
+                current,
+                higher_is_better: false,
+            });
+        }
+    }
+
+    metrics
+}
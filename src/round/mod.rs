@@ -1,18 +1,38 @@
+//! Mqtt 5 request/response. Requesters publish requests with a response topic
+//! and correlation data, responders echo them back to the response topic with
+//! the same correlation data, and requesters time the full round trip
+
 use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
 use futures::future::try_join_all;
+use hdrhistogram::Histogram;
 use log::debug;
-use rumqttc::{AsyncClient, Event, MqttOptions, QoS};
+use rumqttc::v5::{
+    mqttbytes::{
+        v5::{Packet, PublishProperties},
+        QoS,
+    },
+    AsyncClient, Event, MqttOptions,
+};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::{sync::Barrier, task, time};
+use tokio::{select, sync::Barrier, task, time};
 use tokio_util::sync::CancellationToken;
 
+use crate::common::{latency_histogram, LatencySummary};
 use crate::RoundConfig;
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 pub(crate) async fn start(opt: RoundConfig) -> Result<()> {
-    let connections = vec![1usize, 2, 5, 10, 15, 20, 30, 40, 50, 75, 100, 150, 200];
-    // let connections = vec![70];
+    let opt = RoundConfig {
+        responders: opt.responders.max(1),
+        ..opt
+    };
+    let connections = match opt.connections {
+        Some(connections) => vec![connections],
+        None => vec![1usize, 2, 5, 10, 15, 20, 30, 40, 50, 75, 100, 150, 200],
+    };
     let execution_time = opt.duration;
 
     for (iteration, connections) in connections.iter().enumerate() {
@@ -22,10 +42,23 @@ pub(crate) async fn start(opt: RoundConfig) -> Result<()> {
         }
 
         // Barrier to synchronize all connections after connect and subscribe
-        let barrier = Arc::new(Barrier::new(*connections + 1));
+        let barrier = Arc::new(Barrier::new(*connections + opt.responders + 1));
         // Stop token to stop the connections
         let stop = CancellationToken::new();
 
+        // Start responders. They stop once requesters are done
+        let done = CancellationToken::new();
+        let mut responders = Vec::new();
+        for r in 0..opt.responders {
+            let barrier = barrier.clone();
+            let done = done.clone();
+            let opt = opt.clone();
+            let capacity = *connections * opt.in_flight + 10;
+            responders.push(task::spawn(async move {
+                responder(r, opt, capacity, done, barrier).await
+            }));
+        }
+
         // Start connections
         let mut tasks = Vec::new();
         for c in 0..*connections {
@@ -70,19 +103,25 @@ pub(crate) async fn start(opt: RoundConfig) -> Result<()> {
 
         // Wait for connection tasks to finish
         let results = try_join_all(tasks).await?;
-        let mut success: Vec<Status> = results
+        done.cancel();
+        let responses: u64 = try_join_all(responders)
+            .await?
+            .into_iter()
+            .collect::<Result<Vec<u64>>>()?
             .iter()
-            .filter_map(|v| v.as_ref().ok())
-            .cloned()
-            .collect();
+            .sum();
+
+        let mut success: Vec<&Status> = results.iter().filter_map(|v| v.as_ref().ok()).collect();
         let total: u128 = success.iter().map(|v| v.throughput).sum();
-        success.sort();
+        success.sort_by_key(|v| v.id);
 
         let mut sent = 0;
         let mut received = 0;
+        let mut latencies = latency_histogram();
         for v in success {
             sent += v.sent;
             received += v.received;
+            latencies.add(&v.latencies)?;
         }
 
         // println!("-------------------------------------------------------------------------------");
@@ -104,26 +143,52 @@ pub(crate) async fn start(opt: RoundConfig) -> Result<()> {
         // }
 
         println!(
-            "Connections: {:3} Sent: {:10} Miss: {:5} Per connection avg: {:7}/s Total: {}/s",
+            "Requesters: {:3} Requests: {:10} Responses: {:10} Miss: {:5} Per requester avg: {:7}/s Total: {}/s",
             connections,
             sent,
+            responses,
             sent - received,
             total / *connections as u128,
             total
         );
+        println!(
+            "        Round trip latencies: {}",
+            LatencySummary::from(&latencies)
+        );
     }
 
     Ok(())
 }
 
-#[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Debug, Clone)]
 struct Status {
     id: usize,
     sent: u64,
     received: u64,
     throughput: u128,
+    latencies: Histogram<u64>,
+}
+
+/// Topic that responder `r` receives requests on
+fn request_topic(r: usize) -> String {
+    format!("mqttwrk/rpc/requests/{r}")
 }
 
+/// Topic that requester `n` receives responses on
+fn response_topic(n: usize) -> String {
+    format!("mqttwrk/rpc/responses/{n}")
+}
+
+fn options(id: String, opt: &RoundConfig) -> MqttOptions {
+    let mut mqttoptions = MqttOptions::new(id, &opt.broker, opt.port);
+    mqttoptions.set_clean_start(true);
+    mqttoptions.set_outgoing_inflight_upper_limit(opt.in_flight as u16);
+    mqttoptions.set_keep_alive(Duration::from_secs(opt.duration.max(5)));
+    mqttoptions
+}
+
+/// Requester `n`. Keeps `in_flight` requests outstanding to responder
+/// `n % responders` until stopped
 async fn connection(
     n: usize,
     opt: RoundConfig,
@@ -132,44 +197,63 @@ async fn connection(
 ) -> Result<Status> {
     debug!("[{}]: Starting", n);
 
-    let mut mqttoptions = MqttOptions::new(n.to_string(), opt.broker, opt.port);
-    mqttoptions.set_clean_session(true);
-    mqttoptions.set_inflight(opt.in_flight as u16);
-    mqttoptions.set_keep_alive(Duration::from_secs(opt.duration));
+    let mut mqttoptions = options(format!("requester-{n}"), &opt);
     mqttoptions.set_request_channel_capacity(opt.in_flight + 10);
 
     // Initialize the client with a request queue size that is bigger than the in flight number
     let (client, mut eventloop) = AsyncClient::new(mqttoptions, opt.in_flight + 10);
 
-    // Each connection uses it's own random topic
-    // let topic = uuid::Uuid::new_v4().to_string();
-    // let topic = "hello/1/world".to_string();
-    let topic = n.to_string();
+    let topic = request_topic(n % opt.responders);
+    let response_topic = response_topic(n);
 
-    // Count publications sent
+    // Count requests sent
     let mut publications_sent = 0u64;
 
-    // Count publications received
+    // Count responses received
     let mut publications_received = 0u64;
 
     // Start timestamp
     let mut start = Instant::now();
 
+    // Round trip latencies and when outstanding requests were sent, by correlation data
+    let mut latencies = latency_histogram();
+    let mut outstanding: HashMap<Bytes, Instant> = HashMap::new();
+
     // Publication data
     let mut data = bytes::BytesMut::new();
     data.extend(std::iter::repeat(0u8).take(opt.payload_size));
     let data = data.freeze();
 
+    // Sends the request numbered `sent`
+    let request = |sent: u64| {
+        let correlation_data = Bytes::from(sent.to_be_bytes().to_vec());
+        let properties = PublishProperties {
+            response_topic: Some(response_topic.clone()),
+            correlation_data: Some(correlation_data.clone()),
+            ..Default::default()
+        };
+        let publish = client.publish_with_properties(
+            topic.clone(),
+            QoS::AtLeastOnce,
+            false,
+            data.clone(),
+            properties,
+        );
+        (correlation_data, publish)
+    };
+
     'outer: loop {
         match eventloop.poll().await? {
             Event::Incoming(p) => {
                 match p {
-                    rumqttc::Packet::ConnAck(_) => {
+                    Packet::ConnAck(_) => {
                         debug!("[{}]: Connected", n);
-                        // We're connected. Subscribe to our topic
-                        client.subscribe(topic.clone(), QoS::AtLeastOnce).await?;
+                        // We're connected. Subscribe to our response topic
+                        client
+                            .subscribe(response_topic.clone(), QoS::AtLeastOnce)
+                            .await?;
                     }
-                    rumqttc::Packet::SubAck(_) => {
+                    Packet::SubAck(_) => {
                         // This test codes does only one subscription. Receiving the
                         // suback means we're ready to go
                         debug!("[{}]: Subscribed", n);
@@ -180,54 +264,48 @@ async fn connection(
                         // Update the start timestamp to not include the time for connectiong and subscribing
                         start = Instant::now();
 
-                        // Start the publication loop by publishing n messages
+                        // Start the request loop by sending n requests
                         for _ in 0..opt.in_flight {
+                            let (correlation_data, publish) = request(publications_sent);
+                            outstanding.insert(correlation_data, Instant::now());
+                            publish.await?;
                             publications_sent += 1;
-                            client
-                                .publish_bytes(topic.clone(), QoS::AtLeastOnce, false, data.clone())
-                                .await?;
                         }
                     }
-                    rumqttc::Packet::Publish(_) if stop.is_cancelled() => {
-                        // Calculate the rate in pub + res in per s
-                        let micros = Instant::now().duration_since(start).as_micros() + 1;
-                        let rate = (publications_received as u128 * 1_000_000) / micros;
-                        let v = Status {
-                            id: n,
-                            sent: publications_sent,
-                            received: publications_received,
-                            throughput: rate,
-                        };
-
-                        break 'outer Ok(v);
-                    }
-                    rumqttc::Packet::Publish(v) => {
-                        debug!("[{}]: Incoming publish {:?}", n, v);
-                        publications_received += 1;
-
-                        if let Some(max_publishes) = opt.max_publishes {
-                            if publications_sent >= max_publishes {
-                                let micros = Instant::now().duration_since(start).as_micros() + 1;
-                                let rate = (publications_received as u128 * 1_000_000) / micros;
-
-                                let v = Status {
-                                    id: n,
-                                    sent: publications_sent,
-                                    received: publications_received,
-                                    throughput: rate,
-                                };
-
-                                break 'outer Ok(v);
-                            }
+                    Packet::Publish(v) => {
+                        debug!("[{}]: Incoming response {:?}", n, v);
+                        let correlation_data = v
+                            .properties
+                            .and_then(|properties| properties.correlation_data)
+                            .ok_or_else(|| anyhow!("Response without correlation data"))?;
+                        if let Some(sent) = outstanding.remove(&correlation_data) {
+                            publications_received += 1;
+                            latencies.record(sent.elapsed().as_micros() as u64)?;
                         }
 
-                        client
-                            .publish(&topic, QoS::AtMostOnce, false, vec![0; 100])
-                            .await?;
-                        // Not yet finished. Increment the publications sent counter and publish again
+                        let max_publishes = opt.max_publishes.unwrap_or(u64::MAX);
+                        if stop.is_cancelled() || publications_sent >= max_publishes {
+                            // Calculate the rate in requests per s
+                            let micros = Instant::now().duration_since(start).as_micros() + 1;
+                            let rate = (publications_received as u128 * 1_000_000) / micros;
+                            let v = Status {
+                                id: n,
+                                sent: publications_sent,
+                                received: publications_received,
+                                throughput: rate,
+                                latencies,
+                            };
+
+                            break 'outer Ok(v);
+                        }
+
+                        // Not yet finished. Send the next request
+                        let (correlation_data, publish) = request(publications_sent);
+                        outstanding.insert(correlation_data, Instant::now());
+                        publish.await?;
                         publications_sent += 1;
                     }
-                    rumqttc::Packet::Disconnect => {
+                    Packet::Disconnect(_) => {
                         // This is an error. The broker sent us a disconnect message.
                         debug!("[{}]: Disconnected", n);
                         break Err(anyhow!("Disconnected"));
@@ -239,3 +317,65 @@ async fn connection(
         }
     }
 }
+
+/// Responder `r`. Echoes requests to their response topic along with their
+/// correlation data until `done`. Returns the number of responses sent
+async fn responder(
+    r: usize,
+    opt: RoundConfig,
+    capacity: usize,
+    done: CancellationToken,
+    barrier: Arc<Barrier>,
+) -> Result<u64> {
+    let mut mqttoptions = options(format!("responder-{r}"), &opt);
+    mqttoptions.set_request_channel_capacity(capacity);
+
+    // Responses are sent from the event loop, so the queue has to hold a
+    // response to every outstanding request
+    let (client, mut eventloop) = AsyncClient::new(mqttoptions, capacity);
+    let mut responses = 0u64;
+
+    loop {
+        let event = select! {
+            event = eventloop.poll() => event?,
+            _ = done.cancelled() => break,
+        };
+
+        match event {
+            Event::Incoming(Packet::ConnAck(_)) => {
+                client.subscribe(request_topic(r), QoS::AtLeastOnce).await?;
+            }
+            Event::Incoming(Packet::SubAck(_)) => {
+                debug!("[responder-{}]: Subscribed", r);
+                barrier.wait().await;
+            }
+            Event::Incoming(Packet::Publish(v)) => {
+                let Some(properties) = v.properties else {
+                    continue;
+                };
+                let Some(response_topic) = properties.response_topic else {
+                    continue;
+                };
+
+                let properties = PublishProperties {
+                    correlation_data: properties.correlation_data,
+                    ..Default::default()
+                };
+                client
+                    .publish_with_properties(
+                        response_topic,
+                        QoS::AtLeastOnce,
+                        false,
+                        v.payload,
+                        properties,
+                    )
+                    .await?;
+                responses += 1;
+            }
+            _ => {}
+        }
+    }
+
+    let _ = client.try_disconnect();
+    Ok(responses)
+}
@@ -0,0 +1,416 @@
+//! Scenarios. A scenario file describes a whole benchmark, its phases with the
+//! clients and topics of each and the thresholds every phase has to meet, so
+//! that a benchmark is a file to check in and rerun rather than a long command
+//! line. Scenarios are toml, e.g.
+//!
+//! ```toml
+//! name = "fleet"
+//! description = "sensors and gateways, warmed up before the peak"
+//!
+//! # flags of `mqttwrk bench` for every phase, by their long name
+//! [bench]
+//! subscribers = 2
+//! latency-tracking = true
+//! assert-loss = 0.0
+//!
+//! [[phase]]
+//! name = "warmup"
+//! publishers = 10
+//! count = 100
+//!
+//! [[phase]]
+//! name = "peak"
+//! groups = [
+//!     "sensors 100 qos=0 payload=64 rate=10",
+//!     "gateways 5 qos=1 payload=4K count=1000 topic=gw/{id}/data",
+//! ]
+//! sink-filter = "#"
+//! assert-p99-latency = "50ms"
+//! output = "json"
+//! output-file = "reports/{phase}.json"
+//! ```
+//!
+//! Phases run one after the other, each a bench run with the flags of `[bench]`
+//! and its own, which win. Flags that are switches take `true`, flags that can
+//! be repeated take arrays, and `{phase}` in values stands for the name of the
+//! phase. `groups` takes the rows of a `--groups` file inline, or the path of
+//! one. Every phase is checked before the first one starts, and the scenario
+//! stops at the first phase that fails its assertions
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use clap::{builder::NonEmptyStringValueParser, CommandFactory, Parser};
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::{
+    bench::{self, groups},
+    BenchConfig, ScenarioConfig,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    name: Option<String>,
+    description: Option<String>,
+    /// flags of every phase
+    #[serde(default)]
+    bench: Table,
+    #[serde(default, rename = "phase")]
+    phases: Vec<Phase>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Phase {
+    name: String,
+    #[serde(flatten)]
+    flags: Table,
+}
+
+/// A phase ready to run
+struct Run {
+    name: String,
+    /// `mqttwrk bench` command line of the phase
+    args: Vec<String>,
+    /// names of groups given in the scenario rather than in a file
+    inline_groups: Vec<String>,
+    config: BenchConfig,
+}
+
+pub(crate) fn start(config: ScenarioConfig) -> Result<()> {
+    let path = &config.path;
+    let runs = load(path).with_context(|| format!("{}", path.display()))?;
+
+    for (i, run) in runs.iter().enumerate() {
+        if run.config.forever && i + 1 < runs.len() {
+            bail!(
+                "{}: phase {}: only the last phase can run --forever",
+                path.display(),
+                run.name
+            );
+        }
+    }
+
+    let phases = runs.len();
+    for (i, run) in runs.into_iter().enumerate() {
+        println!(
+            "Phase {}/{} {}
+        ----------------------------
+        {}",
+            i + 1,
+            phases,
+            run.name,
+            run.args.join(" ")
+        );
+        if !run.inline_groups.is_empty() {
+            println!("        with groups {}", run.inline_groups.join(", "));
+        }
+        if config.check {
+            continue;
+        }
+
+        bench::start(run.config);
+    }
+
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<Vec<Run>> {
+    let file = fs::read_to_string(path)?;
+    let scenario: Scenario = toml::from_str(&file)?;
+    if scenario.phases.is_empty() {
+        bail!("no [[phase]]");
+    }
+
+    if let Some(name) = &scenario.name {
+        println!("Scenario {name}");
+    }
+    if let Some(description) = &scenario.description {
+        println!("        {description}");
+    }
+
+    scenario
+        .phases
+        .iter()
+        .map(|phase| run(&scenario.bench, phase).with_context(|| format!("phase {}", phase.name)))
+        .collect()
+}
+
+/// Bench config of `phase`, going through the same parser as the command line
+fn run(bench: &Table, phase: &Phase) -> Result<Run> {
+    let mut flags = bench.clone();
+    flags.extend(phase.flags.clone());
+
+    // inline groups aren't a flag, they replace `--groups` once parsed
+    let rows = match flags.remove("groups") {
+        Some(Value::Array(rows)) => Some(rows),
+        Some(path) => {
+            flags.insert("groups".to_owned(), path);
+            None
+        }
+        None => None,
+    };
+
+    let mut args = vec!["mqttwrk".to_owned(), "bench".to_owned()];
+    for (flag, value) in flags.iter() {
+        match value {
+            Value::Boolean(true) => args.push(format!("--{flag}")),
+            Value::Boolean(false) => {}
+            Value::Array(values) => {
+                for value in values {
+                    args.push(format!("--{flag}"));
+                    args.push(arg(flag, value, &phase.name)?);
+                }
+            }
+            value => {
+                args.push(format!("--{flag}"));
+                args.push(arg(flag, value, &phase.name)?);
+            }
+        }
+    }
+
+    let mut config = BenchConfig::try_parse_from(&args[1..])
+        .map_err(|e| anyhow::anyhow!("{}", e.to_string().trim_end()))?;
+
+    let mut inline_groups = Vec::new();
+    if let Some(rows) = rows {
+        // inline groups conflict with the same flags as `--groups`
+        let mut with_groups = args.clone();
+        with_groups.extend(["--groups".to_owned(), "inline".to_owned()]);
+        BenchConfig::command()
+            .mut_arg("groups", |groups| {
+                groups.value_parser(NonEmptyStringValueParser::new())
+            })
+            .try_get_matches_from(&with_groups[1..])
+            .map_err(|e| anyhow::anyhow!("{}", e.to_string().trim_end()))?;
+
+        let rows = rows
+            .iter()
+            .map(|row| match row {
+                Value::String(row) => Ok(row.as_str()),
+                row => bail!("expected rows of groups as strings, got `{row}`"),
+            })
+            .collect::<Result<Vec<&str>>>()?;
+        let groups = groups::parse("groups", rows.into_iter()).map_err(anyhow::Error::msg)?;
+        inline_groups = groups
+            .groups
+            .iter()
+            .map(|group| group.name.clone())
+            .collect();
+        config.groups = Some(groups);
+    }
+
+    Ok(Run {
+        name: phase.name.clone(),
+        args: args.iter().map(|arg| quote(arg)).collect(),
+        inline_groups,
+        config,
+    })
+}
+
+/// Command line argument of `value` of `flag`
+fn arg(flag: &str, value: &Value, phase: &str) -> Result<String> {
+    let arg = match value {
+        Value::String(value) => value.clone(),
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Boolean(value) => value.to_string(),
+        Value::Datetime(value) => value.to_string(),
+        Value::Array(_) | Value::Table(_) => bail!("{flag} takes a value or an array of values"),
+    };
+
+    Ok(arg.replace("{phase}", phase))
+}
+
+/// `arg` as it would be typed into a shell
+fn quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_.,:/=@%+".contains(c);
+    if arg.chars().all(plain) && !arg.is_empty() {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+    use toml::Value;
+
+    use super::{arg, quote, run, Run, Scenario};
+
+    fn runs(scenario: &str) -> Result<Vec<Run>> {
+        let scenario: Scenario = toml::from_str(scenario)?;
+        scenario
+            .phases
+            .iter()
+            .map(|phase| run(&scenario.bench, phase))
+            .collect()
+    }
+
+    fn error(scenario: &str) -> String {
+        match runs(scenario) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => format!("{e:#}"),
+        }
+    }
+
+    #[test]
+    fn phase_flags_win_over_bench() {
+        let runs = runs(
+            r#"
+            [bench]
+            subscribers = 2
+            count = 10
+            latency-tracking = true
+
+            [[phase]]
+            name = "warmup"
+
+            [[phase]]
+            name = "peak"
+            publishers = 3
+            count = 100
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(runs[0].config.count, 10);
+        assert_eq!(runs[1].name, "peak");
+        assert_eq!(runs[1].config.count, 100);
+        assert_eq!(runs[1].config.publishers, 3);
+        assert_eq!(runs[1].config.subscribers, 2);
+        assert!(runs[1].config.latency_tracking);
+        assert_eq!(
+            runs[1].args,
+            [
+                "mqttwrk",
+                "bench",
+                "--count",
+                "100",
+                "--latency-tracking",
+                "--publishers",
+                "3",
+                "--subscribers",
+                "2"
+            ]
+        );
+    }
+
+    #[test]
+    fn converts_switches_arrays_and_phase_names() {
+        let runs = runs(
+            r#"
+            [[phase]]
+            name = "peak"
+            latency-tracking = false
+            user-property = ["site=lab", "note=it's {phase}"]
+            output-file = "reports/{phase}.json"
+            "#,
+        )
+        .unwrap();
+
+        let run = &runs[0];
+        assert_eq!(
+            run.args,
+            [
+                "mqttwrk",
+                "bench",
+                "--output-file",
+                "reports/peak.json",
+                "--user-property",
+                "site=lab",
+                "--user-property",
+                r"'note=it'\''s peak'"
+            ]
+        );
+        assert!(!run.config.latency_tracking);
+        assert_eq!(
+            run.config.output_file,
+            Some(PathBuf::from("reports/peak.json"))
+        );
+        assert_eq!(
+            run.config.user_properties,
+            [
+                ("site".to_owned(), "lab".to_owned()),
+                ("note".to_owned(), "it's peak".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_inline_groups() {
+        let runs = runs(
+            r#"
+            [[phase]]
+            name = "fleet"
+            groups = ["sensors 10 qos=0", "gateways 2 topic=gw/{id}"]
+            "#,
+        )
+        .unwrap();
+
+        let run = &runs[0];
+        assert_eq!(run.inline_groups, ["sensors", "gateways"]);
+        assert!(!run.args.iter().any(|arg| arg == "--groups"));
+        assert_eq!(run.config.groups.as_ref().unwrap().publishers(), 12);
+    }
+
+    #[test]
+    fn rejects_inline_groups_with_flags_groups_conflict_with() {
+        let error = error(
+            r#"
+            [bench]
+            publishers = 2
+
+            [[phase]]
+            name = "fleet"
+            groups = ["sensors 10"]
+            "#,
+        );
+        assert!(error.contains("cannot be used with"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_invalid_phases() {
+        let phase = |flags: &str| error(&format!("[[phase]]\nname = \"p\"\n{flags}"));
+
+        assert_eq!(
+            phase(r#"groups = ["sensors"]"#),
+            "groups:1: expected NAME PUBLISHERS [KEY=VALUE..]"
+        );
+        assert_eq!(
+            phase("groups = [1]"),
+            "expected rows of groups as strings, got `1`"
+        );
+        assert!(phase("unknown-flag = 1").contains("unexpected argument"));
+        assert!(phase("publishers = \"many\"").contains("invalid value 'many'"));
+        assert_eq!(
+            phase("count = { n = 1 }"),
+            "count takes a value or an array of values"
+        );
+    }
+
+    #[test]
+    fn converts_values_to_arguments() {
+        assert_eq!(arg("count", &Value::Integer(10), "p").unwrap(), "10");
+        assert_eq!(arg("ratio", &Value::Float(0.5), "p").unwrap(), "0.5");
+        assert_eq!(
+            arg("topic", &Value::String("t/{phase}".to_owned()), "warmup").unwrap(),
+            "t/warmup"
+        );
+        assert!(arg("count", &Value::Array(vec![]), "p").is_err());
+    }
+
+    #[test]
+    fn quotes_arguments_a_shell_would_split() {
+        assert_eq!(quote("--count"), "--count");
+        assert_eq!(quote("gw/+/data"), "gw/+/data");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("#"), "'#'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+}
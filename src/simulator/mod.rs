@@ -6,7 +6,7 @@ use rumqttc::{MqttOptions, QoS, Transport};
 use tokio::{sync::Barrier, task};
 
 use crate::{
-    common::{PubStats, Stats, SubStats, PROGRESS_STYLE},
+    common::{LatencySummary, PubStats, Stats, SubStats, PROGRESS_STYLE},
     SimulatorConfig,
 };
 
@@ -44,7 +44,7 @@ pub(crate) async fn start(config: SimulatorConfig) {
         sub_bar.set_message(format!("spawning {id}"));
         let mut subscriber = subscriber::Subscriber::new(id, config).await.unwrap();
         handles.push(task::spawn(async move {
-            Stats::SubStats(subscriber.start(barrier_handle).await)
+            Stats::SubStats(Box::new(subscriber.start(barrier_handle).await))
         }));
         sub_bar.inc(1);
     }
@@ -62,7 +62,7 @@ pub(crate) async fn start(config: SimulatorConfig) {
         pub_bar.set_message(format!("spawning {id}"));
         let mut publisher = publisher::Publisher::new(id, config).await.unwrap();
         handles.push(task::spawn(async move {
-            Stats::PubStats(publisher.start(barrier_handle).await)
+            Stats::PubStats(Box::new(publisher.start(barrier_handle).await))
         }));
         pub_bar.inc(1);
     }
@@ -73,23 +73,32 @@ pub(crate) async fn start(config: SimulatorConfig) {
     // await and consume all futures
     while let Some(some_stat) = handles.next().await {
         match some_stat.unwrap() {
-            Stats::SubStats(substats) => {
-                aggregate_substats.publish_count += substats.publish_count;
-                aggregate_substats.puback_count += substats.puback_count;
-                aggregate_substats.reconnects += substats.reconnects;
-                aggregate_substats.throughput += substats.throughput;
-            }
-            Stats::PubStats(pubstats) => {
-                aggregate_pubstats.outgoing_publish += pubstats.outgoing_publish;
-                aggregate_pubstats.throughput += pubstats.throughput;
-                aggregate_pubstats.reconnects += pubstats.reconnects;
-            }
+            Stats::SubStats(substats) => aggregate_substats.merge(&substats),
+            Stats::PubStats(pubstats) => aggregate_pubstats.merge(&pubstats),
         }
     }
 
     println!(
-        "Aggregate PubStats: {:#?}\nAggregate SubStats: {:#?}",
-        &aggregate_pubstats, &aggregate_substats
+        "Aggregate
+        ----------------------------
+        Outgoing publishes : {:<7} Throughput = {} messages/s
+        Reconnects         : {}
+        Ack latencies      : {}
+
+        Incoming publishes : {:<7} Throughput = {} messages/s
+        Outgoing pubacks   : Sent = {}
+        Reconnects         : {}
+        Latencies          : {}
+        ",
+        aggregate_pubstats.outgoing_publish,
+        aggregate_pubstats.throughput,
+        aggregate_pubstats.reconnects,
+        LatencySummary::from(&aggregate_pubstats.ack_latencies),
+        aggregate_substats.publish_count,
+        aggregate_substats.throughput,
+        aggregate_substats.puback_count,
+        aggregate_substats.reconnects,
+        LatencySummary::from(&aggregate_substats.latencies),
     );
 }
 
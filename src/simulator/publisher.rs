@@ -5,7 +5,6 @@ use std::{
 };
 
 use fake::{Dummy, Fake, Faker};
-use hdrhistogram::Histogram;
 use rumqttc::{AsyncClient, Event, EventLoop, Incoming, MqttOptions, Outgoing, QoS, Transport};
 use serde::Serialize;
 use tokio::{
@@ -14,7 +13,12 @@ use tokio::{
     time::{self, Duration},
 };
 
-use crate::{bench::ConnectionError, simulator::PubStats, DataType, SimulatorConfig};
+use crate::{
+    bench::{pacer::Pacer, timeout_secs, ConnectionError},
+    common::{latency_histogram, LatencySummary},
+    simulator::PubStats,
+    DataType, SimulatorConfig,
+};
 
 #[derive(Debug, Serialize, Dummy)]
 struct Imu {
@@ -151,7 +155,7 @@ impl Publisher {
         let (client, mut eventloop) = AsyncClient::new(options(config.clone(), &id)?, 10);
         eventloop
             .network_options
-            .set_connection_timeout(config.conn_timeout);
+            .set_connection_timeout(timeout_secs(config.conn_timeout));
 
         loop {
             let event = match eventloop.poll().await {
@@ -219,10 +223,8 @@ impl Publisher {
         // If publish count is 0, don't publish. This is an idle connection
         // which can be used to test pings
         if count != 0 {
-            // delay between messages in milliseconds
-            let delay = if rate == 0 { 0 } else { 1000 / rate };
             task::spawn(async move {
-                requests(topic, count, client, qos, delay, data_type).await;
+                requests(topic, count, client, qos, rate, data_type).await;
             });
         } else {
             // Just keep this connection alive
@@ -236,7 +238,7 @@ impl Publisher {
 
         let mut reconnects: u64 = 0;
         let mut latencies: Vec<Option<Instant>> = vec![None; inflight as usize + 1];
-        let mut histogram = Histogram::<u64>::new(4).unwrap();
+        let mut histogram = latency_histogram();
 
         loop {
             let event = match self.eventloop.poll().await {
@@ -264,7 +266,7 @@ impl Publisher {
                                 continue;
                             }
                         };
-                        histogram.record(elapsed.as_millis() as u64).unwrap();
+                        histogram.record(elapsed.as_micros() as u64).unwrap();
                     }
                     Incoming::PingResp => {
                         debug!("ping response")
@@ -298,25 +300,13 @@ impl Publisher {
             ----------------------------
             Outgoing publishes : {:<7} Throughput = {} messages/s
             Reconnects         : {}
-
-            Latencies of {} samples
-            ----------------------------
-            100                 : {}
-            99.9999 percentile  : {}
-            99.999 percentile   : {}
-            90 percentile       : {}
-            50 percentile       : {}
+            Ack latencies      : {}
             ",
                 self.id,
                 acks_count,
                 outgoing_throughput,
                 reconnects,
-                histogram.len(),
-                histogram.value_at_percentile(100.0),
-                histogram.value_at_percentile(99.9999),
-                histogram.value_at_percentile(99.999),
-                histogram.value_at_percentile(90.0),
-                histogram.value_at_percentile(50.0),
+                LatencySummary::from(&histogram),
             );
         }
 
@@ -326,9 +316,12 @@ impl Publisher {
         }
 
         PubStats {
+            id: self.id.clone(),
             outgoing_publish: acks_count as u64,
             throughput: outgoing_throughput,
             reconnects,
+            ack_latencies: histogram,
+            ..Default::default()
         }
     }
 }
@@ -396,18 +389,15 @@ async fn requests(
     count: usize,
     client: AsyncClient,
     qos: QoS,
-    delay: u64,
+    rate: u64,
     data_type: DataType,
 ) {
-    let mut interval = match delay {
-        0 => None,
-        delay => Some(time::interval(time::Duration::from_millis(delay))),
-    };
+    let mut pacer = Pacer::new(rate);
 
     for i in 0..count {
         let payload = generate_data(i, data_type);
-        if let Some(interval) = &mut interval {
-            interval.tick().await;
+        if let Some(pacer) = &mut pacer {
+            pacer.wait().await;
         }
 
         // These errors are usually due to eventloop task being dead. We can ignore the
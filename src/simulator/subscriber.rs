@@ -3,11 +3,12 @@ use std::{
     time::{Duration, Instant},
 };
 
-use hdrhistogram::Histogram;
 use rumqttc::{AsyncClient, Event, EventLoop, Incoming, Outgoing};
 use tokio::{sync::Barrier, time};
 
 use crate::{
+    bench::timeout_secs,
+    common::{latency_histogram, LatencySummary},
     simulator::{get_qos, options, ConnectionError, SubStats},
     SimulatorConfig,
 };
@@ -28,7 +29,7 @@ impl Subscriber {
         let (client, mut eventloop) = AsyncClient::new(options(config.clone(), &id)?, 10);
         eventloop
             .network_options
-            .set_connection_timeout(config.conn_timeout);
+            .set_connection_timeout(timeout_secs(config.conn_timeout));
 
         // waiting for connection
         loop {
@@ -79,7 +80,7 @@ impl Subscriber {
         // when the latest publish arrived
         let mut last_publish = Instant::now();
         // to record latencies
-        let mut histogram = Histogram::<u64>::new(4).unwrap();
+        let mut histogram = latency_histogram();
         // number of reconnects attempted
         let mut reconnects = 0;
 
@@ -144,7 +145,7 @@ impl Subscriber {
                     seq += 1;
                     publish_count += 1;
                     histogram
-                        .record(last_publish.elapsed().as_millis() as u64)
+                        .record(last_publish.elapsed().as_micros() as u64)
                         .unwrap();
                     last_publish = Instant::now();
                     // slow consumer every 100 messages
@@ -172,34 +173,25 @@ impl Subscriber {
             Incoming publishes : {:<7} Throughput = {} messages/s
             Outgoing pubacks   : Sent = {}
             Reconnects         : {}
-
-            Latencies of {} samples
-            ----------------------------
-            100                 : {}
-            99.9999 percentile  : {}
-            99.999 percentile   : {}
-            90 percentile       : {}
-            50 percentile       : {}
+            Latencies          : {}
             ",
                 self.id,
                 publish_count,
                 outgoing_throughput,
                 puback_count,
                 reconnects,
-                histogram.len(),
-                histogram.value_at_percentile(100.0),
-                histogram.value_at_percentile(99.9999),
-                histogram.value_at_percentile(99.999),
-                histogram.value_at_percentile(90.0),
-                histogram.value_at_percentile(50.0),
+                LatencySummary::from(&histogram),
             );
         }
 
         SubStats {
+            id: self.id.clone(),
             publish_count: publish_count as u64,
             puback_count,
             reconnects,
             throughput: outgoing_throughput,
+            latencies: histogram,
+            ..Default::default()
         }
     }
 }